@@ -10,7 +10,7 @@ use smoltcp::{
     wire::{EthernetFrame, PrettyPrinter},
 };
 
-use smoltcp_contrib::phy::xdp::{ChunkConfig, Config, RingConfig, UmemConfig, XdpSocket};
+use smoltcp_contrib::phy::xdp::{ChunkSize, XdpSocket, XdpSocketBuilder};
 
 // sudo ip link set dev wlan0 xdp obj xdp.o sec xdp
 // sudo RUST_BACKTRACE=1 cargo run --example tcpdump-xdp -- {IFNAME}
@@ -20,19 +20,24 @@ fn main() {
         .nth(1)
         .expect("usage: xdp-example <ifname>");
 
-    let config = Config {
-        queue_id: 0,
-        umem: UmemConfig {
-            entries: 1024,
-            alignment: ChunkConfig::FourK,
-        },
-        tx: RingConfig { size: 16 },
-        rx: RingConfig { size: 16 },
-        cr: RingConfig { size: 16 },
-        fr: RingConfig { size: 16 },
-    };
-    let mut socket: XdpSocket<'_> = XdpSocket::new(ifname.as_str(), config).unwrap();
+    let queue_id = 0;
+    let mut socket: XdpSocket<'_> = XdpSocketBuilder::new()
+        .queue_id(queue_id)
+        .ring_size(16)
+        .fill_ring_size(16)
+        .umem_entries(1024)
+        .chunk_size(ChunkSize::FourK)
+        .rx_batch(64)
+        .tx_batch(1)
+        .fill_batch(1)
+        .default_program(None)
+        .build(ifname.as_str())
+        .unwrap();
     let socket_fd = socket.as_raw_fd() as i32;
+    match socket.driver_info() {
+        Ok(driver) => println!("{}: {}, {:?}", ifname, driver, socket.bind_mode()),
+        Err(err) => eprintln!("Failed to query the driver of {}: {}", ifname, err),
+    }
 
     let pin_path = CString::new("/sys/fs/bpf/xdp/globals/socket_map").unwrap();
     let map_fd;
@@ -48,7 +53,7 @@ fn main() {
     unsafe {
         let ret = bpf_map_update_elem(
             map_fd,
-            &config.queue_id as *const _ as *const _,
+            &queue_id as *const _ as *const _,
             &socket_fd as *const _ as *const _,
             BPF_ANY as u64,
         );
@@ -1,5 +1,10 @@
 
 #include <linux/bpf.h>
+#include <linux/if_ether.h>
+#include <linux/in.h>
+#include <linux/ip.h>
+#include <linux/ipv6.h>
+#include <bpf/bpf_endian.h>
 #include <bpf/bpf_helpers.h>
 
 struct {
@@ -10,13 +15,256 @@ struct {
     __uint(pinning, 1);
 } socket_map SEC(".maps");
 
+/* Ethertypes left to the kernel stack, see smoltcp_contrib::phy::xdp::passthrough. */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 64);
+    __type(key, __u16);
+    __type(value, __u8);
+    __uint(pinning, 1);
+} passthrough_map SEC(".maps");
+
+/* Reinjection of the flows smoltcp does not own, see
+ * smoltcp_contrib::phy::xdp::reinject. Steering is off while steer_config holds no CPU. */
+struct steer_config {
+    __u32 cpus;
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct steer_config);
+    __uint(pinning, 1);
+} steer_config SEC(".maps");
+
+/* CPUs flows are spread over, the first steer_config.cpus entries are used. */
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 64);
+    __type(key, __u32);
+    __type(value, __u32);
+    __uint(pinning, 1);
+} steer_cpus SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_CPUMAP);
+    __uint(max_entries, 64);
+    __type(key, __u32);
+    __type(value, struct bpf_cpumap_val);
+    __uint(pinning, 1);
+} cpu_map SEC(".maps");
+
+/* Must match smoltcp_contrib::phy::xdp::reinject::Flow, the port is in host order. */
+struct owned_flow {
+    __u8 protocol;
+    __u8 pad;
+    __u16 port;
+};
+
+/* Destination ports redirected to the sockets while steering. */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 256);
+    __type(key, struct owned_flow);
+    __type(value, __u8);
+    __uint(pinning, 1);
+} owned_map SEC(".maps");
+
+/* Key of 802.3 frames carrying a length instead of an ethertype, e.g. STP. */
+#define PASSTHROUGH_LLC 0
+
+struct vlan_hdr {
+    __be16 tci;
+    __be16 proto;
+};
+
+/* Must match smoltcp_contrib::phy::xdp::meta::Meta. */
+#define XDP_META_MAGIC 0x584d0003
+#define XDP_META_HINT_RX_HASH (1 << 0)
+#define XDP_META_HINT_VLAN_TAG (1 << 1)
+
+struct xdp_meta {
+    __u64 timestamp;
+    __u64 hw_timestamp;
+    __u32 rx_hash;
+    __u32 rx_hash_type;
+    __u16 vlan_proto;
+    __u16 vlan_tci;
+    __u32 hints;
+    __u32 mark;
+    __u32 verdict;
+    __u32 rx_queue;
+    __u32 magic;
+};
+
+/* XDP hints, failing with EOPNOTSUPP unless the program is loaded bound to a device
+ * whose driver implements them. */
+extern int bpf_xdp_metadata_rx_timestamp(const struct xdp_md *ctx, __u64 *timestamp) __ksym __weak;
+/* The hash type is an enum xdp_rss_hash_type, which is not part of the UAPI headers. */
+extern int bpf_xdp_metadata_rx_hash(const struct xdp_md *ctx, __u32 *hash, __u32 *rss_type) __ksym __weak;
+extern int bpf_xdp_metadata_rx_vlan_tag(const struct xdp_md *ctx, __be16 *vlan_proto, __u16 *vlan_tci) __ksym __weak;
+
+static __always_inline void write_meta(struct xdp_md *ctx, __u32 verdict) {
+    __u64 hw_timestamp = 0;
+    if (bpf_ksym_exists(bpf_xdp_metadata_rx_timestamp) &&
+        bpf_xdp_metadata_rx_timestamp(ctx, &hw_timestamp))
+        hw_timestamp = 0;
+
+    __u32 hints = 0;
+    __u32 rx_hash = 0, rx_hash_type = 0;
+    if (bpf_ksym_exists(bpf_xdp_metadata_rx_hash) &&
+        !bpf_xdp_metadata_rx_hash(ctx, &rx_hash, &rx_hash_type))
+        hints |= XDP_META_HINT_RX_HASH;
+
+    __be16 vlan_proto = 0;
+    __u16 vlan_tci = 0;
+    if (bpf_ksym_exists(bpf_xdp_metadata_rx_vlan_tag) &&
+        !bpf_xdp_metadata_rx_vlan_tag(ctx, &vlan_proto, &vlan_tci))
+        hints |= XDP_META_HINT_VLAN_TAG;
+
+    if (bpf_xdp_adjust_meta(ctx, -(int)sizeof(struct xdp_meta)))
+        return;
+
+    void *data = (void *)(long)ctx->data;
+    struct xdp_meta *meta = (void *)(long)ctx->data_meta;
+    if ((void *)(meta + 1) > data)
+        return;
+
+    meta->timestamp = bpf_ktime_get_ns();
+    meta->hw_timestamp = hw_timestamp;
+    meta->rx_hash = rx_hash;
+    meta->rx_hash_type = rx_hash_type;
+    meta->vlan_proto = bpf_ntohs(vlan_proto);
+    meta->vlan_tci = vlan_tci;
+    meta->hints = hints;
+    meta->mark = 0;
+    meta->verdict = verdict;
+    meta->rx_queue = ctx->rx_queue_index;
+    meta->magic = XDP_META_MAGIC;
+}
+
+static __always_inline int passthrough(struct xdp_md *ctx) {
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+    struct ethhdr *eth = data;
+    if ((void *)(eth + 1) > data_end)
+        return 0;
+
+    __u16 proto = bpf_ntohs(eth->h_proto);
+    if (proto == ETH_P_8021Q || proto == ETH_P_8021AD) {
+        struct vlan_hdr *vlan = (void *)(eth + 1);
+        if ((void *)(vlan + 1) > data_end)
+            return 0;
+        proto = bpf_ntohs(vlan->proto);
+    }
+    if (proto < ETH_P_802_3_MIN)
+        proto = PASSTHROUGH_LLC;
+
+    return bpf_map_lookup_elem(&passthrough_map, &proto) != NULL;
+}
+
+/* What steering reads from a frame, left zeroed for non-IP frames and the flow
+ * left zeroed for other protocols than TCP and UDP. */
+struct flow_hdrs {
+    int ip;
+    __u8 protocol;
+    __u32 hash;
+    struct owned_flow flow;
+};
+
+static __always_inline void parse_flow(struct xdp_md *ctx, struct flow_hdrs *hdrs) {
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+    struct ethhdr *eth = data;
+    if ((void *)(eth + 1) > data_end)
+        return;
+
+    void *l3 = eth + 1;
+    __u16 proto = bpf_ntohs(eth->h_proto);
+    if (proto == ETH_P_8021Q || proto == ETH_P_8021AD) {
+        struct vlan_hdr *vlan = l3;
+        if ((void *)(vlan + 1) > data_end)
+            return;
+        proto = bpf_ntohs(vlan->proto);
+        l3 = vlan + 1;
+    }
+
+    void *l4;
+    if (proto == ETH_P_IP) {
+        struct iphdr *ip = l3;
+        if ((void *)(ip + 1) > data_end)
+            return;
+        hdrs->ip = 1;
+        hdrs->protocol = ip->protocol;
+        hdrs->hash = ip->saddr ^ ip->daddr;
+        /* Fragments after the first have no L4 header. */
+        if (ip->frag_off & bpf_htons(0x1fff))
+            return;
+        l4 = (void *)ip + ip->ihl * 4;
+    } else if (proto == ETH_P_IPV6) {
+        struct ipv6hdr *ip6 = l3;
+        if ((void *)(ip6 + 1) > data_end)
+            return;
+        hdrs->ip = 1;
+        hdrs->protocol = ip6->nexthdr;
+        for (int i = 0; i < 4; i++)
+            hdrs->hash ^= ip6->saddr.in6_u.u6_addr32[i] ^ ip6->daddr.in6_u.u6_addr32[i];
+        l4 = ip6 + 1;
+    } else {
+        return;
+    }
+
+    if (hdrs->protocol != IPPROTO_TCP && hdrs->protocol != IPPROTO_UDP)
+        return;
+    /* Source and destination ports, at the same offset for TCP and UDP. */
+    __be16 *ports = l4;
+    if ((void *)(ports + 2) > data_end)
+        return;
+    hdrs->hash ^= (__u32)ports[0] << 16 | ports[1];
+    hdrs->flow.protocol = hdrs->protocol;
+    hdrs->flow.port = bpf_ntohs(ports[1]);
+}
+
+/* Spreads the IP flows smoltcp does not own over the steering CPUs, returns -1 to
+ * redirect the frame to the socket. */
+static __always_inline int steer(struct xdp_md *ctx) {
+    __u32 zero = 0;
+    struct steer_config *config = bpf_map_lookup_elem(&steer_config, &zero);
+    if (!config || !config->cpus)
+        return -1;
+
+    struct flow_hdrs hdrs = {};
+    parse_flow(ctx, &hdrs);
+    /* ARP, NDP and other non-IP traffic still reaches smoltcp. */
+    if (!hdrs.ip)
+        return -1;
+    if (hdrs.flow.protocol && bpf_map_lookup_elem(&owned_map, &hdrs.flow))
+        return -1;
+
+    __u32 hash = (hdrs.hash ^ hdrs.protocol) * 0x9e3779b1;
+    __u32 slot = (hash >> 16) % config->cpus;
+    __u32 *cpu = bpf_map_lookup_elem(&steer_cpus, &slot);
+    if (!cpu)
+        return XDP_PASS;
+    return bpf_redirect_map(&cpu_map, *cpu, XDP_PASS);
+}
+
 SEC("xdp")
 int xdp_redirect_prog(struct xdp_md *ctx) {
+    if (passthrough(ctx))
+        return XDP_PASS;
+
+    int steered = steer(ctx);
+    if (steered >= 0)
+        return steered;
+
     __u32 index = ctx->rx_queue_index;
     void *val = bpf_map_lookup_elem(&socket_map, &index);
     __u64 val_int = (__u64)(unsigned long)val;
 
     if (val) {
+        write_meta(ctx, XDP_REDIRECT);
         int ret = bpf_redirect_map(&socket_map, index, 0);
         bpf_trace_printk(
             "XDP_REDIRECT queue=%d, ret=%d val=%llu\n",
@@ -0,0 +1,486 @@
+//! Replays a pcap/pcapng capture through an XDP socket.
+//!
+//! sudo cargo run --bin xdp-replay -- {IFNAME} {FILE} [--timing original|top|<pps>] [--loop <N>]
+//!
+//! `--timing original` (default) honours the inter-packet gaps recorded in the capture,
+//! `--timing top` sends as fast as the TX ring allows and `--timing <pps>` paces the
+//! frames at a fixed rate. `--loop 0` replays the capture forever.
+//!
+//! A frame waits for a TX token while the kernel drains the TX ring, and is skipped if
+//! none frees up within a second. Skipped frames are reported at the end.
+
+use std::time::Duration;
+use std::{fs, io, process, thread};
+
+use smoltcp::phy::{Device, TxToken};
+use smoltcp::time::{self, Instant};
+
+use smoltcp_contrib::phy::xdp::{XdpSocket, XdpSocketBuilder};
+
+#[derive(Clone, Copy)]
+enum Timing {
+    Original,
+    Top,
+    Rate(u64),
+}
+
+// How long a frame waits for a TX token before being skipped.
+const TX_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Packet {
+    // Capture timestamp in nanoseconds.
+    timestamp: u64,
+    data: Vec<u8>,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: xdp-replay <ifname> <file> [--timing original|top|<pps>] [--loop <N>]");
+    process::exit(2)
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let ifname = args.next().unwrap_or_else(|| usage());
+    let path = args.next().unwrap_or_else(|| usage());
+
+    let mut timing = Timing::Original;
+    let mut loops = 1u64;
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--timing" => {
+                timing = match value.as_str() {
+                    "original" => Timing::Original,
+                    "top" => Timing::Top,
+                    pps => match pps.parse() {
+                        Ok(pps) if pps > 0 => Timing::Rate(pps),
+                        _ => usage(),
+                    },
+                }
+            }
+            "--loop" => loops = value.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+
+    let bytes = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("Failed to read {}: {}", path, err);
+        process::exit(1)
+    });
+    let packets = parse(&bytes).unwrap_or_else(|err| {
+        eprintln!("Failed to parse {}: {}", path, err);
+        process::exit(1)
+    });
+
+    // Replaying only sends, so the RX side gets small rings.
+    let builder = XdpSocketBuilder::new()
+        .queue_id(0)
+        .rx_ring_size(16)
+        .fill_ring_size(16)
+        .tx_ring_size(512)
+        .completion_ring_size(512);
+    let mut socket = builder.build(ifname.as_str()).unwrap_or_else(|err| {
+        eprintln!("Failed to open an XDP socket on {}: {}", ifname, err);
+        process::exit(1)
+    });
+
+    let mut iteration = 0;
+    let mut skipped = 0;
+    while loops == 0 || iteration < loops {
+        skipped += replay(&mut socket, &packets, timing);
+        iteration += 1;
+    }
+
+    println!(
+        "Replayed {} packets {} time(s) on {}, {} skipped for lack of a TX token",
+        packets.len(),
+        iteration,
+        ifname,
+        skipped
+    );
+}
+
+/// Sends `packets` once, returning how many were skipped.
+fn replay(socket: &mut XdpSocket<'_>, packets: &[Packet], timing: Timing) -> u64 {
+    let Some(first) = packets.first() else {
+        return 0;
+    };
+    let start = std::time::Instant::now();
+    let mut skipped = 0;
+
+    for (idx, packet) in packets.iter().enumerate() {
+        let offset = match timing {
+            Timing::Original => Some(Duration::from_nanos(
+                packet.timestamp.saturating_sub(first.timestamp),
+            )),
+            Timing::Top => None,
+            Timing::Rate(pps) => Some(Duration::from_nanos(idx as u64 * 1_000_000_000 / pps)),
+        };
+        if let Some(offset) = offset {
+            let elapsed = start.elapsed();
+            if offset > elapsed {
+                thread::sleep(offset - elapsed);
+            }
+        }
+
+        let deadline = std::time::Instant::now() + TX_TIMEOUT;
+        loop {
+            if let Some(tx) = socket.transmit(Instant::now()) {
+                tx.consume(packet.data.len(), |buf| buf.copy_from_slice(&packet.data));
+                break;
+            }
+            if std::time::Instant::now() >= deadline {
+                skipped += 1;
+                break;
+            }
+            // Completions free the TX ring entries and UMEM chunks the token needs.
+            if socket.wait(Some(time::Duration::from_millis(1))).is_err() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+    skipped
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn parse(bytes: &[u8]) -> io::Result<Vec<Packet>> {
+    match bytes.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => parse_pcapng(bytes),
+        Some(_) => parse_pcap(bytes),
+        None => Err(invalid("File too short")),
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize, le: bool) -> io::Result<u16> {
+    let raw: [u8; 2] = bytes
+        .get(offset..offset + 2)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| invalid("Truncated capture"))?;
+    Ok(if le {
+        u16::from_le_bytes(raw)
+    } else {
+        u16::from_be_bytes(raw)
+    })
+}
+
+fn u32_at(bytes: &[u8], offset: usize, le: bool) -> io::Result<u32> {
+    let raw: [u8; 4] = bytes
+        .get(offset..offset + 4)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| invalid("Truncated capture"))?;
+    Ok(if le {
+        u32::from_le_bytes(raw)
+    } else {
+        u32::from_be_bytes(raw)
+    })
+}
+
+fn slice_at(bytes: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    bytes
+        .get(offset..offset + len)
+        .ok_or_else(|| invalid("Truncated capture"))
+}
+
+fn parse_pcap(bytes: &[u8]) -> io::Result<Vec<Packet>> {
+    let (le, nanos) = match u32_at(bytes, 0, true)? {
+        0xa1b2c3d4 => (true, false),
+        0xa1b23c4d => (true, true),
+        0xd4c3b2a1 => (false, false),
+        0x4d3cb2a1 => (false, true),
+        _ => return Err(invalid("Unknown capture format")),
+    };
+
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while offset < bytes.len() {
+        let secs = u32_at(bytes, offset, le)? as u64;
+        let frac = u32_at(bytes, offset + 4, le)? as u64;
+        let caplen = u32_at(bytes, offset + 8, le)? as usize;
+        let data = slice_at(bytes, offset + 16, caplen)?;
+
+        let frac = if nanos { frac } else { frac * 1_000 };
+        packets.push(Packet {
+            timestamp: secs * 1_000_000_000 + frac,
+            data: data.to_vec(),
+        });
+        offset += 16 + caplen;
+    }
+
+    Ok(packets)
+}
+
+fn parse_pcapng(bytes: &[u8]) -> io::Result<Vec<Packet>> {
+    const SECTION_HEADER: u32 = 0x0a0d0d0a;
+    const INTERFACE_DESCRIPTION: u32 = 0x00000001;
+    const SIMPLE_PACKET: u32 = 0x00000003;
+    const ENHANCED_PACKET: u32 = 0x00000006;
+    const IF_TSRESOL: u16 = 9;
+
+    let mut packets = Vec::new();
+    // Timestamp resolution (units per second) of every interface in the current section.
+    let mut resolutions: Vec<u64> = Vec::new();
+    let mut le = true;
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        if u32_at(bytes, offset, true)? == SECTION_HEADER {
+            le = match u32_at(bytes, offset + 8, true)? {
+                0x1a2b3c4d => true,
+                0x4d3c2b1a => false,
+                _ => return Err(invalid("Unknown byte-order magic")),
+            };
+            resolutions.clear();
+        }
+
+        let type_ = u32_at(bytes, offset, le)?;
+        let len = u32_at(bytes, offset + 4, le)? as usize;
+        if len < 12 {
+            return Err(invalid("Invalid block length"));
+        }
+        let body = slice_at(bytes, offset + 8, len - 12)?;
+
+        match type_ {
+            INTERFACE_DESCRIPTION => {
+                let mut resolution = 1_000_000;
+                let mut opt = 8;
+                while opt + 4 <= body.len() {
+                    let code = u16_at(body, opt, le)?;
+                    let opt_len = u16_at(body, opt + 2, le)? as usize;
+                    if code == 0 {
+                        break;
+                    }
+                    if code == IF_TSRESOL && opt_len == 1 {
+                        let value = slice_at(body, opt + 4, 1)?[0];
+                        let exp = u32::from(value & 0x7f);
+                        let base: u64 = if value & 0x80 == 0 { 10 } else { 2 };
+                        resolution = base
+                            .checked_pow(exp)
+                            .ok_or_else(|| invalid("Unsupported timestamp resolution"))?;
+                    }
+                    opt += 4 + opt_len.next_multiple_of(4);
+                }
+                resolutions.push(resolution);
+            }
+            ENHANCED_PACKET => {
+                let iface = u32_at(body, 0, le)? as usize;
+                let ts = (u64::from(u32_at(body, 4, le)?) << 32) | u64::from(u32_at(body, 8, le)?);
+                let caplen = u32_at(body, 12, le)? as usize;
+                let data = slice_at(body, 20, caplen)?;
+
+                let resolution = *resolutions
+                    .get(iface)
+                    .ok_or_else(|| invalid("Unknown interface id"))?;
+                packets.push(Packet {
+                    timestamp: (ts as u128 * 1_000_000_000 / resolution as u128) as u64,
+                    data: data.to_vec(),
+                });
+            }
+            SIMPLE_PACKET => {
+                // Simple packet blocks carry no timestamp, keep the previous one.
+                let orig_len = u32_at(body, 0, le)? as usize;
+                let data = slice_at(body, 4, orig_len.min(body.len().saturating_sub(4)))?;
+                packets.push(Packet {
+                    timestamp: packets.last().map_or(0, |p| p.timestamp),
+                    data: data.to_vec(),
+                });
+            }
+            _ => {}
+        }
+
+        offset += len;
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_bytes(value: u16, le: bool) -> [u8; 2] {
+        if le {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        }
+    }
+
+    fn u32_bytes(value: u32, le: bool) -> [u8; 4] {
+        if le {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        }
+    }
+
+    fn pcap(magic: u32, le: bool, records: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut file = u32_bytes(magic, le).to_vec();
+        file.extend_from_slice(&u16_bytes(2, le));
+        file.extend_from_slice(&u16_bytes(4, le));
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&u32_bytes(65535, le));
+        file.extend_from_slice(&u32_bytes(1, le));
+        for &(secs, frac, data) in records {
+            file.extend_from_slice(&u32_bytes(secs, le));
+            file.extend_from_slice(&u32_bytes(frac, le));
+            file.extend_from_slice(&u32_bytes(data.len() as u32, le));
+            file.extend_from_slice(&u32_bytes(data.len() as u32, le));
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    fn block(type_: u32, body: &[u8], le: bool) -> Vec<u8> {
+        let padded = body.len().next_multiple_of(4);
+        let len = u32_bytes(12 + padded as u32, le);
+        let mut block = u32_bytes(type_, le).to_vec();
+        block.extend_from_slice(&len);
+        block.extend_from_slice(body);
+        block.resize(8 + padded, 0);
+        block.extend_from_slice(&len);
+        block
+    }
+
+    fn section(le: bool) -> Vec<u8> {
+        let mut body = u32_bytes(0x1a2b3c4d, le).to_vec();
+        body.extend_from_slice(&u16_bytes(1, le));
+        body.extend_from_slice(&u16_bytes(0, le));
+        body.extend_from_slice(&[0xff; 8]);
+        block(0x0a0d0d0a, &body, le)
+    }
+
+    fn interface(tsresol: Option<u8>, le: bool) -> Vec<u8> {
+        let mut body = u16_bytes(1, le).to_vec();
+        body.extend_from_slice(&[0; 2]);
+        body.extend_from_slice(&u32_bytes(65535, le));
+        if let Some(tsresol) = tsresol {
+            body.extend_from_slice(&u16_bytes(9, le));
+            body.extend_from_slice(&u16_bytes(1, le));
+            body.extend_from_slice(&[tsresol, 0, 0, 0]);
+            body.extend_from_slice(&[0; 4]);
+        }
+        block(1, &body, le)
+    }
+
+    fn enhanced(iface: u32, ts: u64, data: &[u8], le: bool) -> Vec<u8> {
+        let mut body = u32_bytes(iface, le).to_vec();
+        body.extend_from_slice(&u32_bytes((ts >> 32) as u32, le));
+        body.extend_from_slice(&u32_bytes(ts as u32, le));
+        body.extend_from_slice(&u32_bytes(data.len() as u32, le));
+        body.extend_from_slice(&u32_bytes(data.len() as u32, le));
+        body.extend_from_slice(data);
+        block(6, &body, le)
+    }
+
+    fn simple(data: &[u8], le: bool) -> Vec<u8> {
+        let mut body = u32_bytes(data.len() as u32, le).to_vec();
+        body.extend_from_slice(data);
+        block(3, &body, le)
+    }
+
+    fn summary(packets: &[Packet]) -> Vec<(u64, &[u8])> {
+        packets.iter().map(|p| (p.timestamp, &p.data[..])).collect()
+    }
+
+    #[test]
+    fn pcap_byte_orders_and_resolutions() {
+        let records: &[(u32, u32, &[u8])] = &[(1, 500, &[1, 2, 3]), (2, 0, &[4])];
+        for le in [true, false] {
+            let micros = parse(&pcap(0xa1b2c3d4, le, records)).unwrap();
+            assert_eq!(
+                summary(&micros),
+                [(1_000_500_000, &[1, 2, 3][..]), (2_000_000_000, &[4])]
+            );
+            let nanos = parse(&pcap(0xa1b23c4d, le, records)).unwrap();
+            assert_eq!(
+                summary(&nanos),
+                [(1_000_000_500, &[1, 2, 3][..]), (2_000_000_000, &[4])]
+            );
+        }
+    }
+
+    #[test]
+    fn pcap_rejects_unknown_magic() {
+        assert!(parse(&pcap(0x12345678, true, &[])).is_err());
+        assert!(parse(&[0xd4, 0xc3]).is_err());
+    }
+
+    #[test]
+    fn pcap_rejects_truncated_records() {
+        let mut file = pcap(0xa1b2c3d4, true, &[(1, 0, &[1, 2, 3, 4])]);
+        file.pop();
+        let err = parse(&file).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "Truncated capture");
+    }
+
+    #[test]
+    fn pcapng_packets() {
+        for le in [true, false] {
+            let mut file = section(le);
+            // Default microsecond resolution, then nanoseconds and 2^-10 seconds.
+            file.extend(interface(None, le));
+            file.extend(interface(Some(9), le));
+            file.extend(interface(Some(0x80 | 10), le));
+            file.extend(enhanced(0, 1_500_000, &[1, 2, 3], le));
+            file.extend(simple(&[4, 5], le));
+            file.extend(enhanced(1, 2_000_000_001, &[6], le));
+            file.extend(enhanced(2, 3 << 10, &[7, 8, 9, 10, 11], le));
+            assert_eq!(
+                summary(&parse(&file).unwrap()),
+                [
+                    (1_500_000_000, &[1, 2, 3][..]),
+                    (1_500_000_000, &[4, 5]),
+                    (2_000_000_001, &[6]),
+                    (3_000_000_000, &[7, 8, 9, 10, 11]),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn pcapng_sections_reset_interfaces() {
+        let mut file = section(true);
+        file.extend(interface(Some(9), true));
+        file.extend(section(false));
+        file.extend(interface(None, false));
+        file.extend(enhanced(0, 7, &[1], false));
+        assert_eq!(summary(&parse(&file).unwrap()), [(7_000, &[1][..])]);
+
+        file.extend(enhanced(1, 7, &[1], false));
+        assert_eq!(
+            parse(&file).err().unwrap().to_string(),
+            "Unknown interface id"
+        );
+    }
+
+    #[test]
+    fn pcapng_rejects_oversized_resolution() {
+        for tsresol in [20, 0x80 | 64] {
+            let mut file = section(true);
+            file.extend(interface(Some(tsresol), true));
+            let err = parse(&file).err().unwrap();
+            assert_eq!(err.to_string(), "Unsupported timestamp resolution");
+        }
+    }
+
+    #[test]
+    fn pcapng_rejects_truncated_blocks() {
+        let mut file = section(true);
+        file.extend(interface(None, true));
+        let mut packet = enhanced(0, 0, &[1, 2, 3, 4], true);
+        packet.truncate(packet.len() - 8);
+        file.extend(packet);
+        assert_eq!(parse(&file).err().unwrap().to_string(), "Truncated capture");
+
+        let mut file = section(true);
+        file.extend_from_slice(&[6, 0, 0, 0, 8, 0, 0, 0]);
+        assert_eq!(
+            parse(&file).err().unwrap().to_string(),
+            "Invalid block length"
+        );
+    }
+}
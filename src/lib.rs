@@ -1 +1,2 @@
 pub mod phy;
+pub mod prelude;
@@ -1,3 +1,34 @@
+pub mod announce;
+mod budget;
+pub mod caps;
+pub mod classify;
+pub mod flow;
+pub mod health;
+pub mod heartbeat;
+mod held;
+pub mod hop_limit;
+mod link;
+pub mod mss_clamp;
+pub mod pmtu;
+pub mod quantum;
+pub mod replay;
+pub mod rewrite;
+pub mod scratch;
+pub mod swap;
 mod sys;
 #[cfg(all(feature = "phy-xdp", unix))]
+pub use sys::fanout;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub use sys::fdpass;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub use sys::monitor;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub use sys::multicast;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub use sys::poller;
+pub mod team;
+pub mod transform;
+pub mod validate;
+pub mod warmup;
+#[cfg(all(feature = "phy-xdp", unix))]
 pub mod xdp;
@@ -0,0 +1,100 @@
+use smoltcp::iface::Interface;
+use smoltcp::phy::{Device, TxToken};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress};
+
+use crate::phy::heartbeat::{gratuitous_arp, unsolicited_na};
+
+struct Pending {
+    addr: IpAddress,
+    remaining: u32,
+    next: Instant,
+}
+
+/// Announces the addresses of an `Interface` whenever they change, with gratuitous ARPs
+/// for IPv4 and unsolicited neighbor advertisements for IPv6.
+///
+/// Neighbors and switches then learn the new location of an address without waiting for
+/// their caches to expire, e.g. after a floating IP moved to this node. Every new address,
+/// or every address when the hardware address changes, is announced `count` times spaced
+/// by `interval`, a `count` of 0 announcing nothing.
+pub struct Announcer {
+    count: u32,
+    interval: Duration,
+    hardware_addr: Option<EthernetAddress>,
+    known: Vec<IpAddress>,
+    pending: Vec<Pending>,
+}
+
+impl Announcer {
+    pub fn new(count: u32, interval: Duration) -> Self {
+        Self {
+            count,
+            interval,
+            hardware_addr: None,
+            known: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Picks up address changes of `iface` and sends the announcements that are due
+    /// through `device`, returning how many were sent.
+    ///
+    /// Addresses configured before the first poll are announced as well.
+    pub fn poll<D: Device>(
+        &mut self,
+        iface: &Interface,
+        device: &mut D,
+        timestamp: Instant,
+    ) -> usize {
+        #[allow(irrefutable_let_patterns)]
+        let HardwareAddress::Ethernet(hardware_addr) = iface.hardware_addr() else {
+            return 0;
+        };
+
+        let addrs: Vec<IpAddress> = iface.ip_addrs().iter().map(|cidr| cidr.address()).collect();
+        let relearn = self.hardware_addr != Some(hardware_addr);
+        for &addr in &addrs {
+            if self.count > 0 && (relearn || !self.known.contains(&addr)) {
+                self.pending.retain(|pending| pending.addr != addr);
+                self.pending.push(Pending {
+                    addr,
+                    remaining: self.count,
+                    next: timestamp,
+                });
+            }
+        }
+        self.pending.retain(|pending| addrs.contains(&pending.addr));
+        self.hardware_addr = Some(hardware_addr);
+        self.known = addrs;
+
+        let mut sent = 0;
+        for pending in self.pending.iter_mut() {
+            if pending.next > timestamp {
+                continue;
+            }
+
+            let frame = match pending.addr {
+                IpAddress::Ipv4(addr) => gratuitous_arp(hardware_addr, addr),
+                IpAddress::Ipv6(addr) => unsolicited_na(hardware_addr, addr),
+            };
+            let Some(tx) = device.transmit(timestamp) else {
+                break;
+            };
+            tx.consume(frame.len(), |buf| buf.copy_from_slice(&frame));
+
+            pending.remaining = pending.remaining.saturating_sub(1);
+            pending.next = timestamp + self.interval;
+            sent += 1;
+        }
+        self.pending.retain(|pending| pending.remaining > 0);
+
+        sent
+    }
+
+    /// Earliest time an announcement is due, to bound the poll loop sleep like
+    /// `Interface::poll_at`.
+    pub fn poll_at(&self) -> Option<Instant> {
+        self.pending.iter().map(|pending| pending.next).min()
+    }
+}
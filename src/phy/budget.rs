@@ -0,0 +1,48 @@
+use smoltcp::time::Instant;
+
+/// Frames received during one `Interface::poll`, against an optional limit.
+///
+/// A poll starts on [`PollBudget::replenish`], or on a receive whose timestamp differs
+/// from the previous one since smoltcp hands the same timestamp to every device call of a
+/// poll. The timestamp alone misses polls run back to back within the resolution of the
+/// clock, loops driving `Interface::poll` replenish before every call.
+pub(crate) struct PollBudget {
+    pub(crate) limit: Option<usize>,
+    used: usize,
+    epoch: Option<Instant>,
+}
+
+impl PollBudget {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            used: 0,
+            epoch: None,
+        }
+    }
+
+    /// Starts a new poll.
+    pub(crate) fn replenish(&mut self) {
+        self.used = 0;
+        self.epoch = None;
+    }
+
+    /// Called on every receive, returns whether `timestamp` starts a new poll.
+    pub(crate) fn begin(&mut self, timestamp: Instant) -> bool {
+        if self.epoch == Some(timestamp) {
+            return false;
+        }
+        self.epoch = Some(timestamp);
+        self.used = 0;
+        true
+    }
+
+    pub(crate) fn exhausted(&self) -> bool {
+        self.limit.is_some_and(|limit| self.used >= limit)
+    }
+
+    /// Counts a frame handed out.
+    pub(crate) fn spend(&mut self) {
+        self.used += 1;
+    }
+}
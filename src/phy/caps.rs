@@ -0,0 +1,190 @@
+use std::fmt;
+
+use smoltcp::phy::{Checksum, Device, DeviceCapabilities, Medium};
+
+/// Capabilities reported by a single layer of a device stack.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    pub name: &'static str,
+    pub caps: DeviceCapabilities,
+}
+
+/// Difference between the capabilities of a layer and the ones of the layer it wraps.
+#[derive(Copy, Clone, Debug)]
+pub enum Change {
+    Mtu {
+        inner: usize,
+        outer: usize,
+    },
+    MaxBurstSize {
+        inner: Option<usize>,
+        outer: Option<usize>,
+    },
+    Medium {
+        inner: Medium,
+        outer: Medium,
+    },
+    /// Checksum offload of `protocol`, one of `ipv4`, `udp` or `tcp`.
+    Checksum {
+        protocol: &'static str,
+        inner: Checksum,
+        outer: Checksum,
+    },
+}
+
+impl Change {
+    /// Whether the layer promises more than the layer it wraps can deliver: a larger MTU
+    /// or burst size, or a different medium.
+    pub fn is_suspicious(&self) -> bool {
+        match *self {
+            Self::Mtu { inner, outer } => outer > inner,
+            Self::MaxBurstSize { inner, outer } => match (inner, outer) {
+                (Some(inner), Some(outer)) => outer > inner,
+                (Some(_), None) => true,
+                (None, _) => false,
+            },
+            Self::Medium { .. } => true,
+            Self::Checksum { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mtu { inner, outer } => write!(f, "mtu {} -> {}", inner, outer),
+            Self::MaxBurstSize { inner, outer } => {
+                write!(f, "max burst size {:?} -> {:?}", inner, outer)
+            }
+            Self::Medium { inner, outer } => write!(f, "medium {:?} -> {:?}", inner, outer),
+            Self::Checksum {
+                protocol,
+                inner,
+                outer,
+            } => write!(f, "{} checksum {:?} -> {:?}", protocol, inner, outer),
+        }
+    }
+}
+
+/// Capabilities of every layer of a device stack, outermost first.
+///
+/// Each layer is compared with the one it wraps, so that a wrapper forgetting to take its
+/// overhead off the MTU shows up at startup instead of as frames dropped on the wire.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceCapabilitiesReport {
+    layers: Vec<Layer>,
+}
+
+impl DeviceCapabilitiesReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the capabilities of the next layer down the stack.
+    pub fn layer(&mut self, name: &'static str, caps: DeviceCapabilities) {
+        self.layers.push(Layer { name, caps });
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Capabilities smoltcp sees, the ones of the outermost layer.
+    pub fn effective(&self) -> Option<&DeviceCapabilities> {
+        self.layers.first().map(|layer| &layer.caps)
+    }
+
+    /// Changes made by each layer to the capabilities of the layer it wraps, outermost
+    /// first. The innermost layer has nothing to compare with and reports none.
+    pub fn changes(&self) -> Vec<(&'static str, Change)> {
+        let mut changes = Vec::new();
+        for pair in self.layers.windows(2) {
+            let (outer, inner) = (&pair[0], &pair[1]);
+            for change in diff(&inner.caps, &outer.caps) {
+                changes.push((outer.name, change));
+            }
+        }
+        changes
+    }
+
+    /// Changes that look like misconfigurations, see [`Change::is_suspicious`].
+    pub fn suspicious(&self) -> Vec<(&'static str, Change)> {
+        let mut changes = self.changes();
+        changes.retain(|(_, change)| change.is_suspicious());
+        changes
+    }
+}
+
+impl fmt::Display for DeviceCapabilitiesReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.layers.iter().map(|layer| layer.name).collect();
+        write!(f, "{}", names.join(" > "))?;
+        if let Some(caps) = self.effective() {
+            write!(
+                f,
+                ": mtu {}, max burst size {:?}, medium {:?}",
+                caps.max_transmission_unit, caps.max_burst_size, caps.medium
+            )?;
+        }
+        for (layer, change) in self.changes() {
+            let mark = if change.is_suspicious() { "!" } else { "" };
+            write!(f, "; {}{}: {}", mark, layer, change)?;
+        }
+        Ok(())
+    }
+}
+
+fn diff(inner: &DeviceCapabilities, outer: &DeviceCapabilities) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if inner.max_transmission_unit != outer.max_transmission_unit {
+        changes.push(Change::Mtu {
+            inner: inner.max_transmission_unit,
+            outer: outer.max_transmission_unit,
+        });
+    }
+    if inner.max_burst_size != outer.max_burst_size {
+        changes.push(Change::MaxBurstSize {
+            inner: inner.max_burst_size,
+            outer: outer.max_burst_size,
+        });
+    }
+    if inner.medium != outer.medium {
+        changes.push(Change::Medium {
+            inner: inner.medium,
+            outer: outer.medium,
+        });
+    }
+
+    let checksums = [
+        ("ipv4", inner.checksum.ipv4, outer.checksum.ipv4),
+        ("udp", inner.checksum.udp, outer.checksum.udp),
+        ("tcp", inner.checksum.tcp, outer.checksum.tcp),
+    ];
+    for (protocol, inner, outer) in checksums {
+        // Checksum has no PartialEq, its variants are the four rx/tx combinations.
+        if (inner.rx(), inner.tx()) != (outer.rx(), outer.tx()) {
+            changes.push(Change::Checksum {
+                protocol,
+                inner,
+                outer,
+            });
+        }
+    }
+    changes
+}
+
+/// A backend or wrapper able to report its capabilities.
+///
+/// Wrappers record their own capabilities and then delegate to the device they wrap, so
+/// auditing the outermost device covers the whole stack. Wrappers that only forward to
+/// another device, like [`crate::phy::swap::Swap`], delegate without recording a layer.
+pub trait CapabilitiesAudit: Device {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport);
+}
+
+/// Audits the capabilities of a whole device stack.
+pub fn audit<D: CapabilitiesAudit + ?Sized>(device: &D) -> DeviceCapabilitiesReport {
+    let mut report = DeviceCapabilitiesReport::new();
+    device.audit(&mut report);
+    report
+}
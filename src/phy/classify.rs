@@ -0,0 +1,79 @@
+use smoltcp::wire::{EthernetFrame, EthernetProtocol, Ipv4Packet, Ipv6Packet};
+
+use crate::phy::flow::{FlowKey, RSS_KEY};
+
+/// Maps an Ethernet frame to a class, e.g. a traffic class, a worker or a flow.
+///
+/// Components that sort frames (dispatch, queueing, steering) take a classifier instead
+/// of matching frames themselves, so the same rule can drive all of them. Closures
+/// taking the frame are classifiers too.
+pub trait Classifier {
+    /// Class of `frame`, `None` when the classifier does not apply to it.
+    fn classify(&self, frame: &[u8]) -> Option<u32>;
+}
+
+impl<F: Fn(&[u8]) -> Option<u32>> Classifier for F {
+    fn classify(&self, frame: &[u8]) -> Option<u32> {
+        self(frame)
+    }
+}
+
+/// Classifies frames by ethertype.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Ethertype;
+
+impl Classifier for Ethertype {
+    fn classify(&self, frame: &[u8]) -> Option<u32> {
+        let eth = EthernetFrame::new_checked(frame).ok()?;
+        Some(u16::from(eth.ethertype()).into())
+    }
+}
+
+/// Classifies IPv4 and IPv6 frames by the DSCP of their IP header.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Dscp;
+
+impl Classifier for Dscp {
+    fn classify(&self, frame: &[u8]) -> Option<u32> {
+        let eth = EthernetFrame::new_checked(frame).ok()?;
+        match eth.ethertype() {
+            EthernetProtocol::Ipv4 => {
+                let ip = Ipv4Packet::new_checked(eth.payload()).ok()?;
+                Some(ip.dscp().into())
+            }
+            EthernetProtocol::Ipv6 => {
+                let ip = Ipv6Packet::new_checked(eth.payload()).ok()?;
+                Some((ip.traffic_class() >> 2).into())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Classifies IP frames by the Toeplitz hash of their 5-tuple, as NICs do for RSS.
+#[derive(Copy, Clone, Debug)]
+pub struct FiveTuple {
+    key: [u8; 40],
+}
+
+impl FiveTuple {
+    pub fn new(key: [u8; 40]) -> Self {
+        Self { key }
+    }
+
+    pub fn key(&self) -> &[u8; 40] {
+        &self.key
+    }
+}
+
+impl Default for FiveTuple {
+    fn default() -> Self {
+        Self::new(RSS_KEY)
+    }
+}
+
+impl Classifier for FiveTuple {
+    fn classify(&self, frame: &[u8]) -> Option<u32> {
+        FlowKey::parse(frame).map(|flow| flow.hash(&self.key))
+    }
+}
@@ -0,0 +1,431 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+use smoltcp::phy::{Device, RxToken};
+use smoltcp::time::Instant;
+use smoltcp::wire::{
+    EthernetProtocol, IpAddress, IpProtocol, Ipv4Packet, Ipv6Packet, TcpPacket, UdpPacket,
+};
+
+use crate::phy::classify::{Classifier, FiveTuple};
+use crate::phy::link;
+
+/// Default Toeplitz key used by most NIC drivers for RSS.
+pub const RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+/// Computes the Toeplitz hash of `input` with `key`.
+///
+/// `key` must be at least 4 bytes longer than `input`.
+pub fn toeplitz(key: &[u8], input: &[u8]) -> u32 {
+    let mut hash = 0u32;
+    let mut window = u32::from_be_bytes([key[0], key[1], key[2], key[3]]);
+
+    for (i, byte) in input.iter().enumerate() {
+        let next = key[i + 4];
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                hash ^= window;
+            }
+            window = (window << 1) | u32::from((next >> (7 - bit)) & 1);
+        }
+    }
+
+    hash
+}
+
+/// Addresses, ports and protocol identifying a flow.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FlowKey {
+    pub src_addr: IpAddress,
+    pub dst_addr: IpAddress,
+    pub protocol: IpProtocol,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    /// Extracts the flow of an Ethernet frame carrying IPv4 or IPv6, past any VLAN tags.
+    ///
+    /// Ports are 0 for protocols other than TCP and UDP, and for IPv4 fragments since only
+    /// the first one carries them: every fragment of a packet then hashes on the addresses
+    /// alone, as NIC RSS does. IPv6 extension headers are not followed.
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        let (ethertype, offset) = link::ethernet(frame)?;
+        let packet = &frame[offset..];
+        let (src_addr, dst_addr, protocol, payload) = match EthernetProtocol::from(ethertype) {
+            EthernetProtocol::Ipv4 => {
+                let ip = Ipv4Packet::new_checked(packet).ok()?;
+                let fragment = ip.more_frags() || ip.frag_offset() != 0;
+                (
+                    IpAddress::Ipv4(ip.src_addr()),
+                    IpAddress::Ipv4(ip.dst_addr()),
+                    ip.next_header(),
+                    (!fragment).then(|| &packet[usize::from(ip.header_len())..]),
+                )
+            }
+            EthernetProtocol::Ipv6 => {
+                let ip = Ipv6Packet::new_checked(packet).ok()?;
+                (
+                    IpAddress::Ipv6(ip.src_addr()),
+                    IpAddress::Ipv6(ip.dst_addr()),
+                    ip.next_header(),
+                    Some(&packet[ip.header_len()..]),
+                )
+            }
+            _ => return None,
+        };
+
+        let (src_port, dst_port) = match (protocol, payload) {
+            (IpProtocol::Tcp, Some(payload)) => TcpPacket::new_checked(payload)
+                .map(|tcp| (tcp.src_port(), tcp.dst_port()))
+                .unwrap_or_default(),
+            (IpProtocol::Udp, Some(payload)) => UdpPacket::new_checked(payload)
+                .map(|udp| (udp.src_port(), udp.dst_port()))
+                .unwrap_or_default(),
+            _ => (0, 0),
+        };
+
+        Some(Self {
+            src_addr,
+            dst_addr,
+            protocol,
+            src_port,
+            dst_port,
+        })
+    }
+
+    /// Toeplitz hash over addresses and ports, laid out as NICs do for RSS.
+    pub fn hash(&self, key: &[u8]) -> u32 {
+        let mut input = [0u8; 36];
+        let mut len = 0;
+        for addr in [self.src_addr, self.dst_addr] {
+            let bytes: &[u8] = match &addr {
+                IpAddress::Ipv4(addr) => &addr.octets(),
+                IpAddress::Ipv6(addr) => &addr.octets(),
+            };
+            input[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        }
+        input[len..len + 2].copy_from_slice(&self.src_port.to_be_bytes());
+        input[len + 2..len + 4].copy_from_slice(&self.dst_port.to_be_bytes());
+        len += 4;
+
+        toeplitz(key, &input[..len])
+    }
+}
+
+/// Distributes received frames over worker queues by class, the flow hash by default.
+///
+/// Frames of the same class always land on the same worker. Frames the classifier does
+/// not apply to (ARP, non-IP for the flow hash) go to worker 0.
+pub struct Dispatcher<C: Classifier = FiveTuple> {
+    workers: Vec<SyncSender<Vec<u8>>>,
+    classifier: C,
+    dropped: u64,
+    disconnected: u64,
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher over `workers` queues of `depth` frames each, returning the
+    /// receiving end of every queue to be moved into its worker thread.
+    ///
+    /// Panics if `workers` is 0.
+    pub fn new(workers: usize, depth: usize) -> (Self, Vec<Receiver<Vec<u8>>>) {
+        Self::with_classifier(workers, depth, FiveTuple::default())
+    }
+
+    /// Replaces the Toeplitz key, e.g. to match the NIC configuration.
+    pub fn set_key(&mut self, key: [u8; 40]) {
+        self.classifier = FiveTuple::new(key);
+    }
+}
+
+impl<C: Classifier> Dispatcher<C> {
+    /// Creates a dispatcher sorting frames with `classifier`, see [`Dispatcher::new`].
+    pub fn with_classifier(
+        workers: usize,
+        depth: usize,
+        classifier: C,
+    ) -> (Self, Vec<Receiver<Vec<u8>>>) {
+        assert!(workers > 0, "A dispatcher needs at least one worker");
+        let (senders, receivers) = (0..workers).map(|_| mpsc::sync_channel(depth)).unzip();
+        let dispatcher = Self {
+            workers: senders,
+            classifier,
+            dropped: 0,
+            disconnected: 0,
+        };
+        (dispatcher, receivers)
+    }
+
+    pub fn classifier(&self) -> &C {
+        &self.classifier
+    }
+
+    /// Worker index `frame` belongs to.
+    pub fn worker_for(&self, frame: &[u8]) -> usize {
+        match self.classifier.classify(frame) {
+            Some(class) => class as usize % self.workers.len(),
+            None => 0,
+        }
+    }
+
+    /// Hands a copy of `frame` to its worker. Returns `false` if the worker queue is full
+    /// or the worker is gone, in which case the frame is dropped.
+    pub fn dispatch(&mut self, frame: &[u8]) -> bool {
+        let worker = self.worker_for(frame);
+        self.send(worker, frame)
+    }
+
+    /// Hands a copy of `frame` to the worker of `hash`, e.g. the RX hash of the NIC from
+    /// `xdp::RxToken::rx_hash`, instead of classifying it. Returns `false` like
+    /// [`Dispatcher::dispatch`].
+    pub fn dispatch_hashed(&mut self, frame: &[u8], hash: u32) -> bool {
+        let worker = hash as usize % self.workers.len();
+        self.send(worker, frame)
+    }
+
+    fn send(&mut self, worker: usize, frame: &[u8]) -> bool {
+        match self.workers[worker].try_send(frame.to_vec()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.disconnected += 1;
+                false
+            }
+        }
+    }
+
+    /// Drains up to `budget` frames from `device` into the worker queues, returning how
+    /// many were received.
+    pub fn poll<D: Device>(&mut self, device: &mut D, timestamp: Instant, budget: usize) -> usize {
+        let mut received = 0;
+        while received < budget {
+            let Some((rx, _)) = device.receive(timestamp) else {
+                break;
+            };
+            rx.consume(|frame| self.dispatch(frame));
+            received += 1;
+        }
+        received
+    }
+
+    /// Frames dropped because their worker queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Frames dropped because their worker had dropped the receiving end of its queue.
+    pub fn disconnected(&self) -> u64 {
+        self.disconnected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smoltcp::wire::{Ipv4Address, Ipv6Address};
+
+    use super::*;
+
+    // Verification suite of the Microsoft RSS specification: source, destination, source
+    // port, destination port, hash over the addresses, hash over addresses and ports.
+    type Vector<A> = (A, A, u16, u16, u32, u32);
+
+    const IPV4: [Vector<[u8; 4]>; 5] = [
+        (
+            [66, 9, 149, 187],
+            [161, 142, 100, 80],
+            2794,
+            1766,
+            0x323e8fc2,
+            0x51ccc178,
+        ),
+        (
+            [199, 92, 111, 2],
+            [65, 69, 140, 83],
+            14230,
+            4739,
+            0xd718262a,
+            0xc626b0ea,
+        ),
+        (
+            [24, 19, 198, 95],
+            [12, 22, 207, 184],
+            12898,
+            38024,
+            0xd2d0a5de,
+            0x5c2b394a,
+        ),
+        (
+            [38, 27, 205, 30],
+            [209, 142, 163, 6],
+            48228,
+            2217,
+            0x82989176,
+            0xafc7327f,
+        ),
+        (
+            [153, 39, 163, 191],
+            [202, 188, 127, 2],
+            44251,
+            1303,
+            0x5d1809c5,
+            0x10e828a2,
+        ),
+    ];
+
+    const IPV6: [Vector<&str>; 3] = [
+        (
+            "3ffe:2501:200:1fff::7",
+            "3ffe:2501:200:3::1",
+            2794,
+            1766,
+            0x2cc18cd5,
+            0x40207d3d,
+        ),
+        (
+            "3ffe:501:8::260:97ff:fe40:efab",
+            "ff02::1",
+            14230,
+            4739,
+            0x0f0c461c,
+            0xdde51bbf,
+        ),
+        (
+            "3ffe:1900:4545:3:200:f8ff:fe21:67cf",
+            "fe80::200:f8ff:fe21:67cf",
+            44251,
+            38024,
+            0x4b61e985,
+            0x02d1feef,
+        ),
+    ];
+
+    #[test]
+    fn toeplitz_ipv4_vectors() {
+        for (src, dst, _, _, addrs, _) in IPV4 {
+            let input = [src, dst].concat();
+            assert_eq!(toeplitz(&RSS_KEY, &input), addrs);
+        }
+    }
+
+    #[test]
+    fn toeplitz_ipv6_vectors() {
+        for (src, dst, _, _, addrs, _) in IPV6 {
+            let src: Ipv6Address = src.parse().unwrap();
+            let dst: Ipv6Address = dst.parse().unwrap();
+            let input = [src.octets(), dst.octets()].concat();
+            assert_eq!(toeplitz(&RSS_KEY, &input), addrs);
+        }
+    }
+
+    #[test]
+    fn flow_key_hash_vectors() {
+        for (src, dst, src_port, dst_port, _, hash) in IPV4 {
+            let key = FlowKey {
+                src_addr: IpAddress::Ipv4(Ipv4Address::from(src)),
+                dst_addr: IpAddress::Ipv4(Ipv4Address::from(dst)),
+                protocol: IpProtocol::Tcp,
+                src_port,
+                dst_port,
+            };
+            assert_eq!(key.hash(&RSS_KEY), hash);
+        }
+        for (src, dst, src_port, dst_port, _, hash) in IPV6 {
+            let key = FlowKey {
+                src_addr: IpAddress::Ipv6(src.parse().unwrap()),
+                dst_addr: IpAddress::Ipv6(dst.parse().unwrap()),
+                protocol: IpProtocol::Tcp,
+                src_port,
+                dst_port,
+            };
+            assert_eq!(key.hash(&RSS_KEY), hash);
+        }
+    }
+
+    /// An Ethernet frame behind `tags` carrying an IPv4 TCP segment, or a fragment of it
+    /// at `frag_offset` 8-byte units with `more_frags`.
+    fn tcp_v4(
+        tags: &[u16],
+        (src, dst, src_port, dst_port): ([u8; 4], [u8; 4], u16, u16),
+        more_frags: bool,
+        frag_offset: u16,
+    ) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        for &tpid in tags {
+            frame.extend_from_slice(&tpid.to_be_bytes());
+            frame.extend_from_slice(&[0, 1]);
+        }
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        let ip_at = frame.len();
+        frame.resize(ip_at + 40, 0);
+
+        let mut ip = Ipv4Packet::new_unchecked(&mut frame[ip_at..]);
+        ip.set_version(4);
+        ip.set_header_len(20);
+        ip.set_total_len(40);
+        ip.set_more_frags(more_frags);
+        ip.set_frag_offset(frag_offset * 8);
+        ip.set_hop_limit(64);
+        ip.set_next_header(IpProtocol::Tcp);
+        ip.set_src_addr(Ipv4Address::from(src));
+        ip.set_dst_addr(Ipv4Address::from(dst));
+        if frag_offset == 0 {
+            let mut tcp = TcpPacket::new_unchecked(ip.payload_mut());
+            tcp.set_src_port(src_port);
+            tcp.set_dst_port(dst_port);
+            tcp.set_header_len(20);
+        } else {
+            // Payload of a later fragment, which would read as ports.
+            ip.payload_mut()[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        }
+        frame
+    }
+
+    #[test]
+    fn fragments_hash_on_addresses() {
+        for (src, dst, src_port, dst_port, addrs, ports) in IPV4 {
+            let tuple = (src, dst, src_port, dst_port);
+            let whole = FlowKey::parse(&tcp_v4(&[], tuple, false, 0)).unwrap();
+            assert_eq!((whole.src_port, whole.dst_port), (src_port, dst_port));
+            assert_eq!(whole.hash(&RSS_KEY), ports);
+
+            for (more_frags, frag_offset) in [(true, 0), (true, 185), (false, 370)] {
+                let frame = tcp_v4(&[], tuple, more_frags, frag_offset);
+                let fragment = FlowKey::parse(&frame).unwrap();
+                assert_eq!((fragment.src_port, fragment.dst_port), (0, 0));
+                assert_eq!(fragment.protocol, IpProtocol::Tcp);
+                assert_eq!(fragment.hash(&RSS_KEY), addrs);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_past_vlan_tags() {
+        let (src, dst, src_port, dst_port, ..) = IPV4[0];
+        let tuple = (src, dst, src_port, dst_port);
+        let untagged = FlowKey::parse(&tcp_v4(&[], tuple, false, 0)).unwrap();
+        for tags in [&[0x8100][..], &[0x88a8], &[0x88a8, 0x8100]] {
+            assert_eq!(
+                FlowKey::parse(&tcp_v4(tags, tuple, false, 0)),
+                Some(untagged)
+            );
+        }
+        // A tag cut off by the end of the frame.
+        let mut cut = vec![0; 12];
+        cut.extend_from_slice(&[0x81, 0x00, 0]);
+        assert_eq!(FlowKey::parse(&cut), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one worker")]
+    fn dispatcher_without_workers() {
+        let _ = Dispatcher::new(0, 1);
+    }
+}
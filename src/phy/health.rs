@@ -0,0 +1,98 @@
+use std::fmt;
+
+/// Health of a single layer, ordered from best to worst.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Status {
+    Healthy,
+    /// Traffic flows but the layer is dropping or delaying some of it.
+    Degraded,
+    /// The layer cannot carry traffic.
+    Unhealthy,
+}
+
+/// Outcome of one check performed by a layer.
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub layer: &'static str,
+    pub status: Status,
+    pub reason: Option<String>,
+}
+
+/// Checks of every layer of a device stack, outermost first.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a healthy check.
+    pub fn ok(&mut self, layer: &'static str) {
+        self.push(layer, Status::Healthy, None);
+    }
+
+    /// Records a check that failed for `reason`.
+    pub fn fail(&mut self, layer: &'static str, status: Status, reason: impl Into<String>) {
+        self.push(layer, status, Some(reason.into()));
+    }
+
+    fn push(&mut self, layer: &'static str, status: Status, reason: Option<String>) {
+        self.checks.push(Check {
+            layer,
+            status,
+            reason,
+        });
+    }
+
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+
+    /// Worst status among all checks.
+    pub fn status(&self) -> Status {
+        self.checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(Status::Healthy)
+    }
+
+    /// Whether the stack can serve traffic, i.e. no layer is unhealthy.
+    pub fn is_ready(&self) -> bool {
+        self.status() != Status::Unhealthy
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.status())?;
+        for check in self.checks.iter().filter(|check| check.reason.is_some()) {
+            write!(
+                f,
+                "; {}: {:?} ({})",
+                check.layer,
+                check.status,
+                check.reason.as_deref().unwrap_or_default()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A backend or wrapper able to report its health.
+///
+/// Wrappers record their own checks and then delegate to the device they wrap, so
+/// checking the outermost device covers the whole stack.
+pub trait HealthCheck {
+    fn check(&self, report: &mut Report);
+}
+
+/// Runs the checks of a whole device stack.
+pub fn report<D: HealthCheck + ?Sized>(device: &D) -> Report {
+    let mut report = Report::new();
+    device.check(&mut report);
+    report
+}
@@ -0,0 +1,179 @@
+use smoltcp::phy::{ChecksumCapabilities, Device, TxToken};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+    EthernetRepr, IPV6_LINK_LOCAL_ALL_NODES, Icmpv6Packet, Icmpv6Repr, IpProtocol, Ipv4Address,
+    Ipv6Address, Ipv6Packet, Ipv6Repr, NdiscNeighborFlags, NdiscRepr,
+};
+
+/// Shortest Ethernet frame without FCS, shorter frames are padded.
+const MIN_FRAME_LEN: usize = 60;
+
+struct Entry {
+    frame: Vec<u8>,
+    interval: Duration,
+    next: Instant,
+}
+
+/// Periodically transmits fixed frames through a device, e.g. gratuitous ARPs keeping
+/// switch MAC tables warm or heartbeats watched by HA peers.
+///
+/// It is driven by the application poll loop next to `Interface::poll`, using the same
+/// timestamps.
+#[derive(Default)]
+pub struct Heartbeat {
+    entries: Vec<Option<Entry>>,
+    sent: u64,
+    missed: u64,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedules `frame` every `interval`, the first time at the next poll. Returns an id
+    /// for [`Heartbeat::remove`].
+    pub fn add(&mut self, frame: Vec<u8>, interval: Duration) -> usize {
+        let entry = Entry {
+            frame,
+            interval,
+            next: Instant::ZERO,
+        };
+        match self.entries.iter().position(Option::is_none) {
+            Some(id) => {
+                self.entries[id] = Some(entry);
+                id
+            }
+            None => {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
+            }
+        }
+    }
+
+    /// Stops sending the frame scheduled as `id`.
+    pub fn remove(&mut self, id: usize) {
+        if let Some(entry) = self.entries.get_mut(id) {
+            *entry = None;
+        }
+    }
+
+    /// Sends every frame that is due, returning how many were sent.
+    ///
+    /// Frames the device has no TX token for are retried on the next poll.
+    pub fn poll<D: Device>(&mut self, device: &mut D, timestamp: Instant) -> usize {
+        let mut sent = 0;
+        for entry in self.entries.iter_mut().flatten() {
+            if entry.next > timestamp {
+                continue;
+            }
+
+            let Some(tx) = device.transmit(timestamp) else {
+                self.missed += 1;
+                break;
+            };
+            tx.consume(entry.frame.len(), |buf| buf.copy_from_slice(&entry.frame));
+            entry.next = timestamp + entry.interval;
+            sent += 1;
+        }
+
+        self.sent += sent as u64;
+        sent
+    }
+
+    /// Earliest time a frame is due, to bound the poll loop sleep like
+    /// `Interface::poll_at`.
+    pub fn poll_at(&self) -> Option<Instant> {
+        self.entries.iter().flatten().map(|entry| entry.next).min()
+    }
+
+    /// Frames sent so far.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Polls that could not send a due frame because the device had no TX token.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+/// Builds a broadcast gratuitous ARP request announcing that `ipv4` is at
+/// `hardware_addr`.
+pub fn gratuitous_arp(hardware_addr: EthernetAddress, ipv4: Ipv4Address) -> Vec<u8> {
+    let arp = ArpRepr::EthernetIpv4 {
+        operation: ArpOperation::Request,
+        source_hardware_addr: hardware_addr,
+        source_protocol_addr: ipv4,
+        target_hardware_addr: EthernetAddress([0; 6]),
+        target_protocol_addr: ipv4,
+    };
+    let eth = EthernetRepr {
+        src_addr: hardware_addr,
+        dst_addr: EthernetAddress::BROADCAST,
+        ethertype: EthernetProtocol::Arp,
+    };
+
+    let mut buf = vec![0; MIN_FRAME_LEN.max(eth.buffer_len() + arp.buffer_len())];
+    let mut frame = EthernetFrame::new_unchecked(&mut buf[..]);
+    eth.emit(&mut frame);
+    arp.emit(&mut ArpPacket::new_unchecked(frame.payload_mut()));
+    buf
+}
+
+/// Builds an unsolicited neighbor advertisement to all nodes announcing that `ipv6` is
+/// at `hardware_addr`, overriding cached entries.
+pub fn unsolicited_na(hardware_addr: EthernetAddress, ipv6: Ipv6Address) -> Vec<u8> {
+    let icmp = Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
+        flags: NdiscNeighborFlags::OVERRIDE,
+        target_addr: ipv6,
+        lladdr: Some(hardware_addr.into()),
+    });
+    let ip = Ipv6Repr {
+        src_addr: ipv6,
+        dst_addr: IPV6_LINK_LOCAL_ALL_NODES,
+        next_header: IpProtocol::Icmpv6,
+        payload_len: icmp.buffer_len(),
+        hop_limit: 255,
+    };
+    let eth = EthernetRepr {
+        src_addr: hardware_addr,
+        dst_addr: EthernetAddress([0x33, 0x33, 0, 0, 0, 1]),
+        ethertype: EthernetProtocol::Ipv6,
+    };
+
+    let len = eth.buffer_len() + ip.buffer_len() + icmp.buffer_len();
+    let mut buf = vec![0; MIN_FRAME_LEN.max(len)];
+    let mut frame = EthernetFrame::new_unchecked(&mut buf[..]);
+    eth.emit(&mut frame);
+    let mut packet = Ipv6Packet::new_unchecked(frame.payload_mut());
+    ip.emit(&mut packet);
+    icmp.emit(
+        &ipv6,
+        &IPV6_LINK_LOCAL_ALL_NODES,
+        &mut Icmpv6Packet::new_unchecked(packet.payload_mut()),
+        &ChecksumCapabilities::default(),
+    );
+    buf
+}
+
+/// Builds an Ethernet frame with a custom `ethertype` carrying `payload`.
+pub fn ethernet_frame(
+    src_addr: EthernetAddress,
+    dst_addr: EthernetAddress,
+    ethertype: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let eth = EthernetRepr {
+        src_addr,
+        dst_addr,
+        ethertype: EthernetProtocol::from(ethertype),
+    };
+
+    let mut buf = vec![0; MIN_FRAME_LEN.max(eth.buffer_len() + payload.len())];
+    let mut frame = EthernetFrame::new_unchecked(&mut buf[..]);
+    eth.emit(&mut frame);
+    frame.payload_mut()[..payload.len()].copy_from_slice(payload);
+    buf
+}
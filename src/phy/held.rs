@@ -0,0 +1,179 @@
+use smoltcp::phy::{self, Device, PacketMeta};
+use smoltcp::time::Instant;
+
+use crate::phy::scratch::{FrameBuf, FrameScratch};
+
+/// A received frame copied out of a wrapped device, for wrappers judging or rewriting
+/// frames before smoltcp sees them.
+///
+/// A kept frame is handed out with the TX token the device received it with. Devices may
+/// grant those when `Device::transmit` would not, e.g. from a reserve kept for replies or
+/// on the RX half of a split socket.
+///
+/// Frames are copied into a buffer reused from one frame to the next.
+pub(crate) struct Held {
+    scratch: FrameScratch,
+    // The frame handed out last, given back to `scratch` on the next receive.
+    frame: Option<FrameBuf>,
+}
+
+impl Held {
+    pub(crate) fn new() -> Self {
+        Self {
+            scratch: FrameScratch::new(1, 0, 0),
+            frame: None,
+        }
+    }
+
+    /// Copies the frames received from `device` until `keep` hands back the TX token of
+    /// one, then hands out both. `keep` may rewrite the frame, or consume the token to
+    /// answer a frame it drops.
+    pub(crate) fn receive<'a, D: Device>(
+        &'a mut self,
+        device: &'a mut D,
+        timestamp: Instant,
+        mut keep: impl FnMut(&mut FrameBuf, PacketMeta, D::TxToken<'a>) -> Option<D::TxToken<'a>>,
+    ) -> Option<(RxToken<'a>, D::TxToken<'a>)> {
+        if let Some(frame) = self.frame.take() {
+            self.scratch.give(frame);
+        }
+        let device: *mut D = device;
+        let (frame, meta, tx) = loop {
+            // SAFETY: the tokens of a frame `keep` drops are gone before the next receive,
+            // `keep` owns no place to hold on to a token, so `device` is only borrowed by
+            // the tokens handed out. The borrow checker does not see that borrows of
+            // earlier rounds end (rust-lang/rust#54663).
+            let (rx, tx) = unsafe { &mut *device }.receive(timestamp)?;
+            let meta = phy::RxToken::meta(&rx);
+            let mut frame = self.scratch.take();
+            phy::RxToken::consume(rx, |received| frame.put_slice(received));
+            if let Some(tx) = keep(&mut frame, meta, tx) {
+                break (frame, meta, tx);
+            }
+            self.scratch.give(frame);
+        };
+        let frame: &FrameBuf = self.frame.insert(frame);
+        Some((
+            RxToken {
+                frame: frame.as_ref(),
+                meta,
+            },
+            tx,
+        ))
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken<'a> {
+    frame: &'a [u8],
+    meta: PacketMeta,
+}
+
+impl phy::RxToken for RxToken<'_> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(self.frame)
+    }
+
+    fn meta(&self) -> PacketMeta {
+        self.meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use smoltcp::phy::{DeviceCapabilities, TxToken as _};
+
+    use super::*;
+
+    /// A device handing out TX tokens with received frames only, as the RX half of a
+    /// split socket does.
+    #[derive(Default)]
+    struct ReceiveOnly {
+        received: VecDeque<Vec<u8>>,
+        sent: Vec<Vec<u8>>,
+    }
+
+    struct Rx(Vec<u8>);
+
+    impl phy::RxToken for Rx {
+        fn consume<R, F>(self, f: F) -> R
+        where
+            F: FnOnce(&[u8]) -> R,
+        {
+            f(&self.0)
+        }
+    }
+
+    struct Tx<'a>(&'a mut Vec<Vec<u8>>);
+
+    impl phy::TxToken for Tx<'_> {
+        fn consume<R, F>(self, len: usize, f: F) -> R
+        where
+            F: FnOnce(&mut [u8]) -> R,
+        {
+            let mut frame = vec![0; len];
+            let result = f(&mut frame);
+            self.0.push(frame);
+            result
+        }
+    }
+
+    impl Device for ReceiveOnly {
+        type RxToken<'a> = Rx;
+        type TxToken<'a> = Tx<'a>;
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            DeviceCapabilities::default()
+        }
+
+        fn receive(&mut self, _: Instant) -> Option<(Rx, Tx<'_>)> {
+            let frame = self.received.pop_front()?;
+            Some((Rx(frame), Tx(&mut self.sent)))
+        }
+
+        fn transmit(&mut self, _: Instant) -> Option<Tx<'_>> {
+            None
+        }
+    }
+
+    fn device(frames: &[&[u8]]) -> ReceiveOnly {
+        ReceiveOnly {
+            received: frames.iter().map(|frame| frame.to_vec()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn hands_out_the_paired_token() {
+        let mut device = device(&[&[0, 1], &[1, 2], &[0, 3], &[1, 4]]);
+        let mut held = Held::new();
+        let mut frames = Vec::new();
+        while let Some((rx, tx)) = held.receive(&mut device, Instant::ZERO, |frame, _, tx| {
+            frame.put_slice(&[9]);
+            (frame.as_ref()[0] == 1).then_some(tx)
+        }) {
+            frames.push(phy::RxToken::consume(rx, |frame| frame.to_vec()));
+            tx.consume(1, |frame| frame[0] = frames.len() as u8);
+        }
+        assert_eq!(frames, [[1, 2, 9], [1, 4, 9]]);
+        assert_eq!(device.sent, [[1], [2]]);
+    }
+
+    #[test]
+    fn answers_dropped_frames_with_their_token() {
+        let mut device = device(&[&[0, 1], &[0, 2]]);
+        let mut held = Held::new();
+        let received = held.receive(&mut device, Instant::ZERO, |frame, _, tx| {
+            let reply = frame.as_ref()[1];
+            tx.consume(1, |frame| frame[0] = reply);
+            None
+        });
+        assert!(received.is_none());
+        assert_eq!(device.sent, [[1], [2]]);
+    }
+}
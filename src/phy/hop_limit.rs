@@ -0,0 +1,291 @@
+use smoltcp::phy::{self, ChecksumCapabilities, Device, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+use smoltcp::wire::{
+    Icmpv4Message, Icmpv4Packet, Icmpv6Message, Icmpv6Packet, IpAddress, IpProtocol, Ipv4Address,
+    Ipv4Packet, Ipv4Repr, Ipv6Address, Ipv6Packet, Ipv6Repr,
+};
+
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+use crate::phy::held::{Held, RxToken};
+use crate::phy::link;
+use crate::phy::rewrite::PacketEditor;
+
+const ICMP_HEADER_LEN: usize = 8;
+/// Hop limit of the Time Exceeded messages.
+const REPLY_HOP_LIMIT: u8 = 64;
+/// IPv6 minimum MTU, which the quoted packet of an ICMPv6 error must fit in (RFC 4443).
+const IPV6_MIN_MTU: usize = 1280;
+
+/// Packets handled so far.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Stats {
+    /// Packets handed up with their TTL or hop limit decremented.
+    pub decremented: u64,
+    /// Packets dropped because their TTL or hop limit ran out.
+    pub expired: u64,
+    /// ICMP Time Exceeded messages sent for expired packets.
+    pub time_exceeded: u64,
+}
+
+/// A device wrapper decrementing the TTL or hop limit of the IP packets it receives, for
+/// forwarders reading frames from a device to send them on, e.g. back out of the same
+/// one as a router-on-a-stick.
+///
+/// The IPv4 header checksum is updated along with the TTL. Packets whose TTL or hop
+/// limit runs out are dropped instead of handed up: `expired` is called with the frame
+/// and returns the address to answer from with an ICMP Time Exceeded, sent right away
+/// with the TX token the packet was received with, or `None` to stay silent. ICMP errors
+/// and packets from or to multicast addresses are never answered.
+///
+/// Received frames are copied out of the wrapped device to be rewritten, and every
+/// received packet is counted as a hop, so smoltcp sockets on top see one hop less too.
+pub struct HopLimit<D: Device, F: FnMut(&[u8]) -> Option<IpAddress>> {
+    inner: D,
+    expired: F,
+    stats: Stats,
+    held: Held,
+    reply: Vec<u8>,
+}
+
+impl<D: Device, F: FnMut(&[u8]) -> Option<IpAddress>> HopLimit<D, F> {
+    pub fn new(inner: D, expired: F) -> Self {
+        Self {
+            inner,
+            expired,
+            stats: Stats::default(),
+            held: Held::new(),
+            reply: Vec::new(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Get a reference to the underlying device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Return the underlying device, consuming the wrapper.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+/// Sends with `tx` an ICMP Time Exceeded from `source` for the expired packet in `frame`,
+/// built in `reply`. Returns whether it was sent.
+fn time_exceeded(
+    tx: impl phy::TxToken,
+    medium: Medium,
+    frame: &[u8],
+    reply: &mut Vec<u8>,
+    source: IpAddress,
+) -> bool {
+    let Some(link_len) = link::header_len(medium, frame) else {
+        return false;
+    };
+    let packet = &frame[link_len..];
+    reply.clear();
+    reply.extend_from_slice(&frame[..link_len]);
+    if medium == Medium::Ethernet {
+        // Back to the host the frame came from, from the address it was sent to.
+        reply.copy_within(6..12, 0);
+        reply[6..12].copy_from_slice(&frame[..6]);
+    }
+
+    let built = match source {
+        IpAddress::Ipv4(source) => time_exceeded_v4(reply, packet, source),
+        IpAddress::Ipv6(source) => time_exceeded_v6(reply, packet, source),
+    };
+    if built.is_none() {
+        return false;
+    }
+    phy::TxToken::consume(tx, reply.len(), |buf| buf.copy_from_slice(reply));
+    true
+}
+
+impl<D: Device, F: FnMut(&[u8]) -> Option<IpAddress>> Device for HopLimit<D, F> {
+    type RxToken<'a>
+        = RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    /// Drops expired packets until one can be forwarded.
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let medium = self.inner.capabilities().medium;
+        let (stats, expired, reply) = (&mut self.stats, &mut self.expired, &mut self.reply);
+        self.held
+            .receive(&mut self.inner, timestamp, |frame, _, tx| {
+                match decrement(medium, frame.as_mut()) {
+                    Hop::Forward => {
+                        stats.decremented += 1;
+                        Some(tx)
+                    }
+                    Hop::Other => Some(tx),
+                    Hop::Expired { answer } => {
+                        stats.expired += 1;
+                        if answer
+                            && let Some(source) = expired(frame.as_ref())
+                            && time_exceeded(tx, medium, frame.as_ref(), reply, source)
+                        {
+                            stats.time_exceeded += 1;
+                        }
+                        None
+                    }
+                }
+            })
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.inner.transmit(timestamp)
+    }
+}
+
+impl<D: CapabilitiesAudit, F: FnMut(&[u8]) -> Option<IpAddress>> CapabilitiesAudit
+    for HopLimit<D, F>
+{
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("hop-limit", self.capabilities());
+        self.inner.audit(report);
+    }
+}
+
+/// What a received frame turned out to be.
+enum Hop {
+    /// An IP packet with hops left, decremented.
+    Forward,
+    /// Anything but an IP packet, handed up unchanged.
+    Other,
+    /// An IP packet out of hops, `answer` if it may get a Time Exceeded.
+    Expired { answer: bool },
+}
+
+fn decrement(medium: Medium, frame: &mut [u8]) -> Hop {
+    let Some(link_len) = link::header_len(medium, frame) else {
+        return Hop::Other;
+    };
+    let Some(packet) = frame.get_mut(link_len..) else {
+        return Hop::Other;
+    };
+
+    match packet.first().map(|byte| byte >> 4) {
+        Some(4) => {
+            let Ok(ip) = Ipv4Packet::new_checked(&*packet) else {
+                return Hop::Other;
+            };
+            if ip.hop_limit() <= 1 {
+                let icmp = ip.payload();
+                let error = ip.next_header() == IpProtocol::Icmp
+                    && icmp.first().is_some_and(|&kind| is_icmpv4_error(kind));
+                let src = ip.src_addr();
+                let dst = ip.dst_addr();
+                let unicast = !src.is_multicast()
+                    && !src.is_broadcast()
+                    && !dst.is_multicast()
+                    && !dst.is_broadcast();
+                // Only the first fragment is answered (RFC 1812).
+                return Hop::Expired {
+                    answer: unicast && !error && ip.frag_offset() == 0,
+                };
+            }
+
+            let ttl = ip.hop_limit();
+            if let Some(mut packet) = PacketEditor::new(packet) {
+                packet.set_hop_limit(ttl - 1);
+            }
+            Hop::Forward
+        }
+        Some(6) => {
+            let Ok(ip) = Ipv6Packet::new_checked(&*packet) else {
+                return Hop::Other;
+            };
+            if ip.hop_limit() <= 1 {
+                // ICMPv6 errors are the types below 128.
+                let error = ip.next_header() == IpProtocol::Icmpv6
+                    && ip.payload().first().is_some_and(|&kind| kind < 128);
+                let unicast = !ip.src_addr().is_multicast() && !ip.dst_addr().is_multicast();
+                return Hop::Expired {
+                    answer: unicast && !error,
+                };
+            }
+            let hop_limit = ip.hop_limit();
+            if let Some(mut packet) = PacketEditor::new(packet) {
+                packet.set_hop_limit(hop_limit - 1);
+            }
+            Hop::Forward
+        }
+        _ => Hop::Other,
+    }
+}
+
+/// Destination Unreachable, Source Quench, Redirect, Time Exceeded and Parameter Problem.
+fn is_icmpv4_error(kind: u8) -> bool {
+    matches!(kind, 3 | 4 | 5 | 11 | 12)
+}
+
+/// Appends to `reply` an IPv4 Time Exceeded quoting the header and the first 8 bytes of
+/// the payload of `packet` (RFC 792).
+fn time_exceeded_v4(reply: &mut Vec<u8>, packet: &[u8], source: Ipv4Address) -> Option<()> {
+    let ip = Ipv4Packet::new_checked(packet).ok()?;
+    let quoted = &packet[..(usize::from(ip.header_len()) + 8).min(packet.len())];
+    let repr = Ipv4Repr {
+        src_addr: source,
+        dst_addr: ip.src_addr(),
+        next_header: IpProtocol::Icmp,
+        payload_len: ICMP_HEADER_LEN + quoted.len(),
+        hop_limit: REPLY_HOP_LIMIT,
+    };
+
+    let start = reply.len();
+    reply.resize(start + repr.buffer_len() + repr.payload_len, 0);
+    let mut out = Ipv4Packet::new_unchecked(&mut reply[start..]);
+    repr.emit(&mut out, &ChecksumCapabilities::default());
+    let mut icmp = Icmpv4Packet::new_unchecked(out.payload_mut());
+    icmp.set_msg_type(Icmpv4Message::TimeExceeded);
+    // Time to live exceeded in transit.
+    icmp.set_msg_code(0);
+    icmp.data_mut().copy_from_slice(quoted);
+    icmp.fill_checksum();
+    Some(())
+}
+
+/// Appends to `reply` an ICMPv6 Time Exceeded quoting as much of `packet` as fits in the
+/// IPv6 minimum MTU (RFC 4443).
+fn time_exceeded_v6(reply: &mut Vec<u8>, packet: &[u8], source: Ipv6Address) -> Option<()> {
+    let ip = Ipv6Packet::new_checked(packet).ok()?;
+    let room = IPV6_MIN_MTU - ip.header_len() - ICMP_HEADER_LEN;
+    let quoted = &packet[..packet.len().min(room)];
+    let repr = Ipv6Repr {
+        src_addr: source,
+        dst_addr: ip.src_addr(),
+        next_header: IpProtocol::Icmpv6,
+        payload_len: ICMP_HEADER_LEN + quoted.len(),
+        hop_limit: REPLY_HOP_LIMIT,
+    };
+
+    let start = reply.len();
+    reply.resize(start + repr.buffer_len() + repr.payload_len, 0);
+    let mut out = Ipv6Packet::new_unchecked(&mut reply[start..]);
+    repr.emit(&mut out);
+    let mut icmp = Icmpv6Packet::new_unchecked(out.payload_mut());
+    icmp.set_msg_type(Icmpv6Message::TimeExceeded);
+    // Hop limit exceeded in transit.
+    icmp.set_msg_code(0);
+    icmp.payload_mut().copy_from_slice(quoted);
+    icmp.fill_checksum(&repr.src_addr, &repr.dst_addr);
+    Some(())
+}
@@ -0,0 +1,28 @@
+use smoltcp::phy::Medium;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+/// TPIDs of 802.1Q customer and 802.1ad service VLAN tags.
+const VLAN_TPIDS: [u16; 2] = [0x8100, 0x88a8];
+
+/// Ethertype of the Ethernet `frame` and offset of its payload, past any VLAN tags, e.g.
+/// the service and customer tags of a QinQ frame. `None` if a header is cut off.
+pub(crate) fn ethernet(frame: &[u8]) -> Option<(u16, usize)> {
+    let mut offset = ETHERNET_HEADER_LEN;
+    let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+    while VLAN_TPIDS.contains(&ethertype) {
+        ethertype = u16::from_be_bytes([*frame.get(offset + 2)?, *frame.get(offset + 3)?]);
+        offset += VLAN_TAG_LEN;
+    }
+    Some((ethertype, offset))
+}
+
+/// Length of the link-layer header of `frame` on `medium`.
+pub(crate) fn header_len(medium: Medium, frame: &[u8]) -> Option<usize> {
+    match medium {
+        Medium::Ethernet => ethernet(frame).map(|(_, offset)| offset),
+        Medium::Ip => Some(0),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
@@ -0,0 +1,456 @@
+use std::cell::Cell;
+
+use smoltcp::phy::{self, Device, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+use smoltcp::wire::{IpProtocol, Ipv4Packet, Ipv6Packet, TcpPacket};
+
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+use crate::phy::link;
+use crate::phy::rewrite::PacketEditor;
+
+const TCP_OPTION_MSS: u8 = 2;
+
+/// A device wrapper lowering the MSS option of TCP SYN segments to fit a path MTU.
+///
+/// Both the segments originated by smoltcp and the ones received from the wrapped device
+/// are clamped, so neither side sends segments that do not fit, e.g. through a tunnel with
+/// a smaller MTU than the device.
+pub struct MssClamp<D: Device> {
+    inner: D,
+    mtu: usize,
+    clamped: Cell<u64>,
+}
+
+impl<D: Device> MssClamp<D> {
+    /// Wraps `inner`, clamping to the IP `mtu` of the egress path.
+    pub fn new(inner: D, mtu: usize) -> Self {
+        Self {
+            inner,
+            mtu,
+            clamped: Cell::new(0),
+        }
+    }
+
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Number of SYN segments rewritten so far.
+    pub fn clamped(&self) -> u64 {
+        self.clamped.get()
+    }
+
+    /// Get a reference to the underlying device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Return the underlying device, consuming the wrapper.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn clamp(&self) -> Clamp {
+        let caps = self.inner.capabilities();
+        Clamp {
+            medium: caps.medium,
+            mtu: self.mtu,
+            checksum: caps.checksum.tcp.tx(),
+        }
+    }
+}
+
+impl<D: Device> Device for MssClamp<D> {
+    type RxToken<'a>
+        = RxToken<'a, D::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a, D::TxToken<'a>>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let clamp = self.clamp();
+        let (rx, tx) = self.inner.receive(timestamp)?;
+        Some((
+            RxToken {
+                token: rx,
+                clamp,
+                clamped: &self.clamped,
+            },
+            TxToken {
+                token: tx,
+                clamp,
+                clamped: &self.clamped,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        let clamp = self.clamp();
+        let tx = self.inner.transmit(timestamp)?;
+        Some(TxToken {
+            token: tx,
+            clamp,
+            clamped: &self.clamped,
+        })
+    }
+}
+
+impl<D: CapabilitiesAudit> CapabilitiesAudit for MssClamp<D> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("mss-clamp", self.capabilities());
+        self.inner.audit(report);
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken<'a, Rx: phy::RxToken> {
+    token: Rx,
+    clamp: Clamp,
+    clamped: &'a Cell<u64>,
+}
+
+impl<Rx: phy::RxToken> phy::RxToken for RxToken<'_, Rx> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let clamp = self.clamp;
+        let clamped = self.clamped;
+        self.token.consume(|frame| match clamp.find(frame) {
+            Some(option) => {
+                let mut frame = frame.to_vec();
+                clamp.rewrite(&mut frame, option, true);
+                clamped.set(clamped.get() + 1);
+                f(&frame)
+            }
+            None => f(frame),
+        })
+    }
+
+    fn meta(&self) -> phy::PacketMeta {
+        self.token.meta()
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a, Tx: phy::TxToken> {
+    token: Tx,
+    clamp: Clamp,
+    clamped: &'a Cell<u64>,
+}
+
+impl<Tx: phy::TxToken> phy::TxToken for TxToken<'_, Tx> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let clamp = self.clamp;
+        let clamped = self.clamped;
+        self.token.consume(len, |frame| {
+            let result = f(frame);
+            if let Some(option) = clamp.find(frame) {
+                clamp.rewrite(frame, option, clamp.checksum);
+                clamped.set(clamped.get() + 1);
+            }
+            result
+        })
+    }
+
+    fn set_meta(&mut self, meta: phy::PacketMeta) {
+        self.token.set_meta(meta)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Clamp {
+    medium: Medium,
+    mtu: usize,
+    // Whether smoltcp fills TCP checksums of sent segments. Received segments always
+    // carry one.
+    checksum: bool,
+}
+
+/// Location of an MSS option exceeding the clamp.
+#[derive(Copy, Clone)]
+struct MssOption {
+    // Offset of the IP packet in the frame.
+    ip: usize,
+    // Offset of the option value in the TCP header.
+    value: usize,
+    mss: u16,
+}
+
+impl Clamp {
+    fn find(&self, frame: &[u8]) -> Option<MssOption> {
+        let link_len = link::header_len(self.medium, frame)?;
+        let packet = frame.get(link_len..)?;
+
+        let (ip_len, protocol, limit) = match packet.first()? >> 4 {
+            4 => {
+                let ip = Ipv4Packet::new_checked(packet).ok()?;
+                if ip.frag_offset() != 0 {
+                    return None;
+                }
+                (usize::from(ip.header_len()), ip.next_header(), 40)
+            }
+            6 => {
+                let ip = Ipv6Packet::new_checked(packet).ok()?;
+                (ip.header_len(), ip.next_header(), 60)
+            }
+            _ => return None,
+        };
+        if protocol != IpProtocol::Tcp {
+            return None;
+        }
+
+        let tcp = TcpPacket::new_checked(&packet[ip_len..]).ok()?;
+        if !tcp.syn() {
+            return None;
+        }
+        let clamp = u16::try_from(self.mtu.saturating_sub(limit)).unwrap_or(u16::MAX);
+
+        let options = tcp.options();
+        let mut i = 0;
+        while i < options.len() {
+            match options[i] {
+                // End of option list.
+                0 => break,
+                // No operation.
+                1 => i += 1,
+                kind => {
+                    let len = usize::from(*options.get(i + 1)?);
+                    if len < 2 {
+                        return None;
+                    }
+                    if kind == TCP_OPTION_MSS && len == 4 {
+                        let mss = u16::from_be_bytes([*options.get(i + 2)?, *options.get(i + 3)?]);
+                        return (mss > clamp).then_some(MssOption {
+                            ip: link_len,
+                            value: 20 + i + 2,
+                            mss: clamp,
+                        });
+                    }
+                    i += len;
+                }
+            }
+        }
+
+        None
+    }
+
+    fn rewrite(&self, frame: &mut [u8], option: MssOption, checksum: bool) {
+        let Some(mut packet) = PacketEditor::new(&mut frame[option.ip..]) else {
+            return;
+        };
+        if !checksum {
+            packet.skip_l4_checksum();
+        }
+        packet.set_l4_word(option.value, option.mss);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smoltcp::wire::{IpAddress, Ipv4Address, Ipv6Address};
+
+    use super::*;
+
+    const SRC: Ipv4Address = Ipv4Address::new(192, 168, 1, 10);
+    const DST: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+
+    fn clamp(medium: Medium, mtu: usize) -> Clamp {
+        Clamp {
+            medium,
+            mtu,
+            checksum: true,
+        }
+    }
+
+    /// A TCP segment with `options`, a multiple of 4 bytes long.
+    fn segment(syn: bool, options: &[u8], src: IpAddress, dst: IpAddress) -> Vec<u8> {
+        let mut buf = vec![0; 20 + options.len()];
+        let mut tcp = TcpPacket::new_unchecked(&mut buf[..]);
+        tcp.set_src_port(40000);
+        tcp.set_dst_port(80);
+        tcp.set_header_len(20 + options.len() as u8);
+        tcp.set_syn(syn);
+        tcp.set_window_len(1024);
+        buf[20..].copy_from_slice(options);
+        TcpPacket::new_unchecked(&mut buf[..]).fill_checksum(&src, &dst);
+        buf
+    }
+
+    fn ipv4(segment: &[u8]) -> Vec<u8> {
+        let len = 20 + segment.len();
+        let mut buf = vec![0; len];
+        let mut ip = Ipv4Packet::new_unchecked(&mut buf[..]);
+        ip.set_version(4);
+        ip.set_header_len(20);
+        ip.set_total_len(len as u16);
+        ip.set_hop_limit(64);
+        ip.set_next_header(IpProtocol::Tcp);
+        ip.set_src_addr(SRC);
+        ip.set_dst_addr(DST);
+        ip.fill_checksum();
+        buf[20..].copy_from_slice(segment);
+        buf
+    }
+
+    fn ethernet(tags: &[u16], packet: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        for &tpid in tags {
+            frame.extend_from_slice(&tpid.to_be_bytes());
+            frame.extend_from_slice(&[0, 1]);
+        }
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(packet);
+        frame
+    }
+
+    fn syn_v4(options: &[u8]) -> Vec<u8> {
+        ipv4(&segment(true, options, SRC.into(), DST.into()))
+    }
+
+    /// MSS option of `mss`, padded to 4 bytes.
+    fn mss(mss: u16) -> [u8; 4] {
+        let [high, low] = mss.to_be_bytes();
+        [TCP_OPTION_MSS, 4, high, low]
+    }
+
+    /// Rewrites `option` of the untagged IPv4 `frame`, checks the TCP checksum and
+    /// returns the MSS found in the frame afterwards.
+    fn rewrite(frame: &mut [u8], mtu: usize, option: MssOption) -> u16 {
+        let value = option.value;
+        clamp(Medium::Ethernet, mtu).rewrite(frame, option, true);
+        let ip = Ipv4Packet::new_checked(&frame[14..]).unwrap();
+        let tcp = TcpPacket::new_checked(ip.payload()).unwrap();
+        assert!(tcp.verify_checksum(&SRC.into(), &DST.into()));
+        u16::from_be_bytes([ip.payload()[value], ip.payload()[value + 1]])
+    }
+
+    #[test]
+    fn clamps_mss_above_the_mtu() {
+        let mut frame = ethernet(&[], &syn_v4(&mss(1460)));
+        let clamp = clamp(Medium::Ethernet, 1400);
+        let option = clamp.find(&frame).unwrap();
+        assert_eq!(option.mss, 1360);
+        assert_eq!(option.ip, 14);
+        assert_eq!(option.value, 22);
+
+        clamp.rewrite(&mut frame, option, true);
+        let ip = Ipv4Packet::new_checked(&frame[14..]).unwrap();
+        let tcp = TcpPacket::new_checked(ip.payload()).unwrap();
+        assert_eq!(&tcp.options()[2..4], &1360u16.to_be_bytes());
+        assert!(tcp.verify_checksum(&SRC.into(), &DST.into()));
+        assert!(clamp.find(&frame).is_none());
+    }
+
+    #[test]
+    fn leaves_small_mss_alone() {
+        let frame = ethernet(&[], &syn_v4(&mss(1200)));
+        assert!(clamp(Medium::Ethernet, 1400).find(&frame).is_none());
+        let frame = ethernet(&[], &syn_v4(&mss(1360)));
+        assert!(clamp(Medium::Ethernet, 1400).find(&frame).is_none());
+    }
+
+    #[test]
+    fn skips_other_options() {
+        // NOP, NOP, window scale, NOP then the MSS.
+        let mut options = vec![1, 1, 3, 3, 7, 1];
+        options.extend_from_slice(&mss(9000));
+        options.extend_from_slice(&[0, 0]);
+        let mut frame = ethernet(&[], &syn_v4(&options));
+        let option = clamp(Medium::Ethernet, 1500).find(&frame).unwrap();
+        assert_eq!(option.mss, 1460);
+        assert_eq!(option.value, 20 + 6 + 2);
+        assert_eq!(rewrite(&mut frame, 1500, option), 1460);
+    }
+
+    #[test]
+    fn clamps_mss_at_odd_offset() {
+        // A single NOP puts the MSS value at an odd offset of the TCP header.
+        let mut options = vec![1];
+        options.extend_from_slice(&mss(1460));
+        options.extend_from_slice(&[0, 0, 0]);
+        let mut frame = ethernet(&[], &syn_v4(&options));
+        let option = clamp(Medium::Ethernet, 1400).find(&frame).unwrap();
+        assert_eq!(option.value, 20 + 1 + 2);
+        assert_eq!(rewrite(&mut frame, 1400, option), 1360);
+    }
+
+    #[test]
+    fn stops_at_end_of_options() {
+        let mut options = vec![0, 0, 0, 0];
+        options.extend_from_slice(&mss(9000));
+        let frame = ethernet(&[], &syn_v4(&options));
+        assert!(clamp(Medium::Ethernet, 1500).find(&frame).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_options() {
+        // An option length below 2 would loop forever.
+        let frame = ethernet(&[], &syn_v4(&[3, 1, 0, 0]));
+        assert!(clamp(Medium::Ethernet, 1500).find(&frame).is_none());
+        // An MSS option cut off by the end of the header.
+        let frame = ethernet(&[], &syn_v4(&[1, 1, TCP_OPTION_MSS, 4]));
+        assert!(clamp(Medium::Ethernet, 1500).find(&frame).is_none());
+        // An MSS option of the wrong length.
+        let frame = ethernet(&[], &syn_v4(&[TCP_OPTION_MSS, 3, 0x23, 0]));
+        assert!(clamp(Medium::Ethernet, 1500).find(&frame).is_none());
+    }
+
+    #[test]
+    fn ignores_segments_without_syn() {
+        let packet = ipv4(&segment(false, &mss(9000), SRC.into(), DST.into()));
+        assert!(
+            clamp(Medium::Ethernet, 1500)
+                .find(&ethernet(&[], &packet))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn looks_past_vlan_tags() {
+        let packet = syn_v4(&mss(1460));
+        for tags in [&[0x8100][..], &[0x88a8], &[0x88a8, 0x8100]] {
+            let frame = ethernet(tags, &packet);
+            let option = clamp(Medium::Ethernet, 1400).find(&frame).unwrap();
+            assert_eq!(option.ip, 14 + 4 * tags.len());
+        }
+    }
+
+    #[test]
+    fn clamps_ipv6_and_ip_medium() {
+        let src = Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+        let segment = segment(true, &mss(1460), src.into(), dst.into());
+        let mut packet = vec![0; 40];
+        let mut ip = Ipv6Packet::new_unchecked(&mut packet[..]);
+        ip.set_version(6);
+        ip.set_payload_len(segment.len() as u16);
+        ip.set_next_header(IpProtocol::Tcp);
+        ip.set_hop_limit(64);
+        ip.set_src_addr(src);
+        ip.set_dst_addr(dst);
+        packet.extend_from_slice(&segment);
+
+        let option = clamp(Medium::Ip, 1400).find(&packet).unwrap();
+        assert_eq!((option.ip, option.mss), (0, 1340));
+    }
+}
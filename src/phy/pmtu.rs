@@ -0,0 +1,271 @@
+use smoltcp::phy::{ChecksumCapabilities, Device, RxToken, TxToken};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    EthernetAddress, EthernetFrame, EthernetProtocol, EthernetRepr, Icmpv4Message, Icmpv4Packet,
+    Icmpv4Repr, IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr,
+};
+
+const IPV4_HEADER_LEN: usize = 20;
+const ICMP_HEADER_LEN: usize = 8;
+/// Smallest MTU every IPv4 link supports.
+pub const MIN_IPV4_MTU: usize = 68;
+/// Common MTUs from RFC 1191 tried below the largest one, the next smaller one is tried
+/// once a size gets no reply.
+const PLATEAUS: [usize; 10] = [32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296, 68];
+/// `fragmentation needed` code of destination unreachable messages.
+const FRAG_REQUIRED: u8 = 4;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub src_hardware_addr: EthernetAddress,
+    /// Next hop towards the target, the target itself or a gateway.
+    pub dst_hardware_addr: EthernetAddress,
+    pub src_addr: Ipv4Address,
+    pub dst_addr: Ipv4Address,
+    /// Largest IP packet probed, usually the MTU of the device.
+    pub max_mtu: usize,
+    /// Smallest IP packet probed, giving up below.
+    pub min_mtu: usize,
+    /// Time a probe is waited for before it counts as lost.
+    pub timeout: Duration,
+    /// Probes of a size lost before a smaller one is tried.
+    pub attempts: u32,
+    /// ICMP identifier of the probes, to tell their replies apart from other pings.
+    pub ident: u16,
+}
+
+impl Config {
+    /// Probes from `max_mtu` down to the IPv4 minimum, three times each size with a 1 s
+    /// timeout.
+    pub fn new(
+        src_hardware_addr: EthernetAddress,
+        dst_hardware_addr: EthernetAddress,
+        src_addr: Ipv4Address,
+        dst_addr: Ipv4Address,
+        max_mtu: usize,
+    ) -> Self {
+        Self {
+            src_hardware_addr,
+            dst_hardware_addr,
+            src_addr,
+            dst_addr,
+            max_mtu,
+            min_mtu: MIN_IPV4_MTU,
+            timeout: Duration::from_secs(1),
+            attempts: 3,
+            ident: 0x504d,
+        }
+    }
+}
+
+/// Progress of a [`PmtuProbe`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Status {
+    /// Probes of `mtu` bytes are in flight.
+    Probing { mtu: usize },
+    /// The largest probe answered, the usable MTU of the path.
+    Found(usize),
+    /// No probe of `min_mtu` bytes or more answered, the target is unreachable.
+    Failed,
+}
+
+/// Measures the path MTU towards an IPv4 target through a device, with ICMP echo
+/// requests that have the DF bit set and decreasing sizes.
+///
+/// The first size is `max_mtu`, then the RFC 1191 plateaus below it, a size being given
+/// up after `attempts` lost probes. A `fragmentation needed` message from a router skips
+/// straight to the MTU it reports. The result feeds the wrappers that otherwise assume
+/// an MTU, e.g. [`crate::phy::mss_clamp::MssClamp::set_mtu`].
+///
+/// It is driven by polling like `Interface::poll`, and consumes every frame the device
+/// receives meanwhile, so it is meant to run before the device is handed to the
+/// interface.
+pub struct PmtuProbe {
+    config: Config,
+    // Sizes still to probe, the current one last.
+    sizes: Vec<usize>,
+    // Probes of the current size lost so far.
+    lost: u32,
+    // Sequence number and size of every probe sent.
+    sent: Vec<(u16, usize)>,
+    // A probe is waited for until `next`.
+    waiting: bool,
+    next: Instant,
+    status: Status,
+}
+
+impl PmtuProbe {
+    pub fn new(config: Config) -> Self {
+        let max = config.max_mtu.max(config.min_mtu);
+        let mut sizes: Vec<usize> = PLATEAUS
+            .into_iter()
+            .filter(|&size| size < max && size > config.min_mtu)
+            .chain([config.min_mtu])
+            .rev()
+            .collect();
+        sizes.push(max);
+        sizes.dedup();
+        Self {
+            config,
+            status: Status::Probing { mtu: max },
+            sizes,
+            lost: 0,
+            sent: Vec::new(),
+            waiting: false,
+            next: Instant::ZERO,
+        }
+    }
+
+    /// Handles the replies the device received and sends the probe that is due,
+    /// returning the progress so far.
+    pub fn poll<D: Device>(&mut self, device: &mut D, timestamp: Instant) -> Status {
+        if !matches!(self.status, Status::Probing { .. }) {
+            return self.status;
+        }
+
+        while let Some((rx, _)) = device.receive(timestamp) {
+            match rx.consume(|frame| self.parse(frame)) {
+                Some(Reply::Echo(size)) => {
+                    self.status = Status::Found(size);
+                    return self.status;
+                }
+                Some(Reply::FragRequired(mtu)) => self.shrink(mtu),
+                None => {}
+            }
+        }
+
+        if self.next > timestamp {
+            return self.status;
+        }
+        if self.waiting {
+            self.waiting = false;
+            self.lost += 1;
+        }
+        if self.lost >= self.config.attempts {
+            self.lost = 0;
+            self.sizes.pop();
+        }
+        let Some(&size) = self.sizes.last() else {
+            self.status = Status::Failed;
+            return self.status;
+        };
+        self.status = Status::Probing { mtu: size };
+
+        let Some(tx) = device.transmit(timestamp) else {
+            return self.status;
+        };
+        let seq_no = self.sent.len() as u16;
+        let frame = self.probe(size, seq_no);
+        tx.consume(frame.len(), |buf| buf.copy_from_slice(&frame));
+        self.sent.push((seq_no, size));
+        self.waiting = true;
+        self.next = timestamp + self.config.timeout;
+        self.status
+    }
+
+    /// Drops the sizes above `mtu` and probes `mtu` next. MTUs no smaller than the
+    /// current probe, or below `min_mtu`, are bogus and ignored.
+    fn shrink(&mut self, mtu: usize) {
+        if mtu < self.config.min_mtu || self.sizes.last().is_none_or(|&size| mtu >= size) {
+            return;
+        }
+        self.sizes.retain(|&size| size < mtu);
+        self.sizes.push(mtu);
+        self.lost = 0;
+        self.waiting = false;
+        self.next = Instant::ZERO;
+    }
+
+    fn probe(&self, size: usize, seq_no: u16) -> Vec<u8> {
+        let data = vec![0; size.saturating_sub(IPV4_HEADER_LEN + ICMP_HEADER_LEN)];
+        let icmp = Icmpv4Repr::EchoRequest {
+            ident: self.config.ident,
+            seq_no,
+            data: &data,
+        };
+        // Emitted with the DF bit set.
+        let ip = Ipv4Repr {
+            src_addr: self.config.src_addr,
+            dst_addr: self.config.dst_addr,
+            next_header: IpProtocol::Icmp,
+            payload_len: icmp.buffer_len(),
+            hop_limit: 64,
+        };
+        let eth = EthernetRepr {
+            src_addr: self.config.src_hardware_addr,
+            dst_addr: self.config.dst_hardware_addr,
+            ethertype: EthernetProtocol::Ipv4,
+        };
+
+        let checksum = ChecksumCapabilities::default();
+        let mut buf = vec![0; eth.buffer_len() + ip.buffer_len() + icmp.buffer_len()];
+        let mut frame = EthernetFrame::new_unchecked(&mut buf[..]);
+        eth.emit(&mut frame);
+        let mut packet = Ipv4Packet::new_unchecked(frame.payload_mut());
+        ip.emit(&mut packet, &checksum);
+        icmp.emit(
+            &mut Icmpv4Packet::new_unchecked(packet.payload_mut()),
+            &checksum,
+        );
+        buf
+    }
+
+    fn parse(&self, frame: &[u8]) -> Option<Reply> {
+        let frame = EthernetFrame::new_checked(frame).ok()?;
+        if frame.ethertype() != EthernetProtocol::Ipv4 {
+            return None;
+        }
+        let packet = Ipv4Packet::new_checked(frame.payload()).ok()?;
+        if packet.next_header() != IpProtocol::Icmp || packet.dst_addr() != self.config.src_addr {
+            return None;
+        }
+        let icmp = Icmpv4Packet::new_checked(packet.payload()).ok()?;
+        match icmp.msg_type() {
+            Icmpv4Message::EchoReply
+                if packet.src_addr() == self.config.dst_addr
+                    && icmp.echo_ident() == self.config.ident =>
+            {
+                let seq_no = icmp.echo_seq_no();
+                self.sent
+                    .iter()
+                    .find(|&&(sent, _)| sent == seq_no)
+                    .map(|&(_, size)| Reply::Echo(size))
+            }
+            Icmpv4Message::DstUnreachable if icmp.msg_code() == FRAG_REQUIRED => {
+                // The header of the dropped probe follows the next-hop MTU.
+                let original = icmp.data();
+                if original.len() < IPV4_HEADER_LEN
+                    || Ipv4Packet::new_unchecked(original).dst_addr() != self.config.dst_addr
+                {
+                    return None;
+                }
+                let header = packet.payload();
+                let mtu = u16::from_be_bytes([header[6], header[7]]);
+                Some(Reply::FragRequired(usize::from(mtu)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// When the current probe is lost, to bound the poll loop sleep like
+    /// `Interface::poll_at`.
+    pub fn poll_at(&self) -> Option<Instant> {
+        matches!(self.status, Status::Probing { .. }).then_some(self.next)
+    }
+
+    /// Probes sent so far.
+    pub fn sent(&self) -> usize {
+        self.sent.len()
+    }
+}
+
+enum Reply {
+    /// A probe of this size made it to the target and back.
+    Echo(usize),
+    /// A router dropped a probe, its next hop having this MTU.
+    FragRequired(usize),
+}
@@ -0,0 +1,158 @@
+use std::time::{Duration, Instant as WallClock};
+
+use smoltcp::phy::{Device, DeviceCapabilities};
+use smoltcp::time::Instant;
+
+use crate::phy::budget::PollBudget;
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// Frames received per smoltcp poll iteration before yielding, `None` for no limit.
+    pub frames: Option<usize>,
+    /// Wall-clock time spent receiving per smoltcp poll iteration before yielding, `None`
+    /// for no limit.
+    pub time: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            frames: Some(64),
+            time: None,
+        }
+    }
+}
+
+/// A device wrapper cutting long RX bursts into work quanta, so that a single-threaded
+/// async executor gets control back while the device is flooded.
+///
+/// Once the quantum of a poll iteration is used up, `Device::receive` returns `None` and
+/// `Interface::poll` returns. The poll task should then check [`Quantum::yielded`]: queued
+/// frames do not make the device readable again, so it has to yield to the executor and
+/// poll right after instead of waiting for readiness or `Interface::poll_at`.
+///
+/// A quantum covers one poll iteration, which the wrapper tells apart by the timestamp
+/// smoltcp polls with. A poll task whose clock may not tick between two iterations calls
+/// [`Quantum::replenish`] before each of them.
+pub struct Quantum<D: Device> {
+    inner: D,
+    config: Config,
+    frames: PollBudget,
+    started: Option<WallClock>,
+    yielded: bool,
+    yields: u64,
+}
+
+impl<D: Device> Quantum<D> {
+    pub fn new(inner: D, config: Config) -> Self {
+        Self {
+            inner,
+            frames: PollBudget::new(config.frames),
+            config,
+            started: None,
+            yielded: false,
+            yields: 0,
+        }
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.frames.limit = config.frames;
+        self.config = config;
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Whether the last poll iteration stopped receiving because its quantum ran out,
+    /// in which case the device may still hold frames.
+    pub fn yielded(&self) -> bool {
+        self.yielded
+    }
+
+    /// Starts a fresh quantum for the next poll iteration.
+    pub fn replenish(&mut self) {
+        self.frames.replenish();
+    }
+
+    /// Poll iterations cut short so far.
+    pub fn yields(&self) -> u64 {
+        self.yields
+    }
+
+    /// Get a reference to the underlying device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Return the underlying device, consuming the wrapper.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn exhausted(&mut self, timestamp: Instant) -> bool {
+        if self.frames.begin(timestamp) {
+            self.started = None;
+            self.yielded = false;
+        }
+        if self.yielded {
+            return true;
+        }
+
+        let frames = self.frames.exhausted();
+        let time = self.config.time.is_some_and(|limit| {
+            self.started
+                .is_some_and(|started| started.elapsed() >= limit)
+        });
+        if frames || time {
+            self.yielded = true;
+            self.yields += 1;
+        }
+        self.yielded
+    }
+}
+
+impl<D: Device> Device for Quantum<D> {
+    type RxToken<'a>
+        = D::RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        if self.exhausted(timestamp) {
+            return None;
+        }
+        if self.config.time.is_some() && self.started.is_none() {
+            self.started = Some(WallClock::now());
+        }
+
+        let tokens = self.inner.receive(timestamp)?;
+        self.frames.spend();
+        Some(tokens)
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.inner.transmit(timestamp)
+    }
+}
+
+impl<D: CapabilitiesAudit> CapabilitiesAudit for Quantum<D> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("quantum", self.capabilities());
+        self.inner.audit(report);
+    }
+}
@@ -0,0 +1,201 @@
+/// Why a sequence number was rejected.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Rejection {
+    /// Already seen within the window.
+    Replayed,
+    /// Older than the window, whether seen or not.
+    Late,
+}
+
+/// Sequence numbers accepted and rejected so far.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Stats {
+    pub accepted: u64,
+    pub replayed: u64,
+    pub late: u64,
+}
+
+/// A 64-bit sliding window rejecting replayed and late sequence numbers, as IPsec and
+/// MACsec receivers do, for tunnel devices that would otherwise accept any duplicate.
+///
+/// The window covers the highest sequence number accepted and the 63 below it. Newer
+/// numbers slide it forward, so reordering within 64 frames is tolerated. Frames are
+/// meant to be checked with [`ReplayWindow::check`] before being authenticated and
+/// recorded with [`ReplayWindow::update`] once they are, so that forged frames do not
+/// move the window: [`ReplayWindow::accept`] does both for protocols without
+/// authentication.
+#[derive(Clone, Default, Debug)]
+pub struct ReplayWindow {
+    // Highest sequence number accepted, `None` before the first one.
+    top: Option<u64>,
+    // Bit `n` is set when `top - n` was accepted.
+    seen: u64,
+    stats: Stats,
+}
+
+impl ReplayWindow {
+    pub const SIZE: u64 = 64;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks `seq` without recording it, counting it if rejected.
+    pub fn check(&mut self, seq: u64) -> Result<(), Rejection> {
+        let result = self.peek(seq);
+        match result {
+            Err(Rejection::Replayed) => self.stats.replayed += 1,
+            Err(Rejection::Late) => self.stats.late += 1,
+            Ok(()) => {}
+        }
+        result
+    }
+
+    fn peek(&self, seq: u64) -> Result<(), Rejection> {
+        let Some(top) = self.top else {
+            return Ok(());
+        };
+        if seq > top {
+            return Ok(());
+        }
+        let age = top - seq;
+        if age >= Self::SIZE {
+            Err(Rejection::Late)
+        } else if self.seen & 1 << age != 0 {
+            Err(Rejection::Replayed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Records `seq` as accepted, sliding the window forward if it is the newest. `seq`
+    /// is meant to have passed [`ReplayWindow::check`], a number older than the window
+    /// is ignored.
+    pub fn update(&mut self, seq: u64) {
+        match self.top {
+            Some(top) if seq <= top => {
+                let age = top - seq;
+                if age >= Self::SIZE {
+                    return;
+                }
+                self.seen |= 1 << age;
+            }
+            Some(top) => {
+                let shift = seq - top;
+                self.seen = if shift >= Self::SIZE {
+                    1
+                } else {
+                    self.seen << shift | 1
+                };
+                self.top = Some(seq);
+            }
+            None => {
+                self.seen = 1;
+                self.top = Some(seq);
+            }
+        }
+        self.stats.accepted += 1;
+    }
+
+    /// Checks and records `seq`, returning whether it was accepted.
+    pub fn accept(&mut self, seq: u64) -> bool {
+        let accepted = self.check(seq).is_ok();
+        if accepted {
+            self.update(seq);
+        }
+        accepted
+    }
+
+    /// Forgets every sequence number seen, e.g. when the tunnel is rekeyed. Counters are
+    /// kept.
+    pub fn reset(&mut self) {
+        self.top = None;
+        self.seen = 0;
+    }
+
+    /// Highest sequence number accepted so far.
+    pub fn top(&self) -> Option<u64> {
+        self.top
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_first_and_newer() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(100));
+        assert!(window.accept(101));
+        assert!(window.accept(500));
+        assert_eq!(window.top(), Some(500));
+        assert_eq!(window.stats().accepted, 3);
+    }
+
+    #[test]
+    fn rejects_replays() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(10));
+        assert_eq!(window.check(10), Err(Rejection::Replayed));
+        assert!(window.accept(12));
+        assert!(window.accept(11));
+        assert_eq!(window.check(11), Err(Rejection::Replayed));
+        assert_eq!(window.stats().replayed, 2);
+    }
+
+    #[test]
+    fn window_edges() {
+        let mut window = ReplayWindow::new();
+        let top = 1000;
+        assert!(window.accept(top));
+        // The oldest number still inside the window.
+        let oldest = top - (ReplayWindow::SIZE - 1);
+        assert!(window.accept(oldest));
+        assert_eq!(window.check(oldest), Err(Rejection::Replayed));
+        // Just past it.
+        assert_eq!(window.check(oldest - 1), Err(Rejection::Late));
+        assert_eq!(window.stats().late, 1);
+
+        // Sliding by one pushes the oldest number out.
+        assert!(window.accept(top + 1));
+        assert_eq!(window.check(oldest), Err(Rejection::Late));
+        assert_eq!(window.check(oldest + 1), Ok(()));
+    }
+
+    #[test]
+    fn slides_past_the_window() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(5));
+        assert!(window.accept(5 + ReplayWindow::SIZE));
+        assert_eq!(window.check(5), Err(Rejection::Late));
+        assert_eq!(window.check(6), Ok(()));
+        assert!(window.accept(u64::MAX));
+        assert_eq!(window.check(u64::MAX), Err(Rejection::Replayed));
+    }
+
+    #[test]
+    fn update_ignores_late_numbers() {
+        let mut window = ReplayWindow::new();
+        window.update(200);
+        window.update(200 - ReplayWindow::SIZE);
+        window.update(0);
+        assert_eq!(window.top(), Some(200));
+        assert_eq!(window.check(200 - ReplayWindow::SIZE + 1), Ok(()));
+        assert_eq!(window.stats().accepted, 1);
+    }
+
+    #[test]
+    fn reset_forgets_numbers() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(42));
+        window.reset();
+        assert_eq!(window.top(), None);
+        assert!(window.accept(42));
+        assert_eq!(window.stats().accepted, 2);
+    }
+}
@@ -0,0 +1,410 @@
+//! Editing the headers of IP packets in place, in the buffer of an RX or TX token, with
+//! checksums updated incrementally (RFC 1624) instead of recomputed.
+//!
+//! NAT, MSS clamping or tunnel wrappers then rewrite a field without parsing the packet
+//! into a repr, emitting it again and copying it:
+//!
+//! ```ignore
+//! if let Some(mut packet) = PacketEditor::new(&mut frame[14..]) {
+//!     packet.set_dst_addr(backend.into());
+//!     packet.set_dst_port(8080);
+//! }
+//! ```
+
+use smoltcp::wire::{IpAddress, IpProtocol};
+
+const IPV4_CHECKSUM: usize = 10;
+const IPV6_HEADER_LEN: usize = 40;
+
+/// Incrementally updates an internet checksum after a 16-bit word changed (RFC 1624).
+pub fn update_checksum(checksum: u16, old: u16, new: u16) -> u16 {
+    let mut sum = u32::from(!checksum) + u32::from(!old) + u32::from(new);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Incrementally updates an internet checksum after the 16-bit words of `old`, e.g. an
+/// address, changed to the ones of `new`. Both have the same even length.
+pub fn update_checksum_words(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    debug_assert_eq!(old.len(), new.len());
+    old.chunks_exact(2)
+        .zip(new.chunks_exact(2))
+        .fold(checksum, |checksum, (old, new)| {
+            update_checksum(
+                checksum,
+                u16::from_be_bytes([old[0], old[1]]),
+                u16::from_be_bytes([new[0], new[1]]),
+            )
+        })
+}
+
+/// An IPv4 or IPv6 packet whose header fields are rewritten in place, along with the
+/// IPv4 header checksum and the checksum of the TCP, UDP or ICMP message it carries.
+///
+/// The L4 header is only reached in unfragmented IPv4 packets, or first fragments, and
+/// IPv6 packets without extension headers. Setters return false when the packet has no
+/// such field, leaving it untouched.
+pub struct PacketEditor<'a> {
+    packet: &'a mut [u8],
+    ipv4: bool,
+    protocol: IpProtocol,
+    // Start of the L4 header, `None` when it is out of reach.
+    l4: Option<usize>,
+    // Whether the L4 checksum is updated.
+    l4_checksum: bool,
+}
+
+impl<'a> PacketEditor<'a> {
+    /// Edits the IP packet at the start of `packet`, `None` if it is neither a valid
+    /// IPv4 nor IPv6 header.
+    pub fn new(packet: &'a mut [u8]) -> Option<Self> {
+        let (ipv4, protocol, l4) = match packet.first()? >> 4 {
+            4 => {
+                let header_len = usize::from(packet[0] & 0x0f) * 4;
+                if header_len < 20 || packet.len() < header_len {
+                    return None;
+                }
+                let offset = u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff;
+                (true, packet[9], (offset == 0).then_some(header_len))
+            }
+            6 if packet.len() >= IPV6_HEADER_LEN => (false, packet[6], Some(IPV6_HEADER_LEN)),
+            _ => return None,
+        };
+        let protocol = IpProtocol::from(protocol);
+        let l4 = l4.filter(|&l4| {
+            let len = match protocol {
+                IpProtocol::Tcp => 20,
+                IpProtocol::Udp => 8,
+                IpProtocol::Icmp | IpProtocol::Icmpv6 => 4,
+                _ => return false,
+            };
+            packet.len() >= l4 + len
+        });
+        Some(Self {
+            packet,
+            ipv4,
+            protocol,
+            l4,
+            l4_checksum: true,
+        })
+    }
+
+    /// Leaves the L4 checksum alone, e.g. when smoltcp or the NIC computes it after the
+    /// rewrite.
+    pub fn skip_l4_checksum(&mut self) {
+        self.l4_checksum = false;
+    }
+
+    pub fn protocol(&self) -> IpProtocol {
+        self.protocol
+    }
+
+    /// Offset of the L4 checksum in the packet.
+    fn l4_checksum_at(&self) -> Option<usize> {
+        let offset = match self.protocol {
+            IpProtocol::Tcp => 16,
+            IpProtocol::Udp => 6,
+            IpProtocol::Icmp | IpProtocol::Icmpv6 => 2,
+            _ => return None,
+        };
+        self.l4.filter(|_| self.l4_checksum).map(|l4| l4 + offset)
+    }
+
+    /// Whether the L4 checksum covers the IP addresses through the pseudo-header.
+    fn pseudo_header(&self) -> bool {
+        matches!(
+            self.protocol,
+            IpProtocol::Tcp | IpProtocol::Udp | IpProtocol::Icmpv6
+        )
+    }
+
+    fn read(&self, at: usize) -> u16 {
+        u16::from_be_bytes([self.packet[at], self.packet[at + 1]])
+    }
+
+    fn patch(&mut self, checksum_at: usize, old: &[u8], new: &[u8]) {
+        let checksum = update_checksum_words(self.read(checksum_at), old, new);
+        self.packet[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    fn patch_l4(&mut self, old: &[u8], new: &[u8]) {
+        let Some(checksum_at) = self.l4_checksum_at() else {
+            return;
+        };
+        let udp = self.protocol == IpProtocol::Udp;
+        // A zero UDP checksum means none, and must stay so.
+        if udp && self.read(checksum_at) == 0 {
+            return;
+        }
+        self.patch(checksum_at, old, new);
+        // Zero is sent as all ones, as computed UDP checksums are.
+        if udp && self.read(checksum_at) == 0 {
+            self.packet[checksum_at..checksum_at + 2].copy_from_slice(&[0xff, 0xff]);
+        }
+    }
+
+    /// Overwrites `new.len()` bytes of the IP header at `at`, covered by the L4 checksum
+    /// through the pseudo-header with `pseudo`.
+    fn write_ip(&mut self, at: usize, new: &[u8], pseudo: bool) {
+        let mut old = [0; 16];
+        let old = &mut old[..new.len()];
+        old.copy_from_slice(&self.packet[at..at + new.len()]);
+        self.packet[at..at + new.len()].copy_from_slice(new);
+        if self.ipv4 {
+            self.patch(IPV4_CHECKSUM, old, new);
+        }
+        if pseudo && self.pseudo_header() {
+            self.patch_l4(old, new);
+        }
+    }
+
+    fn set_addr(&mut self, addr: IpAddress, ipv4_at: usize, ipv6_at: usize) -> bool {
+        match addr {
+            IpAddress::Ipv4(addr) if self.ipv4 => self.write_ip(ipv4_at, &addr.octets(), true),
+            IpAddress::Ipv6(addr) if !self.ipv4 => self.write_ip(ipv6_at, &addr.octets(), true),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Rewrites the source address, which must be of the version of the packet.
+    pub fn set_src_addr(&mut self, addr: IpAddress) -> bool {
+        self.set_addr(addr, 12, 8)
+    }
+
+    /// Rewrites the destination address, which must be of the version of the packet.
+    pub fn set_dst_addr(&mut self, addr: IpAddress) -> bool {
+        self.set_addr(addr, 16, 24)
+    }
+
+    pub fn hop_limit(&self) -> u8 {
+        self.packet[if self.ipv4 { 8 } else { 7 }]
+    }
+
+    /// Rewrites the IPv4 TTL or the IPv6 hop limit.
+    pub fn set_hop_limit(&mut self, hop_limit: u8) {
+        if self.ipv4 {
+            // TTL and protocol share the 16-bit word at offset 8.
+            let word = [hop_limit, self.packet[9]];
+            self.write_ip(8, &word, false);
+        } else {
+            // IPv6 has no header checksum.
+            self.packet[7] = hop_limit;
+        }
+    }
+
+    /// Overwrites the 16-bit word `offset` bytes into the L4 header, e.g. an option.
+    /// The offset may be odd, as for an MSS option behind a single NOP.
+    pub fn set_l4_word(&mut self, offset: usize, value: u16) -> bool {
+        let Some(l4) = self.l4 else {
+            return false;
+        };
+        let at = l4 + offset;
+        if self.packet.len() < at + 2 {
+            return false;
+        }
+        let old = self.read(at).to_be_bytes();
+        let new = value.to_be_bytes();
+        self.packet[at..at + 2].copy_from_slice(&new);
+        if offset.is_multiple_of(2) {
+            self.patch_l4(&old, &new);
+        } else {
+            // The bytes straddle two checksum words, low byte of one and high byte of the
+            // next, so they count byte-swapped in the sum (RFC 1071).
+            self.patch_l4(&[old[1], old[0]], &[new[1], new[0]]);
+        }
+        true
+    }
+
+    fn set_port(&mut self, offset: usize, port: u16) -> bool {
+        matches!(self.protocol, IpProtocol::Tcp | IpProtocol::Udp) && self.set_l4_word(offset, port)
+    }
+
+    /// Rewrites the TCP or UDP source port.
+    pub fn set_src_port(&mut self, port: u16) -> bool {
+        self.set_port(0, port)
+    }
+
+    /// Rewrites the TCP or UDP destination port.
+    pub fn set_dst_port(&mut self, port: u16) -> bool {
+        self.set_port(2, port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smoltcp::wire::{Ipv4Address, Ipv4Packet, Ipv6Address, Ipv6Packet, TcpPacket, UdpPacket};
+
+    use super::*;
+
+    const SRC_V4: Ipv4Address = Ipv4Address::new(192, 168, 1, 10);
+    const DST_V4: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+    const SRC_V6: Ipv6Address = Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x10);
+    const DST_V6: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+
+    /// Internet checksum of `data`, computed from scratch.
+    fn checksum(data: &[u8]) -> u16 {
+        let mut sum: u32 = data
+            .chunks(2)
+            .map(|word| u32::from(u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)])))
+            .sum();
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    fn tcp_v4() -> Vec<u8> {
+        let mut buf = vec![0; 20 + 20 + 4];
+        let mut ip = Ipv4Packet::new_unchecked(&mut buf[..]);
+        ip.set_version(4);
+        ip.set_header_len(20);
+        ip.set_total_len(44);
+        ip.set_hop_limit(64);
+        ip.set_next_header(IpProtocol::Tcp);
+        ip.set_src_addr(SRC_V4);
+        ip.set_dst_addr(DST_V4);
+        ip.fill_checksum();
+        let mut tcp = TcpPacket::new_unchecked(&mut buf[20..]);
+        tcp.set_src_port(40000);
+        tcp.set_dst_port(80);
+        tcp.set_header_len(20);
+        tcp.set_syn(true);
+        tcp.set_window_len(1024);
+        tcp.payload_mut().copy_from_slice(b"data");
+        tcp.fill_checksum(&SRC_V4.into(), &DST_V4.into());
+        buf
+    }
+
+    fn udp_v6(checksum: bool) -> Vec<u8> {
+        let mut buf = vec![0; 40 + 8 + 5];
+        let mut ip = Ipv6Packet::new_unchecked(&mut buf[..]);
+        ip.set_version(6);
+        ip.set_payload_len(13);
+        ip.set_next_header(IpProtocol::Udp);
+        ip.set_hop_limit(64);
+        ip.set_src_addr(SRC_V6);
+        ip.set_dst_addr(DST_V6);
+        let mut udp = UdpPacket::new_unchecked(&mut buf[40..]);
+        udp.set_src_port(5353);
+        udp.set_dst_port(53);
+        udp.set_len(13);
+        udp.payload_mut().copy_from_slice(b"query");
+        if checksum {
+            udp.fill_checksum(&SRC_V6.into(), &DST_V6.into());
+        }
+        buf
+    }
+
+    #[test]
+    fn update_checksum_matches_recompute() {
+        let mut data: Vec<u8> = (0u8..64).map(|byte| byte.wrapping_mul(37)).collect();
+        for (at, new) in [(0, 0x0000), (10, 0xffff), (62, 0x1234), (30, 0x8001)] {
+            let before = checksum(&data);
+            let old = u16::from_be_bytes([data[at], data[at + 1]]);
+            data[at..at + 2].copy_from_slice(&u16::to_be_bytes(new));
+            assert_eq!(update_checksum(before, old, new), checksum(&data));
+        }
+    }
+
+    #[test]
+    fn update_checksum_words_matches_recompute() {
+        let mut data = [0u8; 32];
+        data[..16].copy_from_slice(&SRC_V6.octets());
+        data[16..].copy_from_slice(&DST_V6.octets());
+        let before = checksum(&data);
+        data[16..].copy_from_slice(&SRC_V6.octets());
+        let updated = update_checksum_words(before, &DST_V6.octets(), &SRC_V6.octets());
+        assert_eq!(updated, checksum(&data));
+    }
+
+    #[test]
+    fn edits_ipv4_tcp() {
+        let mut buf = tcp_v4();
+        let new_src = Ipv4Address::new(203, 0, 113, 7);
+        let new_dst = Ipv4Address::new(198, 51, 100, 99);
+        let mut packet = PacketEditor::new(&mut buf).unwrap();
+        assert_eq!(packet.protocol(), IpProtocol::Tcp);
+        assert!(packet.set_src_addr(new_src.into()));
+        assert!(packet.set_dst_addr(new_dst.into()));
+        assert!(packet.set_src_port(12345));
+        assert!(packet.set_dst_port(8080));
+        packet.set_hop_limit(packet.hop_limit() - 1);
+        assert!(!packet.set_src_addr(SRC_V6.into()));
+
+        let ip = Ipv4Packet::new_checked(&buf[..]).unwrap();
+        assert!(ip.verify_checksum());
+        assert_eq!(ip.src_addr(), new_src);
+        assert_eq!(ip.dst_addr(), new_dst);
+        assert_eq!(ip.hop_limit(), 63);
+        let tcp = TcpPacket::new_checked(ip.payload()).unwrap();
+        assert!(tcp.verify_checksum(&new_src.into(), &new_dst.into()));
+        assert_eq!((tcp.src_port(), tcp.dst_port()), (12345, 8080));
+    }
+
+    #[test]
+    fn edits_ipv6_udp() {
+        let mut buf = udp_v6(true);
+        let mut packet = PacketEditor::new(&mut buf).unwrap();
+        assert!(packet.set_dst_addr(SRC_V6.into()));
+        assert!(packet.set_src_addr(DST_V6.into()));
+        assert!(packet.set_dst_port(5300));
+        packet.set_hop_limit(1);
+
+        let ip = Ipv6Packet::new_checked(&buf[..]).unwrap();
+        assert_eq!(ip.hop_limit(), 1);
+        let udp = UdpPacket::new_checked(ip.payload()).unwrap();
+        assert!(udp.verify_checksum(&DST_V6.into(), &SRC_V6.into()));
+        assert_eq!(udp.dst_port(), 5300);
+    }
+
+    #[test]
+    fn edits_odd_l4_offsets() {
+        let mut buf = tcp_v4();
+        let mut packet = PacketEditor::new(&mut buf).unwrap();
+        for (offset, value) in [(21, 0xabcd), (5, 0xff00), (1, 0x00ff)] {
+            assert!(packet.set_l4_word(offset, value));
+        }
+        assert!(!packet.set_l4_word(23, 0x1234));
+        let tcp = TcpPacket::new_checked(&buf[20..]).unwrap();
+        assert_eq!(tcp.payload(), &[b'd', 0xab, 0xcd, b'a']);
+        assert!(tcp.verify_checksum(&SRC_V4.into(), &DST_V4.into()));
+
+        let mut buf = udp_v6(true);
+        let mut packet = PacketEditor::new(&mut buf).unwrap();
+        assert!(packet.set_l4_word(9, 0x1234));
+        assert!(!packet.set_l4_word(12, 0x1234));
+        let udp = UdpPacket::new_checked(&buf[40..]).unwrap();
+        assert_eq!(udp.payload(), b"q\x124ry");
+        assert!(udp.verify_checksum(&SRC_V6.into(), &DST_V6.into()));
+    }
+
+    #[test]
+    fn keeps_missing_udp_checksum() {
+        let mut buf = udp_v6(false);
+        let mut packet = PacketEditor::new(&mut buf).unwrap();
+        assert!(packet.set_src_port(1));
+        let udp = UdpPacket::new_checked(&buf[40..]).unwrap();
+        assert_eq!(udp.checksum(), 0);
+    }
+
+    #[test]
+    fn skips_l4_of_fragments() {
+        let mut buf = tcp_v4();
+        let mut ip = Ipv4Packet::new_unchecked(&mut buf[..]);
+        ip.set_frag_offset(8);
+        ip.fill_checksum();
+        let mut packet = PacketEditor::new(&mut buf).unwrap();
+        assert!(!packet.set_dst_port(8080));
+        assert!(packet.set_dst_addr(SRC_V4.into()));
+        assert!(Ipv4Packet::new_checked(&buf[..]).unwrap().verify_checksum());
+    }
+
+    #[test]
+    fn rejects_non_ip() {
+        assert!(PacketEditor::new(&mut [0x55; 40]).is_none());
+        assert!(PacketEditor::new(&mut [0x45; 10]).is_none());
+    }
+}
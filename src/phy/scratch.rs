@@ -0,0 +1,257 @@
+/// A pool of reusable frame buffers.
+///
+/// Wrapper devices that prepend, strip or rewrite headers take a [`FrameBuf`] from the
+/// pool for every packet and give it back once the frame has been handed to the lower
+/// device, so the steady state does not allocate.
+pub struct FrameScratch {
+    free: Vec<Vec<u8>>,
+    max_buffers: usize,
+    size: usize,
+    headroom: usize,
+}
+
+impl FrameScratch {
+    /// Creates a pool retaining up to `max_buffers` buffers able to hold `size` bytes
+    /// of frame data after `headroom` bytes reserved for prepending headers.
+    pub fn new(max_buffers: usize, size: usize, headroom: usize) -> Self {
+        Self {
+            free: Vec::with_capacity(max_buffers),
+            max_buffers,
+            size,
+            headroom,
+        }
+    }
+
+    /// Takes an empty buffer from the pool, allocating a new one if the pool is empty.
+    pub fn take(&mut self) -> FrameBuf {
+        let buf = self
+            .free
+            .pop()
+            .unwrap_or_else(|| vec![0; self.headroom + self.size]);
+        FrameBuf {
+            buf,
+            head: self.headroom,
+            tail: self.headroom,
+        }
+    }
+
+    /// Returns a buffer to the pool. Buffers beyond `max_buffers` are dropped.
+    pub fn give(&mut self, frame: FrameBuf) {
+        if self.free.len() < self.max_buffers {
+            self.free.push(frame.buf);
+        }
+    }
+
+    /// Number of buffers currently available without allocating.
+    pub fn available(&self) -> usize {
+        self.free.len()
+    }
+}
+
+/// A frame buffer with reserved headroom, in the spirit of the kernel `sk_buff`.
+///
+/// The frame occupies `head..tail` of the underlying storage. Headers can be pushed in
+/// front of it or pulled from it without moving the payload as long as the headroom
+/// allows, and the tail can be extended or trimmed in place.
+pub struct FrameBuf {
+    buf: Vec<u8>,
+    head: usize,
+    tail: usize,
+}
+
+impl FrameBuf {
+    /// Length of the frame.
+    pub fn len(&self) -> usize {
+        self.tail - self.head
+    }
+
+    /// Returns `true` if the frame is empty.
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// Bytes that can still be pushed in front of the frame without moving it.
+    pub fn headroom(&self) -> usize {
+        self.head
+    }
+
+    /// Extends the frame by `len` bytes at the end and returns the new zeroed region.
+    pub fn put(&mut self, len: usize) -> &mut [u8] {
+        let tail = self.tail + len;
+        if tail > self.buf.len() {
+            self.buf.resize(tail, 0);
+        }
+        let region = &mut self.buf[self.tail..tail];
+        region.fill(0);
+        self.tail = tail;
+        region
+    }
+
+    /// Appends `bytes` at the end of the frame.
+    pub fn put_slice(&mut self, bytes: &[u8]) {
+        let tail = self.tail + bytes.len();
+        if tail > self.buf.len() {
+            self.buf.resize(tail, 0);
+        }
+        self.buf[self.tail..tail].copy_from_slice(bytes);
+        self.tail = tail;
+    }
+
+    /// Shortens the frame to `len` bytes.
+    pub fn trim(&mut self, len: usize) {
+        self.tail = self.head + len.min(self.len());
+    }
+
+    /// Extends the frame by `len` bytes at the front and returns the new region.
+    ///
+    /// The payload is shifted only if the headroom is exhausted.
+    pub fn push(&mut self, len: usize) -> &mut [u8] {
+        if len > self.head {
+            let shift = len - self.head;
+            self.buf.resize(self.buf.len().max(self.tail + shift), 0);
+            self.buf
+                .copy_within(self.head..self.tail, self.head + shift);
+            self.head += shift;
+            self.tail += shift;
+        }
+        self.head -= len;
+        &mut self.buf[self.head..self.head + len]
+    }
+
+    /// Removes `len` bytes from the front of the frame and returns them.
+    pub fn pull(&mut self, len: usize) -> &[u8] {
+        let len = len.min(self.len());
+        let old = self.head;
+        self.head += len;
+        &self.buf[old..self.head]
+    }
+
+    /// Inserts `bytes` at `offset` inside the frame, moving the bytes before `offset`
+    /// into the headroom (e.g. an 802.1Q tag after the Ethernet addresses).
+    pub fn insert(&mut self, offset: usize, bytes: &[u8]) {
+        let offset = offset.min(self.len());
+        self.push(bytes.len());
+        let head = self.head;
+        self.buf
+            .copy_within(head + bytes.len()..head + bytes.len() + offset, head);
+        self.buf[head + offset..head + offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Removes `len` bytes at `offset` inside the frame, moving the bytes before `offset`
+    /// towards the payload.
+    pub fn remove(&mut self, offset: usize, len: usize) {
+        let offset = offset.min(self.len());
+        let len = len.min(self.len() - offset);
+        let head = self.head;
+        self.buf.copy_within(head..head + offset, head + len);
+        self.head += len;
+    }
+}
+
+impl AsRef<[u8]> for FrameBuf {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf[self.head..self.tail]
+    }
+}
+
+impl AsMut<[u8]> for FrameBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[self.head..self.tail]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(headroom: usize, bytes: &[u8]) -> FrameBuf {
+        let mut frame = FrameScratch::new(1, 16, headroom).take();
+        frame.put_slice(bytes);
+        frame
+    }
+
+    #[test]
+    fn put_and_trim() {
+        let mut frame = frame(4, &[1, 2]);
+        assert_eq!(frame.put(2), &[0, 0]);
+        frame.put_slice(&[3; 16]);
+        assert_eq!(frame.len(), 20);
+        assert_eq!(&frame.as_ref()[..4], &[1, 2, 0, 0]);
+        frame.trim(3);
+        assert_eq!(frame.as_ref(), &[1, 2, 0]);
+        frame.trim(10);
+        assert_eq!(frame.len(), 3);
+        assert_eq!(frame.put(1), &[0]);
+    }
+
+    #[test]
+    fn push_within_headroom() {
+        let mut frame = frame(4, &[3, 4]);
+        frame.push(2).copy_from_slice(&[1, 2]);
+        assert_eq!(frame.as_ref(), &[1, 2, 3, 4]);
+        assert_eq!(frame.headroom(), 2);
+    }
+
+    #[test]
+    fn push_beyond_headroom() {
+        let mut frame = frame(2, &[4, 5]);
+        frame.push(3).copy_from_slice(&[1, 2, 3]);
+        assert_eq!(frame.as_ref(), &[1, 2, 3, 4, 5]);
+        assert_eq!(frame.headroom(), 0);
+        frame.push(1)[0] = 0;
+        assert_eq!(frame.as_ref(), &[0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn pull_from_front() {
+        let mut frame = frame(0, &[1, 2, 3]);
+        assert_eq!(frame.pull(2), &[1, 2]);
+        assert_eq!(frame.as_ref(), &[3]);
+        assert_eq!(frame.headroom(), 2);
+        assert_eq!(frame.pull(5), &[3]);
+        assert!(frame.is_empty());
+    }
+
+    #[test]
+    fn insert_and_remove_tag() {
+        let untagged = [1, 2, 3, 4, 5, 6];
+        let mut frame = frame(2, &untagged);
+        frame.insert(2, &[0x81, 0x00, 0, 7]);
+        assert_eq!(frame.as_ref(), &[1, 2, 0x81, 0x00, 0, 7, 3, 4, 5, 6]);
+        frame.remove(2, 4);
+        assert_eq!(frame.as_ref(), &untagged);
+        // The tag did not fit the headroom, so the payload moved back by 2.
+        assert_eq!(frame.headroom(), 4);
+    }
+
+    #[test]
+    fn insert_and_remove_at_the_edges() {
+        let mut frame = frame(8, &[2, 3]);
+        frame.insert(0, &[1]);
+        frame.insert(3, &[4]);
+        frame.insert(10, &[5]);
+        assert_eq!(frame.as_ref(), &[1, 2, 3, 4, 5]);
+        frame.remove(4, 3);
+        assert_eq!(frame.as_ref(), &[1, 2, 3, 4]);
+        frame.remove(0, 1);
+        assert_eq!(frame.as_ref(), &[2, 3, 4]);
+        frame.remove(7, 1);
+        assert_eq!(frame.as_ref(), &[2, 3, 4]);
+    }
+
+    #[test]
+    fn pool_reuses_buffers() {
+        let mut pool = FrameScratch::new(1, 4, 2);
+        let mut first = pool.take();
+        first.push(2);
+        first.put_slice(&[1; 4]);
+        let second = pool.take();
+        pool.give(first);
+        pool.give(second);
+        assert_eq!(pool.available(), 1);
+        let frame = pool.take();
+        assert!(frame.is_empty());
+        assert_eq!(frame.headroom(), 2);
+        assert_eq!(pool.available(), 0);
+    }
+}
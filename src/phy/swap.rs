@@ -0,0 +1,188 @@
+use smoltcp::phy::{self, Device, DeviceCapabilities};
+use smoltcp::time::Instant;
+
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+use crate::phy::health::{HealthCheck, Report};
+
+/// A device wrapper whose inner device can be replaced at runtime.
+///
+/// Swapping happens between two polls, so the smoltcp `Interface` and any wrapper
+/// stacked above keep their state (sockets, neighbor cache, counters). Combine it with
+/// [`Either`] to fail over to a device of a different type, e.g. from an XDP socket to
+/// an AF_PACKET `RawSocket`.
+pub struct Swap<D: Device> {
+    inner: D,
+    swaps: u64,
+}
+
+impl<D: Device> Swap<D> {
+    pub fn new(inner: D) -> Self {
+        Self { inner, swaps: 0 }
+    }
+
+    /// Replaces the inner device, returning the previous one.
+    ///
+    /// The replacement must use the same medium, since the `Interface` was configured
+    /// for it; otherwise it is handed back as `Err`.
+    pub fn swap(&mut self, new: D) -> Result<D, D> {
+        if new.capabilities().medium != self.inner.capabilities().medium {
+            return Err(new);
+        }
+
+        self.swaps += 1;
+        Ok(core::mem::replace(&mut self.inner, new))
+    }
+
+    /// Number of successful swaps.
+    pub fn swaps(&self) -> u64 {
+        self.swaps
+    }
+
+    /// Get a reference to the underlying device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Return the underlying device, consuming the wrapper.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Device> Device for Swap<D> {
+    type RxToken<'a>
+        = D::RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.inner.receive(timestamp)
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.inner.transmit(timestamp)
+    }
+}
+
+impl<D: Device + HealthCheck> HealthCheck for Swap<D> {
+    fn check(&self, report: &mut Report) {
+        self.inner.check(report);
+    }
+}
+
+impl<D: CapabilitiesAudit> CapabilitiesAudit for Swap<D> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        self.inner.audit(report);
+    }
+}
+
+/// One of two devices of different types.
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+impl<A: Device, B: Device> Device for Either<A, B> {
+    type RxToken<'a>
+        = Either<A::RxToken<'a>, B::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = Either<A::TxToken<'a>, B::TxToken<'a>>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        match self {
+            Self::Left(a) => a.capabilities(),
+            Self::Right(b) => b.capabilities(),
+        }
+    }
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        match self {
+            Self::Left(a) => a
+                .receive(timestamp)
+                .map(|(rx, tx)| (Either::Left(rx), Either::Left(tx))),
+            Self::Right(b) => b
+                .receive(timestamp)
+                .map(|(rx, tx)| (Either::Right(rx), Either::Right(tx))),
+        }
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        match self {
+            Self::Left(a) => a.transmit(timestamp).map(Either::Left),
+            Self::Right(b) => b.transmit(timestamp).map(Either::Right),
+        }
+    }
+}
+
+impl<A: phy::RxToken, B: phy::RxToken> phy::RxToken for Either<A, B> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        match self {
+            Self::Left(a) => a.consume(f),
+            Self::Right(b) => b.consume(f),
+        }
+    }
+
+    fn meta(&self) -> phy::PacketMeta {
+        match self {
+            Self::Left(a) => a.meta(),
+            Self::Right(b) => b.meta(),
+        }
+    }
+}
+
+impl<A: phy::TxToken, B: phy::TxToken> phy::TxToken for Either<A, B> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        match self {
+            Self::Left(a) => a.consume(len, f),
+            Self::Right(b) => b.consume(len, f),
+        }
+    }
+
+    fn set_meta(&mut self, meta: phy::PacketMeta) {
+        match self {
+            Self::Left(a) => a.set_meta(meta),
+            Self::Right(b) => b.set_meta(meta),
+        }
+    }
+}
+
+impl<A: HealthCheck, B: HealthCheck> HealthCheck for Either<A, B> {
+    fn check(&self, report: &mut Report) {
+        match self {
+            Self::Left(a) => a.check(report),
+            Self::Right(b) => b.check(report),
+        }
+    }
+}
+
+impl<A: CapabilitiesAudit, B: CapabilitiesAudit> CapabilitiesAudit for Either<A, B> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        match self {
+            Self::Left(a) => a.audit(report),
+            Self::Right(b) => b.audit(report),
+        }
+    }
+}
@@ -1,2 +1,18 @@
 #[cfg(all(feature = "phy-xdp", unix))]
+pub mod bpf;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub mod ethtool;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub mod fanout;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub mod fdpass;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub mod monitor;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub mod multicast;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub mod netlink;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub mod poller;
+#[cfg(all(feature = "phy-xdp", unix))]
 pub mod xdp;
@@ -0,0 +1,347 @@
+use std::ffi::CString;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::{io, mem};
+
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_MAP_DELETE_ELEM: libc::c_long = 3;
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_OBJ_GET: libc::c_long = 7;
+const BPF_PROG_TEST_RUN: libc::c_long = 10;
+const BPF_LINK_CREATE: libc::c_long = 28;
+#[cfg(feature = "libbpf")]
+const BPF_LINK_UPDATE: libc::c_long = 29;
+
+const BPF_F_TEST_XDP_LIVE_FRAMES: u32 = 1 << 1;
+#[cfg(feature = "libbpf")]
+const BPF_F_REPLACE: u32 = 1 << 2;
+
+#[repr(C)]
+#[derive(Default)]
+struct MapElemAttr {
+    map_fd: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+pub const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+pub const BPF_PROG_TYPE_XDP: u32 = 6;
+pub const BPF_XDP: u32 = 37;
+
+/// Room for the verifier log of a rejected program.
+const LOG_SIZE: usize = 64 * 1024;
+
+#[repr(C)]
+#[derive(Default)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+    inner_map_fd: u32,
+    numa_node: u32,
+    map_name: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+}
+
+/// A single eBPF instruction, `struct bpf_insn`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Insn {
+    pub code: u8,
+    /// Destination register in the low nibble, source register in the high one.
+    pub regs: u8,
+    pub off: i16,
+    pub imm: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct LinkCreateAttr {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+#[cfg(feature = "libbpf")]
+#[repr(C)]
+#[derive(Default)]
+struct LinkUpdateAttr {
+    link_fd: u32,
+    new_prog_fd: u32,
+    flags: u32,
+    old_prog_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct TestRunAttr {
+    prog_fd: u32,
+    retval: u32,
+    data_size_in: u32,
+    data_size_out: u32,
+    data_in: u64,
+    data_out: u64,
+    repeat: u32,
+    duration: u32,
+    ctx_size_in: u32,
+    ctx_size_out: u32,
+    ctx_in: u64,
+    ctx_out: u64,
+    flags: u32,
+    cpu: u32,
+    batch_size: u32,
+}
+
+/// Mirror of the kernel `struct xdp_md` used as BPF_PROG_TEST_RUN context.
+#[repr(C)]
+#[derive(Default)]
+pub struct XdpMd {
+    pub data: u32,
+    pub data_end: u32,
+    pub data_meta: u32,
+    pub ingress_ifindex: u32,
+    pub rx_queue_index: u32,
+    pub egress_ifindex: u32,
+}
+
+pub struct TestRunOutput {
+    pub retval: u32,
+    pub data: Vec<u8>,
+    pub duration_ns: u32,
+}
+
+unsafe fn bpf<T>(cmd: libc::c_long, attr: &mut T) -> io::Result<libc::c_long> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T as *mut libc::c_void,
+            mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(res)
+}
+
+/// Kernel object names are truncated to 15 bytes and NUL-terminated.
+fn object_name(name: &str) -> [u8; 16] {
+    let mut buf = [0u8; 16];
+    let len = name.len().min(buf.len() - 1);
+    buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+    buf
+}
+
+/// Creates a map of `map_type` with `max_entries` entries.
+pub fn map_create(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    name: &str,
+) -> io::Result<OwnedFd> {
+    let mut attr = MapCreateAttr {
+        map_type,
+        key_size,
+        value_size,
+        max_entries,
+        map_name: object_name(name),
+        ..Default::default()
+    };
+
+    // SAFETY: attr matches the BPF_MAP_CREATE layout.
+    let fd = unsafe { bpf(BPF_MAP_CREATE, &mut attr)? };
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Loads the program `insns` of `prog_type`. A program rejected by the verifier is
+/// loaded again with logging on, and the error carries the verifier log.
+pub fn prog_load(prog_type: u32, insns: &[Insn], license: &str, name: &str) -> io::Result<OwnedFd> {
+    let license = CString::new(license)?;
+    let mut attr = ProgLoadAttr {
+        prog_type,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        prog_name: object_name(name),
+        ..Default::default()
+    };
+
+    // SAFETY: attr matches the BPF_PROG_LOAD layout, the instructions and the license
+    // outlive the call.
+    let err = match unsafe { bpf(BPF_PROG_LOAD, &mut attr) } {
+        Ok(fd) => return Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }),
+        Err(err) => err,
+    };
+    if err.raw_os_error() != Some(libc::EACCES) && err.raw_os_error() != Some(libc::EINVAL) {
+        return Err(err);
+    }
+
+    let mut log = vec![0u8; LOG_SIZE];
+    attr.log_level = 1;
+    attr.log_size = log.len() as u32;
+    attr.log_buf = log.as_mut_ptr() as u64;
+    // SAFETY: as above, the log buffer outlives the call too.
+    match unsafe { bpf(BPF_PROG_LOAD, &mut attr) } {
+        Ok(fd) => Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }),
+        Err(_) => {
+            let len = log.iter().position(|&b| b == 0).unwrap_or(log.len());
+            Err(io::Error::new(
+                err.kind(),
+                format!(
+                    "The verifier rejected the program ({}): {}",
+                    err,
+                    String::from_utf8_lossy(&log[..len]).trim_end()
+                ),
+            ))
+        }
+    }
+}
+
+/// Opens a BPF object (program or map) pinned in bpffs.
+pub fn obj_get(path: &str) -> io::Result<OwnedFd> {
+    let path = CString::new(path)?;
+    let mut attr = ObjGetAttr {
+        pathname: path.as_ptr() as u64,
+        ..Default::default()
+    };
+
+    // SAFETY: attr matches the BPF_OBJ_GET layout and the path outlives the call.
+    let fd = unsafe { bpf(BPF_OBJ_GET, &mut attr)? };
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Attaches the program to the interface `ifindex` through a BPF link, detached once the
+/// last fd of the link is closed. Kernels before 5.9 fail with `EINVAL`.
+pub fn link_create(
+    prog_fd: RawFd,
+    ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+) -> io::Result<OwnedFd> {
+    let mut attr = LinkCreateAttr {
+        prog_fd: prog_fd as u32,
+        target_ifindex: ifindex,
+        attach_type,
+        flags,
+    };
+
+    // SAFETY: attr matches the BPF_LINK_CREATE layout.
+    let fd = unsafe { bpf(BPF_LINK_CREATE, &mut attr)? };
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Atomically replaces the program of the link with `new_prog_fd`, failing with `EPERM`
+/// if the link no longer runs `old_prog_fd`.
+#[cfg(feature = "libbpf")]
+pub fn link_update(link_fd: RawFd, new_prog_fd: RawFd, old_prog_fd: RawFd) -> io::Result<()> {
+    let mut attr = LinkUpdateAttr {
+        link_fd: link_fd as u32,
+        new_prog_fd: new_prog_fd as u32,
+        flags: BPF_F_REPLACE,
+        old_prog_fd: old_prog_fd as u32,
+    };
+
+    // SAFETY: attr matches the BPF_LINK_UPDATE layout.
+    unsafe { bpf(BPF_LINK_UPDATE, &mut attr)? };
+    Ok(())
+}
+
+/// Inserts or replaces the entry `key` of the map.
+pub fn map_update_elem<K, V>(map_fd: RawFd, key: &K, value: &V) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: map_fd as u32,
+        key: key as *const K as u64,
+        value: value as *const V as u64,
+        flags: 0,
+    };
+
+    // SAFETY: attr matches the BPF_MAP_*_ELEM layout and key/value outlive the call.
+    unsafe { bpf(BPF_MAP_UPDATE_ELEM, &mut attr)? };
+    Ok(())
+}
+
+pub fn map_delete_elem<K>(map_fd: RawFd, key: &K) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: map_fd as u32,
+        key: key as *const K as u64,
+        ..Default::default()
+    };
+
+    // SAFETY: attr matches the BPF_MAP_*_ELEM layout and key outlives the call.
+    unsafe { bpf(BPF_MAP_DELETE_ELEM, &mut attr)? };
+    Ok(())
+}
+
+/// Runs the program once over `data` through BPF_PROG_TEST_RUN.
+pub fn prog_test_run(prog_fd: RawFd, data: &[u8], ctx: &XdpMd) -> io::Result<TestRunOutput> {
+    // XDP programs may grow the frame up to the page it lives in.
+    let mut data_out = vec![0u8; data.len().max(4096)];
+    let mut attr = TestRunAttr {
+        prog_fd: prog_fd as u32,
+        data_size_in: data.len() as u32,
+        data_size_out: data_out.len() as u32,
+        data_in: data.as_ptr() as u64,
+        data_out: data_out.as_mut_ptr() as u64,
+        repeat: 1,
+        ctx_size_in: mem::size_of::<XdpMd>() as u32,
+        ctx_in: ctx as *const XdpMd as u64,
+        ..Default::default()
+    };
+
+    // SAFETY: attr matches the BPF_PROG_TEST_RUN layout and all buffers outlive the call.
+    unsafe { bpf(BPF_PROG_TEST_RUN, &mut attr)? };
+
+    data_out.truncate(attr.data_size_out as usize);
+    Ok(TestRunOutput {
+        retval: attr.retval,
+        data: data_out,
+        duration_ns: attr.duration,
+    })
+}
+
+/// Runs the program once over `data` in live frames mode (Linux 5.18+): redirects and
+/// transmissions are carried out, as for a frame received on the `ctx` queue.
+pub fn prog_test_run_live(prog_fd: RawFd, data: &[u8], ctx: &XdpMd) -> io::Result<()> {
+    let mut attr = TestRunAttr {
+        prog_fd: prog_fd as u32,
+        data_size_in: data.len() as u32,
+        data_in: data.as_ptr() as u64,
+        repeat: 1,
+        ctx_size_in: mem::size_of::<XdpMd>() as u32,
+        ctx_in: ctx as *const XdpMd as u64,
+        flags: BPF_F_TEST_XDP_LIVE_FRAMES,
+        ..Default::default()
+    };
+
+    // SAFETY: attr matches the BPF_PROG_TEST_RUN layout and all buffers outlive the call.
+    unsafe { bpf(BPF_PROG_TEST_RUN, &mut attr)? };
+    Ok(())
+}
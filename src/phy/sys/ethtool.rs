@@ -0,0 +1,150 @@
+use std::ffi::{CStr, CString};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::{fmt, io, mem};
+
+const ETHTOOL_GDRVINFO: u32 = 0x03;
+const ETHTOOL_GRINGPARAM: u32 = 0x10;
+const ETHTOOL_GRXFHINDIR: u32 = 0x38;
+const ETHTOOL_SRXFHINDIR: u32 = 0x39;
+
+/// Socket used to issue `SIOCETHTOOL` requests for the interface called `name`.
+struct Ethtool {
+    fd: OwnedFd,
+    ifr: libc::ifreq,
+}
+
+impl Ethtool {
+    fn new(name: &str) -> io::Result<Self> {
+        let ifname = CString::new(name)?;
+        let fd = unsafe {
+            let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+
+        let mut ifr: libc::ifreq = unsafe { mem::zeroed() };
+        unsafe { libc::strncpy(ifr.ifr_name.as_mut_ptr(), ifname.as_ptr(), libc::IFNAMSIZ) };
+        Ok(Self { fd, ifr })
+    }
+
+    /// Issues the ethtool command whose struct starts at `data`.
+    fn request(&mut self, data: *mut u32) -> io::Result<()> {
+        self.ifr.ifr_ifru.ifru_data = data as *mut libc::c_char;
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), libc::SIOCETHTOOL, &mut self.ifr) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Spreads the RSS indirection table of the interface called `name` evenly over
+/// `queues`, so that flows hash to every one of them.
+pub fn set_rss_queues(name: &str, queues: &[u32]) -> io::Result<()> {
+    if queues.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "At least one queue is required",
+        ));
+    }
+
+    let mut ethtool = Ethtool::new(name)?;
+
+    // struct ethtool_rxfh_indir { cmd, size, ring_index[size] }, queried with size 0 for
+    // the table size.
+    let mut header = [ETHTOOL_GRXFHINDIR, 0];
+    ethtool.request(header.as_mut_ptr())?;
+    let size = header[1] as usize;
+    if size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} has no RSS indirection table", name),
+        ));
+    }
+
+    let mut table = Vec::with_capacity(2 + size);
+    table.extend([ETHTOOL_SRXFHINDIR, size as u32]);
+    table.extend((0..size).map(|i| queues[i % queues.len()]));
+    ethtool.request(table.as_mut_ptr())
+}
+
+/// `struct ethtool_drvinfo`.
+#[repr(C)]
+struct DrvInfo {
+    cmd: u32,
+    driver: [u8; 32],
+    version: [u8; 32],
+    fw_version: [u8; 32],
+    bus_info: [u8; 32],
+    erom_version: [u8; 32],
+    reserved2: [u8; 12],
+    n_priv_flags: u32,
+    n_stats: u32,
+    testinfo_len: u32,
+    eedump_len: u32,
+    regdump_len: u32,
+}
+
+/// Driver of a network interface, as reported by `ethtool -i`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DriverInfo {
+    /// Driver name, e.g. `ixgbe` or `veth`.
+    pub driver: String,
+    pub version: String,
+    /// Firmware version, empty for devices without firmware.
+    pub firmware: String,
+    /// Bus address of the device, e.g. `0000:01:00.0`, empty for virtual devices.
+    pub bus_info: String,
+}
+
+impl fmt::Display for DriverInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "driver {}", self.driver)?;
+        if !self.version.is_empty() {
+            write!(f, " {}", self.version)?;
+        }
+        if !self.firmware.is_empty() {
+            write!(f, ", firmware {}", self.firmware)?;
+        }
+        if !self.bus_info.is_empty() {
+            write!(f, ", bus {}", self.bus_info)?;
+        }
+        Ok(())
+    }
+}
+
+/// Driver of the interface called `name`.
+pub fn driver_info(name: &str) -> io::Result<DriverInfo> {
+    let mut ethtool = Ethtool::new(name)?;
+
+    // SAFETY: ethtool_drvinfo is valid when zeroed.
+    let mut info: DrvInfo = unsafe { mem::zeroed() };
+    info.cmd = ETHTOOL_GDRVINFO;
+    ethtool.request(&mut info as *mut DrvInfo as *mut u32)?;
+
+    let string = |bytes: &[u8]| {
+        CStr::from_bytes_until_nul(bytes)
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned())
+    };
+    Ok(DriverInfo {
+        driver: string(&info.driver),
+        version: string(&info.version),
+        firmware: string(&info.fw_version),
+        bus_info: string(&info.bus_info),
+    })
+}
+
+/// Current RX and TX descriptor ring sizes of the interface called `name`.
+pub fn ring_sizes(name: &str) -> io::Result<(u32, u32)> {
+    let mut ethtool = Ethtool::new(name)?;
+
+    // struct ethtool_ringparam { cmd, rx_max_pending, rx_mini_max_pending,
+    // rx_jumbo_max_pending, tx_max_pending, rx_pending, rx_mini_pending,
+    // rx_jumbo_pending, tx_pending }
+    let mut params = [0u32; 9];
+    params[0] = ETHTOOL_GRINGPARAM;
+    ethtool.request(params.as_mut_ptr())?;
+    Ok((params[5], params[8]))
+}
@@ -0,0 +1,507 @@
+//! Fanning the frames of a device out to other processes, e.g. a capture tool running
+//! next to the application on the same queue.
+//!
+//! Only one AF_XDP socket owns a queue, so instead of binding one of their own,
+//! subscriber processes get a copy of every frame the [`FanOut`] wrapper receives, and
+//! optionally sends. Each subscriber has its own single-producer single-consumer ring in
+//! a sealed memfd, handed over with [`crate::phy::fdpass::send_fds`] and mapped with
+//! [`Subscriber::open`]. A subscriber falling behind never slows the application down:
+//! frames that do not fit in its ring are dropped and counted.
+//!
+//! ```ignore
+//! // In the application.
+//! let mut device = FanOut::new(socket);
+//! let ring = device.subscribe(1 << 20)?;
+//! fdpass::send_fds(&stream, &[ring.as_raw_fd()])?;
+//!
+//! // In the subscriber.
+//! let mut subscriber = Subscriber::open(fdpass::recv_fds(&stream, 1)?.remove(0))?;
+//! let mut frame = Vec::new();
+//! while let Some((timestamp, direction)) = subscriber.pop_into(&mut frame) {
+//!     write_pcap_record(timestamp, &frame);
+//! }
+//! ```
+
+use std::cell::Cell;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::{io, mem};
+
+use smoltcp::phy::{self, Device, DeviceCapabilities};
+use smoltcp::time::Instant;
+
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+
+/// Identifies the ring layout and its version. Bump it on any layout change.
+const MAGIC: u32 = 0x534d_4601;
+/// Length field of the marker sending the consumer back to the start of the ring.
+const WRAP: u32 = u32::MAX;
+/// Length, direction and timestamp in front of every frame.
+const RECORD_HEADER_LEN: usize = 16;
+/// Records start on 8-byte boundaries so that their timestamps are aligned.
+const RECORD_ALIGN: usize = 8;
+
+/// Which way a fanned out frame went through the device.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+#[repr(C, align(64))]
+struct Padded<T>(T);
+
+/// Shared state at the start of the memfd, followed by the data area.
+#[repr(C)]
+struct Header {
+    magic: u32,
+    // Bytes of the data area.
+    capacity: u32,
+    // Set by the subscriber once it stops reading.
+    closed: AtomicU32,
+    // Frames the producer had no room for.
+    dropped: AtomicU64,
+    // Written by the producer only, bytes written since the ring was created.
+    head: Padded<AtomicU64>,
+    // Written by the subscriber only, bytes read since the ring was created.
+    tail: Padded<AtomicU64>,
+}
+
+const HEADER_LEN: usize = mem::size_of::<Header>();
+
+/// A shared mapping of a ring memfd, unmapped on drop.
+struct Mapping {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping of the fd, owned by the returned value.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr as *mut u8).expect("mmap does not return null on success"),
+            len,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        // SAFETY: the mapping is at least HEADER_LEN bytes long and page aligned, and
+        // the other process only touches the header through its atomics.
+        unsafe { &*(self.ptr.as_ptr() as *const Header) }
+    }
+
+    fn capacity(&self) -> usize {
+        self.header().capacity as usize
+    }
+
+    /// The data area starting at `offset`, `len` bytes of it.
+    fn data(&self, offset: usize, len: usize) -> *mut u8 {
+        debug_assert!(offset + len <= self.capacity());
+        // SAFETY: within the data area, checked against the mapping length on open.
+        unsafe { self.ptr.as_ptr().add(HEADER_LEN + offset) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: unmapped once, nothing borrows it past `self`.
+        unsafe { libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, self.len) };
+    }
+}
+
+fn record_len(frame_len: usize) -> usize {
+    (RECORD_HEADER_LEN + frame_len).next_multiple_of(RECORD_ALIGN)
+}
+
+/// The producing end of a subscriber ring.
+struct Publisher {
+    mapping: Mapping,
+}
+
+impl Publisher {
+    /// Creates a ring with room for `capacity` bytes of records and returns it along with
+    /// the memfd to hand to the subscriber.
+    fn create(capacity: usize) -> io::Result<(Self, OwnedFd)> {
+        let capacity = capacity.next_multiple_of(RECORD_ALIGN);
+        if capacity < record_len(0) || capacity > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid fan-out ring capacity {}", capacity),
+            ));
+        }
+
+        let name = CString::new("smoltcp-fanout").expect("Name has no NUL");
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the fd was just created and is owned by nothing else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let len = HEADER_LEN + capacity;
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // A subscriber shrinking the memfd would crash the application with SIGBUS.
+        let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mapping = Mapping::new(&fd, len)?;
+        // SAFETY: the memfd is zeroed and nobody else maps it yet, the atomics start at 0.
+        unsafe {
+            let header = mapping.ptr.as_ptr() as *mut Header;
+            (*header).magic = MAGIC;
+            (*header).capacity = capacity as u32;
+        }
+        Ok((Self { mapping }, fd))
+    }
+
+    fn closed(&self) -> bool {
+        self.mapping.header().closed.load(Ordering::Relaxed) != 0
+    }
+
+    /// Copies `frame` into the ring, returning `false` if it has no room for it.
+    fn push(&self, timestamp: Instant, direction: Direction, frame: &[u8]) -> bool {
+        let header = self.mapping.header();
+        let capacity = self.mapping.capacity();
+        let record = record_len(frame.len());
+
+        let head = header.head.0.load(Ordering::Relaxed);
+        let tail = header.tail.0.load(Ordering::Acquire);
+        let offset = head as usize % capacity;
+        // Records never wrap, the end of the data area is skipped when too short.
+        let skip = if capacity - offset < record {
+            capacity - offset
+        } else {
+            0
+        };
+        // A subscriber moving its tail past the head only gets its frames dropped.
+        let free = (capacity as u64).saturating_sub(head.wrapping_sub(tail));
+        if (skip + record) as u64 > free {
+            header.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        // SAFETY: the bytes between head and tail plus capacity belong to the producer
+        // until head is published below, and every write stays within the data area.
+        unsafe {
+            if skip > 0 {
+                ptr::write_unaligned(self.mapping.data(offset, 4) as *mut u32, WRAP);
+            }
+            let at = self.mapping.data((offset + skip) % capacity, record);
+            let direction = match direction {
+                Direction::Received => 0u32,
+                Direction::Sent => 1,
+            };
+            ptr::write_unaligned(at as *mut u32, frame.len() as u32);
+            ptr::write_unaligned(at.add(4) as *mut u32, direction);
+            ptr::write_unaligned(at.add(8) as *mut i64, timestamp.total_micros());
+            ptr::copy_nonoverlapping(frame.as_ptr(), at.add(RECORD_HEADER_LEN), frame.len());
+        }
+        header
+            .head
+            .0
+            .store(head + (skip + record) as u64, Ordering::Release);
+        true
+    }
+}
+
+/// The consuming end of a ring, in the subscriber process.
+pub struct Subscriber {
+    mapping: Mapping,
+}
+
+impl Subscriber {
+    /// Maps the ring `fd` created by [`FanOut::subscribe`].
+    pub fn open(fd: OwnedFd) -> io::Result<Self> {
+        // SAFETY: stat is valid when zeroed.
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Not a fan-out ring");
+        let len = stat.st_size as usize;
+        if len < HEADER_LEN {
+            return Err(invalid());
+        }
+
+        let mapping = Mapping::new(&fd, len)?;
+        let header = mapping.header();
+        let capacity = header.capacity as usize;
+        if header.magic != MAGIC
+            || capacity == 0
+            || !capacity.is_multiple_of(RECORD_ALIGN)
+            || HEADER_LEN + capacity > len
+        {
+            return Err(invalid());
+        }
+        Ok(Self { mapping })
+    }
+
+    /// Copies the oldest frame into `frame`, returning when and which way it went through
+    /// the device, or `None` if the ring is empty.
+    pub fn pop_into(&mut self, frame: &mut Vec<u8>) -> Option<(Instant, Direction)> {
+        let header = self.mapping.header();
+        let capacity = self.mapping.capacity();
+        let head = header.head.0.load(Ordering::Acquire);
+        let mut tail = header.tail.0.load(Ordering::Relaxed);
+
+        loop {
+            if tail == head {
+                return None;
+            }
+            let offset = tail as usize % capacity;
+            // SAFETY: the bytes between tail and head were published by the producer and
+            // stay untouched until tail moves past them.
+            let len = unsafe { ptr::read_unaligned(self.mapping.data(offset, 4) as *const u32) };
+            if len == WRAP {
+                tail += (capacity - offset) as u64;
+                continue;
+            }
+
+            let len = len as usize;
+            let record = record_len(len);
+            if record > capacity - offset || tail + record as u64 > head {
+                // Only a misbehaving producer gets here, drop what is left.
+                header.tail.0.store(head, Ordering::Release);
+                return None;
+            }
+            let (timestamp, direction) = unsafe {
+                let at = self.mapping.data(offset, record);
+                let direction = ptr::read_unaligned(at.add(4) as *const u32);
+                let timestamp = ptr::read_unaligned(at.add(8) as *const i64);
+                frame.clear();
+                frame.extend_from_slice(std::slice::from_raw_parts(at.add(RECORD_HEADER_LEN), len));
+                (timestamp, direction)
+            };
+            header.tail.0.store(tail + record as u64, Ordering::Release);
+
+            let direction = match direction {
+                0 => Direction::Received,
+                _ => Direction::Sent,
+            };
+            return Some((Instant::from_micros(timestamp), direction));
+        }
+    }
+
+    /// Frames the producer dropped because the ring was full.
+    pub fn dropped(&self) -> u64 {
+        self.mapping.header().dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.mapping.header().closed.store(1, Ordering::Relaxed);
+    }
+}
+
+/// A device wrapper copying the frames of its inner device to subscriber processes.
+///
+/// Rings of subscribers that dropped their [`Subscriber`] are released on the next
+/// receive or transmit. A subscriber that dies without dropping it keeps its ring until
+/// [`FanOut::unsubscribe_all`], dropping every frame once full.
+pub struct FanOut<D: Device> {
+    inner: D,
+    publishers: Vec<Publisher>,
+    sent: bool,
+    dropped: Cell<u64>,
+}
+
+impl<D: Device> FanOut<D> {
+    /// Wraps `inner`, fanning out the received frames only.
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            publishers: Vec::new(),
+            sent: false,
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Also fans out the frames sent through the wrapper.
+    pub fn set_sent(&mut self, sent: bool) {
+        self.sent = sent;
+    }
+
+    /// Adds a subscriber with a ring of `capacity` bytes, returning the memfd to pass to
+    /// it. Every frame takes its length plus 16 bytes, rounded up to 8.
+    pub fn subscribe(&mut self, capacity: usize) -> io::Result<OwnedFd> {
+        let (publisher, fd) = Publisher::create(capacity)?;
+        self.publishers.push(publisher);
+        Ok(fd)
+    }
+
+    /// Stops fanning out to every subscriber.
+    pub fn unsubscribe_all(&mut self) {
+        self.publishers.clear();
+    }
+
+    /// Subscribers still reading.
+    pub fn subscribers(&mut self) -> usize {
+        self.release_closed();
+        self.publishers.len()
+    }
+
+    /// Frame copies dropped because a subscriber ring was full, over all subscribers.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.get()
+    }
+
+    /// Get a reference to the underlying device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Return the underlying device, consuming the wrapper.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn release_closed(&mut self) {
+        self.publishers.retain(|publisher| !publisher.closed());
+    }
+}
+
+impl<D: Device> Device for FanOut<D> {
+    type RxToken<'a>
+        = RxToken<'a, D::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a, D::TxToken<'a>>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.release_closed();
+        let (rx, tx) = self.inner.receive(timestamp)?;
+        let fan = Fan {
+            publishers: &self.publishers,
+            dropped: &self.dropped,
+            timestamp,
+        };
+        Some((
+            RxToken { token: rx, fan },
+            TxToken {
+                token: tx,
+                fan: self.sent.then_some(fan),
+            },
+        ))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.release_closed();
+        let tx = self.inner.transmit(timestamp)?;
+        Some(TxToken {
+            token: tx,
+            fan: self.sent.then_some(Fan {
+                publishers: &self.publishers,
+                dropped: &self.dropped,
+                timestamp,
+            }),
+        })
+    }
+}
+
+impl<D: CapabilitiesAudit> CapabilitiesAudit for FanOut<D> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("fan-out", self.capabilities());
+        self.inner.audit(report);
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Fan<'a> {
+    publishers: &'a [Publisher],
+    dropped: &'a Cell<u64>,
+    timestamp: Instant,
+}
+
+impl Fan<'_> {
+    fn publish(&self, direction: Direction, frame: &[u8]) {
+        for publisher in self.publishers {
+            if !publisher.push(self.timestamp, direction, frame) {
+                self.dropped.set(self.dropped.get() + 1);
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken<'a, Rx: phy::RxToken> {
+    token: Rx,
+    fan: Fan<'a>,
+}
+
+impl<Rx: phy::RxToken> phy::RxToken for RxToken<'_, Rx> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let fan = self.fan;
+        self.token.consume(|frame| {
+            fan.publish(Direction::Received, frame);
+            f(frame)
+        })
+    }
+
+    fn meta(&self) -> phy::PacketMeta {
+        self.token.meta()
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a, Tx: phy::TxToken> {
+    token: Tx,
+    // Set when sent frames are fanned out.
+    fan: Option<Fan<'a>>,
+}
+
+impl<Tx: phy::TxToken> phy::TxToken for TxToken<'_, Tx> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let fan = self.fan;
+        self.token.consume(len, |frame| {
+            let result = f(frame);
+            if let Some(fan) = fan {
+                fan.publish(Direction::Sent, frame);
+            }
+            result
+        })
+    }
+
+    fn set_meta(&mut self, meta: phy::PacketMeta) {
+        self.token.set_meta(meta)
+    }
+}
@@ -0,0 +1,138 @@
+//! Passing AF_XDP sockets, programs and maps from a privileged helper to an
+//! unprivileged process.
+//!
+//! Creating an AF_XDP socket needs `CAP_NET_RAW` and loading a program or opening a
+//! pinned map `CAP_BPF`, while using them afterwards needs neither. A small helper can
+//! create them and hand the fds over a Unix socket with [`send_fds`], or have systemd
+//! pass them through socket activation, see [`listen_fds`].
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::{env, io, mem, ptr};
+
+/// First fd passed by systemd socket activation, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Most fds carried by a single message, the kernel limit `SCM_MAX_FD`.
+pub const MAX_FDS: usize = 253;
+
+/// Sends `fds` in one `SCM_RIGHTS` message over `socket`.
+pub fn send_fds(socket: &UnixStream, fds: &[RawFd]) -> io::Result<()> {
+    if fds.is_empty() || fds.len() > MAX_FDS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Between 1 and {} fds can be sent at once", MAX_FDS),
+        ));
+    }
+
+    let fds_len = mem::size_of_val(fds);
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize];
+    // At least one byte of data has to go with the fds.
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+
+    // SAFETY: msghdr is valid when zeroed, the buffers outlive the call.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len as u32) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr() as *const u8, libc::CMSG_DATA(cmsg), fds_len);
+    }
+
+    if unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receives the fds of one `SCM_RIGHTS` message sent with [`send_fds`], at most `max`
+/// of them. They are opened close-on-exec.
+pub fn recv_fds(socket: &UnixStream, max: usize) -> io::Result<Vec<OwnedFd>> {
+    let max = max.min(MAX_FDS);
+    let mut control =
+        vec![0u8; unsafe { libc::CMSG_SPACE((max * mem::size_of::<RawFd>()) as u32) } as usize];
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+
+    // SAFETY: msghdr is valid when zeroed, the buffers outlive the call.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if len == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data_len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                for i in 0..data_len / mem::size_of::<RawFd>() {
+                    // SAFETY: the kernel installed these fds for this process.
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    // The kernel discards the fds that did not fit in the control buffer.
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("More than {} fds were sent", max),
+        ));
+    }
+    Ok(fds)
+}
+
+/// Takes the fds passed by systemd socket activation with their `FileDescriptorName=`,
+/// or an empty list when the process was not socket activated.
+///
+/// The fds are made close-on-exec. Like `sd_listen_fds`, this must be called once: a
+/// second call would hand out the same fds again.
+pub fn listen_fds() -> io::Result<Vec<(Option<String>, OwnedFd)>> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    if pid != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+
+    let count: RawFd = match env::var("LISTEN_FDS").ok().and_then(|n| n.parse().ok()) {
+        Some(count) => count,
+        None => return Ok(Vec::new()),
+    };
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':').map(str::to_owned);
+
+    let mut fds = Vec::with_capacity(count.max(0) as usize);
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = names.next().filter(|name| !name.is_empty());
+        // SAFETY: systemd passed the fd to this process, nothing else owns it.
+        fds.push((name, unsafe { OwnedFd::from_raw_fd(fd) }));
+    }
+    Ok(fds)
+}
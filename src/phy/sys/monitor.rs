@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::{io, mem};
+
+use super::netlink::{align, attrs, socket};
+
+/// Large enough for the biggest link dump the kernel sends in a single datagram.
+const BUFFER_LEN: usize = 32 * 1024;
+
+/// Kernel-side change of a network interface.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// The link was created or changed, `up` when it is both administratively up and
+    /// running.
+    Link {
+        ifindex: u32,
+        name: Option<String>,
+        up: bool,
+    },
+    LinkRemoved {
+        ifindex: u32,
+    },
+    AddressAdded {
+        ifindex: u32,
+        addr: IpAddr,
+        prefix_len: u8,
+    },
+    AddressRemoved {
+        ifindex: u32,
+        addr: IpAddr,
+        prefix_len: u8,
+    },
+}
+
+impl Event {
+    /// Index of the interface the event is about.
+    pub fn ifindex(&self) -> u32 {
+        match *self {
+            Self::Link { ifindex, .. }
+            | Self::LinkRemoved { ifindex }
+            | Self::AddressAdded { ifindex, .. }
+            | Self::AddressRemoved { ifindex, .. } => ifindex,
+        }
+    }
+}
+
+/// A NETLINK_ROUTE socket subscribed to link and address notifications of every
+/// interface.
+///
+/// The socket is non-blocking: iterating yields the events received so far and stops
+/// when none is left, so it can be drained from the application poll loop or after its
+/// fd turns readable. An `ENOBUFS` error means the kernel dropped notifications and the
+/// state should be read again.
+pub struct Monitor {
+    fd: OwnedFd,
+    buf: Vec<u8>,
+    pending: VecDeque<Event>,
+}
+
+impl Monitor {
+    pub fn new() -> io::Result<Self> {
+        let groups = libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR;
+        let fd = socket(groups as u32, libc::SOCK_NONBLOCK)?;
+        Ok(Self {
+            fd,
+            buf: vec![0; BUFFER_LEN],
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Calls `f` with every event received so far, returning how many there were.
+    pub fn drain<F: FnMut(Event)>(&mut self, mut f: F) -> io::Result<usize> {
+        let mut count = 0;
+        for event in self.by_ref() {
+            f(event?);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reads one datagram, returning false when none is queued.
+    fn recv(&mut self) -> io::Result<bool> {
+        let len = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                self.buf.as_mut_ptr() as *mut libc::c_void,
+                self.buf.len(),
+                0,
+            )
+        };
+        if len == -1 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::WouldBlock => Ok(false),
+                _ => Err(err),
+            };
+        }
+
+        let len = len as usize;
+        let mut offset = 0;
+        while offset + mem::size_of::<libc::nlmsghdr>() <= len {
+            // SAFETY: bounds checked above, nlmsghdr has no invalid bit patterns.
+            let hdr = unsafe {
+                (self.buf.as_ptr().add(offset) as *const libc::nlmsghdr).read_unaligned()
+            };
+            let msg_len = hdr.nlmsg_len as usize;
+            if msg_len < mem::size_of::<libc::nlmsghdr>() || offset + msg_len > len {
+                break;
+            }
+
+            let payload = &self.buf[offset + mem::size_of::<libc::nlmsghdr>()..offset + msg_len];
+            if let Some(event) = parse(hdr.nlmsg_type, payload) {
+                self.pending.push_back(event);
+            }
+            offset += align(msg_len);
+        }
+        Ok(true)
+    }
+}
+
+impl Iterator for Monitor {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            match self.recv() {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl AsRawFd for Monitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+fn parse(type_: u16, payload: &[u8]) -> Option<Event> {
+    match type_ {
+        libc::RTM_NEWLINK | libc::RTM_DELLINK => {
+            let info: libc::ifinfomsg = read(payload)?;
+            let ifindex = info.ifi_index as u32;
+            if type_ == libc::RTM_DELLINK {
+                return Some(Event::LinkRemoved { ifindex });
+            }
+
+            let name = attrs(&payload[align(mem::size_of::<libc::ifinfomsg>())..])
+                .find(|(type_, _)| *type_ == libc::IFLA_IFNAME)
+                .map(|(_, value)| {
+                    let name = value.split(|&b| b == 0).next().unwrap_or(value);
+                    String::from_utf8_lossy(name).into_owned()
+                });
+            let running = (libc::IFF_UP | libc::IFF_RUNNING) as u32;
+            Some(Event::Link {
+                ifindex,
+                name,
+                up: info.ifi_flags & running == running,
+            })
+        }
+        libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+            let info: libc::ifaddrmsg = read(payload)?;
+
+            // IFA_ADDRESS is the peer on point-to-point links, IFA_LOCAL the local address
+            // when present.
+            let mut addr = None;
+            for (type_, value) in attrs(&payload[align(mem::size_of::<libc::ifaddrmsg>())..]) {
+                if type_ == libc::IFA_LOCAL || (type_ == libc::IFA_ADDRESS && addr.is_none()) {
+                    addr = ip_addr(info.ifa_family, value).or(addr);
+                }
+            }
+
+            let ifindex = info.ifa_index;
+            let prefix_len = info.ifa_prefixlen;
+            let addr = addr?;
+            Some(match type_ {
+                libc::RTM_NEWADDR => Event::AddressAdded {
+                    ifindex,
+                    addr,
+                    prefix_len,
+                },
+                _ => Event::AddressRemoved {
+                    ifindex,
+                    addr,
+                    prefix_len,
+                },
+            })
+        }
+        _ => None,
+    }
+}
+
+fn read<T: Copy>(buf: &[u8]) -> Option<T> {
+    if buf.len() < mem::size_of::<T>() {
+        return None;
+    }
+    // SAFETY: bounds checked above, only used for plain C structs.
+    Some(unsafe { (buf.as_ptr() as *const T).read_unaligned() })
+}
+
+fn ip_addr(family: u8, value: &[u8]) -> Option<IpAddr> {
+    match family as libc::c_int {
+        libc::AF_INET => {
+            let octets: [u8; 4] = value.get(..4)?.try_into().ok()?;
+            Some(Ipv4Addr::from(octets).into())
+        }
+        libc::AF_INET6 => {
+            let octets: [u8; 16] = value.get(..16)?.try_into().ok()?;
+            Some(Ipv6Addr::from(octets).into())
+        }
+        _ => None,
+    }
+}
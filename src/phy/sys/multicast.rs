@@ -0,0 +1,177 @@
+//! Multicast group membership of the interface under an XDP socket.
+//!
+//! ```ignore
+//! let mut memberships = Memberships::new("eth0")?;
+//! memberships.join(&mut iface, IpAddress::v4(239, 1, 2, 3))?;
+//! // After adding or removing addresses of `iface`.
+//! memberships.sync(&iface)?;
+//! ```
+
+use std::ffi::CString;
+use std::net::Ipv6Addr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::{io, mem};
+
+use smoltcp::iface::Interface;
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr};
+
+/// Ethernet address the IP multicast group `addr` is sent to, `None` if it is not a
+/// multicast address.
+///
+/// IPv4 groups map their low 23 bits under `01:00:5e` (RFC 1112), IPv6 groups their low
+/// 32 bits under `33:33` (RFC 2464), so several groups may share an address.
+pub fn group_hardware_addr(addr: IpAddress) -> Option<EthernetAddress> {
+    if !addr.is_multicast() {
+        return None;
+    }
+    Some(match addr {
+        IpAddress::Ipv4(addr) => {
+            let [_, b, c, d] = addr.octets();
+            EthernetAddress([0x01, 0x00, 0x5e, b & 0x7f, c, d])
+        }
+        IpAddress::Ipv6(addr) => {
+            let [.., a, b, c, d] = addr.octets();
+            EthernetAddress([0x33, 0x33, a, b, c, d])
+        }
+    })
+}
+
+/// Solicited-node group of `addr` (RFC 4291), which smoltcp joins for every IPv6
+/// address of the interface.
+fn solicited_node(addr: Ipv6Addr) -> IpAddress {
+    let [.., a, b, c] = addr.octets();
+    IpAddress::Ipv6(Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | u16::from(a),
+        u16::from_be_bytes([b, c]),
+    ))
+}
+
+/// Keeps the multicast filter of an interface in line with the groups a smoltcp
+/// `Interface` is a member of.
+///
+/// XDP and raw sockets only see the multicast frames the NIC lets through, and the NIC
+/// drops those of groups the kernel stack did not join. Joining through
+/// [`Memberships::join`] adds the Ethernet address of the group to the interface with
+/// `SIOCADDMULTI`, which needs `CAP_NET_ADMIN`, as well as to `Interface`. Addresses are
+/// removed again once no group joined maps to them, and when dropped.
+pub struct Memberships {
+    fd: OwnedFd,
+    ifr: libc::ifreq,
+    // Groups joined through `join`.
+    groups: Vec<IpAddress>,
+    // Ethernet addresses added to the interface.
+    added: Vec<EthernetAddress>,
+}
+
+impl Memberships {
+    /// Manages the multicast filter of the interface called `name`, on bond or VLAN
+    /// interfaces the kernel passes the addresses down to the lower devices.
+    pub fn new(name: &str) -> io::Result<Self> {
+        let ifname = CString::new(name)?;
+        let fd = unsafe {
+            let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+
+        let mut ifr: libc::ifreq = unsafe { mem::zeroed() };
+        unsafe { libc::strncpy(ifr.ifr_name.as_mut_ptr(), ifname.as_ptr(), libc::IFNAMSIZ) };
+        Ok(Self {
+            fd,
+            ifr,
+            groups: Vec::new(),
+            added: Vec::new(),
+        })
+    }
+
+    /// Joins the multicast group `addr` on `iface` and lets its frames through the
+    /// interface filter.
+    pub fn join(&mut self, iface: &mut Interface, addr: IpAddress) -> io::Result<()> {
+        iface
+            .join_multicast_group(addr)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        if !self.groups.contains(&addr) {
+            self.groups.push(addr);
+        }
+        self.sync(iface)
+    }
+
+    /// Leaves the multicast group `addr` on `iface`, filtering out its frames again unless
+    /// another group shares its Ethernet address.
+    pub fn leave(&mut self, iface: &mut Interface, addr: IpAddress) -> io::Result<()> {
+        iface
+            .leave_multicast_group(addr)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.groups.retain(|&group| group != addr);
+        self.sync(iface)
+    }
+
+    /// Adds the Ethernet addresses of the groups `iface` is a member of to the interface
+    /// filter and removes the others: groups joined through [`Memberships::join`] and
+    /// since left on `iface` directly, and the solicited-node groups of addresses removed
+    /// from `iface`. Meant to run after changing the addresses of `iface`.
+    pub fn sync(&mut self, iface: &Interface) -> io::Result<()> {
+        self.groups
+            .retain(|&group| iface.has_multicast_group(group));
+        let solicited = iface.ip_addrs().iter().filter_map(|cidr| match cidr {
+            IpCidr::Ipv6(cidr) => Some(solicited_node(cidr.address())),
+            _ => None,
+        });
+        let mut wanted: Vec<EthernetAddress> = self
+            .groups
+            .iter()
+            .copied()
+            .chain(solicited.filter(|&group| iface.has_multicast_group(group)))
+            .filter_map(group_hardware_addr)
+            .collect();
+        wanted.sort_unstable();
+        wanted.dedup();
+
+        for i in (0..self.added.len()).rev() {
+            if !wanted.contains(&self.added[i]) {
+                self.request(libc::SIOCDELMULTI, self.added[i])?;
+                self.added.swap_remove(i);
+            }
+        }
+        for addr in wanted {
+            if !self.added.contains(&addr) {
+                self.request(libc::SIOCADDMULTI, addr)?;
+                self.added.push(addr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ethernet addresses currently added to the interface filter.
+    pub fn hardware_addrs(&self) -> &[EthernetAddress] {
+        &self.added
+    }
+
+    fn request(&mut self, request: libc::Ioctl, addr: EthernetAddress) -> io::Result<()> {
+        let hwaddr = unsafe { &mut self.ifr.ifr_ifru.ifru_hwaddr };
+        hwaddr.sa_family = libc::AF_UNSPEC as libc::sa_family_t;
+        for (byte, &octet) in hwaddr.sa_data.iter_mut().zip(addr.as_bytes()) {
+            *byte = octet as libc::c_char;
+        }
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), request, &mut self.ifr) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Memberships {
+    fn drop(&mut self) {
+        for addr in mem::take(&mut self.added) {
+            let _ = self.request(libc::SIOCDELMULTI, addr);
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::{io, mem};
+
+const IFLA_IFNAME: u16 = 3;
+const IFLA_LINK: u16 = 5;
+const IFLA_MASTER: u16 = 10;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const IFLA_BOND_MODE: u16 = 1;
+const IFLA_XDP: u16 = 43;
+const IFLA_XDP_FD: u16 = 1;
+const IFLA_XDP_ATTACHED: u16 = 2;
+const IFLA_XDP_FLAGS: u16 = 3;
+const IFLA_XDP_EXPECTED_FD: u16 = 8;
+const NLA_F_NESTED: u16 = 1 << 15;
+
+const XDP_ATTACHED_NONE: u8 = 0;
+const XDP_ATTACHED_DRV: u8 = 1;
+const XDP_ATTACHED_SKB: u8 = 2;
+const XDP_ATTACHED_HW: u8 = 3;
+
+const NETDEV_CMD_DEV_GET: u8 = 1;
+const NETDEV_A_DEV_IFINDEX: u16 = 1;
+const NETDEV_A_DEV_XDP_FEATURES: u16 = 3;
+
+/// Large enough for the reply describing a single link.
+const REPLY_LEN: usize = 32 * 1024;
+
+pub const XDP_FLAGS_UPDATE_IF_NOEXIST: u32 = 1 << 0;
+pub const XDP_FLAGS_SKB_MODE: u32 = 1 << 1;
+pub const XDP_FLAGS_DRV_MODE: u32 = 1 << 2;
+pub const XDP_FLAGS_HW_MODE: u32 = 1 << 3;
+pub const XDP_FLAGS_REPLACE: u32 = 1 << 4;
+
+pub const NETDEV_XDP_ACT_BASIC: u64 = 1 << 0;
+pub const NETDEV_XDP_ACT_REDIRECT: u64 = 1 << 1;
+pub const NETDEV_XDP_ACT_XSK_ZEROCOPY: u64 = 1 << 3;
+
+/// A netlink socket issuing requests that are answered with an ack.
+pub struct Netlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl Netlink {
+    pub fn route() -> io::Result<Self> {
+        let fd = socket(0, 0)?;
+        Ok(Self { fd, seq: 0 })
+    }
+
+    pub fn generic() -> io::Result<Self> {
+        let fd = open(libc::NETLINK_GENERIC, 0, 0)?;
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Sends `msg` and waits for the kernel ack.
+    pub fn request(&mut self, msg: Message) -> io::Result<()> {
+        self.exchange(msg, |_| {})
+    }
+
+    /// Sends `msg` and returns the payload of the reply, after its netlink header.
+    pub fn query(&mut self, msg: Message) -> io::Result<Vec<u8>> {
+        let mut reply = None;
+        self.exchange(msg, |payload| {
+            reply.get_or_insert_with(|| payload.to_vec());
+        })?;
+        reply.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No netlink reply"))
+    }
+
+    /// Sends the dump request `msg` and calls `f` with the payload of every reply.
+    pub fn dump(&mut self, msg: Message, f: impl FnMut(&[u8])) -> io::Result<()> {
+        self.exchange(msg, f)
+    }
+
+    /// Sends `msg` and calls `f` with the payload of every reply until the kernel ack,
+    /// or the end of a dump.
+    fn exchange(&mut self, mut msg: Message, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+        self.seq = self.seq.wrapping_add(1);
+        let buf = msg.finish(self.seq);
+
+        let res = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut reply = vec![0u8; REPLY_LEN];
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    reply.as_mut_ptr() as *mut libc::c_void,
+                    reply.len(),
+                    0,
+                )
+            };
+            if len == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut offset = 0;
+            while offset + mem::size_of::<libc::nlmsghdr>() <= len as usize {
+                // SAFETY: bounds checked above, nlmsghdr has no invalid bit patterns.
+                let hdr = unsafe {
+                    (reply.as_ptr().add(offset) as *const libc::nlmsghdr).read_unaligned()
+                };
+                let msg_len = hdr.nlmsg_len as usize;
+                if msg_len < mem::size_of::<libc::nlmsghdr>() || offset + msg_len > len as usize {
+                    break;
+                }
+                if hdr.nlmsg_seq == self.seq && hdr.nlmsg_type == libc::NLMSG_ERROR as u16 {
+                    let errno_offset = offset + mem::size_of::<libc::nlmsghdr>();
+                    let errno = i32::from_ne_bytes(
+                        reply[errno_offset..errno_offset + 4]
+                            .try_into()
+                            .expect("Slice has 4 bytes"),
+                    );
+                    return match errno {
+                        0 => Ok(()),
+                        errno => Err(io::Error::from_raw_os_error(-errno)),
+                    };
+                }
+                if hdr.nlmsg_seq == self.seq && hdr.nlmsg_type == libc::NLMSG_DONE as u16 {
+                    return Ok(());
+                }
+                if hdr.nlmsg_seq == self.seq {
+                    f(&reply[offset + mem::size_of::<libc::nlmsghdr>()..offset + msg_len]);
+                }
+                offset += align(msg_len);
+            }
+        }
+    }
+}
+
+impl AsRawFd for Netlink {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Opens a NETLINK_ROUTE socket subscribed to the multicast `groups`, with extra socket
+/// `flags` such as `SOCK_NONBLOCK`.
+pub(crate) fn socket(groups: u32, flags: libc::c_int) -> io::Result<OwnedFd> {
+    open(libc::NETLINK_ROUTE, groups, flags)
+}
+
+fn open(protocol: libc::c_int, groups: u32, flags: libc::c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC | flags,
+            protocol,
+        );
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        OwnedFd::from_raw_fd(fd)
+    };
+
+    // SAFETY: sockaddr_nl is valid when zeroed, which means "kernel assigns the port".
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as u16;
+    addr.nl_groups = groups;
+    let res = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(fd)
+}
+
+pub(crate) fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Iterates over the `(type, value)` attributes in `buf`.
+pub(crate) fn attrs(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if buf.len() < 4 {
+            return None;
+        }
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        let type_ = u16::from_ne_bytes([buf[2], buf[3]]);
+        if len < 4 || len > buf.len() {
+            return None;
+        }
+
+        let value = &buf[4..len];
+        buf = &buf[align(len).min(buf.len())..];
+        Some((type_, value))
+    })
+}
+
+/// Value of the attribute `type_` in `buf`, nested or not.
+fn attr(buf: &[u8], type_: u16) -> Option<&[u8]> {
+    attrs(buf)
+        .find(|(found, _)| found & !NLA_F_NESTED == type_)
+        .map(|(_, value)| value)
+}
+
+/// A netlink message under construction.
+pub struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(type_: u16, flags: u16) -> Self {
+        let hdr = libc::nlmsghdr {
+            nlmsg_len: 0,
+            nlmsg_type: type_,
+            nlmsg_flags: flags | libc::NLM_F_REQUEST as u16 | libc::NLM_F_ACK as u16,
+            nlmsg_seq: 0,
+            nlmsg_pid: 0,
+        };
+        let mut msg = Self { buf: Vec::new() };
+        msg.push(&hdr);
+        msg
+    }
+
+    /// Adds `flags`, e.g. `NLM_F_DUMP`, to the header.
+    pub fn set_flags(&mut self, flags: u16) {
+        let at = mem::offset_of!(libc::nlmsghdr, nlmsg_flags);
+        let current = u16::from_ne_bytes([self.buf[at], self.buf[at + 1]]);
+        self.buf[at..at + 2].copy_from_slice(&(current | flags).to_ne_bytes());
+    }
+
+    /// Appends a fixed header such as `ifinfomsg`.
+    pub fn push<T: Copy>(&mut self, value: &T) {
+        // SAFETY: T is a plain C struct, reading its bytes is always valid.
+        let bytes = unsafe {
+            std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+        };
+        self.buf.extend_from_slice(bytes);
+        self.buf.resize(align(self.buf.len()), 0);
+    }
+
+    pub fn attr(&mut self, type_: u16, payload: &[u8]) {
+        let len = (4 + payload.len()) as u16;
+        self.buf.extend_from_slice(&len.to_ne_bytes());
+        self.buf.extend_from_slice(&type_.to_ne_bytes());
+        self.buf.extend_from_slice(payload);
+        self.buf.resize(align(self.buf.len()), 0);
+    }
+
+    /// Opens a nested attribute, to be closed with [`Message::end_nested`].
+    pub fn begin_nested(&mut self, type_: u16) -> usize {
+        let start = self.buf.len();
+        self.attr(type_ | NLA_F_NESTED, &[]);
+        start
+    }
+
+    pub fn end_nested(&mut self, start: usize) {
+        let len = (self.buf.len() - start) as u16;
+        self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+    }
+
+    fn finish(&mut self, seq: u32) -> &[u8] {
+        let len = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        &self.buf
+    }
+}
+
+fn link_message(type_: u16, ifindex: u32) -> Message {
+    // SAFETY: ifinfomsg is valid when zeroed.
+    let mut ifinfo: libc::ifinfomsg = unsafe { mem::zeroed() };
+    ifinfo.ifi_family = libc::AF_UNSPEC as u8;
+    ifinfo.ifi_index = ifindex as i32;
+
+    let mut msg = Message::new(type_, 0);
+    msg.push(&ifinfo);
+    msg
+}
+
+/// Attaches the XDP program `prog_fd` to the interface, or detaches it when `prog_fd` is -1.
+pub fn set_xdp_fd(ifindex: u32, prog_fd: RawFd, flags: u32) -> io::Result<()> {
+    Netlink::route()?.request(xdp_message(ifindex, prog_fd, flags, None))
+}
+
+/// Atomically replaces the XDP program `expected_fd` of the interface with `prog_fd`,
+/// failing with `EEXIST` if another program is attached.
+pub fn replace_xdp_fd(
+    ifindex: u32,
+    prog_fd: RawFd,
+    expected_fd: RawFd,
+    flags: u32,
+) -> io::Result<()> {
+    let flags = flags | XDP_FLAGS_REPLACE;
+    Netlink::route()?.request(xdp_message(ifindex, prog_fd, flags, Some(expected_fd)))
+}
+
+fn xdp_message(ifindex: u32, prog_fd: RawFd, flags: u32, expected_fd: Option<RawFd>) -> Message {
+    let mut msg = link_message(libc::RTM_SETLINK, ifindex);
+    let xdp = msg.begin_nested(IFLA_XDP);
+    msg.attr(IFLA_XDP_FD, &prog_fd.to_ne_bytes());
+    if flags != 0 {
+        msg.attr(IFLA_XDP_FLAGS, &flags.to_ne_bytes());
+    }
+    if let Some(expected_fd) = expected_fd {
+        msg.attr(IFLA_XDP_EXPECTED_FD, &expected_fd.to_ne_bytes());
+    }
+    msg.end_nested(xdp);
+    msg
+}
+
+/// Mode of the XDP program attached to the interface, as `XDP_FLAGS_*_MODE` flags,
+/// `None` without program. A driver and an offloaded program show up as both flags.
+pub fn xdp_attached(ifindex: u32) -> io::Result<Option<u32>> {
+    let reply = Netlink::route()?.query(link_message(libc::RTM_GETLINK, ifindex))?;
+    let link_attrs = reply
+        .get(align(mem::size_of::<libc::ifinfomsg>())..)
+        .unwrap_or_default();
+    let attached = attr(link_attrs, IFLA_XDP)
+        .and_then(|xdp| attr(xdp, IFLA_XDP_ATTACHED))
+        .and_then(|value| value.first().copied());
+    Ok(match attached.unwrap_or(XDP_ATTACHED_NONE) {
+        XDP_ATTACHED_NONE => None,
+        XDP_ATTACHED_DRV => Some(XDP_FLAGS_DRV_MODE),
+        XDP_ATTACHED_SKB => Some(XDP_FLAGS_SKB_MODE),
+        XDP_ATTACHED_HW => Some(XDP_FLAGS_HW_MODE),
+        _ => Some(XDP_FLAGS_DRV_MODE | XDP_FLAGS_HW_MODE),
+    })
+}
+
+/// XDP features of the interface driver, as `NETDEV_XDP_ACT_*` flags, from the netdev
+/// generic netlink family. Fails on kernels before 6.3, which do not report them.
+pub fn xdp_features(ifindex: u32) -> io::Result<u64> {
+    let mut netlink = Netlink::generic()?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed netdev reply");
+
+    let mut msg = Message::new(libc::GENL_ID_CTRL as u16, 0);
+    msg.push(&genl_header(libc::CTRL_CMD_GETFAMILY as u8));
+    msg.attr(libc::CTRL_ATTR_FAMILY_NAME as u16, b"netdev\0");
+    let reply = netlink.query(msg)?;
+    let family = genl_attr(&reply, libc::CTRL_ATTR_FAMILY_ID as u16)
+        .and_then(|value| value.get(..2)?.try_into().ok())
+        .map(u16::from_ne_bytes)
+        .ok_or_else(invalid)?;
+
+    let mut msg = Message::new(family, 0);
+    msg.push(&genl_header(NETDEV_CMD_DEV_GET));
+    msg.attr(NETDEV_A_DEV_IFINDEX, &ifindex.to_ne_bytes());
+    let reply = netlink.query(msg)?;
+    genl_attr(&reply, NETDEV_A_DEV_XDP_FEATURES)
+        .and_then(|value| value.get(..8)?.try_into().ok())
+        .map(u64::from_ne_bytes)
+        .ok_or_else(invalid)
+}
+
+fn genl_header(cmd: u8) -> libc::genlmsghdr {
+    libc::genlmsghdr {
+        cmd,
+        version: 1,
+        reserved: 0,
+    }
+}
+
+/// Value of the attribute `type_` in the generic netlink `reply`.
+fn genl_attr(reply: &[u8], type_: u16) -> Option<&[u8]> {
+    attr(reply.get(mem::size_of::<libc::genlmsghdr>()..)?, type_)
+}
+
+/// What the kernel reports about a link, see [`link`].
+#[derive(Clone, Debug)]
+pub struct LinkInfo {
+    pub ifindex: u32,
+    pub name: String,
+    /// `IFLA_INFO_KIND`, e.g. `vlan` or `bond`, empty for physical devices.
+    pub kind: String,
+    /// Device a VLAN is stacked on (`IFLA_LINK`).
+    pub link: Option<u32>,
+    /// Bond or bridge the device is enslaved to (`IFLA_MASTER`).
+    pub master: Option<u32>,
+    /// `IFLA_BOND_MODE` of a bond, e.g. `BOND_MODE_ACTIVEBACKUP`.
+    pub bond_mode: Option<u8>,
+}
+
+pub const BOND_MODE_ACTIVEBACKUP: u8 = 1;
+pub const BOND_MODE_TLB: u8 = 5;
+
+/// The link called `name`.
+pub fn link(name: &str) -> io::Result<LinkInfo> {
+    let mut msg = link_message(libc::RTM_GETLINK, 0);
+    let mut ifname = name.as_bytes().to_vec();
+    ifname.push(0);
+    msg.attr(IFLA_IFNAME, &ifname);
+    let reply = Netlink::route()?.query(msg)?;
+    parse_link(&reply)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed link reply"))
+}
+
+/// Every link of the network namespace.
+pub fn links() -> io::Result<Vec<LinkInfo>> {
+    let mut msg = link_message(libc::RTM_GETLINK, 0);
+    msg.set_flags(libc::NLM_F_DUMP as u16);
+    let mut links = Vec::new();
+    Netlink::route()?.dump(msg, |payload| links.extend(parse_link(payload)))?;
+    Ok(links)
+}
+
+fn parse_link(payload: &[u8]) -> Option<LinkInfo> {
+    let header = mem::size_of::<libc::ifinfomsg>();
+    // SAFETY: bounds checked by `get`, ifinfomsg has no invalid bit patterns.
+    let ifinfo =
+        unsafe { (payload.get(..header)?.as_ptr() as *const libc::ifinfomsg).read_unaligned() };
+    let link_attrs = payload.get(align(header)..)?;
+    let u32_attr = |type_| {
+        attr(link_attrs, type_)
+            .and_then(|value| value.get(..4)?.try_into().ok())
+            .map(u32::from_ne_bytes)
+    };
+    let string = |value: &[u8]| {
+        let value = value.split(|&byte| byte == 0).next().unwrap_or_default();
+        String::from_utf8_lossy(value).into_owned()
+    };
+    let info = attr(link_attrs, IFLA_LINKINFO);
+    Some(LinkInfo {
+        ifindex: ifinfo.ifi_index as u32,
+        name: string(attr(link_attrs, IFLA_IFNAME)?),
+        kind: info
+            .and_then(|info| attr(info, IFLA_INFO_KIND))
+            .map(string)
+            .unwrap_or_default(),
+        link: u32_attr(IFLA_LINK).filter(|&link| link != ifinfo.ifi_index as u32),
+        master: u32_attr(IFLA_MASTER),
+        bond_mode: info
+            .and_then(|info| attr(info, IFLA_INFO_DATA))
+            .and_then(|data| attr(data, IFLA_BOND_MODE))
+            .and_then(|value| value.first().copied()),
+    })
+}
@@ -0,0 +1,181 @@
+//! Waiting on devices and application fds at once.
+//!
+//! `smoltcp::phy::wait` only watches the device, so applications with timers or control
+//! sockets end up with a second event loop. A [`Poller`] watches all of them and sleeps
+//! for at most the `Interface::poll_delay` of the stack:
+//!
+//! ```ignore
+//! let mut poller = Poller::new();
+//! let device_token = poller.register(&device, Interest::Readable);
+//! let control_token = poller.register(&control, Interest::Readable);
+//! loop {
+//!     iface.poll(Instant::now(), &mut device, &mut sockets);
+//!     let delay = iface.poll_delay(Instant::now(), &sockets);
+//!     poller.dispatch(delay, |token, _| {
+//!         if token == control_token {
+//!             handle_control(&control);
+//!         }
+//!     })?;
+//! }
+//! ```
+
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+
+use smoltcp::time::Duration;
+
+/// Handle of a registered fd.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Token(usize);
+
+/// Readiness an fd is watched for.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Interest {
+    Readable,
+    Writable,
+    Both,
+}
+
+impl Interest {
+    fn events(self) -> libc::c_short {
+        match self {
+            Self::Readable => libc::POLLIN,
+            Self::Writable => libc::POLLOUT,
+            Self::Both => libc::POLLIN | libc::POLLOUT,
+        }
+    }
+}
+
+/// Readiness reported for an fd.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Readiness {
+    pub readable: bool,
+    pub writable: bool,
+    /// The fd is in error or was hung up, reading or writing it reports why.
+    pub error: bool,
+}
+
+impl From<libc::c_short> for Readiness {
+    fn from(revents: libc::c_short) -> Self {
+        Self {
+            readable: revents & (libc::POLLIN | libc::POLLPRI) != 0,
+            writable: revents & libc::POLLOUT != 0,
+            error: revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0,
+        }
+    }
+}
+
+/// A set of fds waited on together with `ppoll`.
+///
+/// The poller does not own the fds: they must stay open while registered.
+#[derive(Default)]
+pub struct Poller {
+    fds: Vec<libc::pollfd>,
+    tokens: Vec<Token>,
+    next_token: usize,
+}
+
+impl Poller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Watches `fd`, e.g. an `XdpSocket`, a timerfd or a Unix socket.
+    pub fn register(&mut self, fd: &impl AsRawFd, interest: Interest) -> Token {
+        self.register_fd(fd.as_raw_fd(), interest)
+    }
+
+    pub fn register_fd(&mut self, fd: RawFd, interest: Interest) -> Token {
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        self.fds.push(libc::pollfd {
+            fd,
+            events: interest.events(),
+            revents: 0,
+        });
+        self.tokens.push(token);
+        token
+    }
+
+    /// Changes what the fd of `token` is watched for.
+    pub fn reregister(&mut self, token: Token, interest: Interest) -> io::Result<()> {
+        let index = self.index(token)?;
+        self.fds[index].events = interest.events();
+        Ok(())
+    }
+
+    pub fn deregister(&mut self, token: Token) -> io::Result<()> {
+        let index = self.index(token)?;
+        self.fds.remove(index);
+        self.tokens.remove(index);
+        Ok(())
+    }
+
+    fn index(&self, token: Token) -> io::Result<usize> {
+        self.tokens
+            .iter()
+            .position(|&registered| registered == token)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown poller token"))
+    }
+
+    /// Waits until a registered fd is ready or `timeout` elapses, forever if `None`, and
+    /// returns the ready fds. Being interrupted by a signal counts as a timeout.
+    ///
+    /// `timeout` is meant to be `Interface::poll_delay`, with microsecond precision.
+    pub fn wait(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> io::Result<impl Iterator<Item = (Token, Readiness)> + '_> {
+        let timespec = timeout.map(|timeout| {
+            let micros = timeout.total_micros();
+            libc::timespec {
+                tv_sec: (micros / 1_000_000) as libc::time_t,
+                tv_nsec: ((micros % 1_000_000) * 1000) as libc::c_long,
+            }
+        });
+        let timespec_ptr = timespec
+            .as_ref()
+            .map_or(std::ptr::null(), |timespec| timespec as *const _);
+
+        for fd in &mut self.fds {
+            fd.revents = 0;
+        }
+        // SAFETY: the pollfd array and the timespec outlive the call.
+        let ready = unsafe {
+            libc::ppoll(
+                self.fds.as_mut_ptr(),
+                self.fds.len() as libc::nfds_t,
+                timespec_ptr,
+                std::ptr::null(),
+            )
+        };
+        if ready == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        Ok(self
+            .fds
+            .iter()
+            .zip(&self.tokens)
+            .filter(|(fd, _)| fd.revents != 0)
+            .map(|(fd, &token)| (token, Readiness::from(fd.revents))))
+    }
+
+    /// Waits like [`Poller::wait`] and calls `f` for every ready fd, returning how many
+    /// there were.
+    pub fn dispatch(
+        &mut self,
+        timeout: Option<Duration>,
+        mut f: impl FnMut(Token, Readiness),
+    ) -> io::Result<usize> {
+        let mut count = 0;
+        for (token, readiness) in self.wait(timeout)? {
+            f(token, readiness);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
@@ -1,13 +1,17 @@
+use crate::phy::sys::netlink::{self, BOND_MODE_ACTIVEBACKUP, BOND_MODE_TLB};
 use crate::phy::xdp::rings::Type;
-use crate::phy::xdp::umem::{HeadRoom, Umem};
+use crate::phy::xdp::umem::Umem;
 use std::ffi::CString;
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::{io, mem};
+use std::{fs, io, mem};
 
 pub struct XdpSocketDesc {
     lower: libc::c_int,
     mtu: usize,
     ifindex: u32,
+    // Set once bound.
+    queue_id: Option<u32>,
 }
 
 impl AsRawFd for XdpSocketDesc {
@@ -17,7 +21,13 @@ impl AsRawFd for XdpSocketDesc {
 }
 
 impl XdpSocketDesc {
+    /// Opens an AF_XDP socket for the interface called `name`.
+    ///
+    /// Bonds cannot be bound directly, so the socket targets the slave they resolve to,
+    /// see [`lower_devices`], while keeping the MTU of `name`.
     pub fn new(name: &str) -> io::Result<XdpSocketDesc> {
+        let lower_name = single_lower_device(name)?;
+
         let lower = unsafe {
             let lower = libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_NONBLOCK, 0);
             if lower == -1 {
@@ -26,34 +36,48 @@ impl XdpSocketDesc {
             lower
         };
 
-        let ifname = CString::new(name)?;
-        let ifindex = unsafe { libc::if_nametoindex(ifname.as_ptr()) };
-        if ifindex == 0 {
-            return Err(io::Error::last_os_error());
-        }
+        let ifindex = if_index(&lower_name)?;
+        let mtu = mtu(name)?;
 
-        let mtu = unsafe {
-            let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
-            if fd < 0 {
-                return Err(io::Error::last_os_error());
-            }
-
-            let mut ifr: libc::ifreq = mem::zeroed();
-            libc::strncpy(ifr.ifr_name.as_mut_ptr(), ifname.as_ptr(), libc::IFNAMSIZ);
-
-            if libc::ioctl(fd, libc::SIOCGIFMTU, &mut ifr) < 0 {
-                libc::close(fd);
-                return Err(io::Error::last_os_error());
-            }
+        Ok(XdpSocketDesc {
+            lower,
+            mtu,
+            ifindex,
+            queue_id: None,
+        })
+    }
 
-            libc::close(fd);
-            ifr.ifr_ifru.ifru_mtu
-        } as usize;
+    /// Adopts `fd`, an AF_XDP socket created by another process and not bound yet, for
+    /// the interface called `name`.
+    pub fn from_fd(fd: OwnedFd, name: &str) -> io::Result<XdpSocketDesc> {
+        let mut domain: libc::c_int = 0;
+        let mut len = mem::size_of_val(&domain) as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_DOMAIN,
+                &mut domain as *mut _ as *mut _,
+                &mut len,
+            )
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if domain != libc::AF_XDP {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The fd is not an AF_XDP socket",
+            ));
+        }
 
+        let ifindex = if_index(&single_lower_device(name)?)?;
+        let mtu = mtu(name)?;
         Ok(XdpSocketDesc {
-            lower,
+            lower: fd.into_raw_fd(),
             mtu,
             ifindex,
+            queue_id: None,
         })
     }
 
@@ -65,13 +89,22 @@ impl XdpSocketDesc {
         self.ifindex
     }
 
-    pub fn bind_interface(&mut self, queue_id: u32) -> io::Result<()> {
+    pub fn queue_id(&self) -> Option<u32> {
+        self.queue_id
+    }
+
+    pub fn bind_interface(
+        &mut self,
+        queue_id: u32,
+        flags: u16,
+        shared_umem_fd: Option<RawFd>,
+    ) -> io::Result<()> {
         let sockaddr = libc::sockaddr_xdp {
             sxdp_family: libc::AF_XDP as u16,
-            sxdp_flags: 0,
+            sxdp_flags: flags,
             sxdp_ifindex: self.ifindex(),
             sxdp_queue_id: queue_id,
-            sxdp_shared_umem_fd: 0,
+            sxdp_shared_umem_fd: shared_umem_fd.unwrap_or(0) as u32,
         };
 
         unsafe {
@@ -85,15 +118,45 @@ impl XdpSocketDesc {
             }
         }
 
+        self.queue_id = Some(queue_id);
         Ok(())
     }
 
     pub fn bind_umem(&self, umem: &Umem) -> io::Result<()> {
-        let config = libc::xdp_umem_reg_v1 {
+        let mut config = libc::xdp_umem_reg {
             addr: umem.base_addr() as u64,
             len: (umem.size() * umem.alignment()) as u64,
             chunk_size: umem.alignment() as u32,
-            headroom: std::mem::size_of::<HeadRoom>() as u32,
+            headroom: umem.headroom() as u32,
+            flags: if umem.unaligned() {
+                libc::XDP_UMEM_UNALIGNED_CHUNK_FLAG
+            } else {
+                0
+            },
+            tx_metadata_len: umem.tx_metadata_len() as u32,
+        };
+        if config.tx_metadata_len == 0 {
+            return self.register_umem(&config);
+        }
+
+        // Linux 6.11 only takes the metadata length along with its flag, which 6.8 to 6.10
+        // reject.
+        config.flags |= libc::XDP_UMEM_TX_METADATA_LEN;
+        match self.register_umem(&config) {
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {
+                config.flags &= !libc::XDP_UMEM_TX_METADATA_LEN;
+                self.register_umem(&config)
+            }
+            result => result,
+        }
+    }
+
+    fn register_umem(&self, config: &libc::xdp_umem_reg) -> io::Result<()> {
+        // Kernels without flags nor TX metadata only take the v1 layout.
+        let len = if config.flags == 0 && config.tx_metadata_len == 0 {
+            mem::size_of::<libc::xdp_umem_reg_v1>()
+        } else {
+            mem::size_of::<libc::xdp_umem_reg>()
         };
 
         let result = unsafe {
@@ -101,8 +164,8 @@ impl XdpSocketDesc {
                 self.lower,
                 libc::SOL_XDP,
                 libc::XDP_UMEM_REG,
-                &config as *const _ as *const _,
-                std::mem::size_of::<libc::xdp_umem_reg_v1>() as libc::socklen_t,
+                config as *const _ as *const _,
+                len as libc::socklen_t,
             )
         };
 
@@ -131,7 +194,366 @@ impl XdpSocketDesc {
         Ok(())
     }
 
+    /// Enables busy polling: syscalls on the socket spin on the device queue for up to
+    /// `usecs` and process up to `budget` packets per poll. With `prefer`, interrupts stay
+    /// masked as long as the application keeps polling.
+    pub fn set_busy_poll(&self, usecs: u32, budget: u32, prefer: bool) -> io::Result<()> {
+        self.set_socket_opt(libc::SO_PREFER_BUSY_POLL, prefer as libc::c_int)?;
+        self.set_socket_opt(libc::SO_BUSY_POLL, usecs as libc::c_int)?;
+        self.set_socket_opt(libc::SO_BUSY_POLL_BUDGET, budget as libc::c_int)
+    }
+
+    pub fn set_busy_poll_budget(&self, budget: u32) -> io::Result<()> {
+        self.set_socket_opt(libc::SO_BUSY_POLL_BUDGET, budget as libc::c_int)
+    }
+
+    /// NAPI context the socket last received from (`SO_INCOMING_NAPI_ID`), 0 before the
+    /// first frame.
+    pub fn napi_id(&self) -> io::Result<u32> {
+        let mut id: libc::c_uint = 0;
+        let mut len = mem::size_of_val(&id) as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                self.lower,
+                libc::SOL_SOCKET,
+                libc::SO_INCOMING_NAPI_ID,
+                &mut id as *mut _ as *mut _,
+                &mut len,
+            )
+        };
+
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(id)
+    }
+
+    fn set_socket_opt(&self, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                self.lower,
+                libc::SOL_SOCKET,
+                name,
+                &value as *const _ as *const _,
+                mem::size_of_val(&value) as libc::socklen_t,
+            )
+        };
+
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Kernel drop and error counters of the socket.
+    pub fn statistics(&self) -> io::Result<libc::xdp_statistics> {
+        let mut stats: libc::xdp_statistics = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::xdp_statistics>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                self.lower,
+                libc::SOL_XDP,
+                libc::XDP_STATISTICS,
+                &mut stats as *mut _ as *mut _,
+                &mut len,
+            )
+        };
+
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(stats)
+    }
+
+    /// Whether the socket was bound in zero-copy mode.
+    pub fn zero_copy(&self) -> io::Result<bool> {
+        let mut options = libc::xdp_options { flags: 0 };
+        let mut len = mem::size_of::<libc::xdp_options>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                self.lower,
+                libc::SOL_XDP,
+                libc::XDP_OPTIONS,
+                &mut options as *mut _ as *mut _,
+                &mut len,
+            )
+        };
+
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(options.flags & libc::XDP_OPTIONS_ZEROCOPY != 0)
+    }
+
+    /// Name of the bound device, the lower one for upper devices.
+    pub fn name(&self) -> io::Result<String> {
+        let mut name = [0 as libc::c_char; libc::IFNAMSIZ];
+        if unsafe { libc::if_indextoname(self.ifindex, name.as_mut_ptr()) }.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: if_indextoname wrote a NUL-terminated name.
+        let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+        Ok(name.to_string_lossy().into_owned())
+    }
+
+    /// Whether the bound device is administratively up and has a carrier.
+    pub fn link_up(&self) -> io::Result<bool> {
+        let mut ifr: libc::ifreq = unsafe { mem::zeroed() };
+        if unsafe { libc::if_indextoname(self.ifindex, ifr.ifr_name.as_mut_ptr()) }.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let flags = unsafe {
+            let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut ifr) < 0 {
+                libc::close(fd);
+                return Err(io::Error::last_os_error());
+            }
+
+            libc::close(fd);
+            ifr.ifr_ifru.ifru_flags
+        } as libc::c_int;
+
+        let up = libc::IFF_UP | libc::IFF_RUNNING;
+        Ok(flags & up == up)
+    }
+
     pub fn close(&self) {
         unsafe { libc::close(self.lower) };
     }
 }
+
+/// Wakes up the kernel to process the TX ring of the socket `fd`.
+///
+/// Transient errors meaning the kernel is busy or out of buffers are ignored, the frames
+/// stay queued until the next kick.
+pub fn kick_tx(fd: RawFd) -> io::Result<()> {
+    let result = unsafe {
+        libc::sendto(
+            fd,
+            std::ptr::null(),
+            0,
+            libc::MSG_DONTWAIT,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if result >= 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EAGAIN | libc::EBUSY | libc::ENOBUFS | libc::ENETDOWN) => Ok(()),
+        _ => Err(err),
+    }
+}
+
+/// Wakes up the driver to take frames from the fill ring of the socket `fd`.
+pub fn kick_rx(fd: RawFd) -> io::Result<()> {
+    let result = unsafe {
+        libc::recvfrom(
+            fd,
+            std::ptr::null_mut(),
+            0,
+            libc::MSG_DONTWAIT,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if result >= 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EAGAIN | libc::EBUSY) => Ok(()),
+        _ => Err(err),
+    }
+}
+
+/// An epoll instance watching a single socket, level-triggered.
+pub struct Epoll {
+    epoll: OwnedFd,
+    fd: RawFd,
+    events: u32,
+}
+
+impl Epoll {
+    pub fn new(fd: RawFd) -> io::Result<Self> {
+        let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epoll < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the fd was just created and is owned by nothing else.
+        let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        if unsafe { libc::epoll_ctl(epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut event) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            epoll,
+            fd,
+            events: 0,
+        })
+    }
+
+    /// Waits up to `timeout_ms` milliseconds, forever if negative, for one of `events`,
+    /// returning the ones reported. Being interrupted by a signal counts as a timeout.
+    pub fn wait(&mut self, events: u32, timeout_ms: i32) -> io::Result<u32> {
+        if events != self.events {
+            let mut event = libc::epoll_event { events, u64: 0 };
+            let ret = unsafe {
+                libc::epoll_ctl(
+                    self.epoll.as_raw_fd(),
+                    libc::EPOLL_CTL_MOD,
+                    self.fd,
+                    &mut event,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.events = events;
+        }
+
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        let ready = unsafe { libc::epoll_wait(self.epoll.as_raw_fd(), &mut event, 1, timeout_ms) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::Interrupted => Ok(0),
+                _ => Err(err),
+            };
+        }
+        Ok(if ready == 0 { 0 } else { event.events })
+    }
+}
+
+/// Resolves the devices actually carrying the traffic of the interface called `name`,
+/// as rtnetlink reports them.
+///
+/// Bonds resolve to every slave, recursively. Any other interface resolves to itself,
+/// except for those no socket can serve faithfully, which fail with `InvalidInput`:
+///
+/// - VLANs, since a socket bound to the device they are stacked on would receive the
+///   frames of every VLAN and send untagged ones.
+/// - Active-backup and balance-tlb bonds, which receive on a single slave that changes
+///   on failover, while a socket stays bound to the slave it was opened on.
+pub fn lower_devices(name: &str) -> io::Result<Vec<String>> {
+    let link = netlink::link(name)?;
+    match (link.kind.as_str(), link.bond_mode) {
+        ("vlan", _) => {
+            let lower = link
+                .link
+                .and_then(|ifindex| {
+                    let links = netlink::links().ok()?;
+                    links.into_iter().find(|lower| lower.ifindex == ifindex)
+                })
+                .map_or_else(|| "its lower device".to_owned(), |lower| lower.name);
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is a VLAN, sockets on {} would see every VLAN and send untagged \
+                     frames",
+                    name, lower
+                ),
+            ))
+        }
+        ("bond", Some(BOND_MODE_ACTIVEBACKUP | BOND_MODE_TLB)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} receives on its active slave only, a socket would not follow a failover",
+                name
+            ),
+        )),
+        ("bond", _) => {
+            let mut lowers = Vec::new();
+            for slave in netlink::links()?
+                .into_iter()
+                .filter(|slave| slave.master == Some(link.ifindex))
+            {
+                lowers.extend(lower_devices(&slave.name)?);
+            }
+            if lowers.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    format!("{} has no slave", name),
+                ));
+            }
+            Ok(lowers)
+        }
+        _ => Ok(vec![name.to_owned()]),
+    }
+}
+
+/// Number of RX queues every lower device of the interface called `name` has, i.e. the
+/// queue ids a socket can be bound to.
+pub fn rx_queues(name: &str) -> io::Result<u32> {
+    let mut queues = u32::MAX;
+    for lower in lower_devices(name)? {
+        let mut count = 0;
+        for entry in fs::read_dir(format!("/sys/class/net/{}/queues", lower))? {
+            if entry?.file_name().to_string_lossy().starts_with("rx-") {
+                count += 1;
+            }
+        }
+        queues = queues.min(count);
+    }
+    Ok(queues)
+}
+
+/// The lower device a socket for the interface called `name` binds to.
+pub(crate) fn single_lower_device(name: &str) -> io::Result<String> {
+    match lower_devices(name)?.as_slice() {
+        [lower] => Ok(lower.clone()),
+        lowers => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} has several active lower devices ({}), open one socket per device",
+                name,
+                lowers.join(", ")
+            ),
+        )),
+    }
+}
+
+/// MTU of the interface called `name`.
+pub fn mtu(name: &str) -> io::Result<usize> {
+    let ifname = CString::new(name)?;
+    let mtu = unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut ifr: libc::ifreq = mem::zeroed();
+        libc::strncpy(ifr.ifr_name.as_mut_ptr(), ifname.as_ptr(), libc::IFNAMSIZ);
+
+        if libc::ioctl(fd, libc::SIOCGIFMTU, &mut ifr) < 0 {
+            libc::close(fd);
+            return Err(io::Error::last_os_error());
+        }
+
+        libc::close(fd);
+        ifr.ifr_ifru.ifru_mtu
+    } as usize;
+    Ok(mtu)
+}
+
+pub fn if_index(name: &str) -> io::Result<u32> {
+    let ifname = CString::new(name)?;
+    let ifindex = unsafe { libc::if_nametoindex(ifname.as_ptr()) };
+    if ifindex == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ifindex)
+}
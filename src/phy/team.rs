@@ -0,0 +1,220 @@
+use smoltcp::phy::{self, Device, DeviceCapabilities, PacketMeta};
+use smoltcp::time::Instant;
+
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+use crate::phy::classify::{Classifier, FiveTuple};
+use crate::phy::health::{HealthCheck, Report, Status};
+use crate::phy::swap::Either;
+
+/// A device spreading egress flows over several independent devices and merging their
+/// ingress, for more bandwidth than a single NIC or queue without LAG on the switch.
+///
+/// Every frame sent is classified, by its 5-tuple hash by default, and leaves through the
+/// member of its class, so the frames of a flow stay in order. Frames the classifier does
+/// not apply to, e.g. ARP, leave through the first member, and replies produced while
+/// processing a received frame through the member that received it. Received frames are
+/// taken from the members in turn.
+///
+/// The member of a frame is only known once smoltcp built it, so no TX token is handed
+/// out while any member is out of them: one busy member holds back the egress of the
+/// whole team rather than having its frames dropped.
+///
+/// Members keep sending with the hardware address of the `Interface`, so without LAG they
+/// are best wired to distinct next hops, e.g. ECMP routers, rather than to a switch that
+/// would learn the address on several ports.
+pub struct FlowTeam<D: Device, C: Classifier = FiveTuple> {
+    members: Vec<D>,
+    classifier: C,
+    // Member to receive from first on the next poll.
+    next: usize,
+    // Frames copied out of smoltcp before their member is known.
+    scratch: Vec<u8>,
+    tx_frames: Vec<u64>,
+    dropped: u64,
+}
+
+impl<D: Device> FlowTeam<D> {
+    /// Teams `members` up by the Toeplitz hash of the 5-tuple with the default RSS key.
+    ///
+    /// Panics if `members` is empty.
+    pub fn new(members: Vec<D>) -> Self {
+        Self::with_classifier(members, FiveTuple::default())
+    }
+}
+
+impl<D: Device, C: Classifier> FlowTeam<D, C> {
+    /// Teams `members` up by the class of `classifier`, see [`FlowTeam::new`].
+    pub fn with_classifier(members: Vec<D>, classifier: C) -> Self {
+        assert!(!members.is_empty(), "A team needs at least one member");
+        Self {
+            tx_frames: vec![0; members.len()],
+            members,
+            classifier,
+            next: 0,
+            scratch: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn classifier(&self) -> &C {
+        &self.classifier
+    }
+
+    /// Index of the member `frame` leaves through.
+    pub fn member_for(&self, frame: &[u8]) -> usize {
+        member_for(&self.classifier, self.members.len(), frame)
+    }
+
+    /// Frames classified to every member and sent, in the order of the members. Replies
+    /// leaving through the member that received a frame are not counted.
+    pub fn tx_frames(&self) -> &[u64] {
+        &self.tx_frames
+    }
+
+    /// Frames dropped because their member ran out of TX tokens while the frame was
+    /// built.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Get a reference to the underlying devices.
+    pub fn get_ref(&self) -> &[D] {
+        &self.members
+    }
+
+    /// Get a mutable reference to the underlying devices.
+    pub fn get_mut(&mut self) -> &mut [D] {
+        &mut self.members
+    }
+
+    /// Return the underlying devices, consuming the wrapper.
+    pub fn into_inner(self) -> Vec<D> {
+        self.members
+    }
+}
+
+fn member_for(classifier: &impl Classifier, members: usize, frame: &[u8]) -> usize {
+    match classifier.classify(frame) {
+        Some(class) => class as usize % members,
+        None => 0,
+    }
+}
+
+impl<D: Device, C: Classifier> Device for FlowTeam<D, C> {
+    type RxToken<'a>
+        = D::RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = Either<D::TxToken<'a>, TxToken<'a, D, C>>
+    where
+        Self: 'a;
+
+    /// Capabilities of the first member, with the smallest MTU of all of them.
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = self.members[0].capabilities();
+        caps.max_transmission_unit = self
+            .members
+            .iter()
+            .map(|member| member.capabilities().max_transmission_unit)
+            .min()
+            .expect("A team has members");
+        caps
+    }
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let count = self.members.len();
+        let (head, tail) = self.members.split_at_mut(self.next);
+        let start = self.next;
+        for (i, member) in tail.iter_mut().chain(head).enumerate() {
+            if let Some((rx, tx)) = member.receive(timestamp) {
+                self.next = (start + i + 1) % count;
+                return Some((rx, Either::Left(tx)));
+            }
+        }
+        None
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        // A token dropped unconsumed sends nothing, so the members can be probed.
+        if !self
+            .members
+            .iter_mut()
+            .all(|member| member.transmit(timestamp).is_some())
+        {
+            return None;
+        }
+        Some(Either::Right(TxToken {
+            members: &mut self.members,
+            classifier: &self.classifier,
+            scratch: &mut self.scratch,
+            tx_frames: &mut self.tx_frames,
+            dropped: &mut self.dropped,
+            timestamp,
+            meta: PacketMeta::default(),
+        }))
+    }
+}
+
+impl<D: Device + HealthCheck, C: Classifier> HealthCheck for FlowTeam<D, C> {
+    fn check(&self, report: &mut Report) {
+        if self.dropped == 0 {
+            report.ok("flow-team");
+        } else {
+            report.fail(
+                "flow-team",
+                Status::Degraded,
+                format!("{} frames dropped for lack of a TX token", self.dropped),
+            );
+        }
+        for member in &self.members {
+            member.check(report);
+        }
+    }
+}
+
+impl<D: Device + CapabilitiesAudit, C: Classifier> CapabilitiesAudit for FlowTeam<D, C> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("flow-team", self.capabilities());
+        self.members[0].audit(report);
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a, D: Device, C: Classifier> {
+    members: &'a mut [D],
+    classifier: &'a C,
+    scratch: &'a mut Vec<u8>,
+    tx_frames: &'a mut [u64],
+    dropped: &'a mut u64,
+    timestamp: Instant,
+    meta: PacketMeta,
+}
+
+impl<D: Device, C: Classifier> phy::TxToken for TxToken<'_, D, C> {
+    /// Builds the frame aside, then copies it to a TX token of its member, dropping it
+    /// when the member has none left.
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.scratch.clear();
+        self.scratch.resize(len, 0);
+        let result = f(self.scratch);
+
+        let idx = member_for(self.classifier, self.members.len(), self.scratch);
+        match self.members[idx].transmit(self.timestamp) {
+            Some(mut token) => {
+                token.set_meta(self.meta);
+                token.consume(len, |buf| buf.copy_from_slice(self.scratch));
+                self.tx_frames[idx] += 1;
+            }
+            None => *self.dropped += 1,
+        }
+        result
+    }
+
+    fn set_meta(&mut self, meta: PacketMeta) {
+        self.meta = meta;
+    }
+}
@@ -0,0 +1,204 @@
+use std::cell::RefCell;
+
+use smoltcp::phy::{self, Device, DeviceCapabilities, PacketMeta};
+use smoltcp::time::Instant;
+
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+use crate::phy::held::{Held, RxToken};
+use crate::phy::scratch::{FrameBuf, FrameScratch};
+
+/// Rewrites whole frames in place on their way to and from a device, e.g. to encrypt or
+/// compress the payload of an overlay protocol.
+pub trait FrameTransform {
+    /// Transforms a frame built by smoltcp before it is sent, false to drop it. The frame
+    /// has `overhead` bytes of headroom to push headers into.
+    fn outbound(&mut self, frame: &mut FrameBuf) -> bool;
+
+    /// Transforms a received frame before smoltcp parses it, false to drop it.
+    fn inbound(&mut self, frame: &mut FrameBuf) -> bool;
+
+    /// Bytes `outbound` may add to a frame, taken off the MTU reported to smoltcp.
+    fn overhead(&self) -> usize {
+        0
+    }
+}
+
+/// A device wrapper running every frame through a [`FrameTransform`].
+///
+/// Frames are copied in and out of the wrapped device through buffers reused from one
+/// frame to the next, and received frames dropped by the transform never reach smoltcp.
+pub struct Transform<D: Device, T: FrameTransform> {
+    inner: D,
+    transform: RefCell<T>,
+    // Outbound frames are built here before being copied into a token of `inner`.
+    scratch: RefCell<FrameScratch>,
+    held: Held,
+}
+
+impl<D: Device, T: FrameTransform> Transform<D, T> {
+    pub fn new(inner: D, transform: T) -> Self {
+        let mtu = inner.capabilities().max_transmission_unit;
+        let scratch = FrameScratch::new(1, mtu, transform.overhead());
+        Self {
+            inner,
+            transform: RefCell::new(transform),
+            scratch: RefCell::new(scratch),
+            held: Held::new(),
+        }
+    }
+
+    pub fn transform(&self) -> &RefCell<T> {
+        &self.transform
+    }
+
+    /// Get a reference to the underlying device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Return the underlying device, consuming the wrapper.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Device, T: FrameTransform> Device for Transform<D, T> {
+    type RxToken<'a>
+        = RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a, D::TxToken<'a>, T>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = self.inner.capabilities();
+        caps.max_transmission_unit = caps
+            .max_transmission_unit
+            .saturating_sub(self.transform.borrow().overhead());
+        caps
+    }
+
+    /// Receives frames until the transform accepts one.
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let transform = self.transform.get_mut();
+        let (rx, tx) = self
+            .held
+            .receive(&mut self.inner, timestamp, |frame, _, tx| {
+                transform.inbound(frame).then_some(tx)
+            })?;
+        Some((
+            rx,
+            TxToken {
+                token: tx,
+                transform: &self.transform,
+                scratch: &self.scratch,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        let tx = self.inner.transmit(timestamp)?;
+        Some(TxToken {
+            token: tx,
+            transform: &self.transform,
+            scratch: &self.scratch,
+        })
+    }
+}
+
+impl<D: CapabilitiesAudit, T: FrameTransform> CapabilitiesAudit for Transform<D, T> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("transform", self.capabilities());
+        self.inner.audit(report);
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a, Tx: phy::TxToken, T: FrameTransform> {
+    token: Tx,
+    transform: &'a RefCell<T>,
+    scratch: &'a RefCell<FrameScratch>,
+}
+
+impl<Tx: phy::TxToken, T: FrameTransform> phy::TxToken for TxToken<'_, Tx, T> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut frame = self.scratch.borrow_mut().take();
+        let result = f(frame.put(len));
+
+        if self.transform.borrow_mut().outbound(&mut frame) {
+            self.token
+                .consume(frame.len(), |buf| buf.copy_from_slice(frame.as_ref()));
+        }
+        self.scratch.borrow_mut().give(frame);
+        result
+    }
+
+    fn set_meta(&mut self, meta: PacketMeta) {
+        self.token.set_meta(meta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smoltcp::phy::{Loopback, Medium, RxToken as _, TxToken as _};
+
+    use super::*;
+
+    /// Prepends a two-byte tag recording the headroom left in front of the frame.
+    struct Tag;
+
+    impl FrameTransform for Tag {
+        fn outbound(&mut self, frame: &mut FrameBuf) -> bool {
+            let headroom = frame.headroom() as u8;
+            frame.push(2).copy_from_slice(&[0xab, headroom]);
+            true
+        }
+
+        fn inbound(&mut self, frame: &mut FrameBuf) -> bool {
+            frame.pull(2).first() == Some(&0xab)
+        }
+
+        fn overhead(&self) -> usize {
+            2
+        }
+    }
+
+    fn send(device: &mut impl Device, frame: &[u8]) {
+        let tx = device.transmit(Instant::ZERO).unwrap();
+        tx.consume(frame.len(), |buf| buf.copy_from_slice(frame));
+    }
+
+    fn receive(device: &mut impl Device) -> Option<Vec<u8>> {
+        let (rx, _) = device.receive(Instant::ZERO)?;
+        Some(rx.consume(|frame| frame.to_vec()))
+    }
+
+    #[test]
+    fn pushes_into_reserved_headroom() {
+        let mut device = Transform::new(Loopback::new(Medium::Ethernet), Tag);
+        for _ in 0..2 {
+            send(&mut device, &[1, 2, 3]);
+            assert_eq!(receive(device.get_mut()).unwrap(), [0xab, 2, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn drops_rejected_frames() {
+        let mut device = Transform::new(Loopback::new(Medium::Ethernet), Tag);
+        send(device.get_mut(), &[0, 0, 9]);
+        send(&mut device, &[1, 2, 3]);
+        send(device.get_mut(), &[]);
+        assert_eq!(receive(&mut device).unwrap(), [1, 2, 3]);
+        assert!(receive(&mut device).is_none());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+
+use smoltcp::phy::{Device, DeviceCapabilities, Medium, PacketMeta};
+use smoltcp::time::Instant;
+
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+use crate::phy::held::{Held, RxToken};
+use crate::phy::link;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Shortest Ethernet frame without FCS, shorter payloads are padded up to it.
+const MIN_FRAME_LEN: usize = 60;
+/// Smallest ethertype, lower values are IEEE 802.3 length fields.
+const MIN_ETHERTYPE: u16 = 0x0600;
+
+/// Rule a malformed frame broke.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Violation {
+    /// Shorter than `Config::min_len` or than its own headers.
+    TooShort,
+    /// The ethertype is an 802.3 length field instead of a protocol.
+    Ethertype,
+    /// Shorter than the IP length it carries.
+    Truncated,
+    /// Longer than the IP length it carries past the Ethernet padding, usually an FCS the
+    /// driver did not strip.
+    Trailer,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// Shortest frame accepted.
+    pub min_len: usize,
+    /// Rejects Ethernet frames whose ethertype is below 0x0600.
+    pub check_ethertype: bool,
+    /// Checks that IPv4 and IPv6 frames are exactly as long as their IP length plus
+    /// padding, i.e. that the FCS was stripped and nothing was cut off.
+    pub fcs_stripped: bool,
+    /// Number of malformed frames kept for inspection, the oldest are dropped first.
+    pub capture: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            min_len: ETHERNET_HEADER_LEN,
+            check_ethertype: true,
+            fcs_stripped: true,
+            capture: 0,
+        }
+    }
+}
+
+/// Malformed frames dropped so far, per violation.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Stats {
+    pub too_short: u64,
+    pub ethertype: u64,
+    pub truncated: u64,
+    pub trailer: u64,
+}
+
+impl Stats {
+    pub fn total(&self) -> u64 {
+        self.too_short + self.ethertype + self.truncated + self.trailer
+    }
+}
+
+/// A malformed frame kept for a bug report.
+#[derive(Clone, Debug)]
+pub struct Capture {
+    pub timestamp: Instant,
+    pub violation: Violation,
+    /// Meta of the RX token, carrying the trace id of the frame with `packetmeta-id`.
+    pub meta: PacketMeta,
+    pub frame: Vec<u8>,
+}
+
+/// A device wrapper dropping malformed RX frames before smoltcp parses them.
+///
+/// Frames are copied out of the wrapped device to be checked, so this is meant for
+/// hunting driver bugs rather than for the fast path. Dropped frames are counted and,
+/// when `Config::capture` is set, the latest ones are kept.
+pub struct Validate<D: Device> {
+    inner: D,
+    config: Config,
+    stats: Stats,
+    captured: VecDeque<Capture>,
+    held: Held,
+}
+
+impl<D: Device> Validate<D> {
+    pub fn new(inner: D, config: Config) -> Self {
+        Self {
+            inner,
+            config,
+            stats: Stats::default(),
+            captured: VecDeque::new(),
+            held: Held::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.captured.truncate(config.capture);
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Malformed frames kept so far, oldest first.
+    pub fn captured(&self) -> impl Iterator<Item = &Capture> {
+        self.captured.iter()
+    }
+
+    /// Removes and returns the malformed frames kept so far.
+    pub fn take_captured(&mut self) -> Vec<Capture> {
+        self.captured.drain(..).collect()
+    }
+
+    /// Get a reference to the underlying device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Return the underlying device, consuming the wrapper.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Device> Device for Validate<D> {
+    type RxToken<'a>
+        = RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    /// Drops malformed frames until a valid one is received.
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let medium = self.inner.capabilities().medium;
+        let (config, stats, captured) = (&self.config, &mut self.stats, &mut self.captured);
+        self.held
+            .receive(&mut self.inner, timestamp, |frame, meta, tx| {
+                let Some(violation) = check(config, medium, frame.as_ref()) else {
+                    return Some(tx);
+                };
+                match violation {
+                    Violation::TooShort => stats.too_short += 1,
+                    Violation::Ethertype => stats.ethertype += 1,
+                    Violation::Truncated => stats.truncated += 1,
+                    Violation::Trailer => stats.trailer += 1,
+                }
+                if config.capture > 0 {
+                    if captured.len() == config.capture {
+                        captured.pop_front();
+                    }
+                    captured.push_back(Capture {
+                        timestamp,
+                        violation,
+                        meta,
+                        frame: frame.as_ref().to_vec(),
+                    });
+                }
+                None
+            })
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.inner.transmit(timestamp)
+    }
+}
+
+impl<D: CapabilitiesAudit> CapabilitiesAudit for Validate<D> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("validate", self.capabilities());
+        self.inner.audit(report);
+    }
+}
+
+fn check(config: &Config, medium: Medium, frame: &[u8]) -> Option<Violation> {
+    if frame.len() < config.min_len {
+        return Some(Violation::TooShort);
+    }
+
+    let (ethertype, packet) = match medium {
+        Medium::Ethernet => {
+            let Some((ethertype, offset)) = link::ethernet(frame) else {
+                return Some(Violation::TooShort);
+            };
+            if config.check_ethertype && ethertype < MIN_ETHERTYPE {
+                return Some(Violation::Ethertype);
+            }
+            (Some(ethertype), &frame[offset..])
+        }
+        _ => (None, frame),
+    };
+
+    if !config.fcs_stripped {
+        return None;
+    }
+
+    let version = packet.first().map(|byte| byte >> 4);
+    let ip_len = match (ethertype, version) {
+        (Some(0x0800), _) | (None, Some(4)) => {
+            if packet.len() < 20 {
+                return Some(Violation::Truncated);
+            }
+            u16::from_be_bytes([packet[2], packet[3]]) as usize
+        }
+        (Some(0x86dd), _) | (None, Some(6)) => {
+            if packet.len() < 40 {
+                return Some(Violation::Truncated);
+            }
+            match u16::from_be_bytes([packet[4], packet[5]]) {
+                // Jumbograms carry their length in a hop-by-hop option.
+                0 => return None,
+                payload_len => 40 + payload_len as usize,
+            }
+        }
+        _ => return None,
+    };
+
+    if packet.len() < ip_len {
+        return Some(Violation::Truncated);
+    }
+    let padded = ethertype.is_some() && frame.len() <= MIN_FRAME_LEN;
+    if packet.len() > ip_len && !padded {
+        return Some(Violation::Trailer);
+    }
+    None
+}
@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+
+use smoltcp::phy::{self, ChecksumCapabilities, Device, DeviceCapabilities, Medium, TxToken};
+use smoltcp::time::Instant;
+use smoltcp::wire::{
+    ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+    EthernetRepr, HardwareAddress, Icmpv6Packet, Icmpv6Repr, IpAddress, IpProtocol, Ipv4Address,
+    Ipv6Address, Ipv6Packet, Ipv6Repr, NdiscRepr,
+};
+
+use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+use crate::phy::swap::Either;
+
+/// Outcome of the discovery of one neighbor.
+#[derive(Copy, Clone, Debug)]
+pub struct Resolution {
+    pub addr: IpAddress,
+    /// Link-layer address of the neighbor, `None` if it did not answer in time.
+    pub hardware_addr: Option<EthernetAddress>,
+    /// Time between the first probe and the answer.
+    pub elapsed: Option<Duration>,
+}
+
+/// Resolves a list of neighbors through a device before the smoltcp `Interface` starts
+/// using it.
+///
+/// ARP requests and IPv6 neighbor solicitations are sent from the given addresses, which
+/// must be the ones configured on the `Interface`. The answers are replayed to the
+/// `Interface` by the returned [`Warmed`] device so they land in its neighbor cache and
+/// the first packet to every neighbor does not wait for discovery.
+pub struct Warmup {
+    hardware_addr: EthernetAddress,
+    ipv4: Option<Ipv4Address>,
+    ipv6: Option<Ipv6Address>,
+    timeout: Duration,
+    retransmit: Duration,
+}
+
+impl Warmup {
+    pub fn new(hardware_addr: EthernetAddress) -> Self {
+        Self {
+            hardware_addr,
+            ipv4: None,
+            ipv6: None,
+            timeout: Duration::from_secs(1),
+            retransmit: Duration::from_millis(200),
+        }
+    }
+
+    /// Source address of ARP requests. IPv4 neighbors are skipped without one.
+    pub fn ipv4(mut self, addr: Ipv4Address) -> Self {
+        self.ipv4 = Some(addr);
+        self
+    }
+
+    /// Source address of neighbor solicitations. IPv6 neighbors are skipped without one.
+    pub fn ipv6(mut self, addr: Ipv6Address) -> Self {
+        self.ipv6 = Some(addr);
+        self
+    }
+
+    /// Total time given to the neighbors to answer. Defaults to 1 s.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Interval between probes to neighbors that did not answer yet. Defaults to 200 ms.
+    pub fn retransmit(mut self, retransmit: Duration) -> Self {
+        self.retransmit = retransmit;
+        self
+    }
+
+    /// Probes `neighbors` through `device` until all of them answered or the timeout
+    /// expires.
+    ///
+    /// Frames other than the answers received meanwhile are dropped.
+    pub fn run<D: Device>(&self, mut device: D, neighbors: &[IpAddress]) -> Warmed<D> {
+        let mut resolutions: Vec<_> = neighbors
+            .iter()
+            .map(|&addr| Resolution {
+                addr,
+                hardware_addr: None,
+                elapsed: None,
+            })
+            .collect();
+        let mut replies = VecDeque::new();
+
+        let start = std::time::Instant::now();
+        let mut last_probe: Option<std::time::Instant> = None;
+        while start.elapsed() < self.timeout
+            && resolutions.iter().any(|r| r.hardware_addr.is_none())
+        {
+            let now = Instant::now();
+
+            if last_probe.is_none_or(|probe| probe.elapsed() >= self.retransmit) {
+                for resolution in resolutions.iter().filter(|r| r.hardware_addr.is_none()) {
+                    self.probe(&mut device, now, resolution.addr);
+                }
+                last_probe = Some(std::time::Instant::now());
+            }
+
+            while let Some((rx, _)) = device.receive(now) {
+                phy::RxToken::consume(rx, |frame| {
+                    let Some((addr, hardware_addr)) = self.parse_reply(frame) else {
+                        return;
+                    };
+                    if let Some(resolution) = resolutions
+                        .iter_mut()
+                        .find(|r| r.addr == addr && r.hardware_addr.is_none())
+                    {
+                        resolution.hardware_addr = Some(hardware_addr);
+                        resolution.elapsed = Some(start.elapsed());
+                        replies.push_back(frame.to_vec());
+                    }
+                });
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        Warmed {
+            inner: device,
+            replies,
+            resolutions,
+        }
+    }
+
+    fn probe<D: Device>(&self, device: &mut D, timestamp: Instant, addr: IpAddress) {
+        match addr {
+            IpAddress::Ipv4(target) => {
+                let Some(source) = self.ipv4 else {
+                    return;
+                };
+                let arp = ArpRepr::EthernetIpv4 {
+                    operation: ArpOperation::Request,
+                    source_hardware_addr: self.hardware_addr,
+                    source_protocol_addr: source,
+                    target_hardware_addr: EthernetAddress([0; 6]),
+                    target_protocol_addr: target,
+                };
+                let eth = EthernetRepr {
+                    src_addr: self.hardware_addr,
+                    dst_addr: EthernetAddress::BROADCAST,
+                    ethertype: EthernetProtocol::Arp,
+                };
+
+                let Some(tx) = device.transmit(timestamp) else {
+                    return;
+                };
+                tx.consume(eth.buffer_len() + arp.buffer_len(), |buf| {
+                    let mut frame = EthernetFrame::new_unchecked(buf);
+                    eth.emit(&mut frame);
+                    arp.emit(&mut ArpPacket::new_unchecked(frame.payload_mut()));
+                });
+            }
+            IpAddress::Ipv6(target) => {
+                let Some(source) = self.ipv6 else {
+                    return;
+                };
+                // Solicited-node multicast address and its Ethernet mapping.
+                let low = &target.octets()[13..];
+                let mut group = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0, 0, 0];
+                group[13..].copy_from_slice(low);
+                let group = Ipv6Address::from(group);
+
+                let icmp = Icmpv6Repr::Ndisc(NdiscRepr::NeighborSolicit {
+                    target_addr: target,
+                    lladdr: Some(self.hardware_addr.into()),
+                });
+                let ip = Ipv6Repr {
+                    src_addr: source,
+                    dst_addr: group,
+                    next_header: IpProtocol::Icmpv6,
+                    payload_len: icmp.buffer_len(),
+                    hop_limit: 255,
+                };
+                let eth = EthernetRepr {
+                    src_addr: self.hardware_addr,
+                    dst_addr: EthernetAddress([0x33, 0x33, 0xff, low[0], low[1], low[2]]),
+                    ethertype: EthernetProtocol::Ipv6,
+                };
+
+                let Some(tx) = device.transmit(timestamp) else {
+                    return;
+                };
+                let len = eth.buffer_len() + ip.buffer_len() + icmp.buffer_len();
+                tx.consume(len, |buf| {
+                    let mut frame = EthernetFrame::new_unchecked(buf);
+                    eth.emit(&mut frame);
+                    let mut packet = Ipv6Packet::new_unchecked(frame.payload_mut());
+                    ip.emit(&mut packet);
+                    icmp.emit(
+                        &source,
+                        &group,
+                        &mut Icmpv6Packet::new_unchecked(packet.payload_mut()),
+                        &ChecksumCapabilities::default(),
+                    );
+                });
+            }
+        }
+    }
+
+    /// Extracts the neighbor answering a probe, if `frame` is such an answer.
+    fn parse_reply(&self, frame: &[u8]) -> Option<(IpAddress, EthernetAddress)> {
+        let eth = EthernetFrame::new_checked(frame).ok()?;
+        match eth.ethertype() {
+            EthernetProtocol::Arp => {
+                let packet = ArpPacket::new_checked(eth.payload()).ok()?;
+                match ArpRepr::parse(&packet).ok()? {
+                    ArpRepr::EthernetIpv4 {
+                        operation: ArpOperation::Reply,
+                        source_hardware_addr,
+                        source_protocol_addr,
+                        target_protocol_addr,
+                        ..
+                    } if Some(target_protocol_addr) == self.ipv4 => {
+                        Some((source_protocol_addr.into(), source_hardware_addr))
+                    }
+                    _ => None,
+                }
+            }
+            EthernetProtocol::Ipv6 => {
+                let ip = Ipv6Packet::new_checked(eth.payload()).ok()?;
+                if ip.next_header() != IpProtocol::Icmpv6 || Some(ip.dst_addr()) != self.ipv6 {
+                    return None;
+                }
+                let packet = Icmpv6Packet::new_checked(ip.payload()).ok()?;
+                let repr = Icmpv6Repr::parse(
+                    &ip.src_addr(),
+                    &ip.dst_addr(),
+                    &packet,
+                    &ChecksumCapabilities::default(),
+                )
+                .ok()?;
+                match repr {
+                    Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
+                        target_addr,
+                        lladdr: Some(lladdr),
+                        ..
+                    }) => match lladdr.parse(Medium::Ethernet).ok()? {
+                        HardwareAddress::Ethernet(hardware_addr) => {
+                            Some((target_addr.into(), hardware_addr))
+                        }
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A device that first delivers the neighbor answers collected by [`Warmup::run`], then
+/// forwards to the wrapped device.
+pub struct Warmed<D: Device> {
+    inner: D,
+    replies: VecDeque<Vec<u8>>,
+    resolutions: Vec<Resolution>,
+}
+
+impl<D: Device> Warmed<D> {
+    /// Outcome of the discovery of every neighbor, in the order they were given.
+    pub fn resolutions(&self) -> &[Resolution] {
+        &self.resolutions
+    }
+
+    /// Neighbors that did not answer.
+    pub fn unresolved(&self) -> impl Iterator<Item = IpAddress> + '_ {
+        self.resolutions
+            .iter()
+            .filter(|r| r.hardware_addr.is_none())
+            .map(|r| r.addr)
+    }
+
+    /// Get a reference to the underlying device.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device.
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Return the underlying device, consuming the wrapper.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Device> Device for Warmed<D> {
+    type RxToken<'a>
+        = Either<Reply, D::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        if !self.replies.is_empty() {
+            let tx = self.inner.transmit(timestamp)?;
+            let frame = self.replies.pop_front()?;
+            return Some((Either::Left(Reply { frame }), tx));
+        }
+
+        self.inner
+            .receive(timestamp)
+            .map(|(rx, tx)| (Either::Right(rx), tx))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.inner.transmit(timestamp)
+    }
+}
+
+impl<D: CapabilitiesAudit> CapabilitiesAudit for Warmed<D> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("warmup", self.capabilities());
+        self.inner.audit(report);
+    }
+}
+
+/// A neighbor answer recorded during warm-up.
+pub struct Reply {
+    frame: Vec<u8>,
+}
+
+impl phy::RxToken for Reply {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.frame)
+    }
+}
@@ -1,47 +1,639 @@
 use std::{
-    cell::RefCell,
-    io,
-    os::fd::{AsRawFd, RawFd},
-    rc::Rc,
+    cell::Cell,
+    collections::VecDeque,
+    io, mem,
+    ops::ControlFlow,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
 };
 
 use smoltcp::{
-    phy::{Device, DeviceCapabilities},
-    time::Instant,
+    iface::{Interface, SocketSet},
+    phy::{self, Checksum, Device, DeviceCapabilities},
+    time::{Duration, Instant},
+    wire::{EthernetAddress, EthernetFrame, EthernetProtocol},
 };
 
 use crate::phy::{
-    sys::xdp::XdpSocketDesc,
+    budget::PollBudget,
+    caps::{CapabilitiesAudit, DeviceCapabilitiesReport},
+    health::{HealthCheck, Report, Status},
+    scratch::{FrameBuf, FrameScratch},
+    sys,
+    sys::bpf::BPF_XDP,
+    sys::netlink::{
+        self, XDP_FLAGS_DRV_MODE, XDP_FLAGS_HW_MODE, XDP_FLAGS_SKB_MODE,
+        XDP_FLAGS_UPDATE_IF_NOEXIST,
+    },
+    sys::xdp::{XdpSocketDesc, rx_queues},
     xdp::{
+        redirect::Redirect,
+        refill::Refiller,
         rings::{Reader, Type, Writer, XdpRing},
-        umem::Umem,
+        telemetry::{KernelStats, Meter, Profile, Profiler, Stats, Telemetry},
+        test_run::Action,
+        umem::{ChunkAlignment, HeadRoom},
     },
 };
 
+pub(crate) mod audit;
+pub(crate) mod checksum;
+#[cfg(feature = "serde")]
+pub(crate) mod persist;
+pub(crate) mod priority;
+pub(crate) mod refill;
 pub(crate) mod rings;
 pub(crate) mod umem;
 
+pub mod adaptive;
+#[cfg(feature = "tokio")]
+pub mod async_fd;
+#[cfg(feature = "async-io")]
+pub mod async_io;
+#[cfg(feature = "libbpf")]
+pub mod bpf;
+pub mod builder;
+pub mod error;
+pub mod meta;
+#[cfg(feature = "mio")]
+pub mod mio;
+pub mod multi;
+pub mod passthrough;
+pub mod plan;
+pub mod redirect;
+pub mod reinject;
+pub mod shared;
+pub mod telemetry;
+pub mod test_run;
+
+pub use builder::XdpSocketBuilder;
+pub use error::XdpError;
+pub use multi::{Schedule, XdpMultiQueue};
+pub use plan::Plan;
+pub use refill::Config as RefillConfig;
 pub use rings::Config as RingConfig;
-pub use umem::ChunkAlignment as ChunkConfig;
+pub use rings::{RawRing, Type as RingType};
+pub use umem::ChunkAlignment as ChunkSize;
 pub use umem::Config as UmemConfig;
+pub use umem::RawUmem;
+pub use umem::Umem;
+pub use umem::{Backing as UmemBacking, FreeList as UmemFreeList, HugePageSize};
+
+/// Former name of [`ChunkSize`].
+#[deprecated(note = "renamed to `ChunkSize`")]
+pub type ChunkConfig = ChunkSize;
+
+pub use crate::phy::sys::ethtool::DriverInfo;
+pub use crate::phy::sys::xdp::lower_devices;
 
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Shortest Ethernet frame without FCS.
+pub const MIN_FRAME_LEN: usize = 60;
+/// Room the kernel keeps in front of every received frame, `XDP_PACKET_HEADROOM`.
+const XDP_PACKET_HEADROOM: usize = 256;
+/// Default rate sampling window and EWMA weight of the telemetry.
+const TELEMETRY_WINDOW: Duration = Duration::from_millis(100);
+const TELEMETRY_ALPHA: f64 = 0.25;
+const MAX_UMEM_ENTRIES: usize = umem::MAX_ENTRIES;
+/// Probe frames of [`XdpSocket::self_test`], from a locally administered address with
+/// the IEEE local experimental ethertype.
+const SELF_TEST_SOURCE: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0x01]);
+const SELF_TEST_ETHERTYPE: u16 = 0x88b5;
+
+/// An AF_XDP socket bound to a queue of an interface, as a smoltcp `Device`.
+///
+/// The socket and its tokens are `Send`, so it may be opened on one thread and polled
+/// from a datapath thread of its own, and the halves of [`XdpSocket::split`] from two.
 pub struct XdpSocket<'a> {
     lower: XdpSocketDesc,
-    inner: Rc<RefCell<Inner<'a>>>,
+    inner: Arc<Mutex<Inner<'a>>>,
+    direction: Direction,
+    rx_budget: PollBudget,
+    bind_mode: BindMode,
+    // Index of the socket in `Inner::accounts`.
+    account: usize,
+    // Kernel counters as of the previous health check.
+    last_stats: Cell<libc::xdp_statistics>,
+    // Detached after the socket is closed.
+    #[cfg(feature = "libbpf")]
+    program: Option<bpf::Program>,
+    // Shared with the sockets opened through `share` on the same interface.
+    redirect: Option<Arc<Redirect>>,
+    verdict: Option<VerdictHook<'a>>,
+    // Frames the verdict hook put aside for the application, at most `queue_limit`.
+    queued: VecDeque<FrameBuf>,
+    queue_limit: usize,
+    // Created by the first `wait`.
+    epoll: Option<sys::xdp::Epoll>,
+}
+
+/// What [`XdpSocket::wait`] returned for, nothing on timeout.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Ready {
+    /// Received frames wait in the RX ring.
+    pub rx: bool,
+    /// Sent frames completed, see [`XdpSocket::poll_completions`].
+    pub completions: bool,
+}
+
+/// How frames move between the NIC and the UMEM.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BindMode {
+    /// Zero-copy if the driver supports it, copy otherwise.
+    Auto,
+    /// Zero-copy only, binding fails if the driver does not support it.
+    ZeroCopy,
+    /// The kernel copies frames between its buffers and the UMEM.
+    Copy,
+}
+
+/// Where an XDP program runs.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AttachMode {
+    /// The best mode able to redirect to AF_XDP sockets: in the driver if it supports
+    /// XDP, generic otherwise. Offloaded programs cannot redirect to sockets, so `Auto`
+    /// never offloads.
+    Auto,
+    /// In the driver, attaching fails if it does not support XDP
+    /// (`XDP_FLAGS_DRV_MODE`).
+    Native,
+    /// In the kernel stack after the SKB allocation, supported by every driver
+    /// (`XDP_FLAGS_SKB_MODE`).
+    Generic,
+    /// On the NIC itself, for programs that only drop or pass frames
+    /// (`XDP_FLAGS_HW_MODE`).
+    Offload,
+}
+
+/// A program attached by the crate, detached on drop.
+///
+/// Programs are attached through a BPF link when the kernel supports it, which the kernel
+/// also detaches when the process dies without dropping it, and through netlink
+/// otherwise or to replace another program.
+pub(crate) struct Attachment {
+    ifindex: u32,
+    // Attach flags, the mode in use among them.
+    flags: u32,
+    link: Option<OwnedFd>,
+}
+
+impl Attachment {
+    pub(crate) fn ifindex(&self) -> u32 {
+        self.ifindex
+    }
+
+    pub(crate) fn mode(&self) -> AttachMode {
+        if self.flags & XDP_FLAGS_HW_MODE != 0 {
+            AttachMode::Offload
+        } else if self.flags & XDP_FLAGS_DRV_MODE != 0 {
+            AttachMode::Native
+        } else {
+            AttachMode::Generic
+        }
+    }
+
+    /// Atomically swaps the attached program `old_prog_fd` for `new_prog_fd`, in the same
+    /// mode, without a window where frames bypass XDP.
+    #[cfg(feature = "libbpf")]
+    pub(crate) fn replace(&mut self, old_prog_fd: RawFd, new_prog_fd: RawFd) -> io::Result<()> {
+        match &self.link {
+            Some(link) => sys::bpf::link_update(link.as_raw_fd(), new_prog_fd, old_prog_fd),
+            None => {
+                // XDP_FLAGS_UPDATE_IF_NOEXIST would refuse to touch the attached program.
+                let mode =
+                    self.flags & (XDP_FLAGS_DRV_MODE | XDP_FLAGS_SKB_MODE | XDP_FLAGS_HW_MODE);
+                netlink::replace_xdp_fd(self.ifindex, new_prog_fd, old_prog_fd, mode)
+            }
+        }
+    }
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        // Closing the link detaches the program.
+        if self.link.is_none() {
+            let mode = self.flags & (XDP_FLAGS_DRV_MODE | XDP_FLAGS_SKB_MODE | XDP_FLAGS_HW_MODE);
+            let _ = netlink::set_xdp_fd(self.ifindex, -1, mode);
+        }
+    }
+}
+
+/// Attaches the program `prog_fd` to the interface `ifindex`. Fails with `AlreadyExists`
+/// if the interface has a program and `replace` is false.
+pub(crate) fn attach_program(
+    ifindex: u32,
+    prog_fd: RawFd,
+    mode: AttachMode,
+    replace: bool,
+) -> io::Result<Attachment> {
+    let attach = |mode: u32| {
+        // Links never replace a program, netlink does without XDP_FLAGS_UPDATE_IF_NOEXIST.
+        if !replace {
+            match sys::bpf::link_create(prog_fd, ifindex, BPF_XDP, mode) {
+                Ok(link) => {
+                    return Ok(Attachment {
+                        ifindex,
+                        flags: mode,
+                        link: Some(link),
+                    });
+                }
+                // Kernels before 5.9 have no XDP links.
+                Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let flags = if replace {
+            mode
+        } else {
+            mode | XDP_FLAGS_UPDATE_IF_NOEXIST
+        };
+        netlink::set_xdp_fd(ifindex, prog_fd, flags)?;
+        Ok(Attachment {
+            ifindex,
+            flags,
+            link: None,
+        })
+    };
+    // The kernel answers EBUSY for a program in the same mode, EEXIST in the other one.
+    let attached = |err: &io::Error| {
+        matches!(
+            err.kind(),
+            io::ErrorKind::ResourceBusy | io::ErrorKind::AlreadyExists
+        )
+    };
+
+    let result = match mode {
+        AttachMode::Native => attach(XDP_FLAGS_DRV_MODE),
+        AttachMode::Generic => attach(XDP_FLAGS_SKB_MODE),
+        AttachMode::Offload => attach(XDP_FLAGS_HW_MODE),
+        AttachMode::Auto => match attach(XDP_FLAGS_DRV_MODE) {
+            Err(err) if !attached(&err) => attach(XDP_FLAGS_SKB_MODE),
+            result => result,
+        },
+    };
+    result.map_err(|err| {
+        if attached(&err) {
+            io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "Another XDP program is attached to the interface",
+            )
+        } else {
+            err
+        }
+    })
+}
+
+/// Traffic directions served by a socket.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Both,
+    Rx,
+    Tx,
+}
+
+type VerdictHook<'a> = Box<dyn FnMut(&[u8]) -> Verdict + Send + 'a>;
+
+/// Fate of a received frame, decided by the hook of [`XdpSocket::set_verdict`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Verdict {
+    /// Hands the frame to smoltcp.
+    Pass,
+    /// Recycles the frame without copying it out of the UMEM.
+    Drop,
+    /// Puts the frame aside for the application, see [`XdpSocket::pop_queued`].
+    Queue,
 }
 
 impl Drop for XdpSocket<'_> {
     fn drop(&mut self) {
+        // The socket leaves the XSKMAP and the program it attached is detached, unless
+        // another socket still uses it, before the socket is closed: nothing is
+        // redirected to a socket on its way out.
+        if let Some(queue_id) = self.lower.queue_id() {
+            let fd = self.lower.as_raw_fd();
+            if let Some(redirect) = self.redirect.take() {
+                redirect.release(fd, queue_id);
+            }
+            #[cfg(feature = "libbpf")]
+            if let Some(program) = self.program.take() {
+                let _ = program.unregister(queue_id);
+            }
+        }
         self.lower.close();
     }
 }
 
+/// Locks the state a socket shares with its tokens, and with the other half of a split
+/// socket. Application callbacks run under the lock but a panic in one of them leaves
+/// the state consistent, so poisoning is ignored.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 struct Inner<'a> {
+    umem: Arc<Mutex<SharedUmem<'a>>>,
+    tx: XdpRing<Writer>,
+    rx: XdpRing<Reader>,
+    cr: XdpRing<Reader, u64>,
+    fr: Fill,
+    scratch: FrameScratch,
+    rx_pool: FrameScratch,
+    // Descriptors dequeued from the RX ring in one batch, handed out one per receive.
+    rx_batch: VecDeque<libc::xdp_desc>,
+    rx_batch_size: usize,
+    // When the queued batch was dequeued, shared by all its descriptors.
+    rx_batch_at: std::time::Instant,
+    // Cookies of completed frames not yet handed to the application.
+    completions: VecDeque<u64>,
+    // Socket owning the TX ring, kicked to make the kernel process it.
+    tx_fd: RawFd,
+    // Frames queued on the TX ring since the last kick, the kernel is kicked once
+    // `tx_batch` of them are queued.
+    tx_unkicked: usize,
+    tx_batch: usize,
+    tx_min_len: usize,
+    // TCP and UDP checksums of sent frames are left to the NIC.
+    tx_checksum: bool,
+    // DSCP classes of control-plane frames, set when the UMEM has a reserve for them.
+    tx_priority: Option<u64>,
+    // Bound with XDP_USE_SG, frames larger than a chunk are chained.
+    multi_buffer: bool,
+    // Descriptors of the multi-buffer TX frame being queued.
+    tx_chain: Vec<libc::xdp_desc>,
+    // Fragments of a multi-buffer frame read so far, and the metadata of the first one.
+    rx_partial: Option<(FrameBuf, Option<meta::Meta>, std::time::Instant)>,
+    rx_verified: VerifiedChecksums,
+    max_burst_size: Option<usize>,
+    // RX frames recycled since the last fill ring update, posted once `fill_batch` of
+    // them are queued.
+    fill_queue: Vec<u64>,
+    fill_batch: usize,
+    need_wakeup: bool,
+    // The device queue is only processed from syscalls, so every poll has to make one.
+    busy_poll: bool,
+    rx_meter: Meter,
+    tx_meter: Meter,
+    profiler: Profiler,
+    stats: Stats,
+    // First unexpected TX error since the last `XdpSocket::take_tx_error`.
+    tx_error: Option<XdpError>,
+    // Trace id of the next received frame.
+    next_trace_id: u32,
+}
+
+impl<'a> Inner<'a> {
+    fn new(
+        umem: Arc<Mutex<SharedUmem<'a>>>,
+        rings: Rings,
+        config: &Config,
+        mtu: usize,
+        tx_fd: RawFd,
+    ) -> Result<Arc<Mutex<Self>>, XdpError> {
+        let Rings { tx, rx, cr, mut fr } = rings;
+        if config.tx_checksum && lock(&umem).umem.tx_metadata_len() == 0 {
+            return Err(XdpError::InvalidConfig(
+                "TX checksum offload needs a UMEM with TX metadata".to_owned(),
+            ));
+        }
+
+        let max_burst_size = match config.capabilities.max_burst_size {
+            BurstSize::Unknown => None,
+            BurstSize::Rings => Some(tx.size().min(rx.size()) as usize),
+            BurstSize::Fixed(size) => Some(size),
+        };
+
+        // Frames posted to the fill ring are dedicated to RX and recycled through it, the
+        // rest of the UMEM stays in the free list for TX.
+        let rx_frames = lock(&umem).umem.reserve(fr.size() as usize);
+        fr.write_batch(&rx_frames);
+        if let Some(priority) = config.tx_priority {
+            lock(&umem).umem.reserve_priority(priority.pages);
+        }
+
+        let fr = match config.refill {
+            Some(refill) => Fill::Background(Refiller::spawn(fr, &umem, refill)?),
+            None => Fill::Inline(fr),
+        };
+
+        // TX frames are built in a single scratch buffer before being copied into the UMEM.
+        let scratch = FrameScratch::new(1, mtu + ETHERNET_HEADER_LEN, 0);
+        // Without a registered pool every received frame gets its own allocation.
+        let rx_pool = FrameScratch::new(0, mtu + ETHERNET_HEADER_LEN, 0);
+
+        Ok(Arc::new(Mutex::new(Inner {
+            umem,
+            tx,
+            rx,
+            cr,
+            fr,
+            scratch,
+            rx_pool,
+            rx_batch: VecDeque::with_capacity(config.rx_batch),
+            rx_batch_size: config.rx_batch,
+            rx_batch_at: std::time::Instant::now(),
+            completions: VecDeque::new(),
+            tx_fd,
+            tx_unkicked: 0,
+            tx_batch: config.tx_batch.max(1),
+            tx_min_len: config.tx_min_len,
+            tx_checksum: config.tx_checksum,
+            tx_priority: config.tx_priority.map(|priority| priority.dscp),
+            multi_buffer: config.multi_buffer,
+            tx_chain: Vec::new(),
+            rx_partial: None,
+            rx_verified: config.capabilities.rx_verified,
+            max_burst_size,
+            fill_queue: Vec::with_capacity(config.fill_batch),
+            fill_batch: config.fill_batch.max(1),
+            need_wakeup: config.need_wakeup,
+            busy_poll: config.busy_poll.is_some(),
+            rx_meter: Meter::new(TELEMETRY_WINDOW, TELEMETRY_ALPHA),
+            tx_meter: Meter::new(TELEMETRY_WINDOW, TELEMETRY_ALPHA),
+            profiler: Profiler::default(),
+            stats: Stats::default(),
+            tx_error: None,
+            next_trace_id: 0,
+        })))
+    }
+
+    /// Next RX descriptor, draining a whole batch from the ring when none is queued,
+    /// with the time it left the ring.
+    fn next_rx(&mut self) -> Option<(libc::xdp_desc, std::time::Instant)> {
+        if self.rx_batch_size <= 1 {
+            return self.rx.read().map(|desc| (desc, std::time::Instant::now()));
+        }
+        if self.rx_batch.is_empty()
+            && self.rx.read_batch(&mut self.rx_batch, self.rx_batch_size) > 0
+        {
+            self.rx_batch_at = std::time::Instant::now();
+        }
+        let desc = self.rx_batch.pop_front()?;
+        Some((desc, self.rx_batch_at))
+    }
+
+    /// Hands the frame at `addr` back to the kernel once `fill_batch` frames are queued.
+    fn recycle(&mut self, addr: u64) {
+        if self.fill_batch <= 1 || matches!(self.fr, Fill::Background(_)) {
+            self.fr.push(addr);
+            return;
+        }
+        self.fill_queue.push(addr);
+        if self.fill_queue.len() >= self.fill_batch {
+            self.flush_fill();
+        }
+    }
+
+    /// Posts the recycled frames of an unfinished batch to the fill ring.
+    fn flush_fill(&mut self) {
+        if self.fill_queue.is_empty() {
+            return;
+        }
+        if let Fill::Inline(fr) = &mut self.fr {
+            // The ring holds every RX frame, so there is always room for the recycled ones.
+            fr.write_batch(&self.fill_queue);
+        }
+        self.fill_queue.clear();
+    }
+
+    /// Kicks the kernel to process the frames queued on the TX ring, if any.
+    fn flush_tx(&mut self) {
+        if self.tx_unkicked == 0 {
+            return;
+        }
+        self.tx_unkicked = 0;
+        if self.busy_poll || !self.need_wakeup || self.tx.needs_wakeup() {
+            let _ = sys::xdp::kick_tx(self.tx_fd);
+            self.profiler.tx_kick();
+        }
+    }
+
+    /// Whether a frame not known to be control-plane traffic can be queued right away
+    /// for the socket owning `account`: the TX ring has an entry left, the socket is under
+    /// its TX quota and the UMEM has a page outside the control-plane reserve. A refusal
+    /// for the quota is counted on the account.
+    fn tx_ready(&self, account: usize) -> bool {
+        if self.tx.pending() >= self.tx.size() {
+            return false;
+        }
+        let mut shared = lock(&self.umem);
+        let account = &mut shared.accounts[account];
+        if account
+            .quota
+            .is_some_and(|quota| account.in_flight >= quota)
+        {
+            account.exhausted += 1;
+            return false;
+        }
+        shared.umem.has_free()
+    }
+
+    /// Drains the completion ring in one batch, returning the page of every completed TX
+    /// frame to the UMEM free list. Returns how many frames completed.
+    fn reclaim(&mut self) -> usize {
+        let mut shared = lock(&self.umem);
+        let completions = &mut self.completions;
+        let completed = self.cr.drain(|addr| {
+            let page_id = shared.umem.page_id_from(addr);
+            shared.umem.free(page_id);
+            if let Some(frame) = shared.in_flight[page_id].take() {
+                shared.accounts[frame.account].in_flight -= 1;
+                if let Some(cookie) = frame.cookie {
+                    completions.push_back(cookie);
+                }
+            }
+        });
+        self.stats.completed += completed as u64;
+        completed
+    }
+}
+
+/// A UMEM and its bookkeeping, shared by every socket registered on it.
+struct SharedUmem<'a> {
     umem: Umem<'a>,
+    // Socket that registered the UMEM, the others join it through XDP_SHARED_UMEM. A
+    // duplicate keeps it open, and the registration alive, after its owner is dropped.
+    fd: OwnedFd,
+    // TX frame in flight in each UMEM page.
+    in_flight: Vec<Option<InFlight>>,
+    // UMEM usage of every socket sharing it.
+    accounts: Vec<Account>,
+}
+
+impl<'a> SharedUmem<'a> {
+    fn new(umem: Umem<'a>, owner: RawFd) -> io::Result<Arc<Mutex<Self>>> {
+        // SAFETY: the owner socket is open for the duration of the call.
+        let fd = unsafe { BorrowedFd::borrow_raw(owner) }.try_clone_to_owned()?;
+        Ok(Arc::new(Mutex::new(Self {
+            in_flight: vec![None; umem.size()],
+            umem,
+            fd,
+            accounts: Vec::new(),
+        })))
+    }
+}
+
+/// A TX frame owned by the kernel until it shows up in the completion ring.
+#[derive(Copy, Clone)]
+struct InFlight {
+    account: usize,
+    cookie: Option<u64>,
+}
+
+/// TX frames held by one of the sockets sharing a UMEM.
+#[derive(Default)]
+struct Account {
+    quota: Option<usize>,
+    in_flight: usize,
+    exhausted: u64,
+}
+
+/// Rings of a socket before they are handed to [`Inner`].
+struct Rings {
     tx: XdpRing<Writer>,
     rx: XdpRing<Reader>,
-    cr: XdpRing<Reader>,
-    fr: XdpRing<Writer>,
+    cr: XdpRing<Reader, u64>,
+    fr: XdpRing<Writer, u64>,
+}
+
+/// Owner of the fill ring.
+enum Fill {
+    Inline(XdpRing<Writer, u64>),
+    Background(Refiller),
+}
+
+impl Fill {
+    fn push(&mut self, addr: u64) {
+        match self {
+            // The ring holds every RX frame, so there is always room for a recycled one.
+            Self::Inline(fr) => {
+                let _ = fr.write(addr);
+            }
+            Self::Background(refiller) => refiller.push(addr),
+        }
+    }
+
+    fn pending(&self) -> u32 {
+        match self {
+            Self::Inline(fr) => fr.pending(),
+            Self::Background(refiller) => refiller.pending(),
+        }
+    }
+
+    fn needs_wakeup(&self) -> bool {
+        match self {
+            Self::Inline(fr) => fr.needs_wakeup(),
+            Self::Background(refiller) => refiller.needs_wakeup(),
+        }
+    }
+
+    fn raw(&self) -> RawRing {
+        match self {
+            Self::Inline(fr) => fr.raw(),
+            Self::Background(refiller) => refiller.raw(),
+        }
+    }
 }
 
 impl AsRawFd for XdpSocket<'_> {
@@ -50,14 +642,675 @@ impl AsRawFd for XdpSocket<'_> {
     }
 }
 
+impl AsFd for XdpSocket<'_> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // SAFETY: the fd is owned by the socket, which outlives the borrow.
+        unsafe { BorrowedFd::borrow_raw(self.lower.as_raw_fd()) }
+    }
+}
+
 #[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config {
     pub queue_id: u32,
+    /// Maximum RX descriptors processed per smoltcp poll iteration, `None` for no limit.
+    pub rx_budget: Option<usize>,
+    /// RX descriptors dequeued from the ring at once with a single consumer update, 0 or
+    /// 1 to dequeue them one per receive.
+    pub rx_batch: usize,
+    /// TX frames queued before the kernel is kicked, 0 or 1 to kick after every frame.
+    /// Frames of an unfinished batch are sent by [`XdpSocket::flush_tx`].
+    pub tx_batch: usize,
+    /// Received frames handed back to the fill ring at once with a single producer
+    /// update, 0 or 1 to hand them back one per receive. Frames of an unfinished batch
+    /// are handed back once the RX ring runs empty.
+    pub fill_batch: usize,
+    /// Shorter TX frames are zero-padded to this length, `MIN_FRAME_LEN` for the Ethernet
+    /// minimum without FCS. Some drivers send runts that peers silently drop. 0 to send
+    /// frames as built.
+    pub tx_min_len: usize,
+    /// Requests the TCP and UDP checksums of sent frames from the NIC through TX
+    /// metadata, and advertises it to smoltcp so that it skips them. Needs
+    /// `UmemConfig::tx_metadata` and a driver implementing the offload, frames leave
+    /// with bad checksums otherwise.
+    pub tx_checksum: bool,
+    /// Checksums and burst size reported to smoltcp.
+    pub capabilities: CapabilitiesConfig,
+    /// Sets UMEM pages aside for control-plane frames, so that they still go out once
+    /// bulk traffic has taken every other page. `None` for no reserve.
+    pub tx_priority: Option<PriorityConfig>,
     pub umem: umem::Config,
     pub tx: rings::Config,
     pub rx: rings::Config,
     pub cr: rings::Config,
     pub fr: rings::Config,
+    /// Refills the fill ring from a background thread instead of the RX path, topping it
+    /// up from the UMEM free list when recycled frames fall short. Not supported for
+    /// `UmemBacking::User` UMEMs.
+    pub refill: Option<refill::Config>,
+    /// Binds with `XDP_USE_NEED_WAKEUP` so the kernel is only kicked when it asks for it
+    /// through the ring flags, instead of after every transmitted frame.
+    pub need_wakeup: bool,
+    /// Binds with `XDP_USE_SG` so that frames larger than a chunk, e.g. jumbo frames,
+    /// arrive in several descriptors chained with `XDP_PKT_CONTD` instead of being
+    /// dropped. They are reassembled before smoltcp and the verdict hook see them.
+    /// Needs Linux 6.6, and driver support in zero-copy mode. [`XdpSocket::forward`]
+    /// sends every fragment as a frame of its own.
+    ///
+    /// Sent frames larger than a chunk are split the same way. Without it they are
+    /// dropped and counted in `Stats::tx_too_long`.
+    pub multi_buffer: bool,
+    pub bind_mode: BindMode,
+    /// Busy polls the device queue from the socket syscalls instead of waiting for its
+    /// interrupts.
+    pub busy_poll: Option<BusyPoll>,
+    /// Attaches the built-in [`redirect::Redirect`] program in this mode when the
+    /// interface has no XDP program, detaching it with the last socket using it. An
+    /// interface with a program of its own is left to it, and registering the socket in
+    /// its XSKMAP is up to the application. `None` to never attach it.
+    pub default_program: Option<AttachMode>,
+}
+
+/// UMEM pages reserved for control-plane TX frames, see [`Config::tx_priority`].
+///
+/// ARP and neighbor discovery packets, TCP segments acknowledging data without carrying
+/// any, and IP packets of the `dscp` classes take reserved pages once the free list is
+/// empty, and are not held back by [`XdpSocket::set_tx_quota`]. Pages are handed back to
+/// the reserve first when they complete.
+///
+/// Frames are only classified once built, so [`Device::transmit`] hands out no token
+/// when the reserve is all that is left. Reserved pages go to the replies smoltcp sends
+/// through the TX token of `Device::receive`, i.e. ARP and neighbor discovery answers
+/// and the ACKs of received segments.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriorityConfig {
+    /// Pages moved from the free list of the UMEM to the reserve when the socket opens,
+    /// fewer if the UMEM has fewer free pages. The reserve is shared by the sockets of
+    /// the UMEM.
+    pub pages: usize,
+    /// DSCP values of control-plane IP packets, bit `n` standing for DSCP `n`, e.g.
+    /// `1 << 48` for CS6.
+    pub dscp: u64,
+}
+
+/// What [`XdpSocket`] reports to smoltcp through `Device::capabilities`.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CapabilitiesConfig {
+    /// Checksums of received frames the NIC verifies, skipped by smoltcp.
+    pub rx_verified: VerifiedChecksums,
+    pub max_burst_size: BurstSize,
+}
+
+/// Checksums the NIC verifies on receive, dropping the frames that fail them.
+///
+/// Most drivers only flag bad checksums to the kernel stack and still hand the frames
+/// to XDP, in which case smoltcp has to verify them itself.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerifiedChecksums {
+    pub ipv4: bool,
+    pub tcp: bool,
+    pub udp: bool,
+    pub icmpv4: bool,
+    pub icmpv6: bool,
+}
+
+/// The `DeviceCapabilities::max_burst_size` of a socket.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BurstSize {
+    /// No burst size, smoltcp does not limit its bursts.
+    #[default]
+    Unknown,
+    /// The smaller of the RX and TX ring sizes.
+    Rings,
+    /// Bursts of at most this many frames.
+    Fixed(usize),
+}
+
+/// Busy polling settings of a socket.
+///
+/// `prefer` only keeps interrupts off when the device defers them, see the
+/// `napi_defer_hard_irqs` and `gro_flush_timeout` sysfs attributes.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BusyPoll {
+    /// Time a syscall spins on the device queue, in microseconds (`SO_BUSY_POLL`).
+    pub timeout_us: u32,
+    /// Packets processed per busy poll (`SO_BUSY_POLL_BUDGET`).
+    pub budget: u32,
+    /// Keeps the device interrupts masked while the application polls
+    /// (`SO_PREFER_BUSY_POLL`).
+    pub prefer: bool,
+}
+
+impl Config {
+    /// Preset keeping per-frame latency low and steady on the interface called `name`.
+    ///
+    /// Rings are kept small so that frames do not queue up, the socket busy polls with
+    /// interrupts deferred, and each smoltcp poll handles a bounded number of frames. The
+    /// fill ring still covers the NIC RX ring so that bursts are not dropped. `queue_id`
+    /// is 0.
+    pub fn low_latency(name: &str) -> Result<Self, XdpError> {
+        let probe = Probe::new(name)?;
+        let ring = 256;
+        let fr = probe.rx_ring.max(ring);
+        Self::preset(
+            &probe,
+            Config {
+                queue_id: 0,
+                rx_budget: Some(64),
+                rx_batch: 16,
+                tx_batch: 1,
+                fill_batch: 1,
+                tx_min_len: MIN_FRAME_LEN,
+                tx_checksum: false,
+                capabilities: CapabilitiesConfig::default(),
+                tx_priority: None,
+                umem: umem::Config {
+                    entries: fr + 2 * ring,
+                    alignment: probe.chunk()?,
+                    headroom: 0,
+                    unaligned: false,
+                    free_list: umem::FreeList::Intrusive,
+                    backing: umem::Backing::Heap,
+                    tx_metadata: false,
+                },
+                tx: rings::Config { size: ring },
+                rx: rings::Config { size: ring },
+                cr: rings::Config { size: ring },
+                fr: rings::Config { size: fr },
+                refill: None,
+                need_wakeup: true,
+                multi_buffer: false,
+                bind_mode: BindMode::Auto,
+                busy_poll: Some(BusyPoll {
+                    timeout_us: 50,
+                    budget: 64,
+                    prefer: true,
+                }),
+                default_program: Some(AttachMode::Auto),
+            },
+        )
+    }
+
+    /// Preset maximising the packet rate on the interface called `name`.
+    ///
+    /// Rings are at least as large as the NIC ones, RX descriptors are drained in large
+    /// batches, and the kernel is only woken up when it asks for it. `queue_id` is 0.
+    pub fn high_throughput(name: &str) -> Result<Self, XdpError> {
+        let probe = Probe::new(name)?;
+        let ring = probe.rx_ring.max(probe.tx_ring).max(2048);
+        let fr = 2 * ring;
+        Self::preset(
+            &probe,
+            Config {
+                queue_id: 0,
+                rx_budget: None,
+                rx_batch: 64,
+                tx_batch: 32,
+                fill_batch: 32,
+                tx_min_len: MIN_FRAME_LEN,
+                tx_checksum: false,
+                capabilities: CapabilitiesConfig::default(),
+                tx_priority: None,
+                umem: umem::Config {
+                    entries: fr + 2 * ring,
+                    alignment: probe.chunk()?,
+                    headroom: 0,
+                    unaligned: false,
+                    // Allocating TX pages does not touch their cold memory.
+                    free_list: umem::FreeList::External,
+                    backing: umem::Backing::Heap,
+                    tx_metadata: false,
+                },
+                tx: rings::Config { size: ring },
+                rx: rings::Config { size: ring },
+                cr: rings::Config { size: ring },
+                fr: rings::Config { size: fr },
+                refill: None,
+                need_wakeup: true,
+                multi_buffer: false,
+                bind_mode: BindMode::Auto,
+                busy_poll: None,
+                default_program: Some(AttachMode::Auto),
+            },
+        )
+    }
+
+    fn preset(probe: &Probe, config: Config) -> Result<Self, XdpError> {
+        if config.umem.entries > MAX_UMEM_ENTRIES {
+            return Err(XdpError::InvalidConfig(format!(
+                "The NIC rings of {} need {} UMEM frames, at most {} are supported",
+                probe.name, config.umem.entries, MAX_UMEM_ENTRIES
+            )));
+        }
+        Ok(config)
+    }
+}
+
+/// What the presets need to know about an interface.
+struct Probe {
+    name: String,
+    mtu: usize,
+    // NIC descriptor ring sizes rounded up to a power of two, 1 for devices without
+    // rings such as veth.
+    rx_ring: usize,
+    tx_ring: usize,
+}
+
+impl Probe {
+    fn new(name: &str) -> io::Result<Self> {
+        let mtu = sys::xdp::mtu(name)?;
+        let (rx_ring, tx_ring) = sys::xdp::lower_devices(name)?
+            .iter()
+            .filter_map(|lower| sys::ethtool::ring_sizes(lower).ok())
+            .fold((0, 0), |(rx, tx), (lower_rx, lower_tx)| {
+                (rx.max(lower_rx), tx.max(lower_tx))
+            });
+        Ok(Self {
+            name: name.to_owned(),
+            mtu,
+            rx_ring: (rx_ring as usize).next_power_of_two(),
+            tx_ring: (tx_ring as usize).next_power_of_two(),
+        })
+    }
+
+    /// Smallest chunk holding a VLAN-tagged frame of the interface MTU after the kernel
+    /// and UMEM headrooms.
+    fn chunk(&self) -> io::Result<ChunkAlignment> {
+        let needed =
+            XDP_PACKET_HEADROOM + mem::size_of::<HeadRoom>() + ETHERNET_HEADER_LEN + 4 + self.mtu;
+        [ChunkAlignment::TwoK, ChunkAlignment::FourK]
+            .into_iter()
+            .find(|&chunk| usize::from(chunk) >= needed)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "The MTU of {} ({}) does not fit in a 4 KiB UMEM chunk",
+                        self.name, self.mtu
+                    ),
+                )
+            })
+    }
+}
+
+/// Applies the socket options of `config` that do not depend on the bind.
+fn configure(lower: &XdpSocketDesc, config: &Config) -> io::Result<()> {
+    if let Some(busy_poll) = config.busy_poll {
+        lower.set_busy_poll(busy_poll.timeout_us, busy_poll.budget, busy_poll.prefer)?;
+    }
+    Ok(())
+}
+
+fn bind_flags(config: &Config) -> u16 {
+    let mut flags = 0;
+    if config.need_wakeup {
+        flags |= libc::XDP_USE_NEED_WAKEUP;
+    }
+    if config.multi_buffer {
+        flags |= libc::XDP_USE_SG;
+    }
+    flags
+}
+
+/// Opens an AF_XDP socket for the interface called `name`.
+fn open_socket(name: &str) -> Result<XdpSocketDesc, XdpError> {
+    XdpSocketDesc::new(name).map_err(|err| match err.raw_os_error() {
+        Some(libc::EAFNOSUPPORT) => XdpError::UnsupportedKernel {
+            feature: "AF_XDP sockets (Linux 4.18)",
+        },
+        _ => err.into(),
+    })
+}
+
+/// Registers `umem` with the socket owning it.
+fn register_umem(lower: &XdpSocketDesc, umem: &Umem) -> Result<(), XdpError> {
+    lower.bind_umem(umem).map_err(|err| {
+        if umem.tx_metadata_len() != 0 && err.raw_os_error() == Some(libc::EINVAL) {
+            XdpError::UnsupportedKernel {
+                feature: "TX metadata (Linux 6.8)",
+            }
+        } else {
+            err.into()
+        }
+    })
+}
+
+/// Binds the socket owning the UMEM in the configured mode, falling back to copy mode
+/// in `BindMode::Auto` when the driver has no zero-copy support.
+fn bind(lower: &mut XdpSocketDesc, config: &Config) -> Result<(), XdpError> {
+    let flags = bind_flags(config);
+    let mut mode = match config.bind_mode {
+        BindMode::Copy => libc::XDP_COPY,
+        BindMode::ZeroCopy | BindMode::Auto => libc::XDP_ZEROCOPY,
+    };
+    let mut result = lower.bind_interface(config.queue_id, flags | mode, None);
+    if config.bind_mode == BindMode::Auto
+        && let Err(err) = &result
+        && err.raw_os_error() == Some(libc::EOPNOTSUPP)
+    {
+        mode = libc::XDP_COPY;
+        result = lower.bind_interface(config.queue_id, flags | mode, None);
+    }
+    result.map_err(|err| bind_failed(lower, err, mode == libc::XDP_ZEROCOPY))
+}
+
+/// Turns `err`, from binding `lower`, into [`XdpError::BindFailed`] with the likely cause
+/// and the driver of the bound device, AF_XDP support differing a lot from a driver to
+/// another.
+fn bind_failed(lower: &XdpSocketDesc, err: io::Error, zero_copy: bool) -> XdpError {
+    let Some(errno) = err.raw_os_error() else {
+        return err.into();
+    };
+    XdpError::bind(
+        errno,
+        zero_copy,
+        driver(lower).map(|driver| driver.to_string()),
+    )
+}
+
+/// Names the driver of the bound device in `err`. The error kind is kept, the OS error
+/// code is not.
+fn with_driver(lower: &XdpSocketDesc, err: io::Error) -> io::Error {
+    match driver(lower) {
+        Some(driver) => io::Error::new(err.kind(), format!("{} ({})", err, driver)),
+        None => err,
+    }
+}
+
+fn driver(lower: &XdpSocketDesc) -> Option<DriverInfo> {
+    lower
+        .name()
+        .and_then(|name| sys::ethtool::driver_info(&name))
+        .ok()
+}
+
+impl<'a> XdpSocket<'a> {
+    fn from_parts(
+        lower: XdpSocketDesc,
+        inner: Arc<Mutex<Inner<'a>>>,
+        direction: Direction,
+        config: Config,
+    ) -> Self {
+        // Kernels before 5.3 cannot report the mode, only an explicit zero-copy bind
+        // guarantees it.
+        let bind_mode = match lower.zero_copy() {
+            Ok(true) => BindMode::ZeroCopy,
+            Ok(false) => BindMode::Copy,
+            Err(_) if config.bind_mode == BindMode::ZeroCopy => BindMode::ZeroCopy,
+            Err(_) => BindMode::Copy,
+        };
+
+        let account = {
+            let inner = lock(&inner);
+            let mut shared = lock(&inner.umem);
+            shared.accounts.push(Account::default());
+            shared.accounts.len() - 1
+        };
+
+        XdpSocket {
+            lower,
+            inner,
+            direction,
+            bind_mode,
+            account,
+            rx_budget: PollBudget::new(config.rx_budget),
+            last_stats: Cell::new(unsafe { std::mem::zeroed() }),
+            #[cfg(feature = "libbpf")]
+            program: None,
+            redirect: None,
+            verdict: None,
+            queued: VecDeque::new(),
+            queue_limit: 0,
+            epoll: None,
+        }
+    }
+
+    /// The UMEM of the socket, locked on its own without holding the socket state.
+    fn umem(&self) -> Arc<Mutex<SharedUmem<'a>>> {
+        lock(&self.inner).umem.clone()
+    }
+
+    /// Attaches the built-in program for `Config::default_program`, unless the interface
+    /// has a program already.
+    fn attach_default(&mut self, name: &str, config: &Config) -> io::Result<()> {
+        let mode = match config.default_program {
+            Some(mode) if self.direction != Direction::Tx => mode,
+            _ => return Ok(()),
+        };
+        let queue_id = self
+            .lower
+            .queue_id()
+            .expect("Sockets are bound on construction");
+        let queues = rx_queues(name).unwrap_or(0).max(queue_id + 1);
+        match Redirect::attach_on(self.lower.ifindex(), queues, mode, false) {
+            Ok(redirect) => {
+                redirect.register(self, queue_id)?;
+                self.redirect = Some(Arc::new(redirect));
+                Ok(())
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+            Err(err) => Err(with_driver(&self.lower, err)),
+        }
+    }
+
+    /// Opens a socket on `config.queue_id` of the interface called `name` over the UMEM of
+    /// this socket, binding it with `XDP_SHARED_UMEM`.
+    ///
+    /// The new socket has its own RX, TX, fill and completion rings, and its fill frames
+    /// are taken from the free pages of the shared UMEM once it is bound. The UMEM owner
+    /// stays registered until every socket sharing it is dropped, so any of them can be
+    /// shared again. `config.umem`, `config.need_wakeup` and `config.bind_mode` are
+    /// ignored, the kernel applies those of the UMEM owner.
+    pub fn share(&self, name: &str, config: Config) -> Result<XdpSocket<'a>, XdpError> {
+        let mut lower = open_socket(name)?;
+
+        lower.bind_ring(Type::Tx, config.tx.size)?;
+        lower.bind_ring(Type::Rx, config.rx.size)?;
+        lower.bind_ring(Type::Completion, config.cr.size)?;
+        lower.bind_ring(Type::Fill, config.fr.size)?;
+
+        let offsets = rings::offsets(lower.as_raw_fd())?;
+
+        let tx = rings::build::<Writer, _>(lower.as_raw_fd(), Type::Tx, offsets, config.tx.size)?;
+        let rx = rings::build::<Reader, _>(lower.as_raw_fd(), Type::Rx, offsets, config.rx.size)?;
+        let cr = rings::build::<Reader, _>(
+            lower.as_raw_fd(),
+            Type::Completion,
+            offsets,
+            config.cr.size,
+        )?;
+        let fr = rings::build::<Writer, _>(lower.as_raw_fd(), Type::Fill, offsets, config.fr.size)?;
+
+        let (umem, config) = {
+            let inner = lock(&self.inner);
+            let config = Config {
+                need_wakeup: inner.need_wakeup,
+                ..config
+            };
+            (inner.umem.clone(), config)
+        };
+        let owner = lock(&umem).fd.as_raw_fd();
+        configure(&lower, &config)?;
+        lower
+            .bind_interface(config.queue_id, libc::XDP_SHARED_UMEM, Some(owner))
+            .map_err(|err| bind_failed(&lower, err, false))?;
+
+        // Fill frames are only taken from the shared UMEM once the bind succeeded.
+        let inner = Inner::new(
+            umem,
+            Rings { tx, rx, cr, fr },
+            &config,
+            lower.mtu(),
+            lower.as_raw_fd(),
+        )?;
+
+        let mut socket = XdpSocket::from_parts(lower, inner, Direction::Both, config);
+        match &self.redirect {
+            Some(redirect) if redirect.ifindex() == socket.lower.ifindex() => {
+                redirect.register(&socket, config.queue_id)?;
+                socket.redirect = Some(redirect.clone());
+            }
+            _ => socket.attach_default(name, &config)?,
+        }
+        Ok(socket)
+    }
+
+    /// Moves up to `limit` frames from the RX ring of this socket to the TX ring of `to`
+    /// without copying them, returning how many were forwarded.
+    ///
+    /// Both sockets must share a UMEM, see [`XdpSocket::share`] and [`XdpSocket::split`],
+    /// and this socket must not be opened with `Config::multi_buffer`: frames are forwarded
+    /// one descriptor at a time, which would split fragment chains.
+    ///
+    /// `f` sees every frame in place and may rewrite it, e.g. its MAC addresses, or return
+    /// false to drop it. A forwarded frame is owned by `to` until it completes: its chunk
+    /// is then returned to the free list, and a free chunk takes its place on the fill ring
+    /// of this socket right away so that RX never runs short.
+    ///
+    /// Forwarding stops early, leaving frames on the RX ring, when the TX ring of `to` is
+    /// full, `to` reached its TX quota or the UMEM has no free chunk left. The TX ring of
+    /// `to` is kicked before returning.
+    pub fn forward(
+        &mut self,
+        to: &mut XdpSocket<'a>,
+        timestamp: Instant,
+        limit: usize,
+        mut f: impl FnMut(&mut [u8]) -> bool,
+    ) -> Result<usize, XdpError> {
+        let umem = lock(&self.inner).umem.clone();
+        if !Arc::ptr_eq(&umem, &lock(&to.inner).umem) {
+            return Err(XdpError::InvalidConfig(
+                "Forwarding needs both sockets to share a UMEM".to_owned(),
+            ));
+        }
+        if self.direction == Direction::Tx || to.direction == Direction::Rx {
+            return Err(XdpError::InvalidConfig(
+                "Forwarding needs an RX and a TX ring".to_owned(),
+            ));
+        }
+        if lock(&self.inner).multi_buffer {
+            return Err(XdpError::InvalidConfig(
+                "Forwarding does not support multi-buffer frames".to_owned(),
+            ));
+        }
+
+        // Completed frames free the chunks replacing the forwarded ones on the fill ring.
+        lock(&to.inner).reclaim();
+        lock(&self.inner).rx_meter.tick(timestamp);
+        lock(&to.inner).tx_meter.tick(timestamp);
+
+        let mut forwarded = 0;
+        let mut drained = false;
+        while forwarded < limit {
+            {
+                let to_inner = lock(&to.inner);
+                let shared = lock(&to_inner.umem);
+                let account = &shared.accounts[to.account];
+                if to_inner.tx.pending() >= to_inner.tx.size()
+                    || account
+                        .quota
+                        .is_some_and(|quota| account.in_flight >= quota)
+                    || !shared.umem.has_free()
+                {
+                    break;
+                }
+            }
+
+            // The chunk leaves the RX side, another one joins it.
+            let desc = {
+                let mut inner = lock(&self.inner);
+                let Some((desc, _)) = inner.next_rx() else {
+                    drained = true;
+                    break;
+                };
+                inner.rx_meter.record(timestamp, desc.len as usize);
+                inner.stats.rx_packets += 1;
+                inner.stats.rx_bytes += u64::from(desc.len);
+
+                let mut shared = lock(&inner.umem);
+                let resolved = libc::xdp_desc {
+                    addr: shared.umem.resolve(desc.addr),
+                    ..desc
+                };
+                let page_id = shared.umem.page_id_from(resolved.addr);
+                shared.umem.received(page_id);
+                let keep = f(shared.umem.packet_mut(page_id, resolved));
+                shared.umem.commit(page_id);
+                let fill_page_id = if keep {
+                    shared.umem.alloc().expect("A free chunk was checked for")
+                } else {
+                    page_id
+                };
+                let addr = shared.umem.fill(fill_page_id);
+                drop(shared);
+                inner.recycle(addr);
+                inner.stats.fill_recycled += 1;
+                if !keep {
+                    continue;
+                }
+                inner.stats.forwarded += 1;
+                // Without multi-buffer RX, no descriptor carries XDP_PKT_CONTD.
+                libc::xdp_desc { options: 0, ..desc }
+            };
+
+            let mut guard = lock(&to.inner);
+            let to_inner = &mut *guard;
+            let mut shared = lock(&to_inner.umem);
+            let page_id = shared.umem.page_id_from(desc.addr);
+            if to_inner.tx.write(desc).is_err() {
+                shared.umem.free(page_id);
+                to_inner.stats.tx_ring_full += 1;
+                break;
+            }
+            shared.umem.transmitted(page_id);
+            shared.in_flight[page_id] = Some(InFlight {
+                account: to.account,
+                cookie: None,
+            });
+            shared.accounts[to.account].in_flight += 1;
+            drop(shared);
+            to_inner.tx_meter.record(timestamp, desc.len as usize);
+            to_inner.tx_unkicked += 1;
+            to_inner.stats.tx_packets += 1;
+            to_inner.stats.tx_bytes += u64::from(desc.len);
+            if to_inner.tx_unkicked >= to_inner.tx_batch {
+                to_inner.flush_tx();
+            }
+            forwarded += 1;
+        }
+
+        lock(&to.inner).flush_tx();
+        let mut inner = lock(&self.inner);
+        if drained {
+            inner.flush_fill();
+            if inner.busy_poll || (inner.need_wakeup && inner.fr.needs_wakeup()) {
+                let _ = sys::xdp::kick_rx(self.lower.as_raw_fd());
+                inner.profiler.rx_kick();
+            }
+        }
+        Ok(forwarded)
+    }
+
+    /// Runs `hook` on every received frame, in place in the UMEM, before smoltcp sees it.
+    ///
+    /// Frames the hook drops never reach smoltcp nor count against the RX budget, so
+    /// ACLs or flood filters cost no copy. Queued frames are copied into the RX pool and
+    /// kept for [`XdpSocket::pop_queued`], up to the size of the RX ring; frames queued
+    /// beyond are dropped.
+    pub fn set_verdict(&mut self, hook: impl FnMut(&[u8]) -> Verdict + Send + 'a) {
+        self.verdict = Some(Box::new(hook));
+        self.queue_limit = lock(&self.inner).rx.size() as usize;
+    }
+
+    /// Removes the verdict hook, every frame then goes to smoltcp.
+    pub fn clear_verdict(&mut self) {
+        self.verdict = None;
+    }
 }
 
 impl XdpSocket<'_> {
@@ -67,11 +1320,67 @@ impl XdpSocket<'_> {
     /// set on the executable.
     ///
     ///
-    pub fn new(name: &str, config: Config) -> io::Result<XdpSocket<'_>> {
-        let mut lower = XdpSocketDesc::new(name)?;
-        let umem = Umem::new(config.umem)?;
+    pub fn new(name: &str, config: Config) -> Result<XdpSocket<'_>, XdpError> {
+        Self::open(name, open_socket(name)?, config)
+    }
+
+    /// Sets up `fd`, an AF_XDP socket created by a privileged helper and not bound yet,
+    /// on the interface called `name`.
+    ///
+    /// The UMEM registration, the rings and the bind need no capability, so a process can
+    /// run unprivileged once it received the socket, see `phy::fdpass`.
+    pub fn from_fd(fd: OwnedFd, name: &str, config: Config) -> Result<XdpSocket<'_>, XdpError> {
+        Self::open(name, XdpSocketDesc::from_fd(fd, name)?, config)
+    }
 
-        lower.bind_umem(&umem)?;
+    /// Like [`XdpSocket::new`], registering `umem` instead of allocating one as described
+    /// by `Config::umem`, e.g. memory from [`Umem::from_raw_parts`].
+    pub fn with_umem<'a>(
+        name: &str,
+        umem: Umem<'a>,
+        config: Config,
+    ) -> Result<XdpSocket<'a>, XdpError> {
+        Self::register(name, open_socket(name)?, umem, config)
+    }
+
+    /// Like [`XdpSocket::new`], also loading and attaching the XDP program described by
+    /// `program` and inserting the socket into its XSKMAP, so that no
+    /// `ip link set dev ... xdp obj ...` is needed. On bond or VLAN interfaces the program
+    /// is attached to the lower device the socket is bound to.
+    ///
+    /// The program is detached when the socket is dropped.
+    #[cfg(feature = "libbpf")]
+    pub fn with_program<'a>(
+        name: &str,
+        config: Config,
+        program: &bpf::Config,
+    ) -> Result<XdpSocket<'a>, XdpError> {
+        let config = Config {
+            default_program: None,
+            ..config
+        };
+        let mut socket = Self::open(name, open_socket(name)?, config)?;
+        let loaded = bpf::Program::load_on(socket.lower.ifindex(), program)?;
+        loaded.register(&socket, config.queue_id)?;
+        socket.program = Some(loaded);
+        Ok(socket)
+    }
+
+    fn open<'a>(
+        name: &str,
+        lower: XdpSocketDesc,
+        config: Config,
+    ) -> Result<XdpSocket<'a>, XdpError> {
+        Self::register(name, lower, Umem::new(config.umem)?, config)
+    }
+
+    fn register<'a>(
+        name: &str,
+        mut lower: XdpSocketDesc,
+        umem: Umem<'a>,
+        config: Config,
+    ) -> Result<XdpSocket<'a>, XdpError> {
+        register_umem(&lower, &umem)?;
 
         lower.bind_ring(Type::Tx, config.tx.size)?;
         lower.bind_ring(Type::Rx, config.rx.size)?;
@@ -80,36 +1389,675 @@ impl XdpSocket<'_> {
 
         let offsets = rings::offsets(lower.as_raw_fd())?;
 
-        let tx = rings::build::<Writer>(lower.as_raw_fd(), Type::Tx, offsets, config.tx.size)?;
-        let rx = rings::build::<Reader>(lower.as_raw_fd(), Type::Rx, offsets, config.rx.size)?;
-        let cr =
-            rings::build::<Reader>(lower.as_raw_fd(), Type::Completion, offsets, config.cr.size)?;
-        let mut fr =
-            rings::build::<Writer>(lower.as_raw_fd(), Type::Fill, offsets, config.fr.size)?;
+        let tx = rings::build::<Writer, _>(lower.as_raw_fd(), Type::Tx, offsets, config.tx.size)?;
+        let rx = rings::build::<Reader, _>(lower.as_raw_fd(), Type::Rx, offsets, config.rx.size)?;
+        let cr = rings::build::<Reader, _>(
+            lower.as_raw_fd(),
+            Type::Completion,
+            offsets,
+            config.cr.size,
+        )?;
+        let fr = rings::build::<Writer, _>(lower.as_raw_fd(), Type::Fill, offsets, config.fr.size)?;
+
+        configure(&lower, &config)?;
+        bind(&mut lower, &config)?;
+
+        let inner = Inner::new(
+            SharedUmem::new(umem, lower.as_raw_fd())?,
+            Rings { tx, rx, cr, fr },
+            &config,
+            lower.mtu(),
+            lower.as_raw_fd(),
+        )?;
+
+        let mut socket = XdpSocket::from_parts(lower, inner, Direction::Both, config);
+        socket.attach_default(name, &config)?;
+        Ok(socket)
+    }
+
+    /// Creates an RX-only and a TX-only socket bound to the same queue of the interface
+    /// called `name` and sharing a single UMEM.
+    ///
+    /// The RX socket owns the UMEM registration together with the fill and completion
+    /// rings, while the TX socket only maps a TX ring and joins it through
+    /// `XDP_SHARED_UMEM`. Frames replied through the TX token handed out by the RX socket
+    /// are queued on the TX socket ring.
+    pub fn split(name: &str, config: Config) -> Result<(XdpSocket<'_>, XdpSocket<'_>), XdpError> {
+        let mut rx_lower = open_socket(name)?;
+        let mut tx_lower = open_socket(name)?;
+        let umem = Umem::new(config.umem)?;
+
+        register_umem(&rx_lower, &umem)?;
+
+        rx_lower.bind_ring(Type::Rx, config.rx.size)?;
+        rx_lower.bind_ring(Type::Completion, config.cr.size)?;
+        rx_lower.bind_ring(Type::Fill, config.fr.size)?;
+        tx_lower.bind_ring(Type::Tx, config.tx.size)?;
+
+        let rx_offsets = rings::offsets(rx_lower.as_raw_fd())?;
+        let tx_offsets = rings::offsets(tx_lower.as_raw_fd())?;
+
+        let rx =
+            rings::build::<Reader, _>(rx_lower.as_raw_fd(), Type::Rx, rx_offsets, config.rx.size)?;
+        let cr = rings::build::<Reader, _>(
+            rx_lower.as_raw_fd(),
+            Type::Completion,
+            rx_offsets,
+            config.cr.size,
+        )?;
+        let fr = rings::build::<Writer, _>(
+            rx_lower.as_raw_fd(),
+            Type::Fill,
+            rx_offsets,
+            config.fr.size,
+        )?;
+        let tx =
+            rings::build::<Writer, _>(tx_lower.as_raw_fd(), Type::Tx, tx_offsets, config.tx.size)?;
+
+        // The TX socket inherits the bind and wakeup modes of the UMEM owner, the kernel rejects
+        // any other flag along with XDP_SHARED_UMEM.
+        configure(&rx_lower, &config)?;
+        configure(&tx_lower, &config)?;
+        bind(&mut rx_lower, &config)?;
+        tx_lower
+            .bind_interface(
+                config.queue_id,
+                libc::XDP_SHARED_UMEM,
+                Some(rx_lower.as_raw_fd()),
+            )
+            .map_err(|err| bind_failed(&tx_lower, err, false))?;
+
+        let inner = Inner::new(
+            SharedUmem::new(umem, rx_lower.as_raw_fd())?,
+            Rings { tx, rx, cr, fr },
+            &config,
+            rx_lower.mtu(),
+            tx_lower.as_raw_fd(),
+        )?;
+
+        let mut rx_socket = XdpSocket::from_parts(rx_lower, inner.clone(), Direction::Rx, config);
+        rx_socket.attach_default(name, &config)?;
+        let tx_socket = XdpSocket::from_parts(tx_lower, inner, Direction::Tx, config);
+        Ok((rx_socket, tx_socket))
+    }
+
+    /// Changes the RX budget applied to each smoltcp poll iteration.
+    pub fn set_rx_budget(&mut self, budget: Option<usize>) {
+        self.rx_budget.limit = budget;
+    }
+
+    pub fn rx_budget(&self) -> Option<usize> {
+        self.rx_budget.limit
+    }
+
+    /// Replenishes the RX budget, to be called before every `Interface::poll` of an event
+    /// loop of one's own. Without it the budget is only replenished once the timestamp
+    /// passed to the poll changes. [`XdpSocket::run_busy`] and the async `run` loops call
+    /// it.
+    pub fn replenish_rx_budget(&mut self) {
+        self.rx_budget.replenish();
+    }
+
+    /// Changes `Config::rx_batch`. Descriptors already dequeued are still handed out.
+    pub fn set_rx_batch(&mut self, batch: usize) {
+        lock(&self.inner).rx_batch_size = batch;
+    }
+
+    pub fn rx_batch(&self) -> usize {
+        lock(&self.inner).rx_batch_size
+    }
+
+    /// Changes `Config::tx_batch`.
+    pub fn set_tx_batch(&mut self, batch: usize) {
+        lock(&self.inner).tx_batch = batch.max(1);
+    }
+
+    pub fn tx_batch(&self) -> usize {
+        lock(&self.inner).tx_batch
+    }
 
-        // Expose free pages to kernel
-        for desc in umem.packet_descriptors() {
-            let _ = fr.write(desc);
+    /// Changes `Config::fill_batch`, handing back the frames already queued if the batch
+    /// shrinks below them.
+    pub fn set_fill_batch(&mut self, batch: usize) {
+        let mut inner = lock(&self.inner);
+        inner.fill_batch = batch.max(1);
+        if inner.fill_queue.len() >= inner.fill_batch {
+            inner.flush_fill();
         }
+    }
 
-        lower.bind_interface(config.queue_id)?;
+    pub fn fill_batch(&self) -> usize {
+        lock(&self.inner).fill_batch
+    }
 
-        Ok(XdpSocket {
-            lower,
-            inner: Rc::new(RefCell::new(Inner {
-                umem,
-                tx,
-                rx,
-                cr,
-                fr,
-            })),
-        })
+    /// Frames waiting to be received, relative to the RX ring size. Usually between 0 and
+    /// 1, dequeued batches can push it slightly above.
+    pub fn rx_occupancy(&self) -> f64 {
+        let inner = lock(&self.inner);
+        let waiting = inner.rx.available() as usize + inner.rx_batch.len();
+        waiting as f64 / f64::from(inner.rx.size())
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Mode the socket is bound in, either `BindMode::ZeroCopy` or `BindMode::Copy`.
+    pub fn bind_mode(&self) -> BindMode {
+        self.bind_mode
+    }
+
+    /// Driver of the bound device, the lower one for upper devices, worth including in
+    /// bug reports since AF_XDP behaves differently from a driver to another.
+    pub fn driver_info(&self) -> Result<DriverInfo, XdpError> {
+        Ok(sys::ethtool::driver_info(&self.lower.name()?)?)
+    }
+
+    /// NAPI context of the device queue the socket receives from, `None` until the first
+    /// frame is received. Sockets sharing a CPU busy poll their own queues only when each
+    /// maps to a distinct NAPI id, which is worth checking against
+    /// `/proc/interrupts` or the `napi` netlink family.
+    pub fn napi_id(&self) -> Result<Option<u32>, XdpError> {
+        Ok(Some(self.lower.napi_id()?).filter(|&id| id != 0))
+    }
+
+    /// Packets processed per busy poll, overriding `BusyPoll::budget`. Lower budgets
+    /// share the CPU more evenly between sockets busy polling on it, higher ones drain
+    /// bursts with fewer syscalls.
+    pub fn set_busy_poll_budget(&self, budget: u32) -> Result<(), XdpError> {
+        Ok(self.lower.set_busy_poll_budget(budget)?)
+    }
+
+    /// Checks the whole RX pipeline (XDP program, XSKMAP entry, fill and RX rings, UMEM)
+    /// by injecting a probe frame into the program `prog_fd` as if the NIC received it on
+    /// the bound queue, then waiting up to `timeout` for it to come out of the RX ring.
+    /// Returns the time the probe took.
+    ///
+    /// Meant to run before serving traffic: the frames received in the meantime are
+    /// discarded. Needs Linux 5.18, see [`test_run::inject`].
+    pub fn self_test(
+        &mut self,
+        prog_fd: RawFd,
+        timeout: std::time::Duration,
+    ) -> Result<std::time::Duration, XdpError> {
+        if self.direction == Direction::Tx {
+            return Err(XdpError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "TX-only sockets receive no frame",
+            )));
+        }
+        let ifindex = self.lower.ifindex();
+        let queue_id = self
+            .lower
+            .queue_id()
+            .expect("Sockets are bound on construction");
+
+        let nonce = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut probe = vec![0u8; MIN_FRAME_LEN];
+        let mut frame = EthernetFrame::new_unchecked(&mut probe[..]);
+        frame.set_dst_addr(EthernetAddress::BROADCAST);
+        frame.set_src_addr(SELF_TEST_SOURCE);
+        frame.set_ethertype(EthernetProtocol::Unknown(SELF_TEST_ETHERTYPE));
+        frame.payload_mut()[..8].copy_from_slice(&nonce.to_be_bytes());
+
+        let verdict = test_run::run(prog_fd, &probe, ifindex, queue_id)?.action;
+        if verdict != Action::Redirect {
+            return Err(XdpError::Io(io::Error::other(format!(
+                "The program answered the probe with {:?}, is the socket in its XSKMAP?",
+                verdict
+            ))));
+        }
+
+        let started = std::time::Instant::now();
+        test_run::inject(prog_fd, &probe, ifindex, queue_id)?;
+        loop {
+            if let Some((rx, _)) = self.receive(Instant::now()) {
+                if phy::RxToken::consume(rx, |frame| frame == probe.as_slice()) {
+                    return Ok(started.elapsed());
+                }
+                continue;
+            }
+
+            let elapsed = started.elapsed();
+            if elapsed >= timeout {
+                return Err(XdpError::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "The probe was redirected but never reached the RX ring",
+                )));
+            }
+            let mut poll_fd = libc::pollfd {
+                fd: self.lower.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let wait = (timeout - elapsed).as_millis().clamp(1, i32::MAX as u128) as i32;
+            if unsafe { libc::poll(&mut poll_fd, 1, wait) } == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+
+    /// Built-in program attached for `Config::default_program`, `None` when the
+    /// interface had a program already.
+    pub fn default_program(&self) -> Option<&Redirect> {
+        self.redirect.as_deref()
+    }
+
+    /// Program loaded by [`XdpSocket::with_program`].
+    #[cfg(feature = "libbpf")]
+    pub fn program(&self) -> Option<&bpf::Program> {
+        self.program.as_ref()
+    }
+
+    /// Program loaded by [`XdpSocket::with_program`], e.g. to upgrade it in place with
+    /// [`bpf::Program::reload`].
+    #[cfg(feature = "libbpf")]
+    pub fn program_mut(&mut self) -> Option<&mut bpf::Program> {
+        self.program.as_mut()
+    }
+
+    /// Mode the program attached by this socket, the built-in one or the one of
+    /// [`XdpSocket::with_program`], actually runs in. `None` when the socket attached no
+    /// program.
+    pub fn attach_mode(&self) -> Option<AttachMode> {
+        #[cfg(feature = "libbpf")]
+        if let Some(program) = &self.program {
+            return Some(program.mode());
+        }
+        self.redirect.as_ref().map(|redirect| redirect.mode())
+    }
+
+    /// Oldest frame queued by the verdict hook. Giving it back with
+    /// [`XdpSocket::recycle_queued`] returns it to the RX pool.
+    pub fn pop_queued(&mut self) -> Option<FrameBuf> {
+        self.queued.pop_front()
+    }
+
+    pub fn recycle_queued(&mut self, frame: FrameBuf) {
+        lock(&self.inner).rx_pool.give(frame);
+    }
+
+    /// Registers the pool received frames are copied into, returning the previous one.
+    ///
+    /// Buffers go back to the pool once the RX token is consumed or dropped, so a pool
+    /// retaining as many buffers as frames are in flight removes the per-frame
+    /// allocation. The default pool retains none.
+    pub fn set_rx_pool(&mut self, pool: FrameScratch) -> FrameScratch {
+        std::mem::replace(&mut lock(&self.inner).rx_pool, pool)
+    }
+
+    /// Reaps the completion ring and calls `f` with the cookie of every completed frame
+    /// sent with [`TxToken::set_cookie`], returning how many were reported.
+    ///
+    /// A frame completes once the kernel is done with its buffer, i.e. the driver has
+    /// handed it to the NIC.
+    pub fn poll_completions(&mut self, mut f: impl FnMut(u64)) -> usize {
+        let mut inner = lock(&self.inner);
+        inner.reclaim();
+
+        let completed = inner.completions.len();
+        inner.completions.drain(..).for_each(&mut f);
+        completed
+    }
+
+    /// Memory the UMEM was allocated from, which is the heap if huge pages were requested
+    /// without any being available.
+    pub fn umem_backing(&self) -> UmemBacking {
+        lock(&self.umem()).umem.backing()
+    }
+
+    /// The memfd backing the UMEM with `UmemBacking::Memfd`, to be passed to another
+    /// process mapping the same frames, see `phy::fdpass`. It stays owned by the socket.
+    pub fn umem_memfd(&self) -> Option<RawFd> {
+        lock(&self.umem()).umem.memfd()
+    }
+
+    /// Kicks the kernel to send the frames of an unfinished TX batch, see
+    /// `Config::tx_batch`. Meant to be called after every `Interface::poll`.
+    pub fn flush_tx(&mut self) {
+        lock(&self.inner).flush_tx();
+    }
+
+    /// Sleeps until RX descriptors or TX completions are available, or `timeout`
+    /// elapses, forever if `None`. Returns at once if some are already available, and
+    /// with nothing ready on timeout or when interrupted by a signal.
+    ///
+    /// Unlike `smoltcp::phy::wait`, the socket is only slept on once the frames recycled
+    /// and queued so far are handed to the kernel, so that it has something to wake up
+    /// for. It is polled through epoll, whose wakeup of the socket also kicks the driver
+    /// when the fill or TX ring asks for it with `Config::need_wakeup`. Completions
+    /// carry no readiness event: they are waited for once the TX ring is exhausted, the
+    /// socket turning writable as the kernel drains it.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Ready, XdpError> {
+        let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout.into());
+        loop {
+            let ready = self.ready();
+            if ready.rx || ready.completions {
+                return Ok(ready);
+            }
+            self.prepare_wait();
+            let mut events = 0;
+            if self.direction != Direction::Tx {
+                events |= libc::EPOLLIN as u32;
+            }
+            if self.direction != Direction::Rx && !self.tx_room() {
+                events |= libc::EPOLLOUT as u32;
+            }
+
+            let timeout_ms = match deadline {
+                None => -1,
+                Some(deadline) => {
+                    let now = std::time::Instant::now();
+                    if now >= deadline {
+                        return Ok(Ready::default());
+                    }
+                    // Rounded up, so that the deadline is not missed by a few microseconds.
+                    (deadline - now)
+                        .as_micros()
+                        .div_ceil(1000)
+                        .min(i32::MAX as u128) as i32
+                }
+            };
+            let epoll = match &mut self.epoll {
+                Some(epoll) => epoll,
+                epoll => epoll.insert(sys::xdp::Epoll::new(self.lower.as_raw_fd())?),
+            };
+            if epoll.wait(events, timeout_ms)? == 0 {
+                return Ok(Ready::default());
+            }
+        }
+    }
+
+    /// Received frames and completions available without sleeping.
+    pub fn ready(&self) -> Ready {
+        let inner = lock(&self.inner);
+        Ready {
+            rx: self.direction != Direction::Tx
+                && (!inner.rx_batch.is_empty() || inner.rx.available() > 0),
+            completions: self.direction != Direction::Rx && inner.cr.available() > 0,
+        }
+    }
+
+    /// Whether the TX ring has room for a frame.
+    pub fn tx_room(&self) -> bool {
+        let inner = lock(&self.inner);
+        inner.tx.pending() < inner.tx.size()
+    }
+
+    /// Hands the recycled RX frames and the queued TX frames to the kernel, so that it
+    /// has something to wake the socket up for.
+    ///
+    /// Called by [`XdpSocket::wait`], and to be called before sleeping on the socket fd
+    /// from an event loop of one's own, e.g. with mio.
+    pub fn prepare_wait(&mut self) {
+        let mut inner = lock(&self.inner);
+        if self.direction != Direction::Tx {
+            inner.flush_fill();
+        }
+        if self.direction != Direction::Rx {
+            inner.flush_tx();
+        }
+    }
+
+    /// Runs `iface` over the socket in poll mode, never sleeping, until `f` breaks or
+    /// `budget` iterations have run, returning how many did. `None` spins until `f`
+    /// breaks.
+    ///
+    /// Every iteration polls `iface`, kicks the kernel for the frames it queued, reaps
+    /// the completion ring, posts the recycled frames to the fill ring and then calls
+    /// `f` to let the application use its sockets. The kernel is only woken up when it
+    /// asks for it with `Config::need_wakeup`: the TX ring is kicked while it holds
+    /// frames the driver stopped sending, the fill ring while it waits for a kick, so an
+    /// idle loop makes no syscall. Sockets with `Config::busy_poll` kick both every
+    /// iteration to drive the NAPI loop.
+    ///
+    /// Burns a whole core, meant for sockets pinned to one. Cookies of completed frames
+    /// still wait for [`XdpSocket::poll_completions`].
+    pub fn run_busy<F>(
+        &mut self,
+        iface: &mut Interface,
+        sockets: &mut SocketSet<'_>,
+        budget: Option<usize>,
+        mut f: F,
+    ) -> usize
+    where
+        F: FnMut(&mut Interface, &mut SocketSet<'_>) -> ControlFlow<()>,
+    {
+        let mut iterations = 0;
+        while budget.is_none_or(|budget| iterations < budget) {
+            iterations += 1;
+            self.rx_budget.replenish();
+            iface.poll(Instant::now(), self, sockets);
+
+            let mut inner = lock(&self.inner);
+            inner.flush_tx();
+            if self.direction != Direction::Rx {
+                inner.reclaim();
+                if inner.tx.pending() > 0
+                    && (inner.busy_poll || !inner.need_wakeup || inner.tx.needs_wakeup())
+                {
+                    let _ = sys::xdp::kick_tx(inner.tx_fd);
+                    inner.profiler.tx_kick();
+                }
+            }
+            if self.direction != Direction::Tx {
+                inner.flush_fill();
+                if inner.busy_poll || (inner.need_wakeup && inner.fr.needs_wakeup()) {
+                    let _ = sys::xdp::kick_rx(self.lower.as_raw_fd());
+                    inner.profiler.rx_kick();
+                }
+            }
+            drop(inner);
+
+            if f(iface, sockets).is_break() {
+                break;
+            }
+        }
+        iterations
+    }
+
+    /// RX and TX rates and burst sizes smoothed with an exponentially weighted moving
+    /// average.
+    pub fn telemetry(&self) -> Telemetry {
+        let inner = lock(&self.inner);
+        Telemetry {
+            rx: inner.rx_meter.rates(),
+            tx: inner.tx_meter.rates(),
+        }
+    }
+
+    /// Samples the rates every `window` and weighs each sample by `alpha`, between 0 and 1.
+    /// Defaults to 100 ms and 0.25.
+    pub fn set_telemetry(&mut self, window: Duration, alpha: f64) {
+        let mut inner = lock(&self.inner);
+        inner.rx_meter.configure(window, alpha);
+        inner.tx_meter.configure(window, alpha);
+    }
+
+    /// Takes the first unexpected error the TX path met since the last call, the frame it
+    /// hit was dropped and counted in `Stats::tx_errors`. Running out of TX ring entries
+    /// or UMEM pages outside the control-plane reserve, or reaching the TX quota, is not
+    /// an error: [`Device::transmit`] returns `None` then.
+    pub fn take_tx_error(&mut self) -> Option<XdpError> {
+        lock(&self.inner).tx_error.take()
+    }
+
+    /// Packet, byte and drop counters kept by the socket. Frames the TX path drops for lack
+    /// of room show up here, see also [`XdpSocket::tx_quota_exhausted`].
+    pub fn stats(&self) -> Stats {
+        lock(&self.inner).stats
+    }
+
+    /// Drop and error counters the kernel keeps for the socket, from `XDP_STATISTICS`. They
+    /// are the only trace of frames lost inside the kernel, e.g. for lack of fill buffers.
+    pub fn kernel_stats(&self) -> Result<KernelStats, XdpError> {
+        Ok(self.lower.statistics().map(KernelStats::from)?)
+    }
+
+    /// Starts or stops collecting the [`Profile`] of the socket. Profiling is off by
+    /// default as timing every device call costs two clock reads.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        lock(&self.inner).profiler.set_enabled(enabled);
+    }
+
+    pub fn profiling(&self) -> bool {
+        lock(&self.inner).profiler.enabled()
+    }
+
+    /// Syscalls, busy iterations and poll cycle times counted since profiling was
+    /// enabled or last reset.
+    pub fn profile(&self) -> Profile {
+        lock(&self.inner).profiler.profile()
+    }
+
+    pub fn reset_profile(&mut self) {
+        lock(&self.inner).profiler.reset();
+    }
+
+    /// Limits the UMEM frames this socket's TX path may hold in flight, so that sockets
+    /// sharing the UMEM cannot starve each other. [`Device::transmit`] returns `None` once
+    /// the quota is reached, and frames beyond it sent through other tokens are dropped.
+    ///
+    /// RX frames are recycled through the fill ring and never draw from the pages left
+    /// for TX, so only TX needs a quota.
+    pub fn set_tx_quota(&mut self, quota: Option<usize>) {
+        lock(&self.umem()).accounts[self.account].quota = quota;
+    }
+
+    pub fn tx_quota(&self) -> Option<usize> {
+        lock(&self.umem()).accounts[self.account].quota
+    }
+
+    /// TX frames sent by this socket and not completed yet.
+    pub fn tx_in_flight(&self) -> usize {
+        lock(&self.umem()).accounts[self.account].in_flight
+    }
+
+    /// TX tokens refused, or frames dropped, because this socket reached its TX quota.
+    pub fn tx_quota_exhausted(&self) -> u64 {
+        lock(&self.umem()).accounts[self.account].exhausted
+    }
+
+    /// Returns the UMEM area backing this socket.
+    ///
+    /// # Safety
+    ///
+    /// The area is only valid while the socket is alive. Chunks are owned by the kernel
+    /// between being posted to the fill or TX ring and being returned through the RX or
+    /// completion ring, and by the socket otherwise; the caller must not write to a chunk
+    /// it does not own nor touch the per-chunk headroom reserved by the crate.
+    pub unsafe fn raw_umem(&self) -> RawUmem {
+        lock(&self.umem()).umem.raw()
+    }
+
+    /// Returns the mapping of the ring of the given type.
+    ///
+    /// # Safety
+    ///
+    /// The mapping is only valid while the socket is alive. The socket is the only
+    /// userspace producer of the TX and fill rings and the only consumer of the RX and
+    /// completion rings: the caller must not advance those indices, only observe them.
+    pub unsafe fn raw_ring(&self, type_: RingType) -> RawRing {
+        let inner = lock(&self.inner);
+        match type_ {
+            RingType::Tx => inner.tx.raw(),
+            RingType::Rx => inner.rx.raw(),
+            RingType::Completion => inner.cr.raw(),
+            RingType::Fill => inner.fr.raw(),
+        }
+    }
+}
+
+impl HealthCheck for XdpSocket<'_> {
+    fn check(&self, report: &mut Report) {
+        const LAYER: &str = "xdp";
+        let before = report.checks().len();
+
+        match self.lower.link_up() {
+            Ok(true) => {}
+            Ok(false) => report.fail(LAYER, Status::Unhealthy, "link down"),
+            Err(err) => report.fail(
+                LAYER,
+                Status::Unhealthy,
+                format!("link state unavailable: {}", err),
+            ),
+        }
+
+        let inner = lock(&self.inner);
+        if self.direction != Direction::Tx && inner.fr.pending() == 0 {
+            report.fail(
+                LAYER,
+                Status::Unhealthy,
+                "fill ring empty, the kernel has no buffer for RX",
+            );
+        }
+        if self.direction != Direction::Rx {
+            if inner.tx.pending() == inner.tx.size() {
+                report.fail(
+                    LAYER,
+                    Status::Degraded,
+                    "TX ring full, the kernel is not consuming it",
+                );
+            }
+            if !lock(&inner.umem).umem.has_free() {
+                report.fail(LAYER, Status::Degraded, "no free UMEM frame for TX");
+            }
+        }
+        drop(inner);
+
+        match self.lower.statistics() {
+            Ok(stats) => {
+                let last = self.last_stats.replace(stats);
+                let dropped = stats.rx_dropped.wrapping_sub(last.rx_dropped)
+                    + stats.rx_ring_full.wrapping_sub(last.rx_ring_full)
+                    + stats
+                        .rx_fill_ring_empty_descs
+                        .wrapping_sub(last.rx_fill_ring_empty_descs);
+                let invalid = stats.rx_invalid_descs.wrapping_sub(last.rx_invalid_descs)
+                    + stats.tx_invalid_descs.wrapping_sub(last.tx_invalid_descs);
+                if dropped > 0 {
+                    report.fail(
+                        LAYER,
+                        Status::Degraded,
+                        format!("{} frames dropped since the last check", dropped),
+                    );
+                }
+                if invalid > 0 {
+                    report.fail(
+                        LAYER,
+                        Status::Degraded,
+                        format!("{} invalid descriptors since the last check", invalid),
+                    );
+                }
+            }
+            Err(err) => report.fail(
+                LAYER,
+                Status::Degraded,
+                format!("statistics unavailable: {}", err),
+            ),
+        }
+
+        if report.checks().len() == before {
+            report.ok(LAYER);
+        }
+    }
+}
+
+impl CapabilitiesAudit for XdpSocket<'_> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("xdp", self.capabilities());
     }
 }
 
 impl<'a> Device for XdpSocket<'a> {
     type RxToken<'b>
-        = RxToken
+        = RxToken<'a>
     where
         Self: 'b;
 
@@ -123,56 +2071,306 @@ impl<'a> Device for XdpSocket<'a> {
         let mut caps = DeviceCapabilities::default();
         caps.max_transmission_unit = mtu;
         caps.medium = smoltcp::phy::Medium::Ethernet;
-        caps.max_burst_size = Default::default();
-        caps.checksum = Default::default();
+        let inner = lock(&self.inner);
+        caps.max_burst_size = inner.max_burst_size;
+        // Verified by the NIC on RX, computed by it on TX.
+        let checksum = |rx_verified: bool, tx_offloaded: bool| match (rx_verified, tx_offloaded) {
+            (false, false) => Checksum::Both,
+            (false, true) => Checksum::Rx,
+            (true, false) => Checksum::Tx,
+            (true, true) => Checksum::None,
+        };
+        let verified = inner.rx_verified;
+        caps.checksum.ipv4 = checksum(verified.ipv4, false);
+        caps.checksum.tcp = checksum(verified.tcp, inner.tx_checksum);
+        caps.checksum.udp = checksum(verified.udp, inner.tx_checksum);
+        caps.checksum.icmpv4 = checksum(verified.icmpv4, false);
+        caps.checksum.icmpv6 = checksum(verified.icmpv6, false);
         caps
     }
 
-    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
-        let mut inner = self.inner.borrow_mut();
-        if let Some(desc) = inner.rx.read() {
-            let page_id = inner.umem.page_id_from(desc);
-            let page = inner.umem.read(page_id);
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let mut inner = lock(&self.inner);
+        self.rx_budget.begin(timestamp);
+        if self.direction == Direction::Tx || self.rx_budget.exhausted() {
+            return None;
+        }
+        let started = inner.profiler.enter(timestamp);
+        if self.direction == Direction::Both {
+            inner.reclaim();
+        }
+        inner.rx_meter.tick(timestamp);
+
+        while let Some((desc, dequeued)) = inner.next_rx() {
+            // Fragments of a multi-buffer frame are copied out as they come, the frame is
+            // handed out once its last descriptor, without XDP_PKT_CONTD, is read.
+            let continued = desc.options & libc::XDP_PKT_CONTD != 0;
+            let partial = inner.rx_partial.take();
+            let first = partial.is_none();
+            let fragmented = continued || !first;
+            // A multi-buffer frame is stamped with the time of its first descriptor.
+            let (mut buffer, mut metadata, dequeued) =
+                partial.unwrap_or_else(|| (inner.rx_pool.take(), None, dequeued));
+            let (verdict, addr) = {
+                let mut shared = lock(&inner.umem);
+                let desc = libc::xdp_desc {
+                    addr: shared.umem.resolve(desc.addr),
+                    ..desc
+                };
+                let page_id = shared.umem.page_id_from(desc.addr);
+                shared.umem.received(page_id);
+                shared.umem.acquire(page_id);
+                let page = shared.umem.read(page_id);
 
-            let data = page.read_packet(desc).to_vec();
+                let packet = page.read_packet(desc);
+                // Single-buffer frames are judged in place, before being copied.
+                let verdict = (!fragmented).then(|| {
+                    self.verdict
+                        .as_mut()
+                        .map_or(Verdict::Pass, |hook| hook(packet))
+                });
+                if verdict != Some(Verdict::Drop) {
+                    buffer.put_slice(packet);
+                }
+                if first {
+                    metadata = meta::Meta::parse(page.read_before(desc, meta::Meta::LEN));
+                }
+                shared.umem.release(page_id);
+                (verdict, shared.umem.fill(page_id))
+            };
+            inner.recycle(addr);
+            inner.stats.rx_bytes += u64::from(desc.len);
+            inner.stats.fill_recycled += 1;
+            if continued {
+                inner.rx_partial = Some((buffer, metadata, dequeued));
+                continue;
+            }
 
-            let desc = inner.umem.free(page_id);
-            let _ = inner.fr.write(desc);
+            let len = if fragmented {
+                inner.stats.rx_multi_buffer += 1;
+                buffer.len()
+            } else {
+                desc.len as usize
+            };
+            inner.rx_meter.record(timestamp, len);
+            inner.stats.rx_packets += 1;
+            let verdict = verdict.unwrap_or_else(|| {
+                self.verdict
+                    .as_mut()
+                    .map_or(Verdict::Pass, |hook| hook(buffer.as_ref()))
+            });
+            match verdict {
+                Verdict::Pass => {}
+                Verdict::Drop => {
+                    inner.stats.verdict_dropped += 1;
+                    inner.rx_pool.give(buffer);
+                    continue;
+                }
+                Verdict::Queue if self.queued.len() >= self.queue_limit => {
+                    inner.stats.queue_full += 1;
+                    inner.rx_pool.give(buffer);
+                    continue;
+                }
+                Verdict::Queue => {
+                    inner.stats.verdict_queued += 1;
+                    self.queued.push_back(buffer);
+                    continue;
+                }
+            }
+
+            self.rx_budget.spend();
+            let trace_id = inner.next_trace_id;
+            inner.next_trace_id = trace_id.wrapping_add(1);
+            inner.profiler.leave(started);
 
             return Some((
-                RxToken { buffer: data },
+                RxToken {
+                    buffer: Some(buffer),
+                    inner: self.inner.clone(),
+                    options: desc.options,
+                    timestamp: dequeued,
+                    metadata,
+                    trace_id,
+                },
                 TxToken {
                     inner: self.inner.clone(),
+                    options: 0,
+                    cookie: None,
+                    account: self.account,
+                    timestamp,
                 },
             ));
         }
+
+        inner.flush_fill();
+        // The driver stopped taking frames from the fill ring and waits for a kick.
+        inner.profiler.busy_iteration();
+        if inner.busy_poll || (inner.need_wakeup && inner.fr.needs_wakeup()) {
+            let _ = sys::xdp::kick_rx(self.lower.as_raw_fd());
+            inner.profiler.rx_kick();
+        }
+        inner.profiler.leave(started);
         None
     }
 
-    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        if self.direction == Direction::Rx {
+            return None;
+        }
+        let mut inner = lock(&self.inner);
+        let started = inner.profiler.enter(timestamp);
+        inner.reclaim();
+        inner.tx_meter.tick(timestamp);
+        // Refusing the token is how smoltcp is told to hold the frame back, rather than
+        // building it only to drop it. The frame is not built yet, so it cannot be told
+        // apart from bulk traffic: the control-plane reserve and the quota exemption only
+        // serve the replies sent through the TX token of `receive`, which smoltcp uses for
+        // ARP and neighbor discovery answers and for ACKs.
+        let ready = inner.tx_ready(self.account);
+        if !ready {
+            inner.stats.tx_backpressure += 1;
+        }
+        inner.profiler.leave(started);
+        drop(inner);
+        if !ready {
+            return None;
+        }
+
         Some(TxToken {
             inner: self.inner.clone(),
+            options: 0,
+            cookie: None,
+            account: self.account,
+            timestamp,
         })
     }
 }
 
-#[doc(hidden)]
-pub struct RxToken {
-    buffer: Vec<u8>,
+/// A received frame.
+pub struct RxToken<'a> {
+    // Always set until dropped.
+    buffer: Option<FrameBuf>,
+    inner: Arc<Mutex<Inner<'a>>>,
+    options: u32,
+    timestamp: std::time::Instant,
+    metadata: Option<meta::Meta>,
+    trace_id: u32,
 }
 
-impl smoltcp::phy::RxToken for RxToken {
+impl RxToken<'_> {
+    /// Monotonic time at which the descriptor was taken from the RX ring.
+    ///
+    /// Comparing it with the time the frame is processed gives the queueing delay
+    /// inside the application, independently of hardware timestamping support.
+    pub fn timestamp(&self) -> std::time::Instant {
+        self.timestamp
+    }
+
+    /// The `options` field of the RX descriptor, e.g. `libc::XDP_PKT_CONTD`.
+    pub fn options(&self) -> u32 {
+        self.options
+    }
+
+    /// Metadata written by the XDP program in front of the frame, see [`meta`].
+    ///
+    /// Chunks are recycled without clearing their headroom, so a program must write the
+    /// metadata for every frame or for none of them.
+    pub fn metadata(&self) -> Option<meta::Meta> {
+        self.metadata
+    }
+
+    /// Time in nanoseconds at which the NIC received the frame, in the clock of the NIC,
+    /// as written by the bundled XDP program from the XDP hints of the driver.
+    ///
+    /// `None` without metadata or when the driver reported no timestamp, see
+    /// [`meta::Meta::hw_timestamp`].
+    pub fn hw_timestamp(&self) -> Option<u64> {
+        self.metadata
+            .map(|meta| meta.hw_timestamp)
+            .filter(|&timestamp| timestamp != 0)
+    }
+
+    /// Hash the NIC computed over the headers of the frame, as written by the bundled XDP
+    /// program from the XDP hints of the driver. Frames of a flow share it, so it can
+    /// pick their worker without hashing the headers again, see
+    /// [`crate::phy::flow::Dispatcher::dispatch_hashed`].
+    pub fn rx_hash(&self) -> Option<meta::RxHash> {
+        self.metadata.and_then(|meta| meta.rx_hash())
+    }
+
+    /// VLAN tag the NIC stripped from the frame, as written by the bundled XDP program
+    /// from the XDP hints of the driver.
+    pub fn vlan_tag(&self) -> Option<meta::VlanTag> {
+        self.metadata.and_then(|meta| meta.vlan_tag())
+    }
+
+    /// Id of the frame among those received by the socket, increasing by one per frame
+    /// and wrapping around.
+    ///
+    /// With the `packetmeta-id` feature it is also the `PacketMeta::id` of the token,
+    /// which the wrappers of this crate hand up unchanged, so hooks and captures at any
+    /// layer of a stack can tell which frame they see.
+    pub fn trace_id(&self) -> u32 {
+        self.trace_id
+    }
+}
+
+impl smoltcp::phy::RxToken for RxToken<'_> {
     fn consume<R, F>(self, f: F) -> R
     where
         F: FnOnce(&[u8]) -> R,
     {
-        f(&self.buffer[..])
+        f(self
+            .buffer
+            .as_ref()
+            .expect("RX buffer is only taken on drop")
+            .as_ref())
+    }
+
+    fn meta(&self) -> smoltcp::phy::PacketMeta {
+        #[allow(unused_mut)]
+        let mut meta = smoltcp::phy::PacketMeta::default();
+        #[cfg(feature = "packetmeta-id")]
+        {
+            meta.id = self.trace_id;
+        }
+        meta
+    }
+}
+
+impl Drop for RxToken<'_> {
+    fn drop(&mut self) {
+        if let (Some(buffer), Ok(mut inner)) = (self.buffer.take(), self.inner.try_lock()) {
+            inner.rx_pool.give(buffer);
+        }
     }
 }
 
-#[doc(hidden)]
+/// A frame to be transmitted.
 pub struct TxToken<'a> {
-    inner: Rc<RefCell<Inner<'a>>>,
+    inner: Arc<Mutex<Inner<'a>>>,
+    options: u32,
+    cookie: Option<u64>,
+    account: usize,
+    // Poll timestamp the token was handed out at.
+    timestamp: Instant,
+}
+
+impl TxToken<'_> {
+    /// Sets the `options` field of the TX descriptor. Defaults to 0.
+    ///
+    /// The value is passed to the kernel as is, so the bits must be supported by the
+    /// socket configuration (e.g. `libc::XDP_TX_METADATA` requires TX metadata).
+    pub fn set_options(&mut self, options: u32) {
+        self.options = options;
+    }
+
+    /// Attaches an opaque cookie reported by [`XdpSocket::poll_completions`] once the
+    /// frame completes. Frames dropped, e.g. because the TX ring or the UMEM filled up,
+    /// are never reported.
+    pub fn set_cookie(&mut self, cookie: u64) {
+        self.cookie = Some(cookie);
+    }
 }
 
 impl<'a> smoltcp::phy::TxToken for TxToken<'a> {
@@ -180,26 +2378,136 @@ impl<'a> smoltcp::phy::TxToken for TxToken<'a> {
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        let mut inner = self.inner.borrow_mut();
-        let mut buffer = vec![0; len];
-        let result = f(&mut buffer);
+        let mut guard = lock(&self.inner);
+        let inner = &mut *guard;
+        let started = inner.profiler.enter(self.timestamp);
+        let mut buffer = inner.scratch.take();
+        let result = f(buffer.put(len));
+        if len < inner.tx_min_len {
+            buffer.put(inner.tx_min_len - len);
+        }
 
-        if let Some(desc) = inner.cr.read() {
-            let page_id = inner.umem.page_id_from(desc);
-            inner.umem.free(page_id);
+        // Frames may have completed since the token was handed out.
+        if !lock(&inner.umem).umem.has_free() {
+            inner.reclaim();
         }
 
-        match inner.umem.write(&buffer[..]) {
-            Ok(desc) => {
-                if inner.tx.write(desc).is_err() {
-                    let page_id = inner.umem.page_id_from(desc);
-                    inner.umem.free(page_id);
+        let priority = inner
+            .tx_priority
+            .is_some_and(|dscp| priority::is_control(buffer.as_ref(), dscp));
+        let mut shared = lock(&inner.umem);
+        let account = &mut shared.accounts[self.account];
+        if !priority
+            && account
+                .quota
+                .is_some_and(|quota| account.in_flight >= quota)
+        {
+            account.exhausted += 1;
+            drop(shared);
+            inner.scratch.give(buffer);
+            inner.profiler.leave(started);
+            return result;
+        }
+
+        let request = if inner.tx_checksum {
+            checksum::prepare(buffer.as_mut())
+        } else {
+            None
+        };
+        let room = shared.umem.frame_room();
+        if buffer.len() > room {
+            let fragments = buffer.len().div_ceil(room);
+            if !inner.multi_buffer {
+                inner.stats.tx_too_long += 1;
+            } else if ((inner.tx.size() - inner.tx.pending()) as usize) < fragments {
+                // The chain is queued whole or not at all.
+                inner.stats.tx_ring_full += 1;
+            } else {
+                match shared
+                    .umem
+                    .write_chain(buffer.as_ref(), priority, &mut inner.tx_chain)
+                {
+                    Ok(()) => {
+                        let first = &mut inner.tx_chain[0];
+                        first.options |= self.options;
+                        // The kernel reads the TX metadata of the first chunk only.
+                        if let Some(request) = request {
+                            let page_id = shared.umem.page_id_from(first.addr);
+                            shared
+                                .umem
+                                .request_checksum(page_id, request.start, request.offset);
+                            first.options |= libc::XDP_TX_METADATA;
+                        }
+                        // Only the last chunk reports the cookie.
+                        let last = inner.tx_chain.len() - 1;
+                        for (i, desc) in inner.tx_chain.iter().enumerate() {
+                            let page_id = shared.umem.page_id_from(desc.addr);
+                            shared.umem.transmitted(page_id);
+                            shared.in_flight[page_id] = Some(InFlight {
+                                account: self.account,
+                                cookie: self.cookie.filter(|_| i == last),
+                            });
+                        }
+                        shared.accounts[self.account].in_flight += fragments;
+                        inner.tx.write_batch(&inner.tx_chain);
+                        inner.tx_meter.record(self.timestamp, len);
+                        inner.tx_unkicked += 1;
+                        inner.stats.tx_packets += 1;
+                        inner.stats.tx_multi_buffer += 1;
+                        inner.stats.tx_bytes += buffer.len() as u64;
+                    }
+                    Err(XdpError::NoFreeFrames) => inner.stats.tx_no_buffer += 1,
+                    Err(err) => {
+                        // Dropped, the first error is kept for `XdpSocket::take_tx_error`.
+                        inner.stats.tx_errors += 1;
+                        inner.tx_error.get_or_insert(err);
+                    }
                 }
             }
-            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
-            Err(err) => panic!("{}", err),
+        } else {
+            match shared.umem.write(buffer.as_ref(), priority) {
+                Ok(mut desc) => {
+                    desc.options = self.options;
+                    let page_id = shared.umem.page_id_from(desc.addr);
+                    if let Some(request) = request {
+                        shared
+                            .umem
+                            .request_checksum(page_id, request.start, request.offset);
+                        desc.options |= libc::XDP_TX_METADATA;
+                    }
+                    if inner.tx.write(desc).is_err() {
+                        shared.umem.free(page_id);
+                        inner.stats.tx_ring_full += 1;
+                    } else {
+                        shared.umem.transmitted(page_id);
+                        shared.in_flight[page_id] = Some(InFlight {
+                            account: self.account,
+                            cookie: self.cookie,
+                        });
+                        shared.accounts[self.account].in_flight += 1;
+                        inner.tx_meter.record(self.timestamp, len);
+                        inner.tx_unkicked += 1;
+                        inner.stats.tx_packets += 1;
+                        inner.stats.tx_bytes += u64::from(desc.len);
+                    }
+                }
+                Err(XdpError::NoFreeFrames) => inner.stats.tx_no_buffer += 1,
+                Err(err) => {
+                    // Dropped, the first error is kept for `XdpSocket::take_tx_error`.
+                    inner.stats.tx_errors += 1;
+                    inner.tx_error.get_or_insert(err);
+                }
+            }
+        }
+        drop(shared);
+
+        // A full ring is kicked whatever the batch size, it would not drain otherwise.
+        if inner.tx_unkicked >= inner.tx_batch || inner.tx.pending() >= inner.tx.size() {
+            inner.flush_tx();
         }
 
+        inner.scratch.give(buffer);
+        inner.profiler.leave(started);
         result
     }
 }
@@ -0,0 +1,146 @@
+//! Batching tuned to the observed load of a socket.
+//!
+//! Small batches keep per-frame latency low but cost a syscall or a ring update every
+//! few frames, large ones amortise those costs at the price of frames waiting for their
+//! batch. [`Adaptive`] moves the RX budget, the RX and fill batches and the TX kick
+//! threshold of a socket between both ends as its load changes.
+
+use crate::phy::xdp::XdpSocket;
+
+/// Values a knob takes at no load and at full load.
+#[derive(Copy, Clone, Debug)]
+pub struct Range {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Range {
+    fn at(&self, load: f64) -> usize {
+        let span = self.max.saturating_sub(self.min) as f64;
+        self.min + (load * span).round() as usize
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// RX plus TX frames per second at or below which the socket is tuned for latency.
+    pub low_pps: f64,
+    /// RX plus TX frames per second at or above which the socket is tuned for throughput.
+    pub high_pps: f64,
+    /// RX ring occupancy at or above which the socket is tuned for throughput whatever
+    /// its rate, see [`XdpSocket::rx_occupancy`].
+    pub high_occupancy: f64,
+    /// Weight of each load sample in the smoothed load.
+    pub alpha: f64,
+    pub rx_budget: Range,
+    pub rx_batch: Range,
+    pub tx_batch: Range,
+    pub fill_batch: Range,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            low_pps: 10_000.0,
+            high_pps: 1_000_000.0,
+            high_occupancy: 0.5,
+            alpha: 0.25,
+            rx_budget: Range { min: 16, max: 256 },
+            rx_batch: Range { min: 1, max: 64 },
+            tx_batch: Range { min: 1, max: 64 },
+            fill_batch: Range { min: 1, max: 64 },
+        }
+    }
+}
+
+/// Knob values applied by the last [`Adaptive::update`].
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Tuning {
+    pub rx_budget: usize,
+    pub rx_batch: usize,
+    pub tx_batch: usize,
+    pub fill_batch: usize,
+}
+
+/// Retunes the batching of a socket from its smoothed rates and RX ring occupancy.
+///
+/// The rate is mapped to a load between 0 and 1 on a logarithmic scale from
+/// `Config::low_pps` to `Config::high_pps`, and a filling RX ring counts as full load
+/// since it means the application does not keep up. Every knob then moves linearly
+/// within its range with the smoothed load.
+///
+/// A larger TX batch leaves frames on the TX ring until it fills up, so
+/// [`XdpSocket::flush_tx`] must run after every `Interface::poll`.
+pub struct Adaptive {
+    config: Config,
+    load: f64,
+    tuning: Option<Tuning>,
+}
+
+impl Adaptive {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            load: 0.0,
+            tuning: None,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Smoothed load, from 0 when idle to 1 under full load.
+    pub fn load(&self) -> f64 {
+        self.load
+    }
+
+    /// Knob values applied by the last update, `None` before the first one.
+    pub fn tuning(&self) -> Option<Tuning> {
+        self.tuning
+    }
+
+    /// Samples the load of `socket` and retunes it. Meant to be called after every
+    /// `Interface::poll`, the rates being smoothed by the socket telemetry.
+    pub fn update(&mut self, socket: &mut XdpSocket<'_>) -> Tuning {
+        let telemetry = socket.telemetry();
+        let sample = self
+            .rate_load(telemetry.rx.pps + telemetry.tx.pps)
+            .max(self.occupancy_load(socket.rx_occupancy()));
+        self.load += self.config.alpha * (sample - self.load);
+
+        let tuning = Tuning {
+            rx_budget: self.config.rx_budget.at(self.load),
+            rx_batch: self.config.rx_batch.at(self.load),
+            tx_batch: self.config.tx_batch.at(self.load),
+            fill_batch: self.config.fill_batch.at(self.load),
+        };
+        if self.tuning != Some(tuning) {
+            socket.set_rx_budget(Some(tuning.rx_budget));
+            socket.set_rx_batch(tuning.rx_batch);
+            socket.set_tx_batch(tuning.tx_batch);
+            socket.set_fill_batch(tuning.fill_batch);
+            self.tuning = Some(tuning);
+        }
+        tuning
+    }
+
+    fn rate_load(&self, pps: f64) -> f64 {
+        let low = self.config.low_pps.max(1.0);
+        let high = self.config.high_pps.max(low);
+        if pps <= low {
+            return 0.0;
+        }
+        if pps >= high {
+            return 1.0;
+        }
+        (pps / low).ln() / (high / low).ln()
+    }
+
+    fn occupancy_load(&self, occupancy: f64) -> f64 {
+        if self.config.high_occupancy <= 0.0 {
+            return 0.0;
+        }
+        (occupancy / self.config.high_occupancy).min(1.0)
+    }
+}
@@ -0,0 +1,123 @@
+//! Driving an [`XdpSocket`] from a tokio runtime.
+//!
+//! The socket is registered with the reactor through `AsyncFd`, so a task sleeps on it
+//! instead of blocking the thread in `XdpSocket::wait`. The task may be spawned on a
+//! multi-thread runtime as well:
+//!
+//! ```ignore
+//! let mut socket = AsyncXdpSocket::new(XdpSocket::new("eth0", config)?)?;
+//! socket
+//!     .run(&mut iface, &mut sockets, |_, sockets| {
+//!         serve(sockets);
+//!         ControlFlow::Continue(())
+//!     })
+//!     .await?;
+//! ```
+
+use std::io;
+use std::ops::ControlFlow;
+
+use smoltcp::iface::{Interface, SocketSet};
+use smoltcp::time::Instant;
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+
+use crate::phy::xdp::{XdpError, XdpSocket};
+
+/// An [`XdpSocket`] registered with the tokio reactor.
+pub struct AsyncXdpSocket<'a> {
+    fd: AsyncFd<XdpSocket<'a>>,
+}
+
+impl<'a> AsyncXdpSocket<'a> {
+    /// Registers `socket` with the reactor of the current runtime, failing outside of
+    /// one.
+    pub fn new(socket: XdpSocket<'a>) -> Result<Self, XdpError> {
+        Ok(Self {
+            fd: AsyncFd::with_interest(socket, Interest::READABLE | Interest::WRITABLE)?,
+        })
+    }
+
+    /// Waits until received frames wait in the RX ring.
+    pub async fn readable(&mut self) -> io::Result<()> {
+        self.ready(Interest::READABLE).await
+    }
+
+    /// Waits until the TX ring has room for a frame or sent frames completed.
+    pub async fn writable(&mut self) -> io::Result<()> {
+        self.ready(Interest::WRITABLE).await
+    }
+
+    /// Hands the pending frames to the kernel and waits for `interest`. The readiness
+    /// cached by the reactor is cleared when the rings turn out to have nothing, so
+    /// that the next wait sleeps.
+    async fn ready(&mut self, interest: Interest) -> io::Result<()> {
+        self.fd.get_mut().prepare_wait();
+        loop {
+            let mut guard = self.fd.ready(interest).await?;
+            let socket = guard.get_inner();
+            let ready = socket.ready();
+            if (interest.is_readable() && ready.rx)
+                || (interest.is_writable() && (socket.tx_room() || ready.completions))
+            {
+                return Ok(());
+            }
+            guard.clear_ready();
+        }
+    }
+
+    /// Polls `iface` over the socket until `f` breaks, calling `f` after every poll to
+    /// let the application use its sockets.
+    ///
+    /// Between polls the task sleeps until frames are received, the TX ring has room
+    /// again once exhausted, or `Interface::poll_delay` elapses.
+    pub async fn run<F>(
+        &mut self,
+        iface: &mut Interface,
+        sockets: &mut SocketSet<'_>,
+        mut f: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&mut Interface, &mut SocketSet<'_>) -> ControlFlow<()>,
+    {
+        loop {
+            let socket = self.fd.get_mut();
+            socket.replenish_rx_budget();
+            iface.poll(Instant::now(), socket, sockets);
+            socket.flush_tx();
+            if f(iface, sockets).is_break() {
+                return Ok(());
+            }
+
+            let mut interest = Interest::READABLE;
+            if !self.fd.get_ref().tx_room() {
+                interest |= Interest::WRITABLE;
+            }
+            match iface.poll_delay(Instant::now(), sockets) {
+                Some(delay) => {
+                    if let Ok(result) =
+                        tokio::time::timeout(delay.into(), self.ready(interest)).await
+                    {
+                        result?;
+                    }
+                }
+                None => self.ready(interest).await?,
+            }
+        }
+    }
+
+    /// Get a reference to the underlying socket.
+    pub fn get_ref(&self) -> &XdpSocket<'a> {
+        self.fd.get_ref()
+    }
+
+    /// Get a mutable reference to the underlying socket.
+    pub fn get_mut(&mut self) -> &mut XdpSocket<'a> {
+        self.fd.get_mut()
+    }
+
+    /// Return the underlying socket, deregistering it from the reactor.
+    pub fn into_inner(self) -> XdpSocket<'a> {
+        self.fd.into_inner()
+    }
+}
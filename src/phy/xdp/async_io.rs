@@ -0,0 +1,128 @@
+//! Driving an [`XdpSocket`] from the async-io reactor, the one of smol, as
+//! `xdp::async_fd` does for tokio.
+//!
+//! ```ignore
+//! let mut socket = AsyncXdpSocket::new(XdpSocket::new("eth0", config)?)?;
+//! socket
+//!     .run(&mut iface, &mut sockets, |_, sockets| {
+//!         serve(sockets);
+//!         ControlFlow::Continue(())
+//!     })
+//!     .await?;
+//! ```
+
+use std::io;
+use std::ops::ControlFlow;
+
+use ::async_io::{Async, Timer};
+use futures_lite::future;
+use smoltcp::iface::{Interface, SocketSet};
+use smoltcp::time::Instant;
+
+use crate::phy::xdp::{XdpError, XdpSocket};
+
+/// An [`XdpSocket`] registered with the async-io reactor.
+pub struct AsyncXdpSocket<'a> {
+    io: Async<XdpSocket<'a>>,
+}
+
+impl<'a> AsyncXdpSocket<'a> {
+    /// Registers `socket` with the reactor, which is started on first use.
+    pub fn new(socket: XdpSocket<'a>) -> Result<Self, XdpError> {
+        Ok(Self {
+            io: Async::new(socket)?,
+        })
+    }
+
+    /// Waits until received frames wait in the RX ring.
+    pub async fn readable(&mut self) -> io::Result<()> {
+        self.prepare_wait();
+        while !self.io.get_ref().ready().rx {
+            self.io.readable().await?;
+        }
+        Ok(())
+    }
+
+    /// Waits until the TX ring has room for a frame or sent frames completed.
+    pub async fn writable(&mut self) -> io::Result<()> {
+        self.prepare_wait();
+        loop {
+            let socket = self.io.get_ref();
+            if socket.tx_room() || socket.ready().completions {
+                return Ok(());
+            }
+            self.io.writable().await?;
+        }
+    }
+
+    fn prepare_wait(&mut self) {
+        self.get_mut().prepare_wait();
+    }
+
+    /// Polls `iface` over the socket until `f` breaks, calling `f` after every poll to
+    /// let the application use its sockets.
+    ///
+    /// Between polls the task sleeps until frames are received, the TX ring has room
+    /// again once exhausted, or `Interface::poll_delay` elapses.
+    pub async fn run<F>(
+        &mut self,
+        iface: &mut Interface,
+        sockets: &mut SocketSet<'_>,
+        mut f: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&mut Interface, &mut SocketSet<'_>) -> ControlFlow<()>,
+    {
+        loop {
+            let socket = self.get_mut();
+            socket.replenish_rx_budget();
+            iface.poll(Instant::now(), socket, sockets);
+            socket.flush_tx();
+            if f(iface, sockets).is_break() {
+                return Ok(());
+            }
+
+            let tx_full = !self.io.get_ref().tx_room();
+            let delay = iface.poll_delay(Instant::now(), sockets);
+            self.prepare_wait();
+            if self.io.get_ref().ready().rx {
+                continue;
+            }
+
+            let ready = async {
+                if tx_full {
+                    future::or(self.io.readable(), self.io.writable()).await
+                } else {
+                    self.io.readable().await
+                }
+            };
+            let timeout = async {
+                match delay {
+                    Some(delay) => {
+                        Timer::after(delay.into()).await;
+                    }
+                    None => future::pending::<()>().await,
+                }
+                Ok(())
+            };
+            future::or(ready, timeout).await?;
+        }
+    }
+
+    /// Get a reference to the underlying socket.
+    pub fn get_ref(&self) -> &XdpSocket<'a> {
+        self.io.get_ref()
+    }
+
+    /// Get a mutable reference to the underlying socket.
+    pub fn get_mut(&mut self) -> &mut XdpSocket<'a> {
+        // SAFETY: the socket is only polled through, its fd is never replaced nor closed
+        // while registered.
+        unsafe { self.io.get_mut() }
+    }
+
+    /// Return the underlying socket, deregistering it from the reactor.
+    pub fn into_inner(self) -> Result<XdpSocket<'a>, XdpError> {
+        Ok(self.io.into_inner()?)
+    }
+}
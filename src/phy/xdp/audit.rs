@@ -0,0 +1,141 @@
+//! Ownership tracking of the UMEM pages behind the `audit` feature.
+//!
+//! Every page is owned by the free list, the control-plane reserve, userspace, or the
+//! kernel through the fill or the TX ring. The UMEM reports every hand-over as an
+//! [`Op`], and using or handing over a page its owner does not allow panics with the
+//! latest hand-overs of the page, e.g. writing a frame to a page still on the TX ring
+//! or freeing a page twice.
+
+/// A hand-over of a UMEM page.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Op {
+    /// Taken out of the free list or the reserve by userspace.
+    Alloc,
+    /// Returned to the free list, by userspace or on completion.
+    Free,
+    /// Returned to the reserve, by userspace or on completion.
+    Reserve,
+    /// Posted to the fill ring.
+    Fill,
+    /// Taken back by userspace before it reached the fill ring.
+    Unfill,
+    /// Read from the RX ring.
+    Receive,
+    /// Queued on the TX ring.
+    Transmit,
+}
+
+#[cfg(feature = "audit")]
+pub(crate) use tracking::Audit;
+
+#[cfg(feature = "audit")]
+mod tracking {
+    use std::collections::VecDeque;
+    use std::fmt::Write;
+
+    use super::Op;
+
+    /// Hand-overs kept per page for the panic message.
+    const HISTORY: usize = 32;
+
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    enum Owner {
+        Free,
+        Reserve,
+        User,
+        Fill,
+        Tx,
+    }
+
+    impl Op {
+        /// Owners the page may be handed over from.
+        fn from(self) -> &'static [Owner] {
+            match self {
+                Op::Alloc => &[Owner::Free, Owner::Reserve],
+                Op::Free | Op::Reserve => &[Owner::User, Owner::Tx],
+                Op::Fill | Op::Transmit => &[Owner::User],
+                Op::Receive | Op::Unfill => &[Owner::Fill],
+            }
+        }
+
+        fn to(self) -> Owner {
+            match self {
+                Op::Free => Owner::Free,
+                Op::Reserve => Owner::Reserve,
+                Op::Alloc | Op::Receive | Op::Unfill => Owner::User,
+                Op::Fill => Owner::Fill,
+                Op::Transmit => Owner::Tx,
+            }
+        }
+    }
+
+    struct Page {
+        owner: Owner,
+        // Sequence number and kind of the latest hand-overs, the newest last.
+        history: VecDeque<(u64, Op)>,
+    }
+
+    /// Owner of every page of a UMEM.
+    pub(crate) struct Audit {
+        pages: Vec<Page>,
+        // Hand-overs recorded so far, numbering them across pages.
+        seq: u64,
+    }
+
+    impl Audit {
+        /// Tracks `entries` pages, all of them in the free list.
+        pub(crate) fn new(entries: usize) -> Self {
+            Self {
+                pages: (0..entries)
+                    .map(|_| Page {
+                        owner: Owner::Free,
+                        history: VecDeque::new(),
+                    })
+                    .collect(),
+                seq: 0,
+            }
+        }
+
+        /// Records `op` on `page_id`, panicking if its owner cannot hand it over.
+        pub(crate) fn apply(&mut self, page_id: usize, op: Op) {
+            let page = &mut self.pages[page_id];
+            if !op.from().contains(&page.owner) {
+                panic!(
+                    "UMEM page {} handed over by {:?} while owned by {:?}\n{}",
+                    page_id,
+                    op,
+                    page.owner,
+                    history(page)
+                );
+            }
+            page.owner = op.to();
+            if page.history.len() == HISTORY {
+                page.history.pop_front();
+            }
+            page.history.push_back((self.seq, op));
+            self.seq += 1;
+        }
+
+        /// Panics unless userspace owns `page_id`, before `access` touches its memory.
+        pub(crate) fn check(&self, page_id: usize, access: &str) {
+            let page = &self.pages[page_id];
+            if page.owner != Owner::User {
+                panic!(
+                    "UMEM page {} {} while owned by {:?}\n{}",
+                    page_id,
+                    access,
+                    page.owner,
+                    history(page)
+                );
+            }
+        }
+    }
+
+    fn history(page: &Page) -> String {
+        let mut history = String::from("Latest hand-overs, oldest first:");
+        for (seq, op) in &page.history {
+            let _ = write!(history, "\n  #{} {:?} -> {:?}", seq, op, op.to());
+        }
+        history
+    }
+}
@@ -0,0 +1,308 @@
+//! Loading and attaching the XDP program that redirects frames to the sockets.
+//!
+//! [`Program::load`] opens a compiled object, loads it, opens or pins its maps and
+//! attaches its XDP program to an interface, replacing
+//! `ip link set dev ... xdp obj ...` and the hand-written map updates. Sockets are then
+//! inserted into its XSKMAP with [`Program::register`], or directly opened with
+//! [`crate::phy::xdp::XdpSocket::with_program`].
+//!
+//! A new build of the program is swapped in without unbinding the sockets with
+//! [`Program::reload`].
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::ptr::{self, NonNull};
+
+use libbpf_sys as libbpf;
+
+use crate::phy::sys::{bpf, xdp::if_index};
+use crate::phy::xdp::{AttachMode, Attachment, XdpError, attach_program};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Path of the compiled object file.
+    pub path: String,
+    /// Name of the program to attach, the first XDP program of the object when `None`.
+    pub program: Option<String>,
+    /// Name of the XSKMAP sockets are inserted into.
+    pub map: String,
+    /// Directory maps declared with `pinning` are pinned in, or reused from when already
+    /// pinned, `/sys/fs/bpf` when `None`.
+    pub pin_root: Option<String>,
+    pub mode: AttachMode,
+    /// Replaces a program already attached to the interface instead of failing with
+    /// `AlreadyExists`.
+    pub replace: bool,
+    /// Loads the program bound to the interface, so that the XDP hints kfuncs it calls,
+    /// e.g. `bpf_xdp_metadata_rx_timestamp`, read the hardware metadata of the driver.
+    /// Bound programs only attach to that interface in native mode.
+    pub dev_bound: bool,
+}
+
+impl Config {
+    /// Attaches the `xdp_redirect_prog` program of the object at `path`, redirecting to
+    /// the `socket_map` XSKMAP as the bundled `examples/xdp.c` does.
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            program: None,
+            map: "socket_map".to_owned(),
+            pin_root: None,
+            mode: AttachMode::Auto,
+            replace: false,
+            dev_bound: false,
+        }
+    }
+}
+
+/// A loaded XDP object whose program is attached to an interface, detached on drop.
+pub struct Program {
+    object: NonNull<libbpf::bpf_object>,
+    prog_fd: RawFd,
+    map_fd: RawFd,
+    // Set once attached.
+    attachment: Option<Attachment>,
+}
+
+impl Program {
+    /// Loads the object described by `config` and attaches its program to the interface
+    /// called `ifname`.
+    pub fn load(ifname: &str, config: &Config) -> Result<Self, XdpError> {
+        Ok(Self::load_on(if_index(ifname)?, config)?)
+    }
+
+    pub(crate) fn load_on(ifindex: u32, config: &Config) -> io::Result<Self> {
+        let mut program = Self::open(ifindex, config, None)?;
+        program.attachment = Some(attach_program(
+            ifindex,
+            program.prog_fd,
+            config.mode,
+            config.replace,
+        )?);
+        Ok(program)
+    }
+
+    /// Loads the object described by `config` and atomically swaps its program for the
+    /// attached one, while sockets stay bound and registered: the maps of the new object
+    /// are the ones of the same name in the current object, its XSKMAP among them, so
+    /// their entries carry over. The current object is closed once replaced.
+    ///
+    /// The program stays attached in the same mode, `config.mode` and `config.replace`
+    /// are ignored. On error the current program keeps running.
+    pub fn reload(&mut self, config: &Config) -> Result<(), XdpError> {
+        let attachment = self
+            .attachment
+            .as_mut()
+            .expect("Programs are attached on load");
+        let mut program = Self::open(attachment.ifindex(), config, Some(self.object))?;
+        attachment.replace(self.prog_fd, program.prog_fd)?;
+        program.attachment = self.attachment.take();
+        // The replaced object is closed with `program`.
+        std::mem::swap(self, &mut program);
+        Ok(())
+    }
+
+    /// Opens and loads an object without attaching it, reusing the maps of `reuse`.
+    fn open(
+        ifindex: u32,
+        config: &Config,
+        reuse: Option<NonNull<libbpf::bpf_object>>,
+    ) -> io::Result<Self> {
+        let path = CString::new(config.path.as_str())?;
+        let pin_root = config.pin_root.as_deref().map(CString::new).transpose()?;
+
+        let opts = libbpf::bpf_object_open_opts {
+            sz: std::mem::size_of::<libbpf::bpf_object_open_opts>() as _,
+            pin_root_path: pin_root.as_ref().map_or(ptr::null(), |root| root.as_ptr()),
+            ..Default::default()
+        };
+        // SAFETY: the path and the options outlive the call, libbpf copies what it keeps.
+        let object = unsafe { libbpf::bpf_object__open_file(path.as_ptr(), &opts) };
+        let object = NonNull::new(object).ok_or_else(io::Error::last_os_error)?;
+
+        // Closed on error from here on.
+        let mut program = Self {
+            object,
+            prog_fd: -1,
+            map_fd: -1,
+            attachment: None,
+        };
+
+        if let Some(reuse) = reuse {
+            program.reuse_maps(reuse)?;
+        }
+        let prog = program.find_program(config.program.as_deref())?;
+        if config.dev_bound {
+            // SAFETY: the program belongs to the open object, which is not loaded yet.
+            let ret = unsafe {
+                libbpf::bpf_program__set_ifindex(prog, ifindex);
+                libbpf::bpf_program__set_flags(
+                    prog,
+                    libbpf::bpf_program__flags(prog) | libbpf::BPF_F_XDP_DEV_BOUND_ONLY,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::from_raw_os_error(-ret));
+            }
+        }
+
+        // SAFETY: the object is open and owned by `program`.
+        let ret = unsafe { libbpf::bpf_object__load(object.as_ptr()) };
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+
+        program.prog_fd = unsafe { libbpf::bpf_program__fd(prog) };
+        program.map_fd = program.find_xskmap(&config.map)?;
+        Ok(program)
+    }
+
+    /// Makes the maps of the open object that `other` also has use the maps of `other`.
+    fn reuse_maps(&self, other: NonNull<libbpf::bpf_object>) -> io::Result<()> {
+        // SAFETY: both objects are alive, the object is open and not loaded yet, and
+        // iteration starts from a null map. libbpf duplicates the reused fds.
+        unsafe {
+            let mut map = libbpf::bpf_object__next_map(self.object.as_ptr(), ptr::null());
+            while !map.is_null() {
+                let name = libbpf::bpf_map__name(map);
+                let old = libbpf::bpf_object__find_map_by_name(other.as_ptr(), name);
+                if !old.is_null() && libbpf::bpf_map__fd(old) >= 0 {
+                    let ret = libbpf::bpf_map__reuse_fd(map, libbpf::bpf_map__fd(old));
+                    if ret < 0 {
+                        return Err(io::Error::from_raw_os_error(-ret));
+                    }
+                }
+                map = libbpf::bpf_object__next_map(self.object.as_ptr(), map);
+            }
+        }
+        Ok(())
+    }
+
+    fn find_program(&self, name: Option<&str>) -> io::Result<*mut libbpf::bpf_program> {
+        let object = self.object.as_ptr();
+        let prog = match name {
+            Some(name) => {
+                let name = CString::new(name)?;
+                // SAFETY: the object is open and the name outlives the call.
+                unsafe { libbpf::bpf_object__find_program_by_name(object, name.as_ptr()) }
+            }
+            None => {
+                // SAFETY: the object is open, iteration starts from a null program.
+                let mut prog = unsafe { libbpf::bpf_object__next_program(object, ptr::null_mut()) };
+                while !prog.is_null()
+                    && unsafe { libbpf::bpf_program__type(prog) } != libbpf::BPF_PROG_TYPE_XDP
+                {
+                    prog = unsafe { libbpf::bpf_object__next_program(object, prog) };
+                }
+                prog
+            }
+        };
+
+        if prog.is_null() {
+            let message = match name {
+                Some(name) => format!("No program {} in the object", name),
+                None => "No XDP program in the object".to_owned(),
+            };
+            return Err(io::Error::new(io::ErrorKind::NotFound, message));
+        }
+        if unsafe { libbpf::bpf_program__type(prog) } != libbpf::BPF_PROG_TYPE_XDP {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Program {} is not an XDP program", self::name(prog)),
+            ));
+        }
+        Ok(prog)
+    }
+
+    fn find_xskmap(&self, name: &str) -> io::Result<RawFd> {
+        let map = self.find_map(name)?;
+        if unsafe { libbpf::bpf_map__type(map) } != libbpf::BPF_MAP_TYPE_XSKMAP {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Map {} is not an XSKMAP", name),
+            ));
+        }
+        Ok(unsafe { libbpf::bpf_map__fd(map) })
+    }
+
+    fn find_map(&self, name: &str) -> io::Result<*mut libbpf::bpf_map> {
+        let c_name = CString::new(name)?;
+        // SAFETY: the object is loaded and the name outlives the call.
+        let map =
+            unsafe { libbpf::bpf_object__find_map_by_name(self.object.as_ptr(), c_name.as_ptr()) };
+        if map.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No map {} in the object", name),
+            ));
+        }
+        Ok(map)
+    }
+
+    /// Mode the program was actually attached in, never `Auto`.
+    pub fn mode(&self) -> AttachMode {
+        self.attachment
+            .as_ref()
+            .expect("Programs are attached on load")
+            .mode()
+    }
+
+    /// Redirects the traffic of `queue_id` to `socket`.
+    pub fn register(&self, socket: &impl AsRawFd, queue_id: u32) -> Result<(), XdpError> {
+        let fd = socket.as_raw_fd() as u32;
+        Ok(bpf::map_update_elem(self.map_fd, &queue_id, &fd)?)
+    }
+
+    /// Stops redirecting the traffic of `queue_id`. Closed sockets are removed from the
+    /// map by the kernel.
+    pub fn unregister(&self, queue_id: u32) -> Result<(), XdpError> {
+        Ok(bpf::map_delete_elem(self.map_fd, &queue_id)?)
+    }
+
+    /// The attached program.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        // SAFETY: the fd is owned by the object, which lives as long as `self`.
+        unsafe { BorrowedFd::borrow_raw(self.prog_fd) }
+    }
+
+    /// The XSKMAP, e.g. for [`crate::phy::xdp::shared::SharedProgram::join_map`] once
+    /// cloned.
+    pub fn xskmap(&self) -> BorrowedFd<'_> {
+        // SAFETY: the fd is owned by the object, which lives as long as `self`.
+        unsafe { BorrowedFd::borrow_raw(self.map_fd) }
+    }
+
+    /// Another map of the object, e.g. the `passthrough_map` of the bundled program for
+    /// [`crate::phy::xdp::passthrough::Passthrough::from_map`] once cloned.
+    pub fn map(&self, name: &str) -> Result<BorrowedFd<'_>, XdpError> {
+        let map = self.find_map(name)?;
+        let fd = unsafe { libbpf::bpf_map__fd(map) };
+        if fd < 0 {
+            return Err(io::Error::from_raw_os_error(-fd).into());
+        }
+        // SAFETY: the fd is owned by the object, which lives as long as `self`.
+        Ok(unsafe { BorrowedFd::borrow_raw(fd) })
+    }
+}
+
+// SAFETY: the object is owned by the program alone and libbpf keeps no thread-local
+// state for it, so it may be used and closed from another thread than its loader.
+unsafe impl Send for Program {}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        // Detached before the object closes the program.
+        self.attachment.take();
+        // SAFETY: the object is owned by `self` and closed once, closing its fds. Pinned
+        // maps stay pinned.
+        unsafe { libbpf::bpf_object__close(self.object.as_ptr()) };
+    }
+}
+
+fn name(prog: *const libbpf::bpf_program) -> String {
+    // SAFETY: libbpf returns a NUL-terminated name owned by the program.
+    unsafe { CStr::from_ptr(libbpf::bpf_program__name(prog)) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,301 @@
+use crate::phy::sys;
+use crate::phy::xdp::XdpError;
+use crate::phy::xdp::umem;
+use crate::phy::xdp::{
+    AttachMode, BindMode, BusyPoll, CapabilitiesConfig, ChunkSize, Config, MAX_UMEM_ENTRIES,
+    MIN_FRAME_LEN, PriorityConfig, RefillConfig, UmemBacking, UmemFreeList, XdpSocket, rings,
+};
+
+/// Builds a [`Config`] from defaults and the settings that differ, checking it against
+/// the interface before any socket is opened.
+///
+/// The UMEM gets one page per fill ring, TX ring and completion ring entry unless sized
+/// with [`XdpSocketBuilder::umem_entries`], and the smallest chunk holding a
+/// VLAN-tagged frame of the interface MTU after every headroom unless sized with
+/// [`XdpSocketBuilder::chunk_size`]:
+///
+/// ```ignore
+/// let socket = XdpSocketBuilder::new()
+///     .queue_id(2)
+///     .ring_size(1024)
+///     .busy_poll(BusyPoll { timeout_us: 50, budget: 64, prefer: true })
+///     .build("eth0")?;
+/// ```
+#[derive(Copy, Clone)]
+pub struct XdpSocketBuilder {
+    config: Config,
+    // Derived from the rings when `None`.
+    entries: Option<usize>,
+    // Derived from the MTU when `None`.
+    chunk: Option<ChunkSize>,
+}
+
+impl Default for XdpSocketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XdpSocketBuilder {
+    /// Queue 0, RX, TX and completion rings of 2048 entries and a fill ring twice as
+    /// large, in need-wakeup mode with the built-in redirect program.
+    pub fn new() -> Self {
+        let ring = 2048;
+        Self {
+            config: Config {
+                queue_id: 0,
+                rx_budget: None,
+                rx_batch: 32,
+                tx_batch: 16,
+                fill_batch: 16,
+                tx_min_len: MIN_FRAME_LEN,
+                tx_checksum: false,
+                capabilities: CapabilitiesConfig::default(),
+                tx_priority: None,
+                umem: umem::Config {
+                    entries: 0,
+                    alignment: ChunkSize::TwoK,
+                    headroom: 0,
+                    unaligned: false,
+                    free_list: UmemFreeList::Intrusive,
+                    backing: UmemBacking::Heap,
+                    tx_metadata: false,
+                },
+                tx: rings::Config { size: ring },
+                rx: rings::Config { size: ring },
+                cr: rings::Config { size: ring },
+                fr: rings::Config { size: 2 * ring },
+                refill: None,
+                need_wakeup: true,
+                multi_buffer: false,
+                bind_mode: BindMode::Auto,
+                busy_poll: None,
+                default_program: Some(AttachMode::Auto),
+            },
+            entries: None,
+            chunk: None,
+        }
+    }
+
+    /// Starts from `config`, e.g. a preset, keeping its UMEM entries and chunk size.
+    pub fn from_config(config: Config) -> Self {
+        Self {
+            config,
+            entries: Some(config.umem.entries),
+            chunk: Some(config.umem.alignment),
+        }
+    }
+
+    pub fn queue_id(mut self, queue_id: u32) -> Self {
+        self.config.queue_id = queue_id;
+        self
+    }
+
+    /// Sizes the RX, TX and completion rings to `size` entries and the fill ring to twice
+    /// as many.
+    pub fn ring_size(mut self, size: usize) -> Self {
+        self.config.tx.size = size;
+        self.config.rx.size = size;
+        self.config.cr.size = size;
+        self.config.fr.size = 2 * size;
+        self
+    }
+
+    pub fn rx_ring_size(mut self, size: usize) -> Self {
+        self.config.rx.size = size;
+        self
+    }
+
+    pub fn tx_ring_size(mut self, size: usize) -> Self {
+        self.config.tx.size = size;
+        self
+    }
+
+    pub fn fill_ring_size(mut self, size: usize) -> Self {
+        self.config.fr.size = size;
+        self
+    }
+
+    pub fn completion_ring_size(mut self, size: usize) -> Self {
+        self.config.cr.size = size;
+        self
+    }
+
+    pub fn umem_entries(mut self, entries: usize) -> Self {
+        self.entries = Some(entries);
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk: ChunkSize) -> Self {
+        self.chunk = Some(chunk);
+        self
+    }
+
+    /// See `UmemConfig::headroom`.
+    pub fn headroom(mut self, headroom: usize) -> Self {
+        self.config.umem.headroom = headroom;
+        self
+    }
+
+    pub fn unaligned(mut self, unaligned: bool) -> Self {
+        self.config.umem.unaligned = unaligned;
+        self
+    }
+
+    pub fn free_list(mut self, free_list: UmemFreeList) -> Self {
+        self.config.umem.free_list = free_list;
+        self
+    }
+
+    pub fn backing(mut self, backing: UmemBacking) -> Self {
+        self.config.umem.backing = backing;
+        self
+    }
+
+    pub fn rx_budget(mut self, budget: Option<usize>) -> Self {
+        self.config.rx_budget = budget;
+        self
+    }
+
+    pub fn rx_batch(mut self, batch: usize) -> Self {
+        self.config.rx_batch = batch;
+        self
+    }
+
+    pub fn tx_batch(mut self, batch: usize) -> Self {
+        self.config.tx_batch = batch;
+        self
+    }
+
+    pub fn fill_batch(mut self, batch: usize) -> Self {
+        self.config.fill_batch = batch;
+        self
+    }
+
+    pub fn tx_min_len(mut self, len: usize) -> Self {
+        self.config.tx_min_len = len;
+        self
+    }
+
+    /// See [`Config::tx_checksum`]. Reserves the TX metadata it needs in the UMEM.
+    pub fn tx_checksum(mut self, enabled: bool) -> Self {
+        self.config.tx_checksum = enabled;
+        self.config.umem.tx_metadata |= enabled;
+        self
+    }
+
+    pub fn tx_metadata(mut self, enabled: bool) -> Self {
+        self.config.umem.tx_metadata = enabled;
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: CapabilitiesConfig) -> Self {
+        self.config.capabilities = capabilities;
+        self
+    }
+
+    pub fn tx_priority(mut self, priority: Option<PriorityConfig>) -> Self {
+        self.config.tx_priority = priority;
+        self
+    }
+
+    pub fn refill(mut self, refill: Option<RefillConfig>) -> Self {
+        self.config.refill = refill;
+        self
+    }
+
+    pub fn need_wakeup(mut self, enabled: bool) -> Self {
+        self.config.need_wakeup = enabled;
+        self
+    }
+
+    pub fn multi_buffer(mut self, enabled: bool) -> Self {
+        self.config.multi_buffer = enabled;
+        self
+    }
+
+    pub fn bind_mode(mut self, mode: BindMode) -> Self {
+        self.config.bind_mode = mode;
+        self
+    }
+
+    pub fn busy_poll(mut self, busy_poll: BusyPoll) -> Self {
+        self.config.busy_poll = Some(busy_poll);
+        self
+    }
+
+    pub fn default_program(mut self, mode: Option<AttachMode>) -> Self {
+        self.config.default_program = mode;
+        self
+    }
+
+    /// The config for the interface called `name`, with the UMEM entries and chunk size
+    /// resolved.
+    ///
+    /// Fails when a ring size is not a power of two, the UMEM has more entries than the
+    /// free list can address or too few to leave pages for TX once the fill ring is
+    /// posted, or a frame of the interface MTU does not fit in a chunk after the headroom
+    /// without `multi_buffer`.
+    pub fn config(&self, name: &str) -> Result<Config, XdpError> {
+        let mut config = self.config;
+        let rings = [
+            ("RX", config.rx.size),
+            ("TX", config.tx.size),
+            ("fill", config.fr.size),
+            ("completion", config.cr.size),
+        ];
+        for (ring, size) in rings {
+            if !size.is_power_of_two() {
+                return Err(XdpError::InvalidConfig(format!(
+                    "The {} ring size {} is not a power of two",
+                    ring, size
+                )));
+            }
+        }
+
+        let entries = self
+            .entries
+            .unwrap_or(config.fr.size + config.tx.size + config.cr.size);
+        if entries > MAX_UMEM_ENTRIES {
+            return Err(XdpError::InvalidConfig(format!(
+                "A UMEM of {} entries does not fit in the free list, at most {} do",
+                entries, MAX_UMEM_ENTRIES
+            )));
+        }
+        if entries <= config.fr.size {
+            return Err(XdpError::UmemTooSmall {
+                entries,
+                needed: config.fr.size + 1,
+            });
+        }
+        config.umem.entries = entries;
+
+        let mtu = sys::xdp::mtu(name)?;
+        let needed = config.umem.chunk_needed(mtu);
+        let chunk = match self.chunk {
+            Some(chunk) => chunk,
+            None => [ChunkSize::TwoK, ChunkSize::FourK]
+                .into_iter()
+                .find(|&chunk| usize::from(chunk) >= needed)
+                .unwrap_or(ChunkSize::FourK),
+        };
+        if usize::from(chunk) < needed && !config.multi_buffer {
+            return Err(XdpError::InvalidConfig(format!(
+                "A frame of the MTU of {} ({}) needs {} byte chunks after the headroom, \
+                 chunks are {} bytes",
+                name,
+                mtu,
+                needed,
+                usize::from(chunk)
+            )));
+        }
+        config.umem.alignment = chunk;
+        Ok(config)
+    }
+
+    /// Opens a socket on the interface called `name` with the [`XdpSocketBuilder::config`]
+    /// for it.
+    pub fn build<'a>(&self, name: &'a str) -> Result<XdpSocket<'a>, XdpError> {
+        XdpSocket::new(name, self.config(name)?)
+    }
+}
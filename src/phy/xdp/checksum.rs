@@ -0,0 +1,81 @@
+//! Preparing TX frames for L4 checksum offload through AF_XDP TX metadata.
+//!
+//! The NIC sums the L4 header and payload on top of whatever the checksum field holds,
+//! so the field must carry the pseudo-header sum beforehand, as for `CHECKSUM_PARTIAL`
+//! skbs.
+
+use crate::phy::link;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+/// Offset of the checksum in the TCP and UDP headers.
+const TCP_CHECKSUM: usize = 16;
+const UDP_CHECKSUM: usize = 6;
+
+/// Where the NIC sums the frame from and stores the checksum.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Request {
+    /// Start of the L4 header in the frame.
+    pub start: u16,
+    /// Checksum field in the L4 header.
+    pub offset: u16,
+}
+
+/// Fills the pseudo-header sum of the TCP or UDP segment carried by the Ethernet
+/// `frame`, returning where the NIC must checksum it. Other frames, IPv4 fragments and
+/// IPv6 packets with extension headers are left for software checksums.
+pub(crate) fn prepare(frame: &mut [u8]) -> Option<Request> {
+    let (ethertype, l3) = link::ethernet(frame)?;
+    let packet = frame.get(l3..)?;
+
+    let (l4, protocol, len, addrs) = match ethertype {
+        0x0800 => {
+            let header_len = usize::from(packet.first()? & 0x0f) * 4;
+            let total_len = usize::from(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]));
+            // More fragments or a fragment offset.
+            let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x3fff;
+            if header_len < 20 || fragment != 0 || total_len < header_len {
+                return None;
+            }
+            let protocol = *packet.get(9)?;
+            let len = total_len - header_len;
+            (header_len, protocol, len, sum(packet.get(12..20)?))
+        }
+        0x86dd => {
+            let len = usize::from(u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]));
+            (40, *packet.get(6)?, len, sum(packet.get(8..40)?))
+        }
+        _ => return None,
+    };
+    let offset = match protocol {
+        IPPROTO_TCP => TCP_CHECKSUM,
+        IPPROTO_UDP => UDP_CHECKSUM,
+        _ => return None,
+    };
+
+    let start = l3 + l4;
+    let field = start + offset;
+    if frame.len() < field + 2 || packet.len() < l4 + len {
+        return None;
+    }
+    let pseudo = fold(addrs + u32::from(protocol) + len as u32);
+    frame[field..field + 2].copy_from_slice(&pseudo.to_be_bytes());
+    Some(Request {
+        start: start as u16,
+        offset: offset as u16,
+    })
+}
+
+/// Sum of `data` as 16-bit big-endian words, `data` having an even length.
+fn sum(data: &[u8]) -> u32 {
+    data.chunks_exact(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum()
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
@@ -0,0 +1,145 @@
+use std::{error, fmt, io};
+
+/// Errors of the AF_XDP device.
+///
+/// Converts to and from `io::Error`, so `?` works in functions returning either. An
+/// `XdpError` round-tripped through an `io::Error` comes back as the same variant.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum XdpError {
+    /// No descriptor is free on the TX or fill ring, the kernel has not consumed it yet.
+    RingFull,
+    /// Every UMEM frame is in use, on the rings or held by userspace.
+    NoFreeFrames,
+    /// The kernel refused to bind the socket.
+    BindFailed {
+        errno: i32,
+        /// Likely cause, with the driver of the device when known.
+        hint: String,
+    },
+    /// The UMEM has `entries` frames where the configuration needs at least `needed`.
+    UmemTooSmall { entries: usize, needed: usize },
+    /// The running kernel lacks `feature`.
+    UnsupportedKernel { feature: &'static str },
+    /// The configuration does not fit the interface or is inconsistent.
+    InvalidConfig(String),
+    /// Any other OS or I/O error.
+    Io(io::Error),
+}
+
+impl XdpError {
+    /// OS error code behind the error, if any.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            XdpError::BindFailed { errno, .. } => Some(*errno),
+            XdpError::Io(err) => err.raw_os_error(),
+            _ => None,
+        }
+    }
+
+    /// Whether retrying once the kernel made progress, e.g. after a wait, may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            XdpError::RingFull | XdpError::NoFreeFrames => true,
+            XdpError::Io(err) => matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+            ),
+            _ => false,
+        }
+    }
+
+    /// Kind of the `io::Error` this converts to.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            XdpError::RingFull | XdpError::NoFreeFrames => io::ErrorKind::WouldBlock,
+            XdpError::BindFailed { errno, .. } => io::Error::from_raw_os_error(*errno).kind(),
+            XdpError::UmemTooSmall { .. } | XdpError::InvalidConfig(_) => {
+                io::ErrorKind::InvalidInput
+            }
+            XdpError::UnsupportedKernel { .. } => io::ErrorKind::Unsupported,
+            XdpError::Io(err) => err.kind(),
+        }
+    }
+
+    /// Names what bind failing with `errno` usually means, `zero_copy` when the bind asked
+    /// for it and `driver` describing the device when known.
+    pub(crate) fn bind(errno: i32, zero_copy: bool, driver: Option<String>) -> Self {
+        let cause = match errno {
+            libc::EOPNOTSUPP if zero_copy => {
+                "the driver has no zero-copy support, bind with BindMode::Copy"
+            }
+            libc::EOPNOTSUPP => "the driver does not support the requested bind flags",
+            libc::EBUSY => "another AF_XDP socket is bound to this queue",
+            libc::EINVAL => {
+                "the queue does not exist, or the kernel does not know a bind flag such as \
+                 multi-buffer (Linux 6.6)"
+            }
+            libc::ENETDOWN => "the interface is down",
+            libc::ENODEV | libc::ENXIO => "the interface is gone",
+            _ => "",
+        };
+        let hint = match (cause, driver) {
+            ("", Some(driver)) => driver,
+            (cause, Some(driver)) => format!("{} ({})", cause, driver),
+            (cause, None) => cause.to_owned(),
+        };
+        XdpError::BindFailed { errno, hint }
+    }
+}
+
+impl fmt::Display for XdpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XdpError::RingFull => write!(f, "The ring is full"),
+            XdpError::NoFreeFrames => write!(f, "No free UMEM frame available"),
+            XdpError::BindFailed { errno, hint } if hint.is_empty() => {
+                write!(f, "Bind failed: {}", io::Error::from_raw_os_error(*errno))
+            }
+            XdpError::BindFailed { errno, hint } => write!(
+                f,
+                "Bind failed: {}, {}",
+                io::Error::from_raw_os_error(*errno),
+                hint
+            ),
+            XdpError::UmemTooSmall { entries, needed } => write!(
+                f,
+                "A UMEM of {} entries is too small, at least {} are needed",
+                entries, needed
+            ),
+            XdpError::UnsupportedKernel { feature } => {
+                write!(f, "The running kernel does not support {}", feature)
+            }
+            XdpError::InvalidConfig(reason) => f.write_str(reason),
+            XdpError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for XdpError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            XdpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for XdpError {
+    fn from(err: io::Error) -> Self {
+        if !err.get_ref().is_some_and(|inner| inner.is::<XdpError>()) {
+            return XdpError::Io(err);
+        }
+        let inner = err.into_inner().expect("Checked above");
+        *inner.downcast().expect("Checked above")
+    }
+}
+
+impl From<XdpError> for io::Error {
+    fn from(err: XdpError) -> Self {
+        match err {
+            XdpError::Io(err) => err,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Per-packet metadata written by the XDP program in front of the frame.
+//!
+//! An XDP program reserves the area with `bpf_xdp_adjust_meta(ctx, -sizeof(struct xdp_meta))`
+//! and fills it before redirecting the frame to the socket; the kernel keeps it right
+//! before the packet data in the UMEM chunk. The layout must match the C definition
+//! below, which is what `examples/xdp.c` writes:
+//!
+//! ```c
+//! struct xdp_meta {
+//!     __u64 timestamp;     /* bpf_ktime_get_ns() when the program ran */
+//!     __u64 hw_timestamp;  /* bpf_xdp_metadata_rx_timestamp(), 0 without one */
+//!     __u32 rx_hash;       /* bpf_xdp_metadata_rx_hash() */
+//!     __u32 rx_hash_type;  /* enum xdp_rss_hash_type */
+//!     __u16 vlan_proto;    /* bpf_xdp_metadata_rx_vlan_tag(), host order */
+//!     __u16 vlan_tci;
+//!     __u32 hints;         /* HINT_* bits of the hints the driver reported */
+//!     __u32 mark;          /* free for custom programs */
+//!     __u32 verdict;       /* classification result */
+//!     __u32 rx_queue;      /* ctx->rx_queue_index */
+//!     __u32 magic;         /* XDP_META_MAGIC, last so it sits right before the data */
+//! };
+//! ```
+//!
+//! The hardware timestamp, RX hash and VLAN tag come from the XDP hints of the driver,
+//! which the program only reads when loaded bound to the interface, see
+//! `xdp::bpf::Config::dev_bound`. Timestamps also need the NIC to timestamp received
+//! frames, e.g. after `hwstamp_ctl -r 1`, and VLAN tags to be stripped by the NIC.
+//!
+//! Kernels limiting the metadata area to 32 bytes refuse the layout, frames then carry
+//! no metadata.
+
+/// Identifies the layout and its version. Bump it on any layout change.
+pub const MAGIC: u32 = 0x584d_0003;
+
+/// `Meta::rx_hash` and `Meta::rx_hash_type` were reported by the driver.
+pub const HINT_RX_HASH: u32 = 1 << 0;
+/// `Meta::vlan_proto` and `Meta::vlan_tci` were reported by the driver.
+pub const HINT_VLAN_TAG: u32 = 1 << 1;
+
+/// Hash the NIC computed over the headers of a frame, e.g. for RSS.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RxHash {
+    pub hash: u32,
+    /// Headers the hash covers, an `enum xdp_rss_hash_type` such as
+    /// `XDP_RSS_TYPE_L4_IPV4_TCP`.
+    pub hash_type: u32,
+}
+
+/// VLAN tag the NIC stripped from a frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VlanTag {
+    /// TPID, e.g. `0x8100` for 802.1Q.
+    pub proto: u16,
+    /// Priority, DEI and VLAN id.
+    pub tci: u16,
+}
+
+/// Metadata attached by the XDP program to a received frame.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Meta {
+    /// `CLOCK_MONOTONIC` time in nanoseconds at which the program ran.
+    pub timestamp: u64,
+    /// Time in nanoseconds at which the NIC received the frame, in the clock of the NIC,
+    /// usually its PTP hardware clock. 0 when the driver reported none.
+    pub hw_timestamp: u64,
+    pub rx_hash: u32,
+    pub rx_hash_type: u32,
+    pub vlan_proto: u16,
+    pub vlan_tci: u16,
+    /// `HINT_*` bits telling which of the fields above the driver reported.
+    pub hints: u32,
+    pub mark: u32,
+    pub verdict: u32,
+    /// Queue the frame was received on before any redirection.
+    pub rx_queue: u32,
+    pub magic: u32,
+}
+
+impl Meta {
+    pub const LEN: usize = std::mem::size_of::<Self>();
+
+    /// Parses the `LEN` bytes preceding the frame data, returning `None` if the program
+    /// did not write them.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; Self::LEN] = bytes.try_into().ok()?;
+        // SAFETY: Meta is plain old data and read unaligned.
+        let meta: Self = unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Self) };
+        (meta.magic == MAGIC).then_some(meta)
+    }
+
+    /// RX hash reported by the driver.
+    pub fn rx_hash(&self) -> Option<RxHash> {
+        (self.hints & HINT_RX_HASH != 0).then_some(RxHash {
+            hash: self.rx_hash,
+            hash_type: self.rx_hash_type,
+        })
+    }
+
+    /// Stripped VLAN tag reported by the driver.
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        (self.hints & HINT_VLAN_TAG != 0).then_some(VlanTag {
+            proto: self.vlan_proto,
+            tci: self.vlan_tci,
+        })
+    }
+}
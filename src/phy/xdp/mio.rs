@@ -0,0 +1,61 @@
+//! Registering an [`XdpSocket`] with a mio `Poll`, for event loops built on mio itself.
+//!
+//! mio reports readiness edge-triggered: once woken, the socket is polled until
+//! [`XdpSocket::ready`] reports nothing, and [`XdpSocket::prepare_wait`] hands the
+//! recycled and queued frames to the kernel before sleeping again, the kernel having
+//! nothing to wake the socket up for otherwise:
+//!
+//! ```ignore
+//! poll.registry()
+//!     .register(&mut socket, XDP, Interest::READABLE | Interest::WRITABLE)?;
+//! loop {
+//!     iface.poll(Instant::now(), &mut socket, &mut sockets);
+//!     socket.flush_tx();
+//!     serve(&mut sockets);
+//!
+//!     socket.prepare_wait();
+//!     if socket.ready().rx {
+//!         continue;
+//!     }
+//!     let timeout = iface.poll_delay(Instant::now(), &sockets).map(Into::into);
+//!     poll.poll(&mut events, timeout)?;
+//! }
+//! ```
+//!
+//! Rebinding a socket, e.g. with another `BindMode` once zero-copy fails, opens a new
+//! one with a fd of its own: the old socket is deregistered, or dropped, and the new
+//! one registered, usually under the same token. `Registry::reregister` only changes
+//! the token or interest of a registered socket.
+
+use std::io;
+use std::os::fd::AsRawFd;
+
+use ::mio::event::Source;
+use ::mio::unix::SourceFd;
+use ::mio::{Interest, Registry, Token};
+
+use crate::phy::xdp::XdpSocket;
+
+impl Source for XdpSocket<'_> {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
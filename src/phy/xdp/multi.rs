@@ -0,0 +1,220 @@
+use std::io;
+
+use smoltcp::{
+    phy::{Device, DeviceCapabilities},
+    time::Instant,
+};
+
+use crate::phy::xdp::XdpError;
+use crate::phy::{
+    caps::{CapabilitiesAudit, DeviceCapabilitiesReport},
+    health::{HealthCheck, Report},
+    sys::{
+        ethtool,
+        xdp::{lower_devices, rx_queues},
+    },
+    xdp::{Config, RxToken, TxToken, XdpSocket, redirect::Redirect},
+};
+
+/// Order in which [`XdpMultiQueue`] polls its queues.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Schedule {
+    /// One frame per queue in turn, so a busy queue cannot starve the others.
+    #[default]
+    RoundRobin,
+    /// Frames from the same queue until it is empty, then the next one. Keeps the
+    /// frames of a flow together at the cost of fairness.
+    Drain,
+}
+
+/// A device reading several queues of one interface, each through its own XDP socket
+/// and UMEM.
+///
+/// The UMEM of a live socket cannot be grown, so buffer capacity is added by binding
+/// one more queue with [`XdpMultiQueue::scale_up`] and spreading flows over it.
+pub struct XdpMultiQueue<'a> {
+    name: &'a str,
+    config: Config,
+    queues: Vec<(u32, XdpSocket<'a>)>,
+    schedule: Schedule,
+    // Queue that delivered the last frame, also used for TX.
+    current: usize,
+    // Serves every queue, detached after the sockets are closed.
+    redirect: Option<Redirect>,
+}
+
+impl<'a> XdpMultiQueue<'a> {
+    /// Opens one socket per queue in `queue_ids` on the interface called `name`.
+    ///
+    /// `config.queue_id` is ignored, every socket uses its own queue. With
+    /// `config.default_program` a single built-in program serves all of them.
+    pub fn new(name: &'a str, config: Config, queue_ids: &[u32]) -> Result<Self, XdpError> {
+        let redirect = match config.default_program {
+            Some(mode) => match Redirect::attach(name, mode, false) {
+                Ok(redirect) => Some(redirect),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => None,
+                Err(err) => return Err(err),
+            },
+            None => None,
+        };
+        let mut device = Self {
+            name,
+            config: Config {
+                default_program: None,
+                ..config
+            },
+            queues: Vec::with_capacity(queue_ids.len()),
+            schedule: Schedule::default(),
+            current: 0,
+            redirect,
+        };
+        for &queue_id in queue_ids {
+            device.open(queue_id)?;
+        }
+        Ok(device)
+    }
+
+    /// Opens one socket for every RX queue of the interface called `name`, so that no
+    /// queue RSS hashes frames to is left unread.
+    pub fn all(name: &'a str, config: Config) -> Result<Self, XdpError> {
+        let queue_ids: Vec<u32> = (0..rx_queues(name)?).collect();
+        Self::new(name, config, &queue_ids)
+    }
+
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+    }
+
+    pub fn schedule(&self) -> Schedule {
+        self.schedule
+    }
+
+    fn open(&mut self, queue_id: u32) -> io::Result<&mut XdpSocket<'a>> {
+        if self.queues.iter().any(|(id, _)| *id == queue_id) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Queue {} is already bound", queue_id),
+            ));
+        }
+
+        let config = Config {
+            queue_id,
+            ..self.config
+        };
+        let socket = XdpSocket::new(self.name, config)?;
+        if let Some(redirect) = &self.redirect {
+            redirect.register(&socket, queue_id)?;
+        }
+        self.queues.push((queue_id, socket));
+        Ok(&mut self.queues.last_mut().expect("Just pushed").1)
+    }
+
+    /// Binds `queue_id` with a new socket and UMEM, then spreads the RSS indirection
+    /// table of the NIC over all bound queues.
+    ///
+    /// The socket is returned so it can be registered in the XSKMAP of the XDP program,
+    /// which the built-in one of `Config::default_program` already does.
+    /// If the driver rejects the new indirection table the socket stays bound and
+    /// receives whatever RSS already steers to its queue.
+    pub fn scale_up(&mut self, queue_id: u32) -> Result<&mut XdpSocket<'a>, XdpError> {
+        self.open(queue_id)?;
+        self.rebalance()?;
+        Ok(&mut self.queues.last_mut().expect("Just opened").1)
+    }
+
+    /// Spreads the RSS indirection table of the NIC evenly over the bound queues.
+    pub fn rebalance(&self) -> Result<(), XdpError> {
+        let queue_ids: Vec<u32> = self.queues.iter().map(|(id, _)| *id).collect();
+        for lower in lower_devices(self.name)? {
+            ethtool::set_rss_queues(&lower, &queue_ids)?;
+        }
+        Ok(())
+    }
+
+    /// Built-in program attached for `Config::default_program`, `None` when the
+    /// interface had a program already.
+    pub fn default_program(&self) -> Option<&Redirect> {
+        self.redirect.as_ref()
+    }
+
+    /// Kicks the kernel to send the unfinished TX batch of every queue.
+    pub fn flush_tx(&mut self) {
+        for (_, socket) in &mut self.queues {
+            socket.flush_tx();
+        }
+    }
+
+    /// Bound queues with their socket.
+    pub fn queues(&self) -> impl Iterator<Item = (u32, &XdpSocket<'a>)> {
+        self.queues.iter().map(|(id, socket)| (*id, socket))
+    }
+
+    /// Socket bound to `queue_id`.
+    pub fn queue_mut(&mut self, queue_id: u32) -> Option<&mut XdpSocket<'a>> {
+        self.queues
+            .iter_mut()
+            .find(|(id, _)| *id == queue_id)
+            .map(|(_, socket)| socket)
+    }
+}
+
+impl HealthCheck for XdpMultiQueue<'_> {
+    fn check(&self, report: &mut Report) {
+        for (_, socket) in &self.queues {
+            socket.check(report);
+        }
+    }
+}
+
+impl<'a> Device for XdpMultiQueue<'a> {
+    type RxToken<'b>
+        = RxToken<'a>
+    where
+        Self: 'b;
+
+    type TxToken<'b>
+        = TxToken<'a>
+    where
+        Self: 'b;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        match self.queues.first() {
+            Some((_, socket)) => socket.capabilities(),
+            None => DeviceCapabilities::default(),
+        }
+    }
+
+    /// Polls the queues in the configured [`Schedule`], starting after the queue that
+    /// delivered last, or from it when draining.
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let count = self.queues.len();
+        let start = match self.schedule {
+            Schedule::RoundRobin => self.current + 1,
+            Schedule::Drain => self.current,
+        };
+        for i in 0..count {
+            let idx = (start + i) % count;
+            if let Some(tokens) = self.queues[idx].1.receive(timestamp) {
+                self.current = idx;
+                return Some(tokens);
+            }
+        }
+        None
+    }
+
+    /// Transmits through the queue that delivered last.
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        let count = self.queues.len();
+        if count == 0 {
+            return None;
+        }
+        let idx = self.current % count;
+        self.queues[idx].1.transmit(timestamp)
+    }
+}
+
+impl CapabilitiesAudit for XdpMultiQueue<'_> {
+    fn audit(&self, report: &mut DeviceCapabilitiesReport) {
+        report.layer("xdp-multi", self.capabilities());
+    }
+}
@@ -0,0 +1,48 @@
+//! Ethertypes the bundled XDP program (`examples/xdp.c`) leaves to the kernel stack.
+//!
+//! The program looks every frame up in its pinned `passthrough_map` first and passes
+//! the listed ethertypes on with `XDP_PASS`, so host daemons such as `lldpd`, an STP
+//! bridge or `ptp4l` keep working while IP traffic is redirected to the sockets. The
+//! ethertype of a VLAN-tagged frame is the one after the tag.
+
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use crate::phy::sys::bpf;
+use crate::phy::xdp::XdpError;
+
+/// Link Layer Discovery Protocol.
+pub const LLDP: u16 = 0x88cc;
+/// PTP over Ethernet, IEEE 1588 layer 2 transport.
+pub const PTP: u16 = 0x88f7;
+/// 802.3 frames carrying a length instead of an ethertype, such as STP BPDUs.
+pub const LLC: u16 = 0;
+
+pub struct Passthrough {
+    map: OwnedFd,
+}
+
+impl Passthrough {
+    /// Opens the `passthrough_map` pinned at `map_path`.
+    pub fn open(map_path: &str) -> Result<Self, XdpError> {
+        Ok(Self::from_map(bpf::obj_get(map_path)?))
+    }
+
+    /// Uses `map`, e.g. received from a privileged helper that opened the pinned map.
+    pub fn from_map(map: OwnedFd) -> Self {
+        Self { map }
+    }
+
+    /// Hands the frames of `ethertype` to the kernel stack.
+    pub fn add(&mut self, ethertype: u16) -> Result<(), XdpError> {
+        Ok(bpf::map_update_elem(
+            self.map.as_raw_fd(),
+            &ethertype,
+            &1u8,
+        )?)
+    }
+
+    /// Redirects the frames of `ethertype` to the sockets again.
+    pub fn remove(&mut self, ethertype: u16) -> Result<(), XdpError> {
+        Ok(bpf::map_delete_elem(self.map.as_raw_fd(), &ethertype)?)
+    }
+}
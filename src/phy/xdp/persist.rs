@@ -0,0 +1,77 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::phy::xdp::Config;
+
+/// Steps upgrading a saved config, the one at index `n` from layout `n + 1` to layout
+/// `n + 2`. A step is appended whenever a field of `Config`, or of a type it nests, is
+/// added, renamed or changes meaning, e.g. inserting the default of a new field.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[];
+
+#[derive(Serialize)]
+struct Saved<'a> {
+    version: u32,
+    config: &'a Config,
+}
+
+#[derive(Deserialize)]
+struct Loaded {
+    version: u32,
+    config: Map<String, Value>,
+}
+
+impl Config {
+    /// Layout of the configs written by [`Config::save`].
+    pub const VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+    /// Writes the config to `path` as JSON, tagged with [`Config::VERSION`].
+    ///
+    /// The file is written next to `path` and renamed over it, so a crash leaves either
+    /// the previous config or the new one.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(
+            &mut writer,
+            &Saved {
+                version: Self::VERSION,
+                config: self,
+            },
+        )?;
+        writer.write_all(b"\n")?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Reads a config written by [`Config::save`], upgrading it first if an older version
+    /// of this crate wrote it. Configs from newer versions are refused.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let Loaded {
+            version,
+            mut config,
+        } = serde_json::from_reader(reader)?;
+        if version == 0 || version > Self::VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Config version {} is not supported, at most {} is",
+                    version,
+                    Self::VERSION
+                ),
+            ));
+        }
+
+        for migrate in &MIGRATIONS[version as usize - 1..] {
+            migrate(&mut config);
+        }
+        Ok(serde_json::from_value(Value::Object(config))?)
+    }
+}
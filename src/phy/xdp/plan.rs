@@ -0,0 +1,381 @@
+//! What opening a socket would do, worked out without opening one, see [`Config::plan`].
+//!
+//! ```ignore
+//! let config = XdpSocketBuilder::new().queue_id(2).config("eth0")?;
+//! println!("{}", config.plan("eth0")?);
+//! ```
+
+use std::{fmt, fs};
+
+use crate::phy::sys::ethtool::{self, DriverInfo};
+use crate::phy::sys::netlink::{
+    self, NETDEV_XDP_ACT_BASIC, NETDEV_XDP_ACT_REDIRECT, NETDEV_XDP_ACT_XSK_ZEROCOPY,
+    XDP_FLAGS_DRV_MODE, XDP_FLAGS_HW_MODE,
+};
+use crate::phy::sys::xdp::{if_index, mtu, rx_queues, single_lower_device};
+use crate::phy::xdp::{AttachMode, BindMode, Config, XdpError};
+
+const CAP_NET_ADMIN: u32 = 12;
+const CAP_NET_RAW: u32 = 13;
+const CAP_IPC_LOCK: u32 = 14;
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_BPF: u32 = 39;
+
+/// What [`XdpSocket::new`](crate::phy::xdp::XdpSocket::new) would do with a [`Config`]
+/// on an interface, for review before deployment.
+///
+/// Its `Display` is meant for operators, one line per topic, so that the plans of two
+/// configurations or hosts can be diffed.
+#[derive(Clone, Debug)]
+pub struct Plan {
+    /// Device the socket binds to, the lower device of a bond or VLAN.
+    pub device: String,
+    pub queue_id: u32,
+    pub driver: Option<DriverInfo>,
+    /// Mode of the XDP program attached to the device already, if any.
+    pub existing_program: Option<AttachMode>,
+    /// Mode the built-in redirect program would be attached in, `None` when it is not
+    /// attached: without `Config::default_program` or with a program on the device
+    /// already. `AttachMode::Auto` when the kernel does not report the XDP features of
+    /// the driver, native mode is tried first then.
+    pub attach: Option<AttachMode>,
+    /// Whether frames would move without a copy, `None` when the kernel does not report
+    /// the XDP features of the driver.
+    pub zero_copy: Option<bool>,
+    /// Ring sizes of the config, used as they are.
+    pub rings: RingSizes,
+    /// Current RX and TX descriptor ring sizes of the NIC, when it has any.
+    pub nic_rings: Option<(u32, u32)>,
+    pub umem: UmemPlan,
+    /// `RLIMIT_MEMLOCK` of the process in bytes, `None` when unlimited.
+    pub memlock_limit: Option<u64>,
+    /// Capabilities opening the socket needs, whether the process holds them or not.
+    pub capabilities: Vec<Capability>,
+    /// What would make opening the socket fail, or the socket misbehave.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RingSizes {
+    pub rx: usize,
+    pub tx: usize,
+    pub fill: usize,
+    pub completion: usize,
+}
+
+/// How the UMEM would be laid out and split between RX and TX.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct UmemPlan {
+    pub entries: usize,
+    pub chunk_size: usize,
+    /// Bytes before the frame in every chunk, the kernel headroom included.
+    pub headroom: usize,
+    /// Frames posted to the fill ring when the socket opens.
+    pub fill_frames: usize,
+    /// Frames reserved for control-plane TX frames, see `Config::tx_priority`.
+    pub priority_frames: usize,
+    /// Frames left in the free list for TX.
+    pub tx_frames: usize,
+    /// Bytes the kernel pins when the UMEM is registered, charged to `RLIMIT_MEMLOCK`
+    /// without `CAP_IPC_LOCK`.
+    pub locked_bytes: usize,
+}
+
+/// A capability opening the socket needs.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Capability {
+    /// Name of the capability, e.g. `CAP_NET_RAW`.
+    pub name: &'static str,
+    /// What it is needed for.
+    pub reason: &'static str,
+    /// Whether the effective set of the process has it.
+    pub held: bool,
+}
+
+impl Config {
+    /// Works out what opening a socket with this config on the interface called `name`
+    /// would do, without creating any socket: the attach mode of the built-in program,
+    /// whether frames move without a copy, the memory pinned, the ring sizes and UMEM
+    /// split, and the capabilities needed.
+    ///
+    /// Only queries the interface, so it runs unprivileged. Fails when the interface
+    /// does not exist or resolves to several lower devices.
+    pub fn plan(&self, name: &str) -> Result<Plan, XdpError> {
+        let device = single_lower_device(name)?;
+        let ifindex = if_index(&device)?;
+        let mtu = mtu(name)?;
+        let driver = ethtool::driver_info(&device).ok();
+        let features = netlink::xdp_features(ifindex).ok();
+        let existing_program = netlink::xdp_attached(ifindex)?.map(attach_mode);
+        let mut warnings = Vec::new();
+
+        let attach = match self.default_program {
+            Some(_) if existing_program.is_some() => {
+                warnings.push(format!(
+                    "{} has an XDP program already, registering the socket in its XSKMAP \
+                     is up to the application",
+                    device
+                ));
+                None
+            }
+            Some(AttachMode::Auto) => Some(match features {
+                Some(features) if native(features) => AttachMode::Native,
+                Some(_) => AttachMode::Generic,
+                None => AttachMode::Auto,
+            }),
+            Some(AttachMode::Native) if features.is_some_and(|features| !native(features)) => {
+                warnings.push(format!(
+                    "The driver of {} cannot redirect to sockets in native mode, \
+                     attaching fails",
+                    device
+                ));
+                Some(AttachMode::Native)
+            }
+            Some(AttachMode::Offload) => {
+                warnings.push(
+                    "Offloaded programs cannot redirect to sockets, no frame is received"
+                        .to_owned(),
+                );
+                Some(AttachMode::Offload)
+            }
+            mode => mode,
+        };
+
+        let zero_capable = features.map(|features| features & NETDEV_XDP_ACT_XSK_ZEROCOPY != 0);
+        let zero_copy = match self.bind_mode {
+            BindMode::Copy => Some(false),
+            BindMode::Auto => zero_capable,
+            BindMode::ZeroCopy => {
+                if zero_capable == Some(false) {
+                    warnings.push(format!(
+                        "The driver of {} has no zero-copy support, binding with \
+                         BindMode::ZeroCopy fails",
+                        device
+                    ));
+                }
+                zero_capable
+            }
+        };
+
+        let queues = rx_queues(&device)?;
+        if self.queue_id >= queues {
+            warnings.push(format!(
+                "{} has {} RX queues, queue {} does not exist",
+                device, queues, self.queue_id
+            ));
+        }
+
+        let umem = self.umem_plan(&mut warnings, mtu);
+        let nic_rings = ethtool::ring_sizes(&device).ok();
+        if let Some((nic_rx, _)) = nic_rings
+            && self.fr.size < nic_rx as usize
+        {
+            warnings.push(format!(
+                "The fill ring ({}) is smaller than the NIC RX ring ({}), bursts may be \
+                 dropped",
+                self.fr.size, nic_rx
+            ));
+        }
+
+        let memlock_limit = memlock_limit();
+        let held = effective_capabilities();
+        let has = |cap: u32| held & (1 << cap) != 0;
+        let mut capabilities = vec![Capability {
+            name: "CAP_NET_RAW",
+            reason: "create the AF_XDP socket",
+            held: has(CAP_NET_RAW),
+        }];
+        if attach.is_some() {
+            capabilities.push(Capability {
+                name: "CAP_BPF",
+                reason: "load the built-in program and its XSKMAP",
+                held: has(CAP_BPF) || has(CAP_SYS_ADMIN),
+            });
+        }
+        let net_admin = match (attach.is_some(), self.busy_poll.is_some()) {
+            (true, true) => {
+                Some("attach the built-in program and raise the busy poll timeout and budget")
+            }
+            (true, false) => Some("attach the built-in program"),
+            (false, true) => Some("raise the busy poll timeout and budget"),
+            (false, false) => None,
+        };
+        if let Some(reason) = net_admin {
+            capabilities.push(Capability {
+                name: "CAP_NET_ADMIN",
+                reason,
+                held: has(CAP_NET_ADMIN),
+            });
+        }
+        if memlock_limit.is_some_and(|limit| umem.locked_bytes as u64 > limit) {
+            capabilities.push(Capability {
+                name: "CAP_IPC_LOCK",
+                reason: "pin a UMEM larger than RLIMIT_MEMLOCK",
+                held: has(CAP_IPC_LOCK),
+            });
+        }
+
+        Ok(Plan {
+            device,
+            queue_id: self.queue_id,
+            driver,
+            existing_program,
+            attach,
+            zero_copy,
+            rings: RingSizes {
+                rx: self.rx.size,
+                tx: self.tx.size,
+                fill: self.fr.size,
+                completion: self.cr.size,
+            },
+            nic_rings,
+            umem,
+            memlock_limit,
+            capabilities,
+            warnings,
+        })
+    }
+
+    /// Lays the UMEM out as the socket would, warning about what would fail or drop
+    /// frames of `mtu`.
+    fn umem_plan(&self, warnings: &mut Vec<String>, mtu: usize) -> UmemPlan {
+        let entries = self.umem.entries;
+        let chunk_size = usize::from(self.umem.alignment);
+        let headroom = self.umem.frame_offset();
+        if headroom >= chunk_size {
+            warnings.push(format!(
+                "A headroom of {} bytes leaves no room in {} byte chunks, registering the \
+                 UMEM fails",
+                headroom, chunk_size
+            ));
+        } else if self.umem.chunk_needed(mtu) > chunk_size && !self.multi_buffer {
+            warnings.push(format!(
+                "A VLAN-tagged frame of the MTU ({}) does not fit in a chunk after the \
+                 headroom, such frames are dropped without multi_buffer",
+                mtu
+            ));
+        }
+
+        let fill_frames = self.fr.size.min(entries);
+        let priority_frames = self
+            .tx_priority
+            .map_or(0, |priority| priority.pages.min(entries - fill_frames));
+        let tx_frames = entries - fill_frames - priority_frames;
+        if tx_frames == 0 {
+            warnings.push(format!(
+                "The fill ring takes every UMEM frame ({}), none is left for TX",
+                entries
+            ));
+        }
+
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        UmemPlan {
+            entries,
+            chunk_size,
+            headroom,
+            fill_frames,
+            priority_frames,
+            tx_frames,
+            locked_bytes: (entries * chunk_size).div_ceil(page) * page,
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "device: {} queue {}", self.device, self.queue_id)?;
+        if let Some(driver) = &self.driver {
+            write!(f, " ({})", driver)?;
+        }
+        writeln!(f)?;
+
+        match (self.attach, self.existing_program) {
+            (Some(mode), _) => writeln!(f, "program: built-in, attached in {:?} mode", mode)?,
+            (None, Some(mode)) => writeln!(f, "program: existing, attached in {:?} mode", mode)?,
+            (None, None) => writeln!(f, "program: none")?,
+        }
+        let zero_copy = match self.zero_copy {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown",
+        };
+        writeln!(f, "zero-copy: {}", zero_copy)?;
+
+        let rings = &self.rings;
+        write!(
+            f,
+            "rings: rx {}, tx {}, fill {}, completion {}",
+            rings.rx, rings.tx, rings.fill, rings.completion
+        )?;
+        if let Some((rx, tx)) = self.nic_rings {
+            write!(f, " (NIC rx {}, tx {})", rx, tx)?;
+        }
+        writeln!(f)?;
+
+        let umem = &self.umem;
+        writeln!(
+            f,
+            "umem: {} chunks of {} bytes with {} bytes of headroom, {} on the fill ring, {} \
+             reserved, {} for TX",
+            umem.entries,
+            umem.chunk_size,
+            umem.headroom,
+            umem.fill_frames,
+            umem.priority_frames,
+            umem.tx_frames
+        )?;
+        write!(f, "locked memory: {} KiB", umem.locked_bytes / 1024)?;
+        match self.memlock_limit {
+            Some(limit) => writeln!(f, " (RLIMIT_MEMLOCK {} KiB)", limit / 1024)?,
+            None => writeln!(f, " (RLIMIT_MEMLOCK unlimited)")?,
+        }
+
+        for cap in &self.capabilities {
+            let held = if cap.held { "held" } else { "missing" };
+            writeln!(f, "capability: {} to {}, {}", cap.name, cap.reason, held)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+fn attach_mode(flags: u32) -> AttachMode {
+    if flags & XDP_FLAGS_DRV_MODE != 0 {
+        AttachMode::Native
+    } else if flags & XDP_FLAGS_HW_MODE != 0 {
+        AttachMode::Offload
+    } else {
+        AttachMode::Generic
+    }
+}
+
+/// Whether a driver with the XDP `features` runs programs redirecting to sockets.
+fn native(features: u64) -> bool {
+    let needed = NETDEV_XDP_ACT_BASIC | NETDEV_XDP_ACT_REDIRECT;
+    features & needed == needed
+}
+
+fn memlock_limit() -> Option<u64> {
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+    Some(limit.rlim_cur)
+}
+
+/// Effective capability set of the process, empty when `/proc` is not mounted.
+fn effective_capabilities() -> u64 {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))
+                .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        })
+        .unwrap_or(0)
+}
@@ -0,0 +1,52 @@
+//! Telling control-plane TX frames apart from bulk traffic, so that they can be given
+//! the UMEM pages set aside by `xdp::Config::tx_priority`.
+
+use crate::phy::link;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_ICMPV6: u8 = 58;
+/// Router solicitation to redirect, the neighbor discovery ICMPv6 types.
+const ND_TYPES: std::ops::RangeInclusive<u8> = 133..=137;
+/// ACK flag in the 14th byte of the TCP header.
+const TCP_ACK: u8 = 0x10;
+
+/// Whether the Ethernet `frame` is an ARP or neighbor discovery packet, a TCP segment
+/// acknowledging data without carrying any, or an IP packet whose DSCP has its bit set
+/// in `dscp`.
+pub(crate) fn is_control(frame: &[u8], dscp: u64) -> bool {
+    classify(frame, dscp).unwrap_or(false)
+}
+
+fn classify(frame: &[u8], dscp: u64) -> Option<bool> {
+    let (ethertype, l3) = link::ethernet(frame)?;
+    let packet = frame.get(l3..)?;
+
+    let (class, protocol, l4, payload_len) = match ethertype {
+        0x0806 => return Some(true),
+        0x0800 => {
+            let header_len = usize::from(packet.first()? & 0x0f) * 4;
+            let total_len = usize::from(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]));
+            let len = total_len.checked_sub(header_len)?;
+            (*packet.get(1)?, *packet.get(9)?, header_len, len)
+        }
+        0x86dd => {
+            let class = (packet.first()? & 0x0f) << 4 | packet.get(1)? >> 4;
+            let len = usize::from(u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]));
+            (class, *packet.get(6)?, 40, len)
+        }
+        _ => return Some(false),
+    };
+    if dscp & 1 << (class >> 2) != 0 {
+        return Some(true);
+    }
+
+    let segment = packet.get(l4..)?;
+    match protocol {
+        IPPROTO_ICMPV6 if ethertype == 0x86dd => Some(ND_TYPES.contains(segment.first()?)),
+        IPPROTO_TCP => {
+            let header_len = usize::from(segment.get(12)? >> 4) * 4;
+            Some(segment.get(13)? & TCP_ACK != 0 && payload_len == header_len)
+        }
+        _ => Some(false),
+    }
+}
@@ -0,0 +1,148 @@
+//! A built-in XDP program redirecting every frame to the socket bound to its queue, so
+//! that no separate program has to be written, compiled and attached.
+//!
+//! The program is the equivalent of the libxdp default one:
+//!
+//! ```c
+//! return bpf_redirect_map(&xsks_map, ctx->rx_queue_index, XDP_PASS);
+//! ```
+//!
+//! Frames of queues without a socket go to the kernel stack. It has none of the extras
+//! of the bundled `examples/xdp.c` (metadata, passthrough ethertypes), which needs the
+//! `libbpf` feature to be loaded, see `xdp::bpf`.
+
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::Mutex;
+
+use crate::phy::sys::bpf::{self, BPF_MAP_TYPE_XSKMAP, BPF_PROG_TYPE_XDP, Insn};
+use crate::phy::sys::xdp::{if_index, rx_queues, single_lower_device};
+use crate::phy::xdp::{AttachMode, Attachment, XdpError, attach_program};
+
+const XDP_PASS: i32 = 2;
+const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+/// Offset of `rx_queue_index` in `struct xdp_md`.
+const RX_QUEUE_INDEX: i16 = 16;
+
+/// The built-in program attached to an interface, detached on drop.
+pub struct Redirect {
+    // Dropped first, detaching the program before its map goes away.
+    attachment: Attachment,
+    prog: OwnedFd,
+    map: OwnedFd,
+    // Socket registered for each queue.
+    sockets: Mutex<Vec<(u32, RawFd)>>,
+}
+
+impl Redirect {
+    /// Attaches the program to the interface called `name`, or to the lower device a
+    /// socket for it binds to, with room for every RX queue.
+    ///
+    /// Fails with `AlreadyExists` if the interface has a program and `replace` is false.
+    pub fn attach(name: &str, mode: AttachMode, replace: bool) -> Result<Self, XdpError> {
+        let ifindex = if_index(&single_lower_device(name)?)?;
+        Ok(Self::attach_on(
+            ifindex,
+            rx_queues(name)?.max(1),
+            mode,
+            replace,
+        )?)
+    }
+
+    /// Attaches the program to the interface `ifindex` with an XSKMAP of `queues`
+    /// entries.
+    pub(crate) fn attach_on(
+        ifindex: u32,
+        queues: u32,
+        mode: AttachMode,
+        replace: bool,
+    ) -> io::Result<Self> {
+        let map = bpf::map_create(BPF_MAP_TYPE_XSKMAP, 4, 4, queues, "xsks_map")?;
+        let insns = program(map.as_raw_fd());
+        let prog = bpf::prog_load(BPF_PROG_TYPE_XDP, &insns, "Dual BSD/GPL", "xsk_redirect")?;
+        let attachment = attach_program(ifindex, prog.as_raw_fd(), mode, replace)?;
+        Ok(Self {
+            attachment,
+            prog,
+            map,
+            sockets: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub(crate) fn ifindex(&self) -> u32 {
+        self.attachment.ifindex()
+    }
+
+    /// Mode the program was actually attached in, never `Auto`.
+    pub fn mode(&self) -> AttachMode {
+        self.attachment.mode()
+    }
+
+    /// Redirects the traffic of `queue_id` to `socket`.
+    pub fn register(&self, socket: &impl AsRawFd, queue_id: u32) -> Result<(), XdpError> {
+        let fd = socket.as_raw_fd();
+        bpf::map_update_elem(self.map.as_raw_fd(), &queue_id, &(fd as u32))?;
+        let mut sockets = self
+            .sockets
+            .lock()
+            .expect("Redirect never panics holding the lock");
+        sockets.retain(|&(queue, _)| queue != queue_id);
+        sockets.push((queue_id, fd));
+        Ok(())
+    }
+
+    /// Stops redirecting the traffic of `queue_id`. Closed sockets are removed from the
+    /// map by the kernel.
+    pub fn unregister(&self, queue_id: u32) -> Result<(), XdpError> {
+        bpf::map_delete_elem(self.map.as_raw_fd(), &queue_id)?;
+        self.sockets
+            .lock()
+            .expect("Redirect never panics holding the lock")
+            .retain(|&(queue, _)| queue != queue_id);
+        Ok(())
+    }
+
+    /// Unregisters `queue_id` if it still redirects to `socket`, leaving alone a socket
+    /// registered for the queue since.
+    pub(crate) fn release(&self, socket: RawFd, queue_id: u32) {
+        if self
+            .sockets
+            .lock()
+            .expect("Redirect never panics holding the lock")
+            .contains(&(queue_id, socket))
+        {
+            let _ = self.unregister(queue_id);
+        }
+    }
+
+    /// The attached program, e.g. for [`crate::phy::xdp::XdpSocket::self_test`].
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.prog.as_fd()
+    }
+
+    pub fn xskmap(&self) -> BorrowedFd<'_> {
+        self.map.as_fd()
+    }
+}
+
+/// `r0 = bpf_redirect_map(map_fd, ctx->rx_queue_index, XDP_PASS)`.
+fn program(map_fd: i32) -> [Insn; 6] {
+    let insn = |code, dst: u8, src: u8, off, imm| Insn {
+        code,
+        regs: dst | src << 4,
+        off,
+        imm,
+    };
+    [
+        // r2 = *(u32 *)(r1 + RX_QUEUE_INDEX)
+        insn(0x61, 2, 1, RX_QUEUE_INDEX, 0),
+        // r1 = map_fd, a two-slot 64-bit immediate load
+        insn(0x18, 1, BPF_PSEUDO_MAP_FD, 0, map_fd),
+        insn(0x00, 0, 0, 0, 0),
+        // r3 = XDP_PASS, returned when the queue has no socket
+        insn(0xb7, 3, 0, 0, XDP_PASS),
+        insn(0x85, 0, 0, 0, BPF_FUNC_REDIRECT_MAP),
+        insn(0x95, 0, 0, 0, 0),
+    ]
+}
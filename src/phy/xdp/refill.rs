@@ -0,0 +1,186 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::phy::xdp::rings::{RawRing, Writer, XdpRing};
+use crate::phy::xdp::{SharedUmem, UmemBacking, XdpError, lock};
+
+/// Background refiller settings.
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Config {
+    /// How long the refiller sleeps once the fill ring is at its target depth, unless
+    /// the RX path recycles a frame first.
+    pub interval: Duration,
+    /// Fill ring depth the refiller keeps, at most the ring size. Recycled RX frames are
+    /// posted first, pages of the UMEM free list make up for those still held by the
+    /// kernel or the application, and recycled frames beyond it go back to the free list.
+    pub target: usize,
+}
+
+struct Shared {
+    fr: Mutex<XdpRing<Writer, u64>>,
+    // RX frames released by the application and not yet posted to the fill ring.
+    free: Mutex<Vec<u64>>,
+    stop: AtomicBool,
+}
+
+// The thread locks the fill ring, the recycled frames and the UMEM one at a time, so it
+// cannot deadlock with the socket, which takes its own state before the UMEM.
+type Umem = Arc<Mutex<SharedUmem<'static>>>;
+
+/// Thread keeping the fill ring topped up independently of the smoltcp poll loop.
+pub struct Refiller {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Refiller {
+    /// Spawns the thread refilling `fr` from the RX frames pushed to it and the free
+    /// list of `umem`.
+    ///
+    /// Fails for UMEMs in application memory: the thread outlives any borrow if the
+    /// socket is leaked, and only memory the UMEM owns stays valid for as long as the
+    /// thread holds it.
+    pub(super) fn spawn<'a>(
+        fr: XdpRing<Writer, u64>,
+        umem: &Arc<Mutex<SharedUmem<'a>>>,
+        config: Config,
+    ) -> Result<Self, XdpError> {
+        if lock(umem).umem.backing() == UmemBacking::User {
+            return Err(XdpError::InvalidConfig(
+                "The background refiller needs a UMEM the socket allocates".to_owned(),
+            ));
+        }
+        // SAFETY: the UMEM owns its memory, checked above, so nothing it points to ends
+        // with `'a`, which only bounds borrowed memory.
+        let umem: Umem =
+            unsafe { std::mem::transmute::<Arc<Mutex<SharedUmem<'a>>>, Umem>(umem.clone()) };
+        let capacity = fr.size() as usize;
+        let config = Config {
+            target: config.target.min(capacity),
+            ..config
+        };
+        let shared = Arc::new(Shared {
+            fr: Mutex::new(fr),
+            free: Mutex::new(Vec::with_capacity(capacity)),
+            stop: AtomicBool::new(false),
+        });
+
+        let handle = thread::Builder::new().name("xdp-refill".into()).spawn({
+            let shared = shared.clone();
+            move || run(&shared, &umem, config)
+        })?;
+
+        Ok(Self {
+            shared,
+            handle: Some(handle),
+        })
+    }
+
+    /// Hands a consumed RX frame back to the refiller, waking it up for the first frame
+    /// recycled since it last ran.
+    pub fn push(&self, addr: u64) {
+        let mut free = self
+            .shared
+            .free
+            .lock()
+            .expect("Refiller never panics holding the lock");
+        free.push(addr);
+        if free.len() == 1
+            && let Some(handle) = &self.handle
+        {
+            handle.thread().unpark();
+        }
+    }
+
+    /// Frames currently posted to the fill ring.
+    pub fn pending(&self) -> u32 {
+        self.shared
+            .fr
+            .lock()
+            .expect("Refiller never panics holding the lock")
+            .pending()
+    }
+
+    pub fn needs_wakeup(&self) -> bool {
+        self.shared
+            .fr
+            .lock()
+            .expect("Refiller never panics holding the lock")
+            .needs_wakeup()
+    }
+
+    pub fn raw(&self) -> RawRing {
+        self.shared
+            .fr
+            .lock()
+            .expect("Refiller never panics holding the lock")
+            .raw()
+    }
+}
+
+impl Drop for Refiller {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run(shared: &Shared, umem: &Umem, config: Config) {
+    let mut recycled = Vec::new();
+    let mut batch = Vec::new();
+
+    while !shared.stop.load(Ordering::Acquire) {
+        std::mem::swap(
+            &mut recycled,
+            &mut *shared
+                .free
+                .lock()
+                .expect("Refiller never panics holding the lock"),
+        );
+        let depth = shared
+            .fr
+            .lock()
+            .expect("Refiller never panics holding the lock")
+            .pending() as usize;
+        let wanted = config.target.saturating_sub(depth);
+
+        let take = wanted.min(recycled.len());
+        batch.extend(recycled.drain(..take));
+        if !recycled.is_empty() || batch.len() < wanted {
+            let mut shared = lock(umem);
+            for addr in recycled.drain(..) {
+                shared.umem.unfill(addr);
+            }
+            batch.extend(shared.umem.reserve(wanted - batch.len()));
+        }
+
+        let posted = shared
+            .fr
+            .lock()
+            .expect("Refiller never panics holding the lock")
+            .write_batch(&batch);
+        batch.drain(..posted);
+        // Whatever did not fit is posted on the next round.
+        if !batch.is_empty() {
+            shared
+                .free
+                .lock()
+                .expect("Refiller never panics holding the lock")
+                .append(&mut batch);
+        }
+
+        if posted == 0 {
+            thread::park_timeout(config.interval);
+        }
+    }
+}
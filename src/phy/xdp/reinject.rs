@@ -0,0 +1,158 @@
+//! Spreading the traffic smoltcp does not own over CPUs with the bundled XDP program
+//! (`examples/xdp.c`).
+//!
+//! An AF_XDP socket takes every frame of its queue, so host traffic landing on it
+//! otherwise goes to the kernel stack on the CPU of that single queue. Once steering CPUs
+//! are set, the program only redirects to the sockets the TCP and UDP destination ports
+//! listed in its pinned `owned_map`, along with non-IP frames such as ARP, and hands
+//! other IP flows to a CPUMAP with `bpf_redirect_map`. Each flow sticks to the CPU its
+//! addresses and ports hash to, where the kernel builds its socket buffers.
+//!
+//! ```ignore
+//! let mut reinject = Reinject::open("/sys/fs/bpf")?;
+//! reinject.own(IpProtocol::Tcp, 8080)?;
+//! reinject.set_cpus(&[2, 3, 4, 5], 2048)?;
+//! ```
+
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use smoltcp::wire::IpProtocol;
+
+use crate::phy::sys::bpf;
+use crate::phy::xdp::XdpError;
+
+/// Entries of the `steer_cpus` and `cpu_map` maps.
+pub const MAX_CPUS: usize = 64;
+
+/// Key of `owned_map`, the `struct owned_flow` of the program.
+#[repr(C)]
+struct Flow {
+    protocol: u8,
+    pad: u8,
+    port: u16,
+}
+
+impl Flow {
+    fn new(protocol: IpProtocol, port: u16) -> io::Result<Self> {
+        if !matches!(protocol, IpProtocol::Tcp | IpProtocol::Udp) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only TCP and UDP flows can be owned",
+            ));
+        }
+        Ok(Self {
+            protocol: protocol.into(),
+            pad: 0,
+            port,
+        })
+    }
+}
+
+/// Value of `cpu_map`, a `struct bpf_cpumap_val` without a program.
+#[repr(C)]
+struct CpumapVal {
+    qsize: u32,
+    prog_fd: i32,
+}
+
+/// The steering maps of the bundled program.
+pub struct Reinject {
+    config: OwnedFd,
+    cpus: OwnedFd,
+    cpu_map: OwnedFd,
+    owned: OwnedFd,
+    // CPUs currently in `cpu_map`.
+    active: Vec<u32>,
+}
+
+impl Reinject {
+    /// Opens the `steer_config`, `steer_cpus`, `cpu_map` and `owned_map` maps pinned in
+    /// `pin_root`.
+    pub fn open(pin_root: &str) -> Result<Self, XdpError> {
+        let open = |name: &str| bpf::obj_get(&format!("{}/{}", pin_root, name));
+        Ok(Self::from_maps(
+            open("steer_config")?,
+            open("steer_cpus")?,
+            open("cpu_map")?,
+            open("owned_map")?,
+        ))
+    }
+
+    /// Uses maps opened elsewhere, e.g. cloned from `xdp::bpf::Program::map`.
+    ///
+    /// Steering CPUs set by another process are not known, [`Reinject::set_cpus`] leaves
+    /// their `cpu_map` entries in place.
+    pub fn from_maps(config: OwnedFd, cpus: OwnedFd, cpu_map: OwnedFd, owned: OwnedFd) -> Self {
+        Self {
+            config,
+            cpus,
+            cpu_map,
+            owned,
+            active: Vec::new(),
+        }
+    }
+
+    /// Redirects the `protocol` frames to `port` to the sockets while steering, `protocol`
+    /// being TCP or UDP.
+    pub fn own(&mut self, protocol: IpProtocol, port: u16) -> Result<(), XdpError> {
+        let flow = Flow::new(protocol, port)?;
+        Ok(bpf::map_update_elem(self.owned.as_raw_fd(), &flow, &1u8)?)
+    }
+
+    /// Steers the `protocol` frames to `port` like the rest of the host traffic again.
+    pub fn disown(&mut self, protocol: IpProtocol, port: u16) -> Result<(), XdpError> {
+        let flow = Flow::new(protocol, port)?;
+        Ok(bpf::map_delete_elem(self.owned.as_raw_fd(), &flow)?)
+    }
+
+    /// Spreads the IP flows not owned over `cpus`, each with a CPUMAP queue of
+    /// `queue_size` frames. An empty list turns steering off, every frame then being
+    /// redirected to the sockets as without steering maps.
+    pub fn set_cpus(&mut self, cpus: &[u32], queue_size: u32) -> Result<(), XdpError> {
+        if cpus.len() > MAX_CPUS {
+            return Err(XdpError::InvalidConfig(format!(
+                "At most {} steering CPUs",
+                MAX_CPUS
+            )));
+        }
+        if !cpus.is_empty() && queue_size == 0 {
+            return Err(XdpError::InvalidConfig(
+                "CPUMAP queues must hold at least one frame".to_owned(),
+            ));
+        }
+
+        // Off while the CPU list changes, then on once every entry is in place.
+        self.set_count(0)?;
+        for &cpu in &self.active {
+            if !cpus.contains(&cpu) {
+                bpf::map_delete_elem(self.cpu_map.as_raw_fd(), &cpu)?;
+            }
+        }
+        self.active.clear();
+        for (slot, &cpu) in cpus.iter().enumerate() {
+            let val = CpumapVal {
+                qsize: queue_size,
+                prog_fd: 0,
+            };
+            bpf::map_update_elem(self.cpu_map.as_raw_fd(), &cpu, &val)?;
+            self.active.push(cpu);
+            bpf::map_update_elem(self.cpus.as_raw_fd(), &(slot as u32), &cpu)?;
+        }
+        Ok(self.set_count(cpus.len() as u32)?)
+    }
+
+    /// Turns steering off, see [`Reinject::set_cpus`].
+    pub fn disable(&mut self) -> Result<(), XdpError> {
+        self.set_cpus(&[], 0)
+    }
+
+    /// CPUs flows are currently spread over.
+    pub fn cpus(&self) -> &[u32] {
+        &self.active
+    }
+
+    fn set_count(&self, count: u32) -> io::Result<()> {
+        bpf::map_update_elem(self.config.as_raw_fd(), &0u32, &count)
+    }
+}
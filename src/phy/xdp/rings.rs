@@ -1,4 +1,6 @@
+use crate::phy::xdp::XdpError;
 use std::{
+    collections::VecDeque,
     io,
     marker::PhantomData,
     os::fd::RawFd,
@@ -10,16 +12,13 @@ use libc::{
     XDP_UMEM_FILL_RING, XDP_UMEM_PGOFF_COMPLETION_RING, XDP_UMEM_PGOFF_FILL_RING,
 };
 
-pub fn offsets(socket_fd: RawFd) -> io::Result<libc::xdp_mmap_offsets_v1> {
+/// Ring offsets within their mapping.
+///
+/// Kernels older than 5.4 report offsets without the `flags` field, which is then left
+/// at 0: the producer always lives at offset 0, so no valid flags offset can be 0.
+pub fn offsets(socket_fd: RawFd) -> io::Result<libc::xdp_mmap_offsets> {
     // SAFETY: [0;N] is valid representation of inner u64 offsets
-    let mut offsets = unsafe {
-        libc::xdp_mmap_offsets_v1 {
-            rx: std::mem::zeroed(),
-            tx: std::mem::zeroed(),
-            fr: std::mem::zeroed(),
-            cr: std::mem::zeroed(),
-        }
-    };
+    let mut offsets: libc::xdp_mmap_offsets = unsafe { std::mem::zeroed() };
 
     let mut size = std::mem::size_of_val(&offsets) as u32;
     let result = unsafe {
@@ -36,15 +35,33 @@ pub fn offsets(socket_fd: RawFd) -> io::Result<libc::xdp_mmap_offsets_v1> {
         return Err(io::Error::last_os_error());
     }
 
+    if size as usize == std::mem::size_of::<libc::xdp_mmap_offsets_v1>() {
+        // SAFETY: The kernel filled the buffer with the v1 layout.
+        let v1: libc::xdp_mmap_offsets_v1 =
+            unsafe { std::ptr::read(&offsets as *const _ as *const _) };
+        let upgrade = |ring: libc::xdp_ring_offset_v1| libc::xdp_ring_offset {
+            producer: ring.producer,
+            consumer: ring.consumer,
+            desc: ring.desc,
+            flags: 0,
+        };
+        offsets = libc::xdp_mmap_offsets {
+            rx: upgrade(v1.rx),
+            tx: upgrade(v1.tx),
+            fr: upgrade(v1.fr),
+            cr: upgrade(v1.cr),
+        };
+    }
+
     Ok(offsets)
 }
 
-pub fn build<K: Marker>(
+pub fn build<K: Marker, T: Copy>(
     socket_fd: RawFd,
     type_: Type,
-    ring_offsets: libc::xdp_mmap_offsets_v1,
+    ring_offsets: libc::xdp_mmap_offsets,
     size: usize,
-) -> io::Result<XdpRing<K>> {
+) -> io::Result<XdpRing<K, T>> {
     if !size.is_power_of_two() {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -52,14 +69,14 @@ pub fn build<K: Marker>(
         ));
     }
 
-    let ring_offset: libc::xdp_ring_offset_v1 = match type_ {
+    let ring_offset: libc::xdp_ring_offset = match type_ {
         Type::Tx => ring_offsets.tx,
         Type::Rx => ring_offsets.rx,
         Type::Completion => ring_offsets.cr,
         Type::Fill => ring_offsets.fr,
     };
 
-    let mmap_len = ring_offset.desc as usize + (size * std::mem::size_of::<libc::xdp_desc>());
+    let mmap_len = ring_offset.desc as usize + (size * std::mem::size_of::<T>());
     let ring_ptr = unsafe {
         libc::mmap(
             std::ptr::null_mut(),
@@ -114,21 +131,31 @@ impl Marker for Writer {}
 pub struct Reader {}
 pub struct Writer {}
 
-pub struct XdpRing<K: Marker> {
+/// A ring shared with the kernel.
+///
+/// RX and TX rings carry `libc::xdp_desc` entries while fill and completion rings carry
+/// `u64` UMEM addresses.
+pub struct XdpRing<K: Marker, T: Copy = libc::xdp_desc> {
     type_: Type,
     // Is unsound to be & or &mut because kernel at least read this pointers.
     consumer: *mut AtomicU32,
     producer: *mut AtomicU32,
-    descriptors: *mut [libc::xdp_desc],
+    // Null if the kernel does not expose ring flags.
+    flags: *mut AtomicU32,
+    descriptors: *mut [T],
     mask: u32,
     _marker: PhantomData<K>,
 }
 
-impl<K: Marker> XdpRing<K> {
+// SAFETY: The ring is a MAP_SHARED mapping that is never unmapped, and the userspace side
+// of each ring has a single owner which only touches it through `&mut self`.
+unsafe impl<K: Marker, T: Copy + Send> Send for XdpRing<K, T> {}
+
+impl<K: Marker, T: Copy> XdpRing<K, T> {
     pub fn new(
         type_: Type,
         base_ptr: *mut libc::c_void,
-        offset: libc::xdp_ring_offset_v1,
+        offset: libc::xdp_ring_offset,
         size: usize,
     ) -> Self {
         unsafe fn ptr_at<T>(base: *mut u8, offset: usize) -> *mut T {
@@ -139,13 +166,17 @@ impl<K: Marker> XdpRing<K> {
             unsafe { ptr_at::<AtomicU32>(base_ptr as *mut u8, offset.producer as usize) };
         let consumer =
             unsafe { ptr_at::<AtomicU32>(base_ptr as *mut u8, offset.consumer as usize) };
-        let desc_base =
-            unsafe { ptr_at::<libc::xdp_desc>(base_ptr as *mut u8, offset.desc as usize) };
+        let desc_base = unsafe { ptr_at::<T>(base_ptr as *mut u8, offset.desc as usize) };
+        let flags = match offset.flags {
+            0 => std::ptr::null_mut(),
+            flags => unsafe { ptr_at::<AtomicU32>(base_ptr as *mut u8, flags as usize) },
+        };
 
         Self {
             type_,
             consumer,
             producer,
+            flags,
             descriptors: std::ptr::slice_from_raw_parts_mut(desc_base, size),
             mask: (size - 1) as u32,
             _marker: Default::default(),
@@ -159,10 +190,61 @@ impl<K: Marker> XdpRing<K> {
     pub fn type_(&self) -> Type {
         self.type_
     }
+
+    /// Ring flags set by the kernel, e.g. `libc::XDP_RING_NEED_WAKEUP`. Always 0 on
+    /// kernels without ring flags.
+    pub fn flags(&self) -> u32 {
+        if self.flags.is_null() {
+            return 0;
+        }
+        unsafe { (*self.flags).load(Ordering::Acquire) }
+    }
+
+    /// Whether the kernel asks to be woken up to process the ring.
+    pub fn needs_wakeup(&self) -> bool {
+        self.flags() & libc::XDP_RING_NEED_WAKEUP != 0
+    }
+
+    pub fn raw(&self) -> RawRing {
+        RawRing {
+            type_: self.type_(),
+            producer: self.producer,
+            consumer: self.consumer,
+            flags: self.flags,
+            descriptors: self.descriptors as *mut u8,
+            size: self.size(),
+        }
+    }
 }
 
-impl XdpRing<Reader> {
-    pub fn read(&mut self) -> Option<libc::xdp_desc> {
+/// Raw view of a ring mapped from the kernel.
+///
+/// RX and TX rings hold `libc::xdp_desc` entries while fill and completion rings hold
+/// `u64` UMEM addresses. Entry `i` lives at index `i & (size - 1)`. `flags` is null on
+/// kernels without ring flags.
+#[derive(Copy, Clone)]
+pub struct RawRing {
+    pub type_: Type,
+    pub producer: *mut AtomicU32,
+    pub consumer: *mut AtomicU32,
+    pub flags: *mut AtomicU32,
+    pub descriptors: *mut u8,
+    pub size: u32,
+}
+
+impl<T: Copy> XdpRing<Reader, T> {
+    /// Number of entries produced by the kernel and not read yet.
+    pub fn available(&self) -> u32 {
+        let (c, p) = unsafe {
+            (
+                (*self.consumer).load(Ordering::Relaxed),
+                (*self.producer).load(Ordering::Acquire),
+            )
+        };
+        p.wrapping_sub(c)
+    }
+
+    pub fn read(&mut self) -> Option<T> {
         let (c, p) = unsafe {
             (
                 (*self.consumer).load(Ordering::Relaxed),
@@ -182,10 +264,76 @@ impl XdpRing<Reader> {
 
         Some(res)
     }
+
+    /// Calls `f` with every available entry and releases them with a single consumer
+    /// update, returning how many there were.
+    pub fn drain(&mut self, mut f: impl FnMut(T)) -> usize {
+        let (c, p) = unsafe {
+            (
+                (*self.consumer).load(Ordering::Relaxed),
+                (*self.producer).load(Ordering::Acquire),
+            )
+        };
+        let count = p.wrapping_sub(c);
+
+        for i in 0..count {
+            let idx = c.wrapping_add(i) & self.mask;
+            f(unsafe { (*self.descriptors)[idx as usize] });
+        }
+        unsafe { (*self.consumer).fetch_add(count, Ordering::Release) };
+
+        count as usize
+    }
+
+    /// Appends up to `max` entries to `out` with a single consumer update, returning how
+    /// many were read.
+    pub fn read_batch(&mut self, out: &mut VecDeque<T>, max: usize) -> usize {
+        let (c, p) = unsafe {
+            (
+                (*self.consumer).load(Ordering::Relaxed),
+                (*self.producer).load(Ordering::Acquire),
+            )
+        };
+        let count = (p.wrapping_sub(c) as usize).min(max);
+
+        for i in 0..count {
+            let idx = c.wrapping_add(i as u32) & self.mask;
+            out.push_back(unsafe { (*self.descriptors)[idx as usize] });
+        }
+        unsafe { (*self.consumer).fetch_add(count as u32, Ordering::Release) };
+
+        count
+    }
 }
 
-impl XdpRing<Writer> {
-    pub fn write(&mut self, desc: libc::xdp_desc) -> io::Result<()> {
+impl<T: Copy> XdpRing<Writer, T> {
+    /// Number of entries the kernel has not consumed yet.
+    pub fn pending(&self) -> u32 {
+        let (c, p) = unsafe {
+            (
+                (*self.consumer).load(Ordering::Acquire),
+                (*self.producer).load(Ordering::Relaxed),
+            )
+        };
+        p.wrapping_sub(c)
+    }
+
+    /// Writes as many entries of `descs` as fit with a single producer update, returning
+    /// how many were written.
+    pub fn write_batch(&mut self, descs: &[T]) -> usize {
+        let p = unsafe { (*self.producer).load(Ordering::Relaxed) };
+        let count = descs.len().min((self.size() - self.pending()) as usize);
+
+        for (i, desc) in descs[..count].iter().enumerate() {
+            let idx = p.wrapping_add(i as u32) & self.mask;
+            unsafe { (*self.descriptors)[idx as usize] = *desc };
+        }
+        unsafe { (*self.producer).fetch_add(count as u32, Ordering::Release) };
+
+        count
+    }
+
+    pub fn write(&mut self, desc: T) -> Result<(), XdpError> {
         let (c, p) = unsafe {
             (
                 (*self.consumer).load(Ordering::Acquire),
@@ -193,11 +341,8 @@ impl XdpRing<Writer> {
             )
         };
 
-        if (p - c) > self.mask {
-            return Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "Backpressure detected",
-            ));
+        if p.wrapping_sub(c) > self.mask {
+            return Err(XdpError::RingFull);
         }
 
         let idx = p & self.mask;
@@ -211,6 +356,7 @@ impl XdpRing<Writer> {
 }
 
 #[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config {
     pub size: usize,
 }
@@ -0,0 +1,124 @@
+//! An XDP program and XSKMAP shared by several independent processes.
+//!
+//! The program is attached once (e.g. `ip link set dev ... xdp obj ...`) with its XSKMAP
+//! pinned in bpffs. Every process then [`SharedProgram::join`]s it, registers its own
+//! sockets for a disjoint set of queues and holds a shared `flock` on a common lock file.
+//! When a process leaves it removes its map entries, and the last one out detaches the
+//! program so the interface does not keep redirecting traffic to a dead map.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, OwnedFd};
+
+use crate::phy::sys::netlink::{self, XDP_FLAGS_DRV_MODE, XDP_FLAGS_HW_MODE, XDP_FLAGS_SKB_MODE};
+use crate::phy::sys::{bpf, xdp::if_index};
+use crate::phy::xdp::AttachMode;
+use crate::phy::xdp::XdpError;
+
+pub struct SharedProgram {
+    ifindex: u32,
+    map: OwnedFd,
+    lock: File,
+    queues: Vec<u32>,
+}
+
+impl SharedProgram {
+    /// Joins the program attached to the interface called `ifname`, whose XSKMAP is
+    /// pinned at `map_path`. `lock_path` must be the same for every process of the
+    /// deployment and is created if missing.
+    pub fn join(ifname: &str, map_path: &str, lock_path: &str) -> Result<Self, XdpError> {
+        Self::join_map(ifname, bpf::obj_get(map_path)?, lock_path)
+    }
+
+    /// Joins the program attached to the interface called `ifname` through its XSKMAP
+    /// `map`, e.g. received from a privileged helper that opened the pinned map.
+    ///
+    /// Without `CAP_NET_ADMIN` the last process out cannot detach the program, which
+    /// is then left to the helper.
+    pub fn join_map(ifname: &str, map: OwnedFd, lock_path: &str) -> Result<Self, XdpError> {
+        let ifindex = if_index(ifname)?;
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(lock_path)?;
+
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_SH) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Self {
+            ifindex,
+            map,
+            lock,
+            queues: Vec::new(),
+        })
+    }
+
+    /// Redirects the traffic of `queue_id` to `socket`.
+    pub fn register(&mut self, socket: &impl AsRawFd, queue_id: u32) -> Result<(), XdpError> {
+        let fd = socket.as_raw_fd() as u32;
+        bpf::map_update_elem(self.map.as_raw_fd(), &queue_id, &fd)?;
+        if !self.queues.contains(&queue_id) {
+            self.queues.push(queue_id);
+        }
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, queue_id: u32) -> Result<(), XdpError> {
+        bpf::map_delete_elem(self.map.as_raw_fd(), &queue_id)?;
+        self.queues.retain(|queue| *queue != queue_id);
+        Ok(())
+    }
+
+    /// Atomically replaces the program `old`, attached in `mode`, with `new`, e.g. a new
+    /// build loaded against the same pinned XSKMAP, so that every process keeps its
+    /// sockets bound and registered. Fails with `AlreadyExists` if `old` is no longer the
+    /// attached program.
+    pub fn replace(
+        &self,
+        old: BorrowedFd,
+        new: BorrowedFd,
+        mode: AttachMode,
+    ) -> Result<(), XdpError> {
+        let flags = match mode {
+            AttachMode::Native => XDP_FLAGS_DRV_MODE,
+            AttachMode::Generic => XDP_FLAGS_SKB_MODE,
+            AttachMode::Offload => XDP_FLAGS_HW_MODE,
+            AttachMode::Auto => {
+                return Err(XdpError::InvalidConfig(
+                    "The mode of the attached program is needed to replace it".to_owned(),
+                ));
+            }
+        };
+        Ok(netlink::replace_xdp_fd(
+            self.ifindex,
+            new.as_raw_fd(),
+            old.as_raw_fd(),
+            flags,
+        )?)
+    }
+
+    /// Queues registered by this process.
+    pub fn queues(&self) -> &[u32] {
+        &self.queues
+    }
+}
+
+impl Drop for SharedProgram {
+    fn drop(&mut self) {
+        for queue_id in std::mem::take(&mut self.queues) {
+            let _ = bpf::map_delete_elem(self.map.as_raw_fd(), &queue_id);
+        }
+
+        // Converting the shared lock only succeeds once no other process holds it.
+        let last = unsafe { libc::flock(self.lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if last == 0 {
+            // The attach mode is unknown, so detach all of them.
+            let _ = netlink::set_xdp_fd(self.ifindex, -1, XDP_FLAGS_DRV_MODE);
+            let _ = netlink::set_xdp_fd(self.ifindex, -1, XDP_FLAGS_SKB_MODE);
+            let _ = netlink::set_xdp_fd(self.ifindex, -1, XDP_FLAGS_HW_MODE);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use smoltcp::time::{Duration, Instant};
+
+/// Smoothed rates of one traffic direction.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Rates {
+    /// Frames per second.
+    pub pps: f64,
+    /// Bits per second, counting Ethernet headers but not the FCS.
+    pub bps: f64,
+    /// Frames handled per smoltcp poll iteration that handled any.
+    pub burst: f64,
+}
+
+impl Rates {
+    pub fn gbps(&self) -> f64 {
+        self.bps / 1e9
+    }
+}
+
+/// Smoothed RX and TX rates of a socket, see [`crate::phy::xdp::XdpSocket::telemetry`].
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Telemetry {
+    pub rx: Rates,
+    pub tx: Rates,
+}
+
+/// Exponentially weighted moving averages of the rates of one direction.
+///
+/// Rates are sampled once per `window` and bursts whenever the poll timestamp changes,
+/// smoltcp passing the same timestamp to every call of a single `Interface::poll`.
+pub(crate) struct Meter {
+    window: Duration,
+    alpha: f64,
+    rates: Rates,
+    window_start: Option<Instant>,
+    frames: u64,
+    bytes: u64,
+    burst_epoch: Option<Instant>,
+    burst: u64,
+}
+
+impl Meter {
+    pub(crate) fn new(window: Duration, alpha: f64) -> Self {
+        Self {
+            window,
+            alpha,
+            rates: Rates::default(),
+            window_start: None,
+            frames: 0,
+            bytes: 0,
+            burst_epoch: None,
+            burst: 0,
+        }
+    }
+
+    pub(crate) fn configure(&mut self, window: Duration, alpha: f64) {
+        self.window = window;
+        self.alpha = alpha;
+    }
+
+    /// Accounts one frame of `len` bytes handled at `now`.
+    pub(crate) fn record(&mut self, now: Instant, len: usize) {
+        self.tick(now);
+        self.frames += 1;
+        self.bytes += len as u64;
+        self.burst += 1;
+    }
+
+    /// Closes the window and the burst they are over at `now`. Called on every poll so
+    /// that rates decay once traffic stops.
+    pub(crate) fn tick(&mut self, now: Instant) {
+        if self.burst_epoch != Some(now) {
+            if self.burst > 0 {
+                self.rates.burst = self.smooth(self.rates.burst, self.burst as f64);
+                self.burst = 0;
+            }
+            self.burst_epoch = Some(now);
+        }
+
+        let start = *self.window_start.get_or_insert(now);
+        if now < start + self.window || now == start {
+            return;
+        }
+
+        let secs = (now - start).total_micros() as f64 / 1e6;
+        self.rates.pps = self.smooth(self.rates.pps, self.frames as f64 / secs);
+        self.rates.bps = self.smooth(self.rates.bps, (self.bytes * 8) as f64 / secs);
+        self.frames = 0;
+        self.bytes = 0;
+        self.window_start = Some(now);
+    }
+
+    pub(crate) fn rates(&self) -> Rates {
+        self.rates
+    }
+
+    fn smooth(&self, average: f64, sample: f64) -> f64 {
+        average + self.alpha * (sample - average)
+    }
+}
+
+/// Cost counters of a socket, see [`crate::phy::xdp::XdpSocket::set_profiling`].
+///
+/// A poll cycle spans the device calls of one `Interface::poll`, recognised by their
+/// shared timestamp, and its time is the wall-clock time spent inside them.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Profile {
+    /// `sendto` calls kicking the kernel to process the TX ring.
+    pub tx_kicks: u64,
+    /// `recvfrom` calls kicking the kernel to process the fill ring or busy poll.
+    pub rx_kicks: u64,
+    /// Receive calls that found the RX ring empty.
+    pub busy_iterations: u64,
+    /// Poll cycles completed.
+    pub polls: u64,
+    /// Time spent in the completed poll cycles.
+    pub poll_time: std::time::Duration,
+    /// Longest completed poll cycle.
+    pub max_poll_time: std::time::Duration,
+}
+
+impl Profile {
+    pub fn syscalls(&self) -> u64 {
+        self.tx_kicks + self.rx_kicks
+    }
+
+    pub fn mean_poll_time(&self) -> std::time::Duration {
+        match u32::try_from(self.polls) {
+            Ok(0) => std::time::Duration::ZERO,
+            Ok(polls) => self.poll_time / polls,
+            Err(_) => {
+                std::time::Duration::from_secs_f64(self.poll_time.as_secs_f64() / self.polls as f64)
+            }
+        }
+    }
+}
+
+/// Accumulates a [`Profile`] while enabled, costing nothing otherwise.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    enabled: bool,
+    profile: Profile,
+    epoch: Option<Instant>,
+    // Time spent in the device calls of the current poll cycle.
+    cycle: std::time::Duration,
+}
+
+impl Profiler {
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.epoch = None;
+        self.cycle = std::time::Duration::ZERO;
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.profile = Profile::default();
+    }
+
+    /// Starts timing a device call of the poll cycle at `now`, closing the previous cycle
+    /// if `now` started a new one.
+    pub(crate) fn enter(&mut self, now: Instant) -> Option<std::time::Instant> {
+        if !self.enabled {
+            return None;
+        }
+        if self.epoch != Some(now) {
+            if self.epoch.is_some() {
+                self.profile.polls += 1;
+                self.profile.poll_time += self.cycle;
+                self.profile.max_poll_time = self.profile.max_poll_time.max(self.cycle);
+            }
+            self.epoch = Some(now);
+            self.cycle = std::time::Duration::ZERO;
+        }
+        Some(std::time::Instant::now())
+    }
+
+    /// Ends timing a device call started by [`Profiler::enter`].
+    pub(crate) fn leave(&mut self, started: Option<std::time::Instant>) {
+        if let Some(started) = started {
+            self.cycle += started.elapsed();
+        }
+    }
+
+    pub(crate) fn tx_kick(&mut self) {
+        if self.enabled {
+            self.profile.tx_kicks += 1;
+        }
+    }
+
+    pub(crate) fn rx_kick(&mut self) {
+        if self.enabled {
+            self.profile.rx_kicks += 1;
+        }
+    }
+
+    pub(crate) fn busy_iteration(&mut self) {
+        if self.enabled {
+            self.profile.busy_iterations += 1;
+        }
+    }
+}
+
+/// Drop and error counters kept by the kernel for a socket, see
+/// [`crate::phy::xdp::XdpSocket::kernel_stats`].
+///
+/// Kernels older than 5.9 only report the first three, the others read 0.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct KernelStats {
+    /// Frames dropped for reasons other than invalid descriptors, e.g. no fill buffer.
+    pub rx_dropped: u64,
+    /// Invalid descriptors found in the fill ring.
+    pub rx_invalid_descs: u64,
+    /// Invalid descriptors found in the TX ring.
+    pub tx_invalid_descs: u64,
+    /// Frames dropped because the RX ring was full.
+    pub rx_ring_full: u64,
+    /// Times the fill ring was found empty.
+    pub rx_fill_ring_empty_descs: u64,
+    /// Times the TX ring was found empty while the kernel was woken up.
+    pub tx_ring_empty_descs: u64,
+}
+
+impl From<libc::xdp_statistics> for KernelStats {
+    fn from(stats: libc::xdp_statistics) -> Self {
+        Self {
+            rx_dropped: stats.rx_dropped,
+            rx_invalid_descs: stats.rx_invalid_descs,
+            tx_invalid_descs: stats.tx_invalid_descs,
+            rx_ring_full: stats.rx_ring_full,
+            rx_fill_ring_empty_descs: stats.rx_fill_ring_empty_descs,
+            tx_ring_empty_descs: stats.tx_ring_empty_descs,
+        }
+    }
+}
+
+/// Counters kept by a socket in userspace, see [`crate::phy::xdp::XdpSocket::stats`].
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Stats {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    /// RX frames reassembled from several descriptors, see `xdp::Config::multi_buffer`.
+    pub rx_multi_buffer: u64,
+    pub tx_packets: u64,
+    /// Bytes queued for TX, padding included.
+    pub tx_bytes: u64,
+    /// TX frames dropped because the TX ring was full.
+    pub tx_ring_full: u64,
+    /// TX frames dropped because no UMEM page was free.
+    pub tx_no_buffer: u64,
+    /// TX tokens refused, see `Device::transmit`, because the TX ring was full, no UMEM
+    /// page outside the control-plane reserve was free or the TX quota was reached.
+    pub tx_backpressure: u64,
+    /// TX frames dropped on an unexpected error, see
+    /// [`crate::phy::xdp::XdpSocket::take_tx_error`].
+    pub tx_errors: u64,
+    /// TX frames split across several descriptors, see `xdp::Config::multi_buffer`.
+    pub tx_multi_buffer: u64,
+    /// TX frames dropped because they do not fit in a chunk and the socket is not bound
+    /// for multi-buffer frames.
+    pub tx_too_long: u64,
+    /// RX frames handed back to the fill ring.
+    pub fill_recycled: u64,
+    /// TX frames reclaimed from the completion ring.
+    pub completed: u64,
+    /// RX frames dropped by the verdict hook, see
+    /// [`crate::phy::xdp::XdpSocket::set_verdict`].
+    pub verdict_dropped: u64,
+    /// RX frames the verdict hook queued for the application.
+    pub verdict_queued: u64,
+    /// RX frames dropped because the application queue was full.
+    pub queue_full: u64,
+    /// RX frames handed without a copy to the TX ring of another socket, see
+    /// [`crate::phy::xdp::XdpSocket::forward`].
+    pub forwarded: u64,
+}
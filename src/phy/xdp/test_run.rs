@@ -0,0 +1,84 @@
+//! Software injection of frames into an XDP program through `BPF_PROG_TEST_RUN`.
+//!
+//! This exercises the eBPF side of a deployment without a NIC, e.g. in CI: pin the
+//! program (`bpftool prog pin ...`), open it with [`open_pinned`] and assert on the
+//! [`Action`] taken for crafted frames.
+
+use std::os::fd::{OwnedFd, RawFd};
+use std::time::Duration;
+
+use crate::phy::sys::bpf::{self, XdpMd};
+use crate::phy::xdp::XdpError;
+
+/// Verdict returned by an XDP program.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    Aborted,
+    Drop,
+    Pass,
+    Tx,
+    Redirect,
+    Unknown(u32),
+}
+
+impl From<u32> for Action {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::Aborted,
+            1 => Self::Drop,
+            2 => Self::Pass,
+            3 => Self::Tx,
+            4 => Self::Redirect,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+/// Outcome of a single test run.
+pub struct TestRun {
+    pub action: Action,
+    /// Frame as left by the program.
+    pub data: Vec<u8>,
+    pub duration: Duration,
+}
+
+/// Opens a program or map pinned in bpffs.
+pub fn open_pinned(path: &str) -> Result<OwnedFd, XdpError> {
+    Ok(bpf::obj_get(path)?)
+}
+
+/// Runs the XDP program `prog_fd` over `frame` as if it was received on queue `queue_id`
+/// of interface `ifindex`.
+///
+/// `ifindex` must name an existing interface with at least `queue_id + 1` RX queues, or
+/// be 0 to skip the ingress device altogether (then `queue_id` must be 0 as well).
+pub fn run(prog_fd: RawFd, frame: &[u8], ifindex: u32, queue_id: u32) -> Result<TestRun, XdpError> {
+    let ctx = XdpMd {
+        data_end: frame.len() as u32,
+        ingress_ifindex: ifindex,
+        rx_queue_index: queue_id,
+        ..Default::default()
+    };
+
+    let output = bpf::prog_test_run(prog_fd, frame, &ctx)?;
+    Ok(TestRun {
+        action: output.retval.into(),
+        data: output.data,
+        duration: Duration::from_nanos(output.duration_ns.into()),
+    })
+}
+
+/// Runs the XDP program `prog_fd` over `frame` like [`run`], carrying out its verdict:
+/// a redirected frame reaches the socket registered for `queue_id` in the XSKMAP.
+///
+/// Needs Linux 5.18 (`BPF_F_TEST_XDP_LIVE_FRAMES`), and `ifindex` must name an
+/// existing interface. The verdict itself is not reported.
+pub fn inject(prog_fd: RawFd, frame: &[u8], ifindex: u32, queue_id: u32) -> Result<(), XdpError> {
+    let ctx = XdpMd {
+        data_end: frame.len() as u32,
+        ingress_ifindex: ifindex,
+        rx_queue_index: queue_id,
+        ..Default::default()
+    };
+    Ok(bpf::prog_test_run_live(prog_fd, frame, &ctx)?)
+}
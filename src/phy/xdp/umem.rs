@@ -1,69 +1,263 @@
-use std::{alloc::Layout, io, mem::ManuallyDrop};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::{alloc::Layout, io, mem, mem::ManuallyDrop};
+
+#[cfg(feature = "audit")]
+use crate::phy::xdp::audit::Audit;
+use crate::phy::xdp::audit::Op;
+use crate::phy::xdp::{ETHERNET_HEADER_LEN, XDP_PACKET_HEADROOM, XdpError};
+
+/// Page ids are `u32`, with `u32::MAX` ending the intrusive free list.
+pub(crate) const MAX_ENTRIES: usize = u32::MAX as usize;
+/// Room of the `struct xsk_tx_metadata` in front of TX frames.
+pub(crate) const TX_METADATA_LEN: usize = mem::size_of::<libc::xsk_tx_metadata>();
+const VLAN_TAG_LEN: usize = 4;
 
 pub struct Umem<'a> {
     base_addr: usize,
     pages: Box<[ManuallyDrop<UmemPage<'a>>]>,
     alignment: usize,
-    free_page_id: Option<u16>,
+    // Bytes reserved at the start of every chunk, the intrusive free list and the TX
+    // metadata included.
+    headroom: usize,
+    // Registered with the kernel, 0 or `TX_METADATA_LEN`.
+    tx_metadata_len: usize,
+    // Registered with XDP_UMEM_UNALIGNED_CHUNK_FLAG.
+    unaligned: bool,
+    free: Free,
+    // Pages set aside for control-plane TX frames, refilled before the free list.
+    priority: Vec<u32>,
+    // Pages the reserve holds when none is in flight.
+    priority_len: usize,
+    mapping: Mapping,
+    #[cfg(feature = "audit")]
+    audit: Audit,
+}
+
+/// Pages available for TX.
+enum Free {
+    // Head of the list threaded through the page headrooms.
+    Intrusive(Option<u32>),
+    // Stack of page ids, the next one on top.
+    External(Vec<u32>),
+}
+
+/// Memory the area was actually allocated from, released on drop.
+struct Mapping {
+    backing: Backing,
+    len: usize,
+    memfd: Option<OwnedFd>,
 }
 
+// SAFETY: The pages point into the area, which the UMEM owns or borrows mutably for
+// `'a`, and are only reached through the UMEM, so it moves to another thread with them.
+unsafe impl Send for Umem<'_> {}
+
 impl<'a> Drop for Umem<'a> {
     fn drop(&mut self) {
-        let layout =
-            Layout::from_size_align(self.alignment * self.pages.len(), self.alignment.into())
-                .expect("Alignment and Size are always valid");
-        unsafe {
-            std::alloc::dealloc(self.base_addr as *mut u8, layout);
+        match self.mapping.backing {
+            Backing::Heap | Backing::Sanitize => {
+                let layout =
+                    Layout::from_size_align(self.alignment * self.pages.len(), self.alignment)
+                        .expect("Alignment and Size are always valid");
+                unsafe {
+                    std::alloc::dealloc(self.base_addr as *mut u8, layout);
+                }
+            }
+            Backing::HugePages(_) | Backing::Memfd => unsafe {
+                libc::munmap(self.base_addr as *mut libc::c_void, self.mapping.len);
+            },
+            // Owned by the application.
+            Backing::User => {}
         }
     }
 }
 
 impl<'a> Umem<'a> {
-    pub fn new(config: Config) -> io::Result<Self> {
+    pub fn new(config: Config) -> Result<Self, XdpError> {
+        if config.entries == 0 || config.entries > MAX_ENTRIES {
+            return Err(XdpError::InvalidConfig(format!(
+                "A UMEM holds between 1 and {} entries",
+                MAX_ENTRIES
+            )));
+        }
         let layout = Layout::from_size_align(
             config.entries * usize::from(config.alignment),
             config.alignment.into(),
         )
-        .map_err(|_| {
-            io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Entries or Alignment are wrong",
-            )
-        })?;
+        .map_err(|_| XdpError::InvalidConfig("Entries or Alignment are wrong".to_owned()))?;
+        let tx_metadata_len = if config.tx_metadata {
+            TX_METADATA_LEN
+        } else {
+            0
+        };
+        let headroom = config.free_list.reserved() + config.headroom + tx_metadata_len;
+        // The kernel rejects headrooms leaving no room for its own.
+        if headroom + super::XDP_PACKET_HEADROOM >= usize::from(config.alignment) {
+            return Err(XdpError::InvalidConfig(format!(
+                "A headroom of {} bytes does not fit in {} byte chunks",
+                config.headroom,
+                usize::from(config.alignment)
+            )));
+        }
 
-        // SAFETY: Umem alloc is not null nor uninitialized. Also all inner values are interpretable as [0;N]
-        let umem_ptr = unsafe {
-            let ptr = std::alloc::alloc(layout);
-            if ptr.is_null() {
-                return Err(io::Error::last_os_error());
+        let mut memfd = None;
+        let mapping = match config.backing {
+            Backing::Heap | Backing::Sanitize => None,
+            Backing::User => {
+                return Err(XdpError::InvalidConfig(
+                    "Application memory is handed over with Umem::from_raw_parts".to_owned(),
+                ));
+            }
+            Backing::HugePages(size) => map_huge_pages(layout.size(), size),
+            Backing::Memfd => {
+                let (fd, ptr) = map_memfd(layout.size())?;
+                memfd = Some(fd);
+                Some((ptr, layout.size()))
             }
-            std::ptr::write_bytes(ptr, 0, usize::from(config.alignment) * config.entries);
-            ptr
         };
+        let (umem_ptr, backing, mapped_len) = match mapping {
+            // Anonymous and fresh memfd mappings are already zeroed.
+            Some((ptr, len)) => (ptr, config.backing, len),
+            // SAFETY: Umem alloc is not null nor uninitialized. Also all inner values are interpretable as [0;N]
+            None => unsafe {
+                let ptr = std::alloc::alloc(layout);
+                if ptr.is_null() {
+                    return Err(io::Error::last_os_error().into());
+                }
+                std::ptr::write_bytes(ptr, 0, usize::from(config.alignment) * config.entries);
+                let backing = match config.backing {
+                    Backing::Sanitize => Backing::Sanitize,
+                    _ => Backing::Heap,
+                };
+                (ptr, backing, layout.size())
+            },
+        };
+
+        let mapping = Mapping {
+            backing,
+            len: mapped_len,
+            memfd,
+        };
+        // SAFETY: the area was just allocated with room for every entry.
+        let mut umem = unsafe {
+            Self::from_area(
+                umem_ptr,
+                config.entries,
+                config.alignment,
+                config.headroom + tx_metadata_len,
+                config.free_list,
+                mapping,
+            )
+        };
+        umem.unaligned = config.unaligned;
+        umem.tx_metadata_len = tx_metadata_len;
+        Ok(umem)
+    }
+
+    /// Builds a UMEM over `len` bytes of application memory starting at `ptr`, split in
+    /// chunks of `chunk_size`. Trailing bytes that do not fill a chunk are left unused.
+    ///
+    /// The UMEM never frees the memory, see `Backing::User`.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` must be valid for reads and writes of `len` bytes until the UMEM and every
+    ///   socket using it are dropped.
+    /// - Nothing else may access the memory in that time: the first bytes of every chunk
+    ///   hold the free list and the rest are written by the kernel.
+    pub unsafe fn from_raw_parts(
+        ptr: *mut u8,
+        len: usize,
+        chunk_size: ChunkAlignment,
+    ) -> Result<Self, XdpError> {
+        let entries = len / usize::from(chunk_size);
+        // The kernel pins the area page by page.
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        if ptr.is_null() || !ptr.addr().is_multiple_of(page) {
+            return Err(XdpError::InvalidConfig(
+                "UMEM memory must be page aligned".to_owned(),
+            ));
+        }
+        if entries == 0 || entries > MAX_ENTRIES {
+            return Err(XdpError::InvalidConfig(format!(
+                "UMEM memory must hold between 1 and {} chunks",
+                MAX_ENTRIES
+            )));
+        }
 
-        let mut pages = Vec::with_capacity(config.entries);
+        let mapping = Mapping {
+            backing: Backing::User,
+            len,
+            memfd: None,
+        };
+        // SAFETY: upheld by the caller.
+        Ok(unsafe { Self::from_area(ptr, entries, chunk_size, 0, FreeList::Intrusive, mapping) })
+    }
+
+    /// Splits the area at `ptr` in `entries` chunks, all of them free, leaving `headroom`
+    /// bytes in front of every frame.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for `entries` chunks of `alignment` bytes.
+    unsafe fn from_area(
+        ptr: *mut u8,
+        entries: usize,
+        alignment: ChunkAlignment,
+        headroom: usize,
+        free_list: FreeList,
+        mapping: Mapping,
+    ) -> Self {
+        let intrusive = free_list == FreeList::Intrusive;
+        let mut pages = Vec::with_capacity(entries);
         // Free Pages Initialization
-        for i in 0..config.entries {
+        for i in 0..entries {
             let mut page = unsafe {
-                let page_ptr = umem_ptr.add(i * usize::from(config.alignment));
-                UmemPage::from(page_ptr, config.alignment.into())
+                let page_ptr = ptr.add(i * usize::from(alignment));
+                UmemPage::from(page_ptr, alignment.into(), intrusive)
             };
 
-            let free_page_id = if i == config.entries - 1 {
+            let free_page_id = if i == entries - 1 {
                 None
             } else {
-                Some((i + 1) as u16)
+                Some((i + 1) as u32)
             };
 
-            page.headroom_mut().set_free_page_id(free_page_id);
+            if let Some(h) = page.h.as_mut() {
+                h.set_free_page_id(free_page_id);
+            }
             pages.push(ManuallyDrop::new(page));
         }
-        Ok(Self {
-            base_addr: umem_ptr.addr(),
+        let free = match free_list {
+            FreeList::Intrusive => Free::Intrusive(Some(0)),
+            FreeList::External => Free::External((0..entries as u32).rev().collect()),
+        };
+        Self {
+            base_addr: ptr.addr(),
             pages: pages.into_boxed_slice(),
-            alignment: config.alignment.into(),
-            free_page_id: Some(0),
-        })
+            alignment: alignment.into(),
+            headroom: free_list.reserved() + headroom,
+            tx_metadata_len: 0,
+            unaligned: false,
+            free,
+            priority: Vec::new(),
+            priority_len: 0,
+            mapping,
+            #[cfg(feature = "audit")]
+            audit: Audit::new(entries),
+        }
+    }
+
+    /// The memfd backing the area with `Backing::Memfd`, which other processes can map to
+    /// share the frames.
+    pub fn memfd(&self) -> Option<RawFd> {
+        self.mapping.memfd.as_ref().map(AsRawFd::as_raw_fd)
+    }
+
+    /// Backing the area was allocated with, `Backing::Heap` when huge pages were
+    /// requested but none were available.
+    pub fn backing(&self) -> Backing {
+        self.mapping.backing
     }
 
     pub fn base_addr(&self) -> usize {
@@ -78,93 +272,379 @@ impl<'a> Umem<'a> {
         self.alignment
     }
 
-    pub fn read(&self, page_id: usize) -> &UmemPage<'_> {
+    /// Bytes reserved at the start of every chunk, `Config::headroom` plus the crate's
+    /// own bookkeeping with `FreeList::Intrusive` and the TX metadata with
+    /// `Config::tx_metadata`.
+    pub fn headroom(&self) -> usize {
+        self.headroom
+    }
+
+    /// Whether descriptors use the unaligned addressing scheme, see `Config::unaligned`.
+    pub fn unaligned(&self) -> bool {
+        self.unaligned
+    }
+
+    /// Bytes of TX metadata in front of every TX frame, see `Config::tx_metadata`.
+    pub fn tx_metadata_len(&self) -> usize {
+        self.tx_metadata_len
+    }
+
+    /// Offset of the data a descriptor address points to. In unaligned mode the kernel
+    /// carries the offset of the data inside the buffer in the upper 16 bits.
+    pub fn resolve(&self, addr: u64) -> u64 {
+        if self.unaligned {
+            (addr & libc::XSK_UNALIGNED_BUF_ADDR_MASK)
+                + (addr >> libc::XSK_UNALIGNED_BUF_OFFSET_SHIFT)
+        } else {
+            addr
+        }
+    }
+
+    pub fn raw(&self) -> RawUmem {
+        RawUmem {
+            base: self.base_addr as *mut u8,
+            len: self.size() * self.alignment,
+            chunk_size: self.alignment,
+            headroom: self.headroom(),
+            unaligned: self.unaligned,
+        }
+    }
+
+    /// Records the hand-over of `page_id` with the `audit` feature, which panics when the
+    /// page is not owned as `op` expects.
+    fn track(&mut self, page_id: usize, op: Op) {
+        #[cfg(feature = "audit")]
+        self.audit.apply(page_id, op);
+        #[cfg(not(feature = "audit"))]
+        let _ = (page_id, op);
+    }
+
+    /// Panics with the `audit` feature unless userspace owns `page_id`.
+    fn check(&self, page_id: usize, access: &str) {
+        #[cfg(feature = "audit")]
+        self.audit.check(page_id, access);
+        #[cfg(not(feature = "audit"))]
+        let _ = (page_id, access);
+    }
+
+    /// Takes the page of a descriptor read from the RX ring.
+    pub(crate) fn received(&mut self, page_id: usize) {
+        self.track(page_id, Op::Receive);
+    }
+
+    /// Hands the page of a descriptor queued on the TX ring to the kernel.
+    pub(crate) fn transmitted(&mut self, page_id: usize) {
+        self.track(page_id, Op::Transmit);
+    }
+
+    /// Hands the page to the kernel for RX, returning its address for the fill ring.
+    pub(crate) fn fill(&mut self, page_id: usize) -> u64 {
+        self.track(page_id, Op::Fill);
+        self.fill_addr(page_id)
+    }
+
+    /// Returns the page at the fill ring address `addr`, recycled but never posted, to
+    /// the free list.
+    pub(crate) fn unfill(&mut self, addr: u64) {
+        let page_id = self.page_id_from(addr);
+        self.track(page_id, Op::Unfill);
+        self.free(page_id);
+    }
+
+    pub(crate) fn read(&self, page_id: usize) -> &UmemPage<'_> {
+        self.check(page_id, "read");
         &self.pages[page_id]
     }
 
+    /// With `Backing::Sanitize`, copies a received chunk out of the area into its own
+    /// allocation, which `read` then sees until `release`.
+    pub(crate) fn acquire(&mut self, page_id: usize) {
+        if self.mapping.backing == Backing::Sanitize {
+            self.pages[page_id].attach_shadow();
+        }
+    }
+
+    /// Frees the allocation made by `acquire`, if any.
+    pub(crate) fn release(&mut self, page_id: usize) {
+        self.pages[page_id].detach_shadow(false);
+    }
+
+    /// The received frame `desc` points to, to be edited in place before being handed to
+    /// the TX ring. With `Backing::Sanitize` the edits go to a copy written back by
+    /// `commit`.
+    pub(crate) fn packet_mut(&mut self, page_id: usize, desc: libc::xdp_desc) -> &mut [u8] {
+        self.check(page_id, "edited");
+        self.acquire(page_id);
+        self.pages[page_id].read_packet_mut(desc)
+    }
+
+    /// Writes back the edits made through `packet_mut`, if any.
+    pub(crate) fn commit(&mut self, page_id: usize) {
+        self.pages[page_id].detach_shadow(true);
+    }
+
     fn read_mut<'b>(&'b mut self, page_id: usize) -> &'b mut UmemPage<'a>
     where
         'a: 'b,
     {
+        self.check(page_id, "written");
         &mut self.pages[page_id]
     }
 
-    pub fn page_id_from(&self, desc: libc::xdp_desc) -> usize {
-        desc.addr as usize / self.alignment
+    pub(crate) fn page_id_from(&self, addr: u64) -> usize {
+        self.resolve(addr) as usize / self.alignment
+    }
+
+    /// Address of the page as posted to the fill ring, its start: the kernel adds the
+    /// headroom itself, and only masks the address down to the chunk in aligned mode.
+    fn fill_addr(&self, page_id: usize) -> u64 {
+        (page_id * self.alignment) as u64
     }
 
     fn desc_addr_from(&self, page_id: usize) -> usize {
-        (page_id * self.alignment) + std::mem::size_of::<HeadRoom>()
+        (page_id * self.alignment) + self.headroom
     }
 
-    pub fn free(&mut self, page_id: usize) -> libc::xdp_desc {
-        let last_free_page_id = self.free_page_id;
-        let page = self.read_mut(page_id);
-        if last_free_page_id.is_some() {
-            page.headroom_mut().set_free_page_id(last_free_page_id);
+    pub(crate) fn free(&mut self, page_id: usize) {
+        if self.priority.len() < self.priority_len {
+            self.track(page_id, Op::Reserve);
+            self.priority.push(page_id as u32);
+            return;
         }
+        self.track(page_id, Op::Free);
+        match &mut self.free {
+            Free::Intrusive(head) => {
+                let last_free_page_id = head.replace(page_id as u32);
+                self.pages[page_id]
+                    .headroom_mut()
+                    .set_free_page_id(last_free_page_id);
+            }
+            Free::External(stack) => stack.push(page_id as u32),
+        }
+    }
 
-        self.free_page_id = Some(page_id as u16);
-
-        libc::xdp_desc {
-            addr: self.desc_addr_from(page_id) as u64,
-            len: (self.alignment - std::mem::size_of::<HeadRoom>()) as u32,
-            options: 0,
+    /// Whether a page is available for TX.
+    pub(crate) fn has_free(&self) -> bool {
+        match &self.free {
+            Free::Intrusive(head) => head.is_some(),
+            Free::External(stack) => !stack.is_empty(),
         }
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> io::Result<libc::xdp_desc> {
-        let (page, id) = if let Some(val) = self.free_page_id {
-            (self.read_mut(val as usize), val)
-        } else {
-            return Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "No free page available",
-            ));
+    /// Takes a page out of the free list.
+    pub(crate) fn alloc(&mut self) -> Option<usize> {
+        let id = match &mut self.free {
+            Free::Intrusive(head) => {
+                let id = (*head)? as usize;
+                let page = &mut self.pages[id];
+                *head = page.headroom().free_page_id();
+                page.headroom_mut().set_free_page_id(None);
+                id
+            }
+            Free::External(stack) => stack.pop()? as usize,
         };
+        self.track(id, Op::Alloc);
+        Some(id)
+    }
+
+    /// Moves up to `count` pages from the free list to the reserve of control-plane
+    /// frames, returning how many were moved.
+    pub(crate) fn reserve_priority(&mut self, count: usize) -> usize {
+        let mut moved = 0;
+        while moved < count {
+            let Some(page_id) = self.alloc() else {
+                break;
+            };
+            self.track(page_id, Op::Reserve);
+            self.priority.push(page_id as u32);
+            moved += 1;
+        }
+        self.priority_len += moved;
+        moved
+    }
+
+    /// Takes a page out of the free list, or out of the reserve for `priority` frames
+    /// once the free list is empty.
+    fn alloc_for(&mut self, priority: bool) -> Option<usize> {
+        match self.alloc() {
+            Some(page_id) => Some(page_id),
+            None if priority => {
+                let page_id = self.priority.pop()? as usize;
+                self.track(page_id, Op::Alloc);
+                Some(page_id)
+            }
+            None => None,
+        }
+    }
+
+    /// Takes up to `count` pages out of the free list for the fill ring, returning their
+    /// addresses.
+    pub(crate) fn reserve(&mut self, count: usize) -> Vec<u64> {
+        let mut addrs = Vec::with_capacity(count);
+        while addrs.len() < count {
+            let Some(page_id) = self.alloc() else {
+                break;
+            };
+            addrs.push(self.fill(page_id));
+        }
+        addrs
+    }
+
+    /// Requests the NIC to checksum the frame written to `page_id`, from `start` bytes
+    /// into the frame and storing the result `offset` bytes further. The descriptor
+    /// must carry `XDP_TX_METADATA`.
+    pub(crate) fn request_checksum(&mut self, page_id: usize, start: u16, offset: u16) {
+        debug_assert_eq!(self.tx_metadata_len, TX_METADATA_LEN);
+        let mut meta = [0u8; TX_METADATA_LEN];
+        meta[..8].copy_from_slice(&u64::from(libc::XDP_TXMD_FLAGS_CHECKSUM).to_ne_bytes());
+        meta[8..10].copy_from_slice(&start.to_ne_bytes());
+        meta[10..12].copy_from_slice(&offset.to_ne_bytes());
+        let at = self.headroom - TX_METADATA_LEN;
+        self.read_mut(page_id).write_packet(at, &meta);
+    }
+
+    /// Bytes of a TX frame a single chunk holds after the headroom.
+    pub(crate) fn frame_room(&self) -> usize {
+        self.alignment - self.headroom
+    }
+
+    /// Writes `buf` across as many pages as it needs into `descs`, every descriptor but
+    /// the last one carrying `XDP_PKT_CONTD`. Nothing is taken when the pages run out.
+    pub(crate) fn write_chain(
+        &mut self,
+        buf: &[u8],
+        priority: bool,
+        descs: &mut Vec<libc::xdp_desc>,
+    ) -> Result<(), XdpError> {
+        descs.clear();
+        for fragment in buf.chunks(self.frame_room()) {
+            match self.write(fragment, priority) {
+                Ok(desc) => descs.push(libc::xdp_desc {
+                    options: libc::XDP_PKT_CONTD,
+                    ..desc
+                }),
+                Err(err) => {
+                    for desc in descs.drain(..) {
+                        let page_id = self.page_id_from(desc.addr);
+                        self.free(page_id);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        if let Some(last) = descs.last_mut() {
+            last.options = 0;
+        }
+        Ok(())
+    }
 
-        let next_free_page_id = page.headroom().free_page_id();
-        page.headroom_mut().set_free_page_id(None);
-        page.write_packet(buf);
+    /// Writes `buf` to a free page, `priority` frames falling back to the reserve.
+    pub(crate) fn write(&mut self, buf: &[u8], priority: bool) -> Result<libc::xdp_desc, XdpError> {
+        debug_assert!(
+            buf.len() <= self.frame_room(),
+            "Frames larger than a chunk are chained"
+        );
+        let Some(id) = self.alloc_for(priority) else {
+            return Err(XdpError::NoFreeFrames);
+        };
 
-        self.free_page_id = next_free_page_id;
+        let offset = self.headroom;
+        let sanitize = self.mapping.backing == Backing::Sanitize;
+        let page = self.read_mut(id);
+        if sanitize {
+            page.attach_shadow();
+        }
+        page.write_packet(offset, buf);
+        page.detach_shadow(true);
 
         Ok(libc::xdp_desc {
-            addr: self.desc_addr_from(id as usize) as u64,
+            addr: self.desc_addr_from(id) as u64,
             len: buf.len() as u32,
             options: 0,
         })
     }
+}
 
-    pub fn packet_descriptors(&self) -> Vec<libc::xdp_desc> {
-        (0..self.pages.len())
-            .map(|page_id| libc::xdp_desc {
-                addr: self.desc_addr_from(page_id) as u64,
-                len: (self.alignment - std::mem::size_of::<HeadRoom>()) as u32,
-                options: 0,
-            })
-            .collect()
-    }
+/// Raw view of the UMEM area registered with the kernel.
+///
+/// The area is `len` bytes starting at `base`, split in chunks of `chunk_size` bytes.
+/// The first `headroom` bytes of every chunk are reserved by the crate. With `unaligned`
+/// descriptor addresses have to go through `Umem::resolve`.
+#[derive(Copy, Clone)]
+pub struct RawUmem {
+    pub base: *mut u8,
+    pub len: usize,
+    pub chunk_size: usize,
+    pub headroom: usize,
+    pub unaligned: bool,
 }
 
 pub struct UmemPage<'a> {
-    // Exclusive access from one userspace Thread. No interaction with the kernel.
-    h: &'a mut HeadRoom,
-    // Shared read-write access from userspace and kernel.
+    // Exclusive access from one userspace Thread. No interaction with the kernel. Only
+    // with the intrusive free list.
+    h: Option<&'a mut HeadRoom>,
+    // Shared read-write access from userspace and kernel, the chunk past `h`.
+    area: *mut [u8],
+    // With `Backing::Sanitize`, a copy of `area` userspace works on while attached.
+    shadow: Option<Box<[u8]>>,
+    // The memory read and written, `shadow` if attached and `area` otherwise.
     buffer: *mut [u8],
 }
 
 impl UmemPage<'_> {
-    unsafe fn from(ptr: *mut u8, len: usize) -> Self {
+    unsafe fn from(ptr: *mut u8, len: usize, intrusive: bool) -> Self {
+        if !intrusive {
+            let buffer = std::ptr::slice_from_raw_parts_mut(ptr, len);
+            return Self {
+                h: None,
+                area: buffer,
+                shadow: None,
+                buffer,
+            };
+        }
+
         let h = unsafe {
             (ptr as *mut HeadRoom)
                 .as_mut()
                 .expect("Always initialized with valid memory type")
         };
-        let ptr = unsafe { ptr.add(std::mem::size_of::<HeadRoom>()) };
-        let len = len - std::mem::size_of::<HeadRoom>();
+        let ptr = unsafe { ptr.add(mem::size_of::<HeadRoom>()) };
+        let len = len - mem::size_of::<HeadRoom>();
         let buffer = std::ptr::slice_from_raw_parts_mut(ptr, len);
-        Self { h, buffer }
+        Self {
+            h: Some(h),
+            area: buffer,
+            shadow: None,
+            buffer,
+        }
+    }
+
+    fn attach_shadow(&mut self) {
+        // SAFETY: UmemPage lives as long as Umem.
+        let mut shadow = Box::<[u8]>::from(unsafe { &*self.area });
+        self.buffer = std::ptr::from_mut(&mut *shadow);
+        self.shadow = Some(shadow);
+    }
+
+    /// Goes back to the area, copying the shadow into it first with `write_back`.
+    fn detach_shadow(&mut self, write_back: bool) {
+        let Some(shadow) = self.shadow.take() else {
+            return;
+        };
+        if write_back {
+            // SAFETY: UmemPage lives as long as Umem.
+            unsafe { (*self.area).copy_from_slice(&shadow) };
+        }
+        self.buffer = self.area;
+    }
+
+    // Bytes taken by the free list at the start of the chunk.
+    fn reserved(&self) -> usize {
+        if self.h.is_some() {
+            mem::size_of::<HeadRoom>()
+        } else {
+            0
+        }
     }
 
     fn buffer(&self) -> &[u8] {
@@ -173,52 +653,215 @@ impl UmemPage<'_> {
     }
 
     pub fn read_packet(&self, desc: libc::xdp_desc) -> &[u8] {
-        let umem_page_len = std::mem::size_of::<HeadRoom>() + self.buffer.len();
-        let offset = (desc.addr as usize % umem_page_len) - std::mem::size_of::<HeadRoom>();
+        let umem_page_len = self.reserved() + self.buffer.len();
+        let offset = (desc.addr as usize % umem_page_len) - self.reserved();
         &self.buffer()[offset..offset + desc.len as usize]
     }
 
-    pub fn write_packet(&mut self, buf: &[u8]) {
+    pub fn read_packet_mut(&mut self, desc: libc::xdp_desc) -> &mut [u8] {
+        let umem_page_len = self.reserved() + self.buffer.len();
+        let offset = (desc.addr as usize % umem_page_len) - self.reserved();
+        // SAFETY: UmemPage lives as long as Umem.
+        unsafe { &mut self.buffer.as_mut().unwrap_unchecked()[offset..offset + desc.len as usize] }
+    }
+
+    /// The `len` bytes preceding the packet data, where XDP programs place metadata.
+    pub fn read_before(&self, desc: libc::xdp_desc, len: usize) -> &[u8] {
+        let umem_page_len = self.reserved() + self.buffer.len();
+        let offset = (desc.addr as usize % umem_page_len) - self.reserved();
+        &self.buffer()[offset.saturating_sub(len)..offset]
+    }
+
+    /// Writes `buf` at `offset` bytes from the start of the chunk.
+    pub fn write_packet(&mut self, offset: usize, buf: &[u8]) {
+        let offset = offset - self.reserved();
         // SAFETY: UmemPage lives as long as Umem.
         unsafe {
-            self.buffer.as_mut().unwrap_unchecked()[..buf.len()].copy_from_slice(buf);
+            self.buffer.as_mut().unwrap_unchecked()[offset..offset + buf.len()]
+                .copy_from_slice(buf);
         }
     }
 
-    pub fn headroom(&self) -> &HeadRoom {
+    fn headroom(&self) -> &HeadRoom {
         self.h
+            .as_ref()
+            .expect("Only read with the intrusive free list")
     }
 
-    pub fn headroom_mut(&mut self) -> &mut HeadRoom {
+    fn headroom_mut(&mut self) -> &mut HeadRoom {
         self.h
+            .as_mut()
+            .expect("Only written with the intrusive free list")
     }
 }
 
 pub struct HeadRoom {
-    free_page_id: u16,
+    free_page_id: u32,
 }
 
 impl HeadRoom {
-    pub fn free_page_id(&self) -> Option<u16> {
-        if self.free_page_id == u16::MAX {
+    pub fn free_page_id(&self) -> Option<u32> {
+        if self.free_page_id == u32::MAX {
             None
         } else {
             Some(self.free_page_id)
         }
     }
 
-    pub fn set_free_page_id(&mut self, page_id: Option<u16>) {
-        self.free_page_id = page_id.unwrap_or(u16::MAX);
+    pub fn set_free_page_id(&mut self, page_id: Option<u32>) {
+        self.free_page_id = page_id.unwrap_or(u32::MAX);
     }
 }
 
 #[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config {
     pub entries: usize,
     pub alignment: ChunkAlignment,
+    /// Bytes left free in front of every frame, on top of the kernel's
+    /// `XDP_PACKET_HEADROOM` for RX, e.g. to prepend VXLAN or GRE headers in place.
+    pub headroom: usize,
+    /// Registers the UMEM with `XDP_UMEM_UNALIGNED_CHUNK_FLAG`, so TX descriptors may
+    /// point anywhere in a chunk, e.g. in front of the frame after prepending a header,
+    /// and RX descriptors carry their offset in the upper address bits.
+    pub unaligned: bool,
+    pub free_list: FreeList,
+    pub backing: Backing,
+    /// Reserves a `struct xsk_tx_metadata` in front of every TX frame and registers it
+    /// with the kernel (Linux 6.8), for TX offloads such as `xdp::Config::tx_checksum`.
+    pub tx_metadata: bool,
+}
+
+impl Config {
+    /// Bytes before the frame in every chunk, the kernel headroom included.
+    pub(crate) fn frame_offset(&self) -> usize {
+        XDP_PACKET_HEADROOM
+            + self.free_list.reserved()
+            + self.headroom
+            + if self.tx_metadata { TX_METADATA_LEN } else { 0 }
+    }
+
+    /// Chunk size a VLAN-tagged frame of `mtu` needs after the headroom.
+    pub(crate) fn chunk_needed(&self, mtu: usize) -> usize {
+        self.frame_offset() + ETHERNET_HEADER_LEN + VLAN_TAG_LEN + mtu
+    }
+}
+
+/// Where the UMEM keeps track of the pages available for TX.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FreeList {
+    /// Threaded through the first bytes of every chunk, which are then registered as
+    /// headroom. Needs no allocation besides the UMEM.
+    Intrusive,
+    /// A stack of page ids next to the UMEM, so that allocating a page does not touch
+    /// its cold memory and the chunks carry no allocator state.
+    External,
+}
+
+impl FreeList {
+    // Bytes the free list takes at the start of every chunk.
+    pub(crate) fn reserved(self) -> usize {
+        match self {
+            Self::Intrusive => mem::size_of::<HeadRoom>(),
+            Self::External => 0,
+        }
+    }
+}
+
+/// Memory the UMEM area is allocated from.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Backing {
+    Heap,
+    /// Huge pages from `mmap(MAP_HUGETLB)`, cutting TLB misses on large UMEMs. Falls back
+    /// to the heap when the pool of the requested size is empty, see
+    /// `XdpSocket::umem_backing`.
+    HugePages(HugePageSize),
+    /// Application memory handed over with `Umem::from_raw_parts`.
+    User,
+    /// Heap memory registered with the kernel like `Heap`, but userspace only touches
+    /// separate per-chunk allocations that frames are copied in and out of. Overruns
+    /// and accesses to recycled chunks then hit allocator redzones or freed memory, so
+    /// AddressSanitizer reports them. Meant for CI runs, not for production.
+    Sanitize,
+    /// A shared mapping of an anonymous `memfd_create` file, which can be passed to other
+    /// processes, see `XdpSocket::umem_memfd`.
+    Memfd,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HugePageSize {
+    TwoM,
+    OneG,
+}
+
+impl From<HugePageSize> for usize {
+    fn from(value: HugePageSize) -> Self {
+        match value {
+            HugePageSize::TwoM => 2 << 20,
+            HugePageSize::OneG => 1 << 30,
+        }
+    }
+}
+
+/// Maps `len` bytes of huge pages, rounded up to whole pages. Returns the mapping and
+/// its length, or `None` if the kernel has no such pages to give.
+fn map_huge_pages(len: usize, size: HugePageSize) -> Option<(*mut u8, usize)> {
+    let page = usize::from(size);
+    let len = len.div_ceil(page) * page;
+    let flag = match size {
+        HugePageSize::TwoM => libc::MAP_HUGE_2MB,
+        HugePageSize::OneG => libc::MAP_HUGE_1GB,
+    };
+
+    let ptr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB | flag,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        return None;
+    }
+    Some((ptr as *mut u8, len))
+}
+
+/// Creates a memfd of `len` bytes and maps it shared.
+fn map_memfd(len: usize) -> io::Result<(OwnedFd, *mut u8)> {
+    let fd = unsafe { libc::memfd_create(c"smoltcp-umem".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the fd was just created and is owned by nobody else.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let ptr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd.as_raw_fd(),
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((fd, ptr as *mut u8))
 }
 
 #[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChunkAlignment {
     TwoK,
     FourK,
@@ -0,0 +1,50 @@
+//! The devices and wrappers of the crate under a single import, with names that do not
+//! clash once their modules are out of sight:
+//!
+//! ```ignore
+//! use smoltcp_contrib::prelude::*;
+//!
+//! let config = XdpConfig {
+//!     umem: UmemConfig {
+//!         alignment: ChunkSize::FourK,
+//!         ..
+//!     },
+//!     tx: RingConfig { size: 512 },
+//!     ..
+//! };
+//! ```
+//!
+//! Names exported here are kept stable, a renamed item keeps its old name as a
+//! deprecated alias.
+
+pub use crate::phy::announce::Announcer;
+pub use crate::phy::caps::{CapabilitiesAudit, DeviceCapabilitiesReport};
+pub use crate::phy::classify::{Classifier, Dscp, Ethertype, FiveTuple};
+pub use crate::phy::flow::{Dispatcher, FlowKey};
+pub use crate::phy::health::{HealthCheck, Report as HealthReport};
+pub use crate::phy::heartbeat::Heartbeat;
+pub use crate::phy::hop_limit::HopLimit;
+pub use crate::phy::mss_clamp::MssClamp;
+pub use crate::phy::pmtu::{Config as PmtuConfig, PmtuProbe};
+pub use crate::phy::quantum::{Config as QuantumConfig, Quantum};
+pub use crate::phy::replay::ReplayWindow;
+pub use crate::phy::rewrite::PacketEditor;
+pub use crate::phy::scratch::{FrameBuf, FrameScratch};
+pub use crate::phy::swap::{Either, Swap};
+pub use crate::phy::team::FlowTeam;
+pub use crate::phy::transform::{FrameTransform, Transform};
+pub use crate::phy::validate::{Config as ValidateConfig, Validate};
+pub use crate::phy::warmup::{Warmed, Warmup};
+
+#[cfg(all(feature = "phy-xdp", unix))]
+pub use crate::phy::fanout::FanOut;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub use crate::phy::monitor::Monitor;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub use crate::phy::poller::Poller;
+#[cfg(all(feature = "phy-xdp", unix))]
+pub use crate::phy::xdp::{
+    AttachMode, BindMode, BurstSize, BusyPoll, CapabilitiesConfig, ChunkSize, Config as XdpConfig,
+    HugePageSize, PriorityConfig, RefillConfig, RingConfig, Schedule, Umem, UmemBacking,
+    UmemConfig, UmemFreeList, VerifiedChecksums, XdpMultiQueue, XdpSocket, XdpSocketBuilder,
+};